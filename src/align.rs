@@ -0,0 +1,361 @@
+//! Ligand superposition: Find the maximum common substructure (MCS) between two small molecules,
+//! then rigidly superpose the matched atoms using the quaternion form of the Kabsch algorithm.
+//! Used to line up congeneric series.
+
+use std::{
+    array,
+    io::{self, ErrorKind},
+};
+
+use lin_alg::f64::{Quaternion, Vec3};
+
+use crate::{State, mol_characterization::PerceivedMol, mol_lig::MoleculeSmall};
+
+/// Limits MCS search time on large, or highly symmetric molecules. If reached, we use the
+/// largest substructure found so far.
+const MAX_MCS_STEPS: usize = 200_000;
+
+/// We need at least this many matched atoms to define a rotation.
+const MIN_ALIGN_ATOMS: usize = 3;
+
+/// The result of superposing one ligand onto another.
+#[derive(Clone, Debug)]
+pub struct Alignment {
+    /// Matched atoms; (index in the moved molecule, index in the reference).
+    pub atom_map: Vec<(usize, usize)>,
+    /// Over matched atoms, after superposition. Å.
+    pub rmsd: f64,
+}
+
+/// Find the maximum common connected substructure between two molecules, over heavy atoms.
+/// Atoms match by element and aromaticity; bonds by type. Returns (index in `a`, index in `b`)
+/// pairs.
+pub fn find_mcs(a: &PerceivedMol, b: &PerceivedMol) -> Vec<(usize, usize)> {
+    // Searching from the smaller molecule makes the bound effective earlier.
+    if a.heavy().len() > b.heavy().len() {
+        return find_mcs(b, a).into_iter().map(|(i, j)| (j, i)).collect();
+    }
+
+    let mut search = McsSearch {
+        a,
+        b,
+        heavy_a: a.heavy(),
+        heavy_b: b.heavy(),
+        map_ab: vec![None; a.atoms.len()],
+        map_ba: vec![None; b.atoms.len()],
+        excluded: vec![false; a.atoms.len()],
+        mapped: Vec::new(),
+        best: Vec::new(),
+        steps: 0,
+    };
+
+    // Once all MCSs containing a seed atom have been tried, exclude it from later seeds.
+    for i in a.heavy() {
+        for j in b.heavy() {
+            if !search.atoms_match(i, j) {
+                continue;
+            }
+            search.push(i, j);
+            search.extend();
+            search.pop();
+        }
+        search.excluded[i] = true;
+
+        if search.steps >= MAX_MCS_STEPS {
+            break;
+        }
+    }
+
+    search.best
+}
+
+struct McsSearch<'a> {
+    a: &'a PerceivedMol,
+    b: &'a PerceivedMol,
+    heavy_a: Vec<usize>,
+    heavy_b: Vec<usize>,
+    map_ab: Vec<Option<usize>>,
+    map_ba: Vec<Option<usize>>,
+    /// Atoms in `a` we've decided not to map, in the current branch.
+    excluded: Vec<bool>,
+    /// Mapped atoms in `a`, in order.
+    mapped: Vec<usize>,
+    /// The largest mapping found so far.
+    best: Vec<(usize, usize)>,
+    steps: usize,
+}
+
+impl McsSearch<'_> {
+    fn atoms_match(&self, i: usize, j: usize) -> bool {
+        let (ai, bj) = (&self.a.atoms[i], &self.b.atoms[j]);
+        ai.element == bj.element && ai.aromatic == bj.aromatic
+    }
+
+    fn push(&mut self, i: usize, j: usize) {
+        self.map_ab[i] = Some(j);
+        self.map_ba[j] = Some(i);
+        self.mapped.push(i);
+    }
+
+    fn pop(&mut self) {
+        let i = self.mapped.pop().unwrap();
+        let j = self.map_ab[i].take().unwrap();
+        self.map_ba[j] = None;
+    }
+
+    /// `j` in `b` is a consistent image for `i` in `a`: Bonds to all mapped atoms agree.
+    fn consistent(&self, i: usize, j: usize) -> bool {
+        self.mapped.iter().all(|&m| {
+            let bond_a = self.a.atoms[i].nbrs.iter().find(|(n, _)| *n == m);
+            let bond_b = self.b.atoms[j]
+                .nbrs
+                .iter()
+                .find(|(n, _)| *n == self.map_ab[m].unwrap());
+
+            match (bond_a, bond_b) {
+                (Some((_, bt_a)), Some((_, bt_b))) => bt_a == bt_b,
+                (None, None) => true,
+                _ => false,
+            }
+        })
+    }
+
+    fn extend(&mut self) {
+        self.steps += 1;
+
+        if self.mapped.len() > self.best.len() {
+            self.best = self
+                .mapped
+                .iter()
+                .map(|&i| (i, self.map_ab[i].unwrap()))
+                .collect();
+        }
+        if self.steps >= MAX_MCS_STEPS {
+            return;
+        }
+
+        // An upper bound on the size reachable from here.
+        let remaining_a = self
+            .heavy_a
+            .iter()
+            .filter(|&&i| self.map_ab[i].is_none() && !self.excluded[i])
+            .count();
+        let remaining_b = self
+            .heavy_b
+            .iter()
+            .filter(|&&j| self.map_ba[j].is_none())
+            .count();
+        if self.mapped.len() + remaining_a.min(remaining_b) <= self.best.len() {
+            return;
+        }
+
+        // Grow from the first unmapped, non-excluded atom bonded to the mapped set.
+        let next = self.mapped.iter().find_map(|&m| {
+            self.a.atoms[m]
+                .nbrs
+                .iter()
+                .map(|(n, _)| *n)
+                .find(|&n| self.map_ab[n].is_none() && !self.excluded[n])
+        });
+        let Some(i) = next else {
+            return;
+        };
+
+        let candidates: Vec<usize> = self
+            .heavy_b
+            .iter()
+            .copied()
+            .filter(|&j| {
+                self.map_ba[j].is_none() && self.atoms_match(i, j) && self.consistent(i, j)
+            })
+            .collect();
+
+        for j in candidates {
+            self.push(i, j);
+            self.extend();
+            self.pop();
+        }
+
+        // Also try leaving this atom out of the common substructure.
+        self.excluded[i] = true;
+        self.extend();
+        self.excluded[i] = false;
+    }
+}
+
+/// Find the rotation and translation which best superposes `mobile` onto `reference`, in the
+/// least-squares sense. Returns the rotation, and the two centroids; apply as
+/// `rot.rotate_vec(posit - ctr_mobile) + ctr_ref`.
+pub fn kabsch(mobile: &[Vec3], reference: &[Vec3]) -> (Quaternion, Vec3, Vec3) {
+    let n = mobile.len() as f64;
+    let ctr_mobile = mobile.iter().fold(Vec3::new_zero(), |acc, p| acc + *p) / n;
+    let ctr_ref = reference.iter().fold(Vec3::new_zero(), |acc, p| acc + *p) / n;
+
+    // The cross-covariance matrix; s[a][b] is the sum of mobile[a] * reference[b].
+    let mut s = [[0.; 3]; 3];
+    for (m, r) in mobile.iter().zip(reference) {
+        let m = *m - ctr_mobile;
+        let r = *r - ctr_ref;
+        for (row, m) in s.iter_mut().zip([m.x, m.y, m.z]) {
+            for (el, r) in row.iter_mut().zip([r.x, r.y, r.z]) {
+                *el += m * r;
+            }
+        }
+    }
+
+    // Horn, 1987: The optimal rotation is the eigenvector of this matrix, with the largest
+    // eigenvalue, as a unit quaternion.
+    let [[xx, xy, xz], [yx, yy, yz], [zx, zy, zz]] = s;
+    let k = [
+        [xx + yy + zz, yz - zy, zx - xz, xy - yx],
+        [yz - zy, xx - yy - zz, xy + yx, zx + xz],
+        [zx - xz, xy + yx, -xx + yy - zz, yz + zy],
+        [xy - yx, zx + xz, yz + zy, -xx - yy + zz],
+    ];
+
+    let [w, x, y, z] = max_eigenvector(k);
+    (
+        Quaternion::new(w, x, y, z).to_normalized(),
+        ctr_mobile,
+        ctr_ref,
+    )
+}
+
+/// The eigenvector of a symmetric 4x4 matrix with the largest eigenvalue, using Jacobi rotations.
+fn max_eigenvector(mut a: [[f64; 4]; 4]) -> [f64; 4] {
+    let mut v = [[0.; 4]; 4];
+    for (i, row) in v.iter_mut().enumerate() {
+        row[i] = 1.;
+    }
+
+    for _ in 0..50 {
+        let off_diag: f64 = (0..4)
+            .flat_map(|p| (p + 1..4).map(move |q| (p, q)))
+            .map(|(p, q)| a[p][q].powi(2))
+            .sum();
+        if off_diag < 1e-20 {
+            break;
+        }
+
+        for p in 0..4 {
+            for q in p + 1..4 {
+                if a[p][q].abs() < 1e-15 {
+                    continue;
+                }
+
+                let theta = (a[q][q] - a[p][p]) / (2. * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta.powi(2) + 1.).sqrt());
+                let c = 1. / (t.powi(2) + 1.).sqrt();
+                let s = t * c;
+
+                // Apply the rotation to columns, then rows of `a`, and to columns of `v`.
+                for row in a.iter_mut().chain(v.iter_mut()) {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = c * kp - s * kq;
+                    row[q] = s * kp + c * kq;
+                }
+                let (row_p, row_q) = (a[p], a[q]);
+                a[p] = array::from_fn(|k| c * row_p[k] - s * row_q[k]);
+                a[q] = array::from_fn(|k| s * row_p[k] + c * row_q[k]);
+            }
+        }
+    }
+
+    let best = (0..4).max_by(|&i, &j| a[i][i].total_cmp(&a[j][j])).unwrap();
+    [v[0][best], v[1][best], v[2][best], v[3][best]]
+}
+
+impl MoleculeSmall {
+    /// Superpose this molecule onto a reference, by the maximum common substructure. Moves all
+    /// atoms, including those outside the MCS, as a rigid body.
+    pub fn align_to(&mut self, reference: &MoleculeSmall) -> io::Result<Alignment> {
+        let atom_map = find_mcs(
+            &PerceivedMol::new(&self.common),
+            &PerceivedMol::new(&reference.common),
+        );
+
+        if atom_map.len() < MIN_ALIGN_ATOMS {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Only {} atoms in common between {} and {}; unable to align",
+                    atom_map.len(),
+                    self.common.ident,
+                    reference.common.ident
+                ),
+            ));
+        }
+
+        let mobile: Vec<_> = atom_map
+            .iter()
+            .map(|(i, _)| self.common.atom_posits[*i])
+            .collect();
+        let ref_posits: Vec<_> = atom_map
+            .iter()
+            .map(|(_, j)| reference.common.atom_posits[*j])
+            .collect();
+
+        let (rot, ctr_mobile, ctr_ref) = kabsch(&mobile, &ref_posits);
+
+        for posit in &mut self.common.atom_posits {
+            *posit = rot.rotate_vec(*posit - ctr_mobile) + ctr_ref;
+        }
+
+        let sum_sq: f64 = atom_map
+            .iter()
+            .map(|(i, j)| {
+                (self.common.atom_posits[*i] - reference.common.atom_posits[*j]).magnitude_squared()
+            })
+            .sum();
+
+        Ok(Alignment {
+            rmsd: (sum_sq / atom_map.len() as f64).sqrt(),
+            atom_map,
+        })
+    }
+}
+
+impl State {
+    /// Superpose all other open ligands onto one. Returns a status message, with RMSDs.
+    pub fn align_ligands_to(&mut self, ref_i: usize) -> io::Result<String> {
+        let Some(reference) = self.ligands.get(ref_i).cloned() else {
+            return Err(io::Error::new(ErrorKind::NotFound, "No reference ligand"));
+        };
+
+        let mut results = Vec::new();
+        let mut failed = Vec::new();
+
+        for (i, lig) in self.ligands.iter_mut().enumerate() {
+            if i == ref_i {
+                continue;
+            }
+            match lig.align_to(&reference) {
+                Ok(alignment) => results.push(format!(
+                    "{}: {:.2} Å ({} atoms)",
+                    lig.common.ident,
+                    alignment.rmsd,
+                    alignment.atom_map.len()
+                )),
+                Err(_) => failed.push(lig.common.ident.clone()),
+            }
+        }
+
+        if results.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("No ligands could be aligned to {}", reference.common.ident),
+            ));
+        }
+
+        let mut msg = format!(
+            "Aligned to {}. RMSD: {}",
+            reference.common.ident,
+            results.join(", ")
+        );
+        if !failed.is_empty() {
+            msg += &format!(". Too few atoms in common: {}", failed.join(", "));
+        }
+
+        Ok(msg)
+    }
+}
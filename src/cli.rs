@@ -31,7 +31,7 @@ fn new_invalid(msg: &str) -> io::Error {
 }
 
 // We use this for autocomplete.
-pub const CLI_CMDS: [&str; 20] = [
+pub const CLI_CMDS: [&str; 21] = [
     "help",
     "fetch",
     "save",
@@ -51,6 +51,7 @@ pub const CLI_CMDS: [&str; 20] = [
     "select resn",
     "select resi",
     "select elem",
+    "select smarts",
     "set",
 ];
 
@@ -93,6 +94,8 @@ pub fn handle_cmd(
     let re_sel_resi = Regex::new(r"(?i)^(?:sele|select)\s+resi\s+([0-9]+)$").unwrap();
     let re_sel_resn = Regex::new(r"(?i)^(?:sele|select)\s+resn\s+([a-z]{3})$").unwrap();
     let re_sel_elem = Regex::new(r"(?i)^(?:sele|select)\s+elem\s+([a-z]{1,2})$").unwrap();
+    // SMARTS is case-sensitive, so only the command is case-insensitive.
+    let re_sel_smarts = Regex::new(r"^(?i:sele|select)\s+(?i:smarts)\s+(\S+)$").unwrap();
 
     let re_set = Regex::new(r"(?i)^set\s+([a-z0-9\s\-_]+)(?:,\s*([a-z0-9]+))?$").unwrap();
 
@@ -375,12 +378,12 @@ pub fn handle_cmd(
     if let Some(caps) = re_sel_resi.captures(&input)
         && let Some(mol) = &state.peptide
     {
-        let i: u32 = caps[1]
+        let i: i32 = caps[1]
             .parse()
             .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Invalid index."))?;
 
         for (i_res, res) in mol.residues.iter().enumerate() {
-            if res.serial_number == i {
+            if res.seq_num == i {
                 state.ui.selection = Selection::Residue(i_res);
                 *redraw = true;
                 return Ok("Complete".to_owned());
@@ -407,6 +410,10 @@ pub fn handle_cmd(
         return Ok("Complete".to_owned());
     }
 
+    if let Some(caps) = re_sel_smarts.captures(&input) {
+        return state.select_smarts(&caps[1]);
+    }
+
     if let Some(caps) = re_set.captures(&input) {
         let action = &caps[1].to_lowercase();
 
@@ -0,0 +1,367 @@
+//! Importing results from other docking programs, e.g. AutoDock Vina and Gnina, for inspection.
+//! These write several poses of one ligand to a file: As MODEL blocks in PDBQT, or as records
+//! of a multi-record SDF. Each has the program's scores as remarks or tags.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, ErrorKind},
+    ops::Range,
+};
+
+use bio_files::{Pdbqt, Sdf};
+use graphics::{EngineUpdates, EntityUpdate, Scene};
+use lin_alg::f64::Vec3;
+
+use crate::{
+    State,
+    docking::{
+        score::{VinaScore, dock_types},
+        score_pose,
+    },
+    drawing_wrappers::draw_all_ligs,
+    mol_library::split_records,
+    mol_lig::MoleculeSmall,
+    molecule::{MoleculeCommon, MoleculePeptide},
+};
+
+/// Remarks or tags holding a program's score for a pose, in kcal/mol, in order of preference.
+/// Vina writes `REMARK VINA RESULT:` in PDBQT; Gnina and Smina write `minimizedAffinity`.
+const SCORE_KEYS: [&str; 2] = ["VINA RESULT", "minimizedAffinity"];
+/// Å. Ligand and protein atoms this close are in contact.
+const CONTACT_DIST: f64 = 4.;
+/// Å. Heavy-atom donor-acceptor distance for hydrogen bonds.
+const H_BOND_DIST: f64 = 3.5;
+/// Å. Heavy atoms this close are clashing.
+const CLASH_DIST: f64 = 2.2;
+
+/// One pose from another program's docking output.
+#[derive(Clone, Debug)]
+pub struct ExternalPose {
+    /// In the order of the ligand's atoms.
+    pub posits: Vec<Vec3>,
+    /// kcal/mol. The program's score, e.g. Vina's affinity. Lower is better.
+    pub score: Option<f64>,
+    /// Other numerical values the program reports for the pose, e.g. RMSD bounds, or Gnina's
+    /// CNN scores. (Name, value)
+    pub props: Vec<(String, f64)>,
+}
+
+/// Split PDBQT text into the text of each MODEL. Text without MODEL records is a single model.
+fn pdbqt_models(text: &str) -> Vec<String> {
+    if !text.lines().any(|l| l.starts_with("MODEL")) {
+        return vec![text.to_owned()];
+    }
+
+    let mut result = Vec::new();
+    let mut current = None;
+
+    for line in text.lines() {
+        if line.starts_with("MODEL") {
+            current = Some(String::new());
+        } else if line.starts_with("ENDMDL") {
+            if let Some(model) = current.take() {
+                result.push(model);
+            }
+        } else if let Some(model) = &mut current {
+            model.push_str(line);
+            model.push('\n');
+        }
+    }
+
+    result
+}
+
+/// Parse a numerical PDBQT remark, e.g. `REMARK VINA RESULT:  -7.5  0.000  0.000` from Vina,
+/// or `REMARK CNNscore 0.81` from Gnina.
+fn pdbqt_remark(line: &str) -> Option<(String, Vec<f64>)> {
+    let remark = line.strip_prefix("REMARK")?.trim();
+
+    let (name, values) = match remark.split_once(':') {
+        Some((n, v)) => (n.trim(), v),
+        None => remark.split_once(char::is_whitespace)?,
+    };
+
+    let values: Vec<f64> = values
+        .split_whitespace()
+        .map(|v| v.parse().ok())
+        .collect::<Option<_>>()?;
+
+    if name.is_empty() || values.is_empty() {
+        return None;
+    }
+    Some((name.to_owned(), values))
+}
+
+/// Atom positions and scores from each model of PDBQT text.
+pub fn poses_from_pdbqt(text: &str) -> io::Result<Vec<ExternalPose>> {
+    let mut result = Vec::new();
+
+    for model in pdbqt_models(text) {
+        let mut pose = ExternalPose {
+            posits: Vec::new(),
+            score: None,
+            props: Vec::new(),
+        };
+
+        for line in model.lines() {
+            if line.starts_with("ATOM") || line.starts_with("HETATM") {
+                let coord = |range: Range<usize>| {
+                    line.get(range)
+                        .and_then(|v| v.trim().parse().ok())
+                        .ok_or_else(|| {
+                            io::Error::new(
+                                ErrorKind::InvalidData,
+                                format!("Invalid atom coordinates: {line}"),
+                            )
+                        })
+                };
+                pose.posits
+                    .push(Vec3::new(coord(30..38)?, coord(38..46)?, coord(46..54)?));
+            } else if let Some((name, values)) = pdbqt_remark(line) {
+                if name == "VINA RESULT" && values.len() == 3 {
+                    pose.props.push(("RMSD l.b.".to_owned(), values[1]));
+                    pose.props.push(("RMSD u.b.".to_owned(), values[2]));
+                }
+                pose.props.push((name, values[0]));
+            }
+        }
+
+        if pose.posits.is_empty() {
+            continue;
+        }
+        set_score(&mut pose);
+        result.push(pose);
+    }
+
+    Ok(result)
+}
+
+/// Move the preferred score from the props, to the score field.
+fn set_score(pose: &mut ExternalPose) {
+    for key in SCORE_KEYS {
+        if let Some(i) = pose.props.iter().position(|(name, _)| name == key) {
+            pose.score = Some(pose.props.remove(i).1);
+            return;
+        }
+    }
+}
+
+/// Check that each pose has one position per ligand atom, then attach the poses, and apply the
+/// first.
+fn attach_poses(mol: &mut MoleculeSmall, poses: Vec<ExternalPose>) -> io::Result<()> {
+    let n = mol.common.atoms.len();
+    for (i, pose) in poses.iter().enumerate() {
+        if pose.posits.len() != n {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Pose {} has {} atoms; expected {n}",
+                    i + 1,
+                    pose.posits.len()
+                ),
+            ));
+        }
+    }
+
+    if let Some(pose) = poses.first() {
+        mol.common.atom_posits = pose.posits.clone();
+    }
+    mol.ext_poses = poses;
+    mol.ext_pose_i = 0;
+
+    Ok(())
+}
+
+/// Load a ligand from PDBQT text. If this is docking output with several models, e.g. from Vina,
+/// the ligand is built from the first, and all are attached as poses.
+pub fn load_pdbqt(text: &str) -> io::Result<MoleculeSmall> {
+    let models = pdbqt_models(text);
+    let Some(first) = models.first() else {
+        return Err(io::Error::new(ErrorKind::InvalidData, "No models found"));
+    };
+
+    let mut mol: MoleculeSmall = Pdbqt::new(first)?.try_into()?;
+
+    let poses = poses_from_pdbqt(text)?;
+    if poses.len() > 1 || poses.iter().any(|p| p.score.is_some()) {
+        attach_poses(&mut mol, poses)?;
+    }
+
+    Ok(mol)
+}
+
+/// If SDF text is docking output, e.g. from Gnina, load the ligand from the first record, with
+/// all attached as poses. Returns `None` if it isn't: We require multiple records of the same
+/// atoms, each with a score tag. Other multi-record files are libraries.
+pub fn load_sdf_poses(text: &str) -> io::Result<Option<MoleculeSmall>> {
+    let records = split_records(text, false);
+    if records.len() < 2 {
+        return Ok(None);
+    }
+
+    let mut sdfs = Vec::with_capacity(records.len());
+    for rec in &records {
+        let Ok(sdf) = Sdf::new(rec) else {
+            return Ok(None);
+        };
+        if !SCORE_KEYS.iter().any(|k| sdf.metadata.contains_key(*k)) {
+            return Ok(None);
+        }
+        sdfs.push(sdf);
+    }
+
+    let same_atoms = sdfs.iter().all(|s| {
+        s.atoms.len() == sdfs[0].atoms.len()
+            && s.atoms
+                .iter()
+                .zip(&sdfs[0].atoms)
+                .all(|(a, b)| a.element == b.element)
+    });
+    if !same_atoms {
+        return Ok(None);
+    }
+
+    let poses = sdfs
+        .iter()
+        .map(|sdf| {
+            let mut props: Vec<_> = sdf
+                .metadata
+                .iter()
+                .filter_map(|(k, v)| Some((k.clone(), v.trim().parse().ok()?)))
+                .collect();
+            // Sorted by name, so poses list them consistently.
+            props.sort_by(|a, b| a.0.cmp(&b.0));
+
+            let mut pose = ExternalPose {
+                posits: sdf.atoms.iter().map(|a| a.posit).collect(),
+                score: None,
+                props,
+            };
+            set_score(&mut pose);
+            pose
+        })
+        .collect();
+
+    let mut mol: MoleculeSmall = sdfs.remove(0).try_into()?;
+    attach_poses(&mut mol, poses)?;
+
+    Ok(Some(mol))
+}
+
+/// Interactions of one pose with the protein, for comparing poses.
+#[derive(Clone, Debug)]
+pub struct PoseInteractions {
+    /// Our empirical score of the pose. See `score.rs`.
+    pub score: VinaScore,
+    /// Donor-acceptor heavy-atom pairs between ligand and protein, within hydrogen bond distance.
+    pub h_bonds: usize,
+    /// Hydrophobic ligand-protein atom pairs in contact.
+    pub hydrophobic: usize,
+    /// Ligand-protein heavy-atom pairs which are too close.
+    pub clashes: usize,
+    /// Indices of protein residues in contact with the ligand.
+    pub residues: Vec<usize>,
+}
+
+impl PoseInteractions {
+    pub fn new(pep: &MoleculePeptide, lig: &MoleculeCommon, posits: &[Vec3]) -> Self {
+        let mut lig = lig.clone();
+        lig.atom_posits = posits.to_vec();
+
+        let lig_types = dock_types(&lig);
+        let pep_types = dock_types(&pep.common);
+
+        let mut h_bonds = 0;
+        let mut hydrophobic = 0;
+        let mut clashes = 0;
+        let mut residues = BTreeSet::new();
+
+        for (i, lig_type) in lig_types.iter().enumerate() {
+            let Some(lt) = lig_type else {
+                continue;
+            };
+            let posit_lig = lig.atom_posits[i];
+
+            for (j, pep_type) in pep_types.iter().enumerate() {
+                let Some(pt) = pep_type else {
+                    continue;
+                };
+                if pep.common.atoms[j].hetero {
+                    continue;
+                }
+
+                let dist = (pep.common.atom_posits[j] - posit_lig).magnitude();
+                if dist > CONTACT_DIST {
+                    continue;
+                }
+
+                if let Some(res) = pep.common.atoms[j].residue {
+                    residues.insert(res);
+                }
+                if dist < CLASH_DIST {
+                    clashes += 1;
+                }
+                if lt.hydrophobic() && pt.hydrophobic() {
+                    hydrophobic += 1;
+                }
+                if dist < H_BOND_DIST
+                    && ((lt.donor() && pt.acceptor()) || (lt.acceptor() && pt.donor()))
+                {
+                    h_bonds += 1;
+                }
+            }
+        }
+
+        Self {
+            score: score_pose(pep, &lig),
+            h_bonds,
+            hydrophobic,
+            clashes,
+            residues: residues.into_iter().collect(),
+        }
+    }
+}
+
+/// Interactions of each of a ligand's imported poses, for display.
+#[derive(Clone, Debug)]
+pub struct PoseComparison {
+    pub lig_i: usize,
+    pub interactions: Vec<PoseInteractions>,
+}
+
+impl PoseComparison {
+    pub fn new(pep: &MoleculePeptide, lig: &MoleculeSmall, lig_i: usize) -> Self {
+        Self {
+            lig_i,
+            interactions: lig
+                .ext_poses
+                .iter()
+                .map(|pose| PoseInteractions::new(pep, &lig.common, &pose.posits))
+                .collect(),
+        }
+    }
+}
+
+impl State {
+    /// Apply one of a ligand's imported poses.
+    pub fn set_ext_pose(
+        &mut self,
+        lig_i: usize,
+        pose_i: usize,
+        scene: &mut Scene,
+        engine_updates: &mut EngineUpdates,
+    ) {
+        let Some(mol) = self.ligands.get_mut(lig_i) else {
+            return;
+        };
+        let Some(pose) = mol.ext_poses.get(pose_i) else {
+            return;
+        };
+
+        mol.common.atom_posits = pose.posits.clone();
+        mol.ext_pose_i = pose_i;
+
+        draw_all_ligs(self, scene);
+        engine_updates.entities = EntityUpdate::All;
+    }
+}
@@ -0,0 +1,293 @@
+//! Binding-pocket detection on proteins, using grid buriedness, similar to LIGSITE. Empty grid
+//! points enclosed by protein along most of a set of scan lines through them are pocket points.
+//! We cluster these into docking sites, and rank them with a druggability-style score.
+
+use std::collections::{HashSet, VecDeque};
+
+use lin_alg::f64::Vec3;
+use na_seq::Element;
+use rayon::prelude::*;
+
+use crate::{docking::DockingSite, molecule::MoleculePeptide};
+
+/// Å
+const GRID_SPACING: f64 = 1.;
+/// Å. Padding around the protein's bounding box.
+const GRID_PAD: f64 = 4.;
+/// Å. How far along each scan line we look for protein.
+const SCAN_DIST: f64 = 10.;
+/// The 3 axes, and the 4 cube diagonals. Each line is scanned in both directions.
+const SCAN_LINES: [(isize, isize, isize); 7] = [
+    (1, 0, 0),
+    (0, 1, 0),
+    (0, 0, 1),
+    (1, 1, 1),
+    (1, 1, -1),
+    (1, -1, 1),
+    (-1, 1, 1),
+];
+/// Of the 7 scan lines, how many must meet protein on both sides of a point for it to be in a pocket.
+const MIN_BURIEDNESS: u8 = 5;
+/// Smaller clusters of pocket points are discarded. At 1Å spacing, this is a volume in Å³.
+const MIN_SITE_POINTS: usize = 40;
+/// Å. Atoms within this distance of a site's pocket points line it.
+const LINING_DIST: f64 = 4.;
+/// Å³. The volume at which the size term of the score saturates.
+const REF_VOLUME: f64 = 500.;
+
+/// A detected binding pocket. Unlike `DockingSite`, which is saved per peptide, this isn't
+/// persisted; run detection again after loading.
+#[derive(Clone, Debug)]
+pub struct DetectedSite {
+    pub site: DockingSite,
+    /// Å³
+    pub volume: f64,
+    /// Indices of the peptide residues lining the pocket.
+    pub residues: Vec<usize>,
+    /// A rough druggability estimate, from 0 to 1. Weights pocket size, enclosure,
+    /// and hydrophobicity of the lining atoms.
+    pub score: f64,
+}
+
+struct Grid {
+    min: Vec3,
+    dims: [usize; 3],
+}
+
+impl Grid {
+    fn len(&self) -> usize {
+        self.dims[0] * self.dims[1] * self.dims[2]
+    }
+
+    fn coords(&self, i: usize) -> [isize; 3] {
+        let x = i % self.dims[0];
+        let y = (i / self.dims[0]) % self.dims[1];
+        let z = i / (self.dims[0] * self.dims[1]);
+        [x as isize, y as isize, z as isize]
+    }
+
+    /// The index of a grid point, if in bounds.
+    fn index(&self, c: [isize; 3]) -> Option<usize> {
+        for (v, &dim) in c.iter().zip(&self.dims) {
+            if *v < 0 || *v as usize >= dim {
+                return None;
+            }
+        }
+        let [x, y, z] = c.map(|v| v as usize);
+        Some(x + self.dims[0] * (y + self.dims[1] * z))
+    }
+
+    fn posit(&self, i: usize) -> Vec3 {
+        let [x, y, z] = self.coords(i);
+        self.min + Vec3::new(x as f64, y as f64, z as f64) * GRID_SPACING
+    }
+
+    fn nearest(&self, posit: Vec3) -> [isize; 3] {
+        let d = (posit - self.min) / GRID_SPACING;
+        [
+            d.x.round() as isize,
+            d.y.round() as isize,
+            d.z.round() as isize,
+        ]
+    }
+
+    /// Indices of grid points within `dist` of `posit`.
+    fn near(&self, posit: Vec3, dist: f64) -> Vec<usize> {
+        let c = self.nearest(posit);
+        let n = (dist / GRID_SPACING).ceil() as isize;
+
+        let mut result = Vec::new();
+        for dz in -n..=n {
+            for dy in -n..=n {
+                for dx in -n..=n {
+                    let Some(i) = self.index([c[0] + dx, c[1] + dy, c[2] + dz]) else {
+                        continue;
+                    };
+                    if (self.posit(i) - posit).magnitude_squared() < dist.powi(2) {
+                        result.push(i);
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Find pockets on the protein suitable for docking, ranked by score, best first. Hetero atoms,
+/// e.g. ligands and water from the structure file, are ignored, so occupied pockets are found too.
+pub fn find_docking_sites(mol: &MoleculePeptide) -> Vec<DetectedSite> {
+    let atoms: Vec<_> = (0..mol.common.atoms.len())
+        .filter(|&i| {
+            let atom = &mol.common.atoms[i];
+            !atom.hetero && atom.element != Element::Hydrogen
+        })
+        .collect();
+
+    if atoms.is_empty() {
+        return Vec::new();
+    }
+
+    let posits = &mol.common.atom_posits;
+
+    let mut min = posits[atoms[0]];
+    let mut max = min;
+    for &i in &atoms {
+        let p = posits[i];
+        min = Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+        max = Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+    }
+    let pad = Vec3::new(GRID_PAD, GRID_PAD, GRID_PAD);
+    let min = min - pad;
+    let size = max + pad - min;
+
+    let grid = Grid {
+        min,
+        dims: [size.x, size.y, size.z].map(|v| (v / GRID_SPACING).ceil() as usize + 1),
+    };
+
+    // Grid points inside any atom's VDW radius.
+    let mut occupied = vec![false; grid.len()];
+    for &i in &atoms {
+        let radius = mol.common.atoms[i].element.vdw_radius() as f64;
+        for j in grid.near(posits[i], radius) {
+            occupied[j] = true;
+        }
+    }
+
+    let buriedness: Vec<u8> = (0..grid.len())
+        .into_par_iter()
+        .map(|i| {
+            if occupied[i] {
+                return 0;
+            }
+            let c = grid.coords(i);
+
+            let hits = |(dx, dy, dz): (isize, isize, isize), sign: isize| {
+                let step_len = ((dx * dx + dy * dy + dz * dz) as f64).sqrt() * GRID_SPACING;
+                let steps = (SCAN_DIST / step_len) as isize;
+
+                (1..=steps).any(|k| {
+                    let k = k * sign;
+                    grid.index([c[0] + k * dx, c[1] + k * dy, c[2] + k * dz])
+                        .is_some_and(|j| occupied[j])
+                })
+            };
+
+            SCAN_LINES
+                .iter()
+                .filter(|&&line| hits(line, 1) && hits(line, -1))
+                .count() as u8
+        })
+        .collect();
+
+    // Cluster pocket points, using 26-connectivity.
+    let mut cluster_of = vec![None; grid.len()];
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+
+    for start in 0..grid.len() {
+        if buriedness[start] < MIN_BURIEDNESS || cluster_of[start].is_some() {
+            continue;
+        }
+
+        let cluster_i = clusters.len();
+        let mut members = Vec::new();
+        let mut queue = VecDeque::from([start]);
+        cluster_of[start] = Some(cluster_i);
+
+        while let Some(i) = queue.pop_front() {
+            members.push(i);
+            let c = grid.coords(i);
+
+            for dz in -1..=1 {
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        let Some(j) = grid.index([c[0] + dx, c[1] + dy, c[2] + dz]) else {
+                            continue;
+                        };
+                        if buriedness[j] >= MIN_BURIEDNESS && cluster_of[j].is_none() {
+                            cluster_of[j] = Some(cluster_i);
+                            queue.push_back(j);
+                        }
+                    }
+                }
+            }
+        }
+        clusters.push(members);
+    }
+
+    // Lining residues, and (atom count, hydrophobic atom count), per cluster.
+    let mut residues = vec![HashSet::new(); clusters.len()];
+    let mut lining = vec![(0, 0); clusters.len()];
+
+    for &i in &atoms {
+        let atom = &mol.common.atoms[i];
+
+        let touching: HashSet<_> = grid
+            .near(posits[i], LINING_DIST)
+            .into_iter()
+            .filter_map(|j| cluster_of[j])
+            .collect();
+
+        for cluster_i in touching {
+            if let Some(res) = atom.residue {
+                residues[cluster_i].insert(res);
+            }
+            lining[cluster_i].0 += 1;
+            if matches!(atom.element, Element::Carbon | Element::Sulfur) {
+                lining[cluster_i].1 += 1;
+            }
+        }
+    }
+
+    let mut result: Vec<_> = clusters
+        .iter()
+        .enumerate()
+        .filter(|(_, members)| members.len() >= MIN_SITE_POINTS)
+        .map(|(cluster_i, members)| {
+            let n = members.len() as f64;
+
+            let center = members
+                .iter()
+                .fold(Vec3::new_zero(), |a, &i| a + grid.posit(i))
+                / n;
+
+            let msd = members
+                .iter()
+                .map(|&i| (grid.posit(i) - center).magnitude_squared())
+                .sum::<f64>()
+                / n;
+
+            let volume = n * GRID_SPACING.powi(3);
+
+            let mean_buriedness = members.iter().map(|&i| buriedness[i] as f64).sum::<f64>() / n;
+
+            let (lining_count, hydrophobic_count) = lining[cluster_i];
+            let hydrophobic = if lining_count == 0 {
+                0.
+            } else {
+                hydrophobic_count as f64 / lining_count as f64
+            };
+
+            let size_term = (volume / REF_VOLUME).min(1.);
+            let enclosure_term = (mean_buriedness - MIN_BURIEDNESS as f64)
+                / (SCAN_LINES.len() as f64 - MIN_BURIEDNESS as f64);
+
+            let mut residues: Vec<_> = residues[cluster_i].iter().copied().collect();
+            residues.sort_unstable();
+
+            DetectedSite {
+                site: DockingSite {
+                    site_center: center,
+                    // The radius of a uniformly-filled sphere with the same RMS distance from its center.
+                    site_radius: (msd * 5. / 3.).sqrt(),
+                },
+                volume,
+                residues,
+                score: 0.4 * size_term + 0.3 * enclosure_term + 0.3 * hydrophobic,
+            }
+        })
+        .collect();
+
+    result.sort_by(|a, b| b.score.total_cmp(&a.score));
+    result
+}
@@ -1,5 +1,11 @@
 //! A new approach, leveraging our molecular dynamics state and processes.
 
+pub mod external;
+pub mod find_sites;
+pub mod score;
+pub mod screen;
+pub mod search;
+
 use std::{
     collections::{HashMap, HashSet},
     time::Instant,
@@ -11,14 +17,20 @@ use dynamics::{
     ComputationDevice, FfMolType, HydrogenConstraint, MdConfig, MdState, MolDynamics, ParamError,
     params::FfParamSet,
 };
-use graphics::{EngineUpdates, Scene};
+use graphics::{EngineUpdates, EntityUpdate, Scene};
 use lin_alg::{f32::Vec3 as Vec3F32, f64::Vec3};
 
 use crate::{
     State,
+    docking::{
+        score::{DockType, ScoreGrid, ScoreReceptor, VinaScore, dock_types},
+        search::{Nonbonded, Receptor, search_poses},
+    },
+    drawing_wrappers::draw_all_ligs,
     md::{filter_peptide_atoms, post_run_cleanup, reassign_snapshot_indices, run_dynamics},
+    mol_characterization::PerceivedMol,
     mol_lig::MoleculeSmall,
-    molecule::MoleculePeptide,
+    molecule::{MoleculeCommon, MoleculePeptide},
 };
 
 #[derive(Clone, Debug, Default)]
@@ -69,15 +81,276 @@ pub struct Pose {
     pub posits: Vec<Vec3>,
 }
 
+#[derive(Clone, Debug)]
 pub struct DockingPose {
-    lig_atom_posits: Vec<Vec3>,
-    potential_energy: f64,
+    pub lig_atom_posits: Vec<Vec3>,
+    /// kcal/mol. The ligand-receptor interaction energy; lower is better.
+    pub potential_energy: f64,
+    /// kcal/mol. The Lennard-Jones and electrostatic components of the energy.
+    pub energy_vdw: f64,
+    pub energy_elec: f64,
+    /// kcal/mol. The empirical score, from `score.rs`. An estimate of binding free energy.
+    pub score: f64,
+    /// Å. Heavy-atom RMSD from the top-ranked pose.
+    pub rmsd_to_best: f64,
 }
 
+/// The result of a docking run: ranked poses of one ligand.
 #[derive(Debug, Default)]
-pub struct DockingState {}
+pub struct DockingState {
+    pub lig_i: usize,
+    /// Lowest energy first.
+    pub poses: Vec<DockingPose>,
+    /// The pose currently applied to the ligand.
+    pub pose_i: usize,
+    /// The ligand's positions prior to docking, so they can be restored.
+    pub posits_orig: Vec<Vec3>,
+}
+
+/// Nonbonded parameters for each atom. Atoms missing a force field type or LJ parameters use
+/// element-based defaults.
+fn nonbonded_params(mol: &MoleculeCommon, param_sets: &[&ForceFieldParams]) -> Vec<Nonbonded> {
+    mol.atoms
+        .iter()
+        .map(|atom| {
+            let lj = atom
+                .force_field_type
+                .as_ref()
+                .and_then(|ff_type| param_sets.iter().find_map(|p| p.lennard_jones.get(ff_type)));
+
+            match lj {
+                Some(lj) => Nonbonded {
+                    charge: atom.partial_charge.unwrap_or_default() as f64,
+                    sigma: lj.sigma as f64,
+                    eps: lj.eps as f64,
+                },
+                None => Nonbonded {
+                    charge: atom.partial_charge.unwrap_or_default() as f64,
+                    ..Nonbonded::from_element(atom.element)
+                },
+            }
+        })
+        .collect()
+}
+
+/// Å. The furthest atom from the molecule's centroid.
+fn mol_radius(mol: &MoleculeCommon) -> f64 {
+    let ctr = mol.centroid();
+    mol.atom_posits
+        .iter()
+        .map(|p| (*p - ctr).magnitude())
+        .fold(0., f64::max)
+}
+
+/// Indices of the peptide's protein atoms. We assume hetero atoms are ligands, water etc, and
+/// are not part of the protein.
+fn protein_atoms(pep: &MoleculeCommon) -> Vec<usize> {
+    (0..pep.atoms.len())
+        .filter(|&i| !pep.atoms[i].hetero)
+        .collect()
+}
+
+/// Receptor data for docking in a site: Protein atoms near it, and the score grid. This is
+/// independent of the ligand, so can be shared between ligands docked in the same site.
+pub struct SiteSetup {
+    pub site: DockingSite,
+    receptor: Receptor,
+    grid: ScoreGrid,
+}
+
+impl SiteSetup {
+    /// `pep_params` is indexed by peptide atom. `lig_radius` is the largest ligand radius to
+    /// accommodate, and `lig_types` the ligand atom types to build score maps for.
+    pub fn new(
+        pep: &MoleculeCommon,
+        pep_params: &[Nonbonded],
+        site: &DockingSite,
+        lig_radius: f64,
+        lig_types: &[DockType],
+    ) -> Self {
+        let pep_atoms = protein_atoms(pep);
+
+        let receptor = Receptor::new(
+            &pep_atoms
+                .iter()
+                .map(|&i| pep.atom_posits[i])
+                .collect::<Vec<_>>(),
+            &pep_atoms.iter().map(|&i| pep_params[i]).collect::<Vec<_>>(),
+            site,
+            lig_radius,
+        );
+
+        let grid = ScoreGrid::new(&ScoreReceptor::new(pep, &pep_atoms, site), site, lig_types);
+
+        Self {
+            site: site.clone(),
+            receptor,
+            grid,
+        }
+    }
+
+    /// Search for poses of a ligand in the site, and score them. Poses are ranked by
+    /// interaction energy.
+    pub fn dock(&self, lig: &MoleculeCommon, lig_params: &[Nonbonded]) -> Vec<DockingPose> {
+        let mut poses = search_poses(lig, lig_params, &self.receptor, &self.site);
+
+        let lig_types = dock_types(lig);
+        let num_rot_bonds = PerceivedMol::new(lig).rotatable_bonds().len();
+        for pose in &mut poses {
+            pose.score = self
+                .grid
+                .score(&pose.lig_atom_posits, &lig_types, num_rot_bonds);
+        }
 
+        poses
+    }
+}
+
+/// Dock a ligand: Search for poses in the site, and rank them by interaction energy with
+/// the protein. If no site is passed, we use one centered on the ligand's current position.
+/// Applies the top-ranked pose, and returns the number of distinct poses found.
 pub fn dock(
+    state: &mut State,
+    mol_i: usize,
+    site: Option<&DockingSite>,
+    scene: &mut Scene,
+    engine_updates: &mut EngineUpdates,
+) -> Result<usize, ParamError> {
+    let Some(pep) = &state.peptide else {
+        return Err(ParamError::new("No peptide; can't dock."));
+    };
+    let mol = &state.ligands[mol_i];
+
+    let Some(msp) = state.mol_specific_params.get(&mol.common.ident) else {
+        return Err(ParamError::new(&format!(
+            "Missing molecule-specific parameters for  {}",
+            mol.common.ident
+        )));
+    };
+
+    let site = match site {
+        Some(s) => s.clone(),
+        None => DockingSite {
+            site_center: mol.common.centroid(),
+            ..Default::default()
+        },
+    };
+
+    let mut lig_sets = vec![msp];
+    if let Some(p) = &state.ff_param_set.small_mol {
+        lig_sets.push(p);
+    }
+    let lig_params = nonbonded_params(&mol.common, &lig_sets);
+
+    let pep_sets: Vec<_> = state.ff_param_set.peptide.iter().collect();
+    let pep_params = nonbonded_params(&pep.common, &pep_sets);
+
+    let start = Instant::now();
+
+    let lig_types: Vec<_> = dock_types(&mol.common).into_iter().flatten().collect();
+    let setup = SiteSetup::new(
+        &pep.common,
+        &pep_params,
+        &site,
+        mol_radius(&mol.common),
+        &lig_types,
+    );
+    let poses = setup.dock(&mol.common, &lig_params);
+
+    println!(
+        "Docking complete in {:.1} s",
+        start.elapsed().as_millis() as f32 / 1_000.
+    );
+
+    if poses.is_empty() {
+        return Err(ParamError::new("No docking poses found"));
+    }
+    let count = poses.len();
+
+    state.docking = Some(DockingState {
+        lig_i: mol_i,
+        poses,
+        pose_i: 0,
+        posits_orig: mol.common.atom_posits.clone(),
+    });
+    state.set_docking_pose(0, scene, engine_updates);
+
+    Ok(count)
+}
+
+/// Score a ligand's current pose against the protein with the empirical scoring function. For
+/// example, after MD, or for a pose imported from another docking program.
+pub fn score_pose(pep: &MoleculePeptide, lig: &MoleculeCommon) -> VinaScore {
+    let site = DockingSite {
+        site_center: lig.centroid(),
+        site_radius: mol_radius(lig),
+    };
+
+    ScoreReceptor::new(&pep.common, &protein_atoms(&pep.common), &site).score(
+        &lig.atom_posits,
+        &dock_types(lig),
+        PerceivedMol::new(lig).rotatable_bonds().len(),
+    )
+}
+
+impl State {
+    /// Apply one of the docked poses to its ligand.
+    pub fn set_docking_pose(
+        &mut self,
+        pose_i: usize,
+        scene: &mut Scene,
+        engine_updates: &mut EngineUpdates,
+    ) {
+        let Some(docking) = &mut self.docking else {
+            return;
+        };
+        let Some(pose) = docking.poses.get(pose_i) else {
+            return;
+        };
+        let Some(mol) = self.ligands.get_mut(docking.lig_i) else {
+            return;
+        };
+
+        mol.common.atom_posits = pose.lig_atom_posits.clone();
+        docking.pose_i = pose_i;
+
+        draw_all_ligs(self, scene);
+        engine_updates.entities = EntityUpdate::All;
+    }
+
+    /// End the docking session. Keeps the current pose, or restores the ligand's original positions.
+    pub fn finish_docking(
+        &mut self,
+        keep: bool,
+        scene: &mut Scene,
+        engine_updates: &mut EngineUpdates,
+    ) {
+        let Some(docking) = self.docking.take() else {
+            return;
+        };
+
+        let Some(mol) = self.ligands.get_mut(docking.lig_i) else {
+            return;
+        };
+
+        if keep {
+            // Saving writes atom positions, vice `atom_posits`; apply the pose there too.
+            let common = &mut mol.common;
+            for (atom, posit) in common.atoms.iter_mut().zip(&common.atom_posits) {
+                atom.posit = *posit;
+            }
+        } else {
+            mol.common.atom_posits = docking.posits_orig;
+
+            draw_all_ligs(self, scene);
+            engine_updates.entities = EntityUpdate::All;
+        }
+    }
+}
+
+/// Refine the ligand's current pose with molecular dynamics, including nearby protein atoms.
+/// The result is viewable as MD snapshots.
+pub fn refine_md(
     state: &mut State,
     mol_i: usize,
     scene: &mut Scene,
@@ -87,24 +360,11 @@ pub fn dock(
         return Err(ParamError::new("No peptide; can't dock."));
     };
     let mol = &mut state.ligands[mol_i];
-    // Move the ligand away from the docking site prior to vectoring it towards it.
 
     // todo: QC if you need these.
     pep.common.selected_for_md = true; // Required to properly re-assign snapshot indices.
     mol.common.selected_for_md = true; // Required to not get filtered out in `build_dynamics`.
 
-    let start_dist = 8.;
-    let speed = 120.; // Å/ps
-
-    let docking_site = mol.common.centroid(); // for now
-
-    let dir = (docking_site - pep.common.centroid()).to_normalized();
-
-    let starting_posit = docking_site + dir * start_dist;
-    let starting_vel = -dir * speed;
-
-    mol.common.move_to(starting_posit);
-
     let cfg = MdConfig {
         zero_com_drift: false, // May already be false.
         // todo: A/R. Have to relax proteins currently, or hydrogens are likely to end up
@@ -123,7 +383,7 @@ pub fn dock(
         &state.dev,
         &mol,
         Some(pep),
-        starting_vel.into(),
+        Vec3F32::new_zero(),
         &state.ff_param_set,
         &state.mol_specific_params,
         &cfg,
@@ -133,14 +393,9 @@ pub fn dock(
     state.volatile.md_local.start = Some(Instant::now());
     // state.volatile.md_local.running = true;
 
-    // todo: We may opt for a higher-than-normal DT here.
     let dt = 0.002;
     let n_steps = 800;
 
-    // todo: We may need to interrupt periodically e.g. to relax once close.
-
-    // todo: You need a binding energy computation each step.
-
     // Blocking for now.
     run_dynamics(&mut md_state, &state.dev, dt, n_steps);
 
@@ -0,0 +1,486 @@
+//! An empirical scoring function for ligand poses, similar to AutoDock Vina's. Heavy atoms are
+//! assigned X-Score-style types, and each ligand-receptor pair within a cutoff contributes
+//! steric, hydrophobic, and hydrogen bond terms, as functions of the surface distance between them:
+//! The distance between atom centers, minus both VDW radii.
+//!
+//! For fast evaluation of many poses, we precompute these per ligand atom type on a grid over
+//! the docking site, and interpolate.
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use lin_alg::f32::{f32x8, pack_x8};
+use lin_alg::{f32::Vec3 as Vec3F32, f64::Vec3};
+use na_seq::Element;
+use rayon::prelude::*;
+
+use crate::{docking::DockingSite, molecule::MoleculeCommon};
+
+/// Å. Pairs further apart than this don't contribute.
+const CUTOFF: f32 = 8.;
+/// Å
+const GRID_SPACING: f64 = 0.375;
+/// Å. How far the grid extends past the site radius, so atoms of ligands centered near the
+/// edge of the site are still in it.
+const GRID_MARGIN: f64 = 4.;
+/// kcal/mol/Å. Applied to ligand atoms outside the grid, per Å outside.
+const K_OUTSIDE: f64 = 10.;
+
+// Vina's term weights, fit to experimental binding affinities.
+const W_GAUSS_1: f32 = -0.035579;
+const W_GAUSS_2: f32 = -0.005156;
+const W_REPULSION: f32 = 0.840245;
+const W_HYDROPHOBIC: f32 = -0.035069;
+const W_H_BOND: f32 = -0.587439;
+/// Penalizes the entropy lost by freezing rotatable bonds on binding.
+const W_ROT: f64 = 0.05846;
+
+/// Atom types for scoring, after X-Score. Hydrogens aren't typed; they're accounted for by
+/// marking their heavy atoms as donors.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DockType {
+    /// Carbon not bonded to a heteroatom.
+    CHydrophobic,
+    /// Carbon bonded to a heteroatom.
+    CPolar,
+    NPolar,
+    NDonor,
+    NAcceptor,
+    OAcceptor,
+    ODonorAcceptor,
+    S,
+    P,
+    F,
+    Cl,
+    Br,
+    I,
+    /// Metal ions. These act as hydrogen bond donors, to approximate coordination.
+    Metal,
+}
+
+impl DockType {
+    pub const ALL: [Self; 14] = [
+        Self::CHydrophobic,
+        Self::CPolar,
+        Self::NPolar,
+        Self::NDonor,
+        Self::NAcceptor,
+        Self::OAcceptor,
+        Self::ODonorAcceptor,
+        Self::S,
+        Self::P,
+        Self::F,
+        Self::Cl,
+        Self::Br,
+        Self::I,
+        Self::Metal,
+    ];
+
+    /// Å
+    fn vdw_radius(self) -> f32 {
+        match self {
+            Self::CHydrophobic | Self::CPolar => 1.9,
+            Self::NPolar | Self::NDonor | Self::NAcceptor => 1.8,
+            Self::OAcceptor | Self::ODonorAcceptor => 1.7,
+            Self::S => 2.0,
+            Self::P => 2.1,
+            Self::F => 1.5,
+            Self::Cl => 1.8,
+            Self::Br => 2.0,
+            Self::I => 2.2,
+            Self::Metal => 1.2,
+        }
+    }
+
+    pub fn hydrophobic(self) -> bool {
+        matches!(
+            self,
+            Self::CHydrophobic | Self::F | Self::Cl | Self::Br | Self::I
+        )
+    }
+
+    pub fn donor(self) -> bool {
+        matches!(self, Self::NDonor | Self::ODonorAcceptor | Self::Metal)
+    }
+
+    pub fn acceptor(self) -> bool {
+        matches!(
+            self,
+            Self::NAcceptor | Self::OAcceptor | Self::ODonorAcceptor
+        )
+    }
+}
+
+/// Assign scoring types to each atom. Hydrogens, and elements without a type, are `None`, and
+/// don't contribute to the score. Donors are identified by bonded or implicit hydrogens, so
+/// structures without either will have none.
+pub fn dock_types(mol: &MoleculeCommon) -> Vec<Option<DockType>> {
+    // (Bonded to hydrogen, bonded to a heteroatom, heavy neighbor count)
+    let mut nbr_info = vec![(false, false, 0); mol.atoms.len()];
+
+    for bond in &mol.bonds {
+        for (i, j) in [(bond.atom_0, bond.atom_1), (bond.atom_1, bond.atom_0)] {
+            match mol.atoms[j].element {
+                Element::Hydrogen => nbr_info[i].0 = true,
+                Element::Carbon => nbr_info[i].2 += 1,
+                _ => {
+                    nbr_info[i].1 = true;
+                    nbr_info[i].2 += 1;
+                }
+            }
+        }
+    }
+
+    mol.atoms
+        .iter()
+        .zip(nbr_info)
+        .map(|(atom, (bonded_h, bonded_hetero, heavy_nbrs))| {
+            let has_h = bonded_h || atom.implicit_h.unwrap_or(0) > 0;
+
+            Some(match atom.element {
+                Element::Carbon => {
+                    if bonded_hetero {
+                        DockType::CPolar
+                    } else {
+                        DockType::CHydrophobic
+                    }
+                }
+                Element::Nitrogen => {
+                    if has_h {
+                        DockType::NDonor
+                    } else if heavy_nbrs < 3 && atom.formal_charge <= 0 {
+                        // A free lone pair, e.g. in pyridine, or nitriles.
+                        DockType::NAcceptor
+                    } else {
+                        DockType::NPolar
+                    }
+                }
+                Element::Oxygen => {
+                    if has_h {
+                        DockType::ODonorAcceptor
+                    } else {
+                        DockType::OAcceptor
+                    }
+                }
+                Element::Sulfur => DockType::S,
+                Element::Phosphorus => DockType::P,
+                Element::Fluorine => DockType::F,
+                Element::Chlorine => DockType::Cl,
+                Element::Bromine => DockType::Br,
+                Element::Iodine => DockType::I,
+                Element::Zinc
+                | Element::Iron
+                | Element::Magnesium
+                | Element::Calcium
+                | Element::Manganese
+                | Element::Copper
+                | Element::Potassium => DockType::Metal,
+                _ => return None,
+            })
+        })
+        .collect()
+}
+
+/// The weighted terms of the score, in kcal/mol.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VinaTerms {
+    pub gauss_1: f64,
+    pub gauss_2: f64,
+    pub repulsion: f64,
+    pub hydrophobic: f64,
+    pub h_bond: f64,
+}
+
+impl VinaTerms {
+    /// kcal/mol. The intermolecular energy.
+    pub fn sum(&self) -> f64 {
+        self.gauss_1 + self.gauss_2 + self.repulsion + self.hydrophobic + self.h_bond
+    }
+
+    fn add(mut self, other: Self) -> Self {
+        self.gauss_1 += other.gauss_1;
+        self.gauss_2 += other.gauss_2;
+        self.repulsion += other.repulsion;
+        self.hydrophobic += other.hydrophobic;
+        self.h_bond += other.h_bond;
+        self
+    }
+}
+
+/// The weighted terms for one atom pair, `dist` apart.
+fn pair_terms(t_0: DockType, t_1: DockType, dist: f32) -> [f32; 5] {
+    let d = dist - t_0.vdw_radius() - t_1.vdw_radius();
+
+    let gauss_1 = (-(d / 0.5).powi(2)).exp();
+    let gauss_2 = (-((d - 3.) / 2.).powi(2)).exp();
+    let repulsion = if d < 0. { d * d } else { 0. };
+
+    let hydrophobic = if t_0.hydrophobic() && t_1.hydrophobic() {
+        (1.5 - d).clamp(0., 1.)
+    } else {
+        0.
+    };
+
+    let h_bond = if (t_0.donor() && t_1.acceptor()) || (t_0.acceptor() && t_1.donor()) {
+        (-d / 0.7).clamp(0., 1.)
+    } else {
+        0.
+    };
+
+    [
+        W_GAUSS_1 * gauss_1,
+        W_GAUSS_2 * gauss_2,
+        W_REPULSION * repulsion,
+        W_HYDROPHOBIC * hydrophobic,
+        W_H_BOND * h_bond,
+    ]
+}
+
+/// Divide the intermolecular energy by this, to get the score.
+fn rot_factor(num_rot_bonds: usize) -> f64 {
+    1. + W_ROT * num_rot_bonds as f64
+}
+
+/// A pose's score, and the terms that make it up.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VinaScore {
+    pub terms: VinaTerms,
+    /// kcal/mol. An estimate of the binding free energy; lower is better.
+    pub score: f64,
+}
+
+/// Typed receptor heavy atoms near a docking site. Coordinates are also stored packed, so the
+/// neighbor search uses SIMD; pair terms are evaluated per neighbor, without it.
+pub struct ScoreReceptor {
+    posits: Vec<Vec3F32>,
+    types: Vec<DockType>,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    x_x8: Vec<f32x8>,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    y_x8: Vec<f32x8>,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    z_x8: Vec<f32x8>,
+}
+
+impl ScoreReceptor {
+    /// `atoms` are the indices of `mol` to include, e.g. to skip hetero atoms. Only those that
+    /// can interact with a ligand in the site are kept.
+    pub fn new(mol: &MoleculeCommon, atoms: &[usize], site: &DockingSite) -> Self {
+        let types_all = dock_types(mol);
+        let dist_max = site.site_radius + GRID_MARGIN + CUTOFF as f64;
+
+        let (posits, types): (Vec<Vec3F32>, Vec<DockType>) = atoms
+            .iter()
+            .filter(|&&i| (mol.atom_posits[i] - site.site_center).magnitude() < dist_max)
+            .filter_map(|&i| types_all[i].map(|t| (mol.atom_posits[i].into(), t)))
+            .unzip();
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let (x_x8, _) = pack_x8(&posits.iter().map(|p| p.x).collect::<Vec<_>>());
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let (y_x8, _) = pack_x8(&posits.iter().map(|p| p.y).collect::<Vec<_>>());
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let (z_x8, _) = pack_x8(&posits.iter().map(|p| p.z).collect::<Vec<_>>());
+
+        Self {
+            posits,
+            types,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            x_x8,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            y_x8,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            z_x8,
+        }
+    }
+
+    /// (Index, distance) of receptor atoms within the cutoff of a point.
+    fn neighbors(&self, posit: Vec3F32) -> Vec<(usize, f32)> {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        if is_x86_feature_detected!("avx") {
+            return self.neighbors_x8(posit);
+        }
+
+        self.posits
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| {
+                let dist_sq = (*p - posit).magnitude_squared();
+                (dist_sq < CUTOFF * CUTOFF).then(|| (i, dist_sq.sqrt()))
+            })
+            .collect()
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    /// See notes on `neighbors()`.
+    fn neighbors_x8(&self, posit: Vec3F32) -> Vec<(usize, f32)> {
+        let (px, py, pz) = (
+            f32x8::splat(posit.x),
+            f32x8::splat(posit.y),
+            f32x8::splat(posit.z),
+        );
+
+        let mut result = Vec::new();
+        for (chunk_i, ((x, y), z)) in self.x_x8.iter().zip(&self.y_x8).zip(&self.z_x8).enumerate() {
+            let (dx, dy, dz) = (*x - px, *y - py, *z - pz);
+            let dist_sq = (dx * dx + dy * dy + dz * dz).to_array();
+
+            for (lane, d_sq) in dist_sq.into_iter().enumerate() {
+                let i = chunk_i * 8 + lane;
+                // The last chunk is padded.
+                if i < self.posits.len() && d_sq < CUTOFF * CUTOFF {
+                    result.push((i, d_sq.sqrt()));
+                }
+            }
+        }
+        result
+    }
+
+    fn terms_at(&self, posit: Vec3F32, lig_type: DockType) -> [f32; 5] {
+        self.terms_nbrs(&self.neighbors(posit), lig_type)
+    }
+
+    /// Summed terms for neighbors from `neighbors()`. Lets us reuse a neighbor list for
+    /// multiple ligand types.
+    fn terms_nbrs(&self, nbrs: &[(usize, f32)], lig_type: DockType) -> [f32; 5] {
+        let mut result = [0.; 5];
+        for &(i, dist) in nbrs {
+            let terms = pair_terms(lig_type, self.types[i], dist);
+            for (r, t) in result.iter_mut().zip(terms) {
+                *r += t;
+            }
+        }
+        result
+    }
+
+    /// Score a pose directly, from all atom pairs. `lig_types` is from `dock_types()`.
+    pub fn score(
+        &self,
+        lig_posits: &[Vec3],
+        lig_types: &[Option<DockType>],
+        num_rot_bonds: usize,
+    ) -> VinaScore {
+        let terms = lig_posits
+            .par_iter()
+            .zip(lig_types)
+            .filter_map(|(posit, t)| t.map(|t| self.terms_at((*posit).into(), t)))
+            .map(|t| VinaTerms {
+                gauss_1: t[0] as f64,
+                gauss_2: t[1] as f64,
+                repulsion: t[2] as f64,
+                hydrophobic: t[3] as f64,
+                h_bond: t[4] as f64,
+            })
+            .reduce(VinaTerms::default, VinaTerms::add);
+
+        VinaScore {
+            terms,
+            score: terms.sum() / rot_factor(num_rot_bonds),
+        }
+    }
+}
+
+/// Precomputed energies over a docking site, for each ligand atom type. Evaluating a pose
+/// is then independent of receptor size.
+pub struct ScoreGrid {
+    min: Vec3,
+    dims: [usize; 3],
+    types: Vec<DockType>,
+    /// Summed weighted terms. Indexed by grid point, then by `types`.
+    values: Vec<f32>,
+}
+
+impl ScoreGrid {
+    /// `lig_types` are the types to compute maps for; usually those present in the ligand.
+    pub fn new(receptor: &ScoreReceptor, site: &DockingSite, lig_types: &[DockType]) -> Self {
+        let mut types = Vec::new();
+        for t in lig_types {
+            if !types.contains(t) {
+                types.push(*t);
+            }
+        }
+
+        let half = site.site_radius + GRID_MARGIN;
+        let min = site.site_center - Vec3::new(half, half, half);
+        let n = (2. * half / GRID_SPACING).ceil() as usize + 1;
+
+        let mut result = Self {
+            min,
+            dims: [n; 3],
+            types,
+            values: Vec::new(),
+        };
+
+        result.values = (0..n * n * n)
+            .into_par_iter()
+            .flat_map_iter(|i| {
+                let nbrs = receptor.neighbors(result.posit(i).into());
+                result
+                    .types
+                    .iter()
+                    .map(|&t| receptor.terms_nbrs(&nbrs, t).iter().sum::<f32>())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        result
+    }
+
+    fn posit(&self, i: usize) -> Vec3 {
+        let x = i % self.dims[0];
+        let y = (i / self.dims[0]) % self.dims[1];
+        let z = i / (self.dims[0] * self.dims[1]);
+        self.min + Vec3::new(x as f64, y as f64, z as f64) * GRID_SPACING
+    }
+
+    /// Trilinear interpolation of one type's map. Points outside the grid are clamped to it, and
+    /// penalized by their distance outside.
+    fn interp(&self, posit: Vec3, type_i: usize) -> f64 {
+        let g = (posit - self.min) / GRID_SPACING;
+        let mut outside = 0.;
+
+        let mut base = [0; 3];
+        let mut frac = [0.; 3];
+        for (axis, v) in [g.x, g.y, g.z].into_iter().enumerate() {
+            let max = (self.dims[axis] - 1) as f64;
+            let clamped = v.clamp(0., max);
+            outside += (v - clamped).abs() * GRID_SPACING;
+
+            let b = (clamped.floor() as usize).min(self.dims[axis] - 2);
+            base[axis] = b;
+            frac[axis] = clamped - b as f64;
+        }
+
+        let n_types = self.types.len();
+        let mut result = 0.;
+        for (dz, wz) in [(0, 1. - frac[2]), (1, frac[2])] {
+            for (dy, wy) in [(0, 1. - frac[1]), (1, frac[1])] {
+                for (dx, wx) in [(0, 1. - frac[0]), (1, frac[0])] {
+                    let i = base[0]
+                        + dx
+                        + self.dims[0] * (base[1] + dy + self.dims[1] * (base[2] + dz));
+                    result += wx * wy * wz * self.values[i * n_types + type_i] as f64;
+                }
+            }
+        }
+
+        result + K_OUTSIDE * outside
+    }
+
+    /// kcal/mol. Score a pose using the grid. Atoms of types not in the grid are ignored.
+    pub fn score(
+        &self,
+        lig_posits: &[Vec3],
+        lig_types: &[Option<DockType>],
+        num_rot_bonds: usize,
+    ) -> f64 {
+        let inter: f64 = lig_posits
+            .iter()
+            .zip(lig_types)
+            .filter_map(|(posit, t)| {
+                let type_i = self.types.iter().position(|g| Some(*g) == *t)?;
+                Some(self.interp(*posit, type_i))
+            })
+            .sum();
+
+        inter / rot_factor(num_rot_bonds)
+    }
+}
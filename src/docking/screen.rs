@@ -0,0 +1,531 @@
+//! Virtual screening: Dock each molecule of a library into one site, and rank them by score.
+//! This runs in a background thread, docking molecules in parallel. Each result is appended to
+//! a progress file as it completes, so an interrupted screen resumes where it left off. Its first
+//! record holds the screen's settings; progress from a screen with different ones is discarded.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, TryRecvError},
+    },
+    thread,
+};
+
+use bio_files::md_params::ForceFieldParams;
+use rayon::prelude::*;
+
+use crate::{
+    State,
+    docking::{DockingSite, SiteSetup, nonbonded_params, score::DockType, search::Nonbonded},
+    mol_library::{MolLibrary, SDF_RECORD_END, csv_field, sdf_mol, sdf_record, split_records},
+    mol_lig::MoleculeSmall,
+    molecule::MoleculeCommon,
+    util::handle_success,
+};
+
+// SDF tags we add to each docked molecule.
+const TAG_LIB_INDEX: &str = "SCREEN_LIB_INDEX";
+const TAG_SCORE: &str = "SCREEN_SCORE";
+const TAG_ENERGY: &str = "SCREEN_ENERGY";
+/// On molecules which failed to dock, with the reason.
+const TAG_FAILED: &str = "SCREEN_FAILED";
+// Progress file header tags.
+const TAG_PEPTIDE: &str = "SCREEN_PEPTIDE";
+const TAG_SITE: &str = "SCREEN_SITE";
+const TAG_PH: &str = "SCREEN_PH";
+const TAG_FILTER: &str = "SCREEN_FILTER";
+const TAG_LIBRARY: &str = "SCREEN_LIBRARY";
+
+/// Å. The receptor includes atoms that can reach ligands up to this size, from their centroid.
+const LIG_RADIUS_MAX: f64 = 10.;
+
+/// The result of docking one library molecule.
+#[derive(Clone, Debug)]
+pub struct ScreenHit {
+    /// Index into the library's molecules.
+    pub lib_i: usize,
+    pub ident: String,
+    /// kcal/mol. The empirical score of the best-scoring pose.
+    pub score: f64,
+    /// kcal/mol. The interaction energy of that pose.
+    pub energy: f64,
+}
+
+impl ScreenHit {
+    fn tag(&self, metadata: &mut HashMap<String, String>) {
+        metadata.insert(TAG_LIB_INDEX.to_owned(), self.lib_i.to_string());
+        metadata.insert(TAG_SCORE.to_owned(), format!("{:.3}", self.score));
+        metadata.insert(TAG_ENERGY.to_owned(), format!("{:.3}", self.energy));
+    }
+
+    /// Read from the tags of a docked molecule.
+    fn from_tags(mol: &MoleculeSmall) -> Option<Self> {
+        let tag = |k: &str| mol.common.metadata.get(k).map(|v| v.trim());
+
+        Some(Self {
+            lib_i: tag(TAG_LIB_INDEX)?.parse().ok()?,
+            ident: mol.common.ident.clone(),
+            score: tag(TAG_SCORE)?.parse().ok()?,
+            energy: tag(TAG_ENERGY)?.parse().ok()?,
+        })
+    }
+}
+
+/// Output files. These are placed next to the library file.
+#[derive(Clone, Debug)]
+pub struct ScreenPaths {
+    /// The screen's settings, then docked and failed molecules, in the order completed. Used to
+    /// resume, and removed on completion.
+    pub progress: PathBuf,
+    /// The best pose of each molecule, ranked by score.
+    pub sdf: PathBuf,
+    /// The ranked results table.
+    pub csv: PathBuf,
+}
+
+impl ScreenPaths {
+    pub(crate) fn new(lib_path: &Path) -> Self {
+        let stem = lib_path.file_stem().unwrap_or_default().to_string_lossy();
+        let path = |suffix: &str| lib_path.with_file_name(format!("{stem}_{suffix}"));
+
+        Self {
+            progress: path("screen_progress.sdf"),
+            sdf: path("screen.sdf"),
+            csv: path("screen.csv"),
+        }
+    }
+}
+
+enum ScreenUpdate {
+    Docked(Result<ScreenHit, String>),
+    /// The number of molecules in the results.
+    Finished(io::Result<usize>),
+}
+
+/// A screen in progress.
+pub struct ScreenJob {
+    pub total: usize,
+    /// Includes molecules docked in previous, interrupted runs.
+    pub done: usize,
+    pub failed: usize,
+    /// The best-scoring molecule so far.
+    pub best: Option<ScreenHit>,
+    pub paths: ScreenPaths,
+    rx: Receiver<ScreenUpdate>,
+    cancel: Arc<AtomicBool>,
+}
+
+/// Prepare a molecule for docking: Set its dominant protonation state at the pH, then assign
+/// force field types, partial charges, and molecule-specific parameters. Returns its nonbonded
+/// parameters.
+fn prepare(
+    mol: &mut MoleculeSmall,
+    ph: f32,
+    gaff2: &ForceFieldParams,
+) -> Result<Vec<Nonbonded>, String> {
+    mol.protonate(ph);
+
+    let mut msp = HashMap::new();
+    mol.update_ff_related(&mut msp, gaff2);
+    if !mol.ff_params_loaded {
+        return Err(format!(
+            "Unable to assign force field parameters to {}",
+            mol.common.ident
+        ));
+    }
+
+    let mut param_sets: Vec<_> = msp.values().collect();
+    param_sets.push(gaff2);
+    Ok(nonbonded_params(&mol.common, &param_sets))
+}
+
+/// Prepare and dock one molecule. Returns it in its best-scoring pose, tagged with the results.
+fn screen_mol(
+    setup: &SiteSetup,
+    lib_i: usize,
+    mut mol: MoleculeSmall,
+    ph: f32,
+    gaff2: &ForceFieldParams,
+) -> Result<(MoleculeSmall, ScreenHit), String> {
+    let params = prepare(&mut mol, ph, gaff2)?;
+
+    let poses = setup.dock(&mol.common, &params);
+    let Some(best) = poses.into_iter().min_by(|a, b| a.score.total_cmp(&b.score)) else {
+        return Err(format!("No poses found for {}", mol.common.ident));
+    };
+
+    for (atom, posit) in mol.common.atoms.iter_mut().zip(&best.lig_atom_posits) {
+        atom.posit = *posit;
+    }
+    mol.common.atom_posits = best.lig_atom_posits;
+
+    let hit = ScreenHit {
+        lib_i,
+        ident: mol.common.ident.clone(),
+        score: best.score,
+        energy: best.potential_energy,
+    };
+    hit.tag(&mut mol.common.metadata);
+
+    Ok((mol, hit))
+}
+
+/// A record without atoms, holding only tags. We use these for the progress file's header, and
+/// for molecules which failed to dock.
+fn tag_record(name: &str, tags: &[(&str, String)]) -> String {
+    let mut result =
+        format!("{name}\n  Molchanica\n\n  0  0  0  0  0  0  0  0  0  0999 V2000\nM  END\n");
+    for (key, val) in tags {
+        result += &format!("> <{key}>\n{val}\n\n");
+    }
+    result += SDF_RECORD_END;
+    result.push('\n');
+
+    result
+}
+
+/// Tags of an SDF record, read directly from its text.
+fn record_tags(rec: &str) -> HashMap<&str, &str> {
+    let mut result = HashMap::new();
+    let mut lines = rec.lines();
+
+    while let Some(line) = lines.next() {
+        if let Some(key) = line.strip_prefix("> <").and_then(|l| l.strip_suffix('>')) {
+            result.insert(key, lines.next().unwrap_or_default().trim());
+        }
+    }
+    result
+}
+
+/// Identifies a library's contents: Each molecule's name, elements and bonds. Records refer to
+/// molecules by library index, so they don't apply to an edited library. This is FNV-1a; unlike
+/// `DefaultHasher`, its output is stable across Rust versions. Positions are left out, since
+/// those of molecules loaded from SMILES aren't reproducible.
+fn library_hash(lib: &MolLibrary) -> u64 {
+    let mut result: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut add = |bytes: &[u8]| {
+        for b in bytes {
+            result = (result ^ u64::from(*b)).wrapping_mul(0x100_0000_01b3);
+        }
+    };
+
+    for mol in &lib.mols {
+        add(mol.common.ident.as_bytes());
+        for atom in &mol.common.atoms {
+            add(atom.element.to_letter().as_bytes());
+        }
+        for bond in &mol.common.bonds {
+            add(&(bond.atom_0 as u64).to_le_bytes());
+            add(&(bond.atom_1 as u64).to_le_bytes());
+        }
+        // Separates molecules, so moving an atom to the next one changes the hash.
+        add(SDF_RECORD_END.as_bytes());
+    }
+
+    result
+}
+
+/// The progress file's first record: The settings the screen was run with.
+pub(crate) fn progress_header(
+    lib: &MolLibrary,
+    pep: &MoleculeCommon,
+    site: &DockingSite,
+    ph: f32,
+) -> String {
+    let c = site.site_center;
+    tag_record(
+        "Screen settings",
+        &[
+            (TAG_PEPTIDE, pep.ident.clone()),
+            (
+                TAG_SITE,
+                format!("{:.3} {:.3} {:.3} {:.3}", c.x, c.y, c.z, site.site_radius),
+            ),
+            (TAG_PH, format!("{ph:.2}")),
+            (TAG_FILTER, format!("{:?}", lib.filter)),
+            (TAG_LIBRARY, format!("{:016x}", library_hash(lib))),
+        ],
+    )
+}
+
+/// Records a molecule which failed to dock, so resuming doesn't retry it.
+pub(crate) fn failure_record(ident: &str, lib_i: usize, err: &str) -> String {
+    tag_record(
+        ident,
+        &[
+            (TAG_LIB_INDEX, lib_i.to_string()),
+            (TAG_FAILED, err.replace('\n', " ")),
+        ],
+    )
+}
+
+/// Completed records from a progress file.
+#[derive(Default)]
+pub(crate) struct Progress {
+    /// Each docked molecule's result, and its record.
+    pub docked: Vec<(ScreenHit, String)>,
+    /// Library index of each molecule which failed to dock, and its record.
+    pub failed: Vec<(usize, String)>,
+}
+
+/// Load the progress file, if present, and if its header matches `header`. An interrupted write
+/// may leave an incomplete last record; it's skipped.
+pub(crate) fn load_progress(path: &Path, header: &str) -> Progress {
+    let mut result = Progress::default();
+
+    let Ok(text) = fs::read_to_string(path) else {
+        return result;
+    };
+    let mut records = split_records(&text, false).into_iter();
+
+    // Progress from screening a different library, filter, site, peptide, or pH doesn't apply.
+    if records.next().as_deref() != Some(header) {
+        return result;
+    }
+
+    for rec in records {
+        if !rec.trim_end().ends_with(SDF_RECORD_END) {
+            continue;
+        }
+
+        let tags = record_tags(&rec);
+        if tags.contains_key(TAG_FAILED) {
+            let Some(lib_i) = tags.get(TAG_LIB_INDEX).and_then(|v| v.parse().ok()) else {
+                continue;
+            };
+            result.failed.push((lib_i, rec));
+        } else {
+            let Some(hit) = sdf_mol(&rec).ok().and_then(|m| ScreenHit::from_tags(&m)) else {
+                continue;
+            };
+            result.docked.push((hit, rec));
+        }
+    }
+
+    result
+}
+
+/// Write the ranked results table and poses from the progress file, then remove it. Returns the
+/// number of molecules ranked.
+fn write_results(paths: &ScreenPaths, header: &str) -> io::Result<usize> {
+    let mut docked = load_progress(&paths.progress, header).docked;
+    docked.sort_by(|a, b| a.0.score.total_cmp(&b.0.score));
+
+    let mut sdf = String::new();
+    let mut csv =
+        "Rank,Name,Score (kcal/mol),Interaction energy (kcal/mol),Library #\n".to_owned();
+
+    // Records are copied as written by `sdf_record`, preserving charges and stereo.
+    for (rank, (hit, rec)) in docked.iter().enumerate() {
+        sdf += rec;
+        csv += &format!(
+            "{},{},{:.2},{:.2},{}\n",
+            rank + 1,
+            csv_field(&hit.ident),
+            hit.score,
+            hit.energy,
+            hit.lib_i + 1
+        );
+    }
+
+    fs::write(&paths.sdf, sdf)?;
+    fs::write(&paths.csv, csv)?;
+
+    // The screen is complete; the next one starts fresh.
+    fs::remove_file(&paths.progress)?;
+
+    Ok(docked.len())
+}
+
+impl ScreenJob {
+    /// Start screening the library's filtered molecules in a background thread. Resumes from
+    /// a previous run's progress file, if present. `pep_params` is indexed by peptide atom.
+    pub fn start(
+        lib: &MolLibrary,
+        pep: &MoleculeCommon,
+        pep_params: Vec<Nonbonded>,
+        site: &DockingSite,
+        gaff2: &ForceFieldParams,
+        ph: f32,
+    ) -> io::Result<Self> {
+        let paths = ScreenPaths::new(&lib.path);
+        let header = progress_header(lib, pep, site, ph);
+
+        // Progress from a screen with different settings is discarded, as are records of
+        // molecules outside the filter.
+        let filtered: HashSet<_> = lib.filtered.iter().copied().collect();
+        let mut prev = load_progress(&paths.progress, &header);
+        prev.docked.retain(|(h, _)| filtered.contains(&h.lib_i));
+        prev.failed.retain(|(i, _)| filtered.contains(i));
+
+        // Rewrite the progress file with only the records kept, so we can append to it.
+        let mut text = header.clone();
+        for (_, rec) in &prev.docked {
+            text += rec;
+        }
+        for (_, rec) in &prev.failed {
+            text += rec;
+        }
+        fs::write(&paths.progress, text)?;
+
+        let done_prev: HashSet<_> = prev
+            .docked
+            .iter()
+            .map(|(h, _)| h.lib_i)
+            .chain(prev.failed.iter().map(|(i, _)| *i))
+            .collect();
+        let to_dock: Vec<_> = lib
+            .filtered
+            .iter()
+            .filter(|&&i| !done_prev.contains(&i))
+            .map(|&i| (i, lib.mols[i].clone()))
+            .collect();
+
+        let (tx, rx) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let result = Self {
+            total: lib.filtered.len(),
+            done: lib.filtered.len() - to_dock.len(),
+            failed: prev.failed.len(),
+            best: prev
+                .docked
+                .into_iter()
+                .map(|(h, _)| h)
+                .min_by(|a, b| a.score.total_cmp(&b.score)),
+            paths: paths.clone(),
+            rx,
+            cancel: cancel.clone(),
+        };
+
+        let pep = pep.clone();
+        let site = site.clone();
+        let gaff2 = gaff2.clone();
+
+        thread::spawn(move || {
+            let setup = SiteSetup::new(&pep, &pep_params, &site, LIG_RADIUS_MAX, &DockType::ALL);
+
+            let file = match OpenOptions::new().append(true).open(&paths.progress) {
+                Ok(f) => Mutex::new(f),
+                Err(e) => {
+                    let _ = tx.send(ScreenUpdate::Finished(Err(e)));
+                    return;
+                }
+            };
+
+            to_dock
+                .into_par_iter()
+                .for_each_with(tx.clone(), |tx, (lib_i, mol)| {
+                    if cancel.load(Ordering::Relaxed) {
+                        return;
+                    }
+
+                    let ident = mol.common.ident.clone();
+                    let result = screen_mol(&setup, lib_i, mol, ph, &gaff2);
+
+                    let record = match &result {
+                        Ok((mol, _)) => {
+                            let mut text = String::new();
+                            sdf_record(mol, &mut text).map(|_| text)
+                        }
+                        Err(e) => Ok(failure_record(&ident, lib_i, e)),
+                    };
+                    let saved =
+                        record.and_then(|text| file.lock().unwrap().write_all(text.as_bytes()));
+
+                    if let Err(e) = saved {
+                        eprintln!("Error saving screening progress: {e}");
+                    }
+
+                    let _ = tx.send(ScreenUpdate::Docked(result.map(|(_, hit)| hit)));
+                });
+
+            let result = if cancel.load(Ordering::Relaxed) {
+                Err(io::Error::new(
+                    ErrorKind::Interrupted,
+                    "Screening stopped. Progress is saved; screen again to resume.",
+                ))
+            } else {
+                write_results(&paths, &header)
+            };
+            let _ = tx.send(ScreenUpdate::Finished(result));
+        });
+
+        Ok(result)
+    }
+
+    /// Stop after the molecules currently being docked.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// Handle updates from the worker. Call this periodically; it's non-blocking. Returns the
+    /// result once the screen has finished or stopped.
+    pub fn poll(&mut self) -> Option<io::Result<usize>> {
+        loop {
+            match self.rx.try_recv() {
+                Ok(ScreenUpdate::Docked(result)) => {
+                    self.done += 1;
+
+                    match result {
+                        Ok(hit) => {
+                            if self.best.as_ref().is_none_or(|b| hit.score < b.score) {
+                                self.best = Some(hit);
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("Screening: {e}");
+                            self.failed += 1;
+                        }
+                    }
+                }
+                Ok(ScreenUpdate::Finished(result)) => return Some(result),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {
+                    return Some(Err(io::Error::other(
+                        "The screening thread stopped unexpectedly",
+                    )));
+                }
+            }
+        }
+    }
+}
+
+impl State {
+    /// Screen the library's filtered molecules against a site on the open peptide.
+    pub fn start_screen(&mut self, site: &DockingSite) -> io::Result<()> {
+        let (Some(lib), Some(pep)) = (&self.library, &self.peptide) else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Screening requires a library, and a protein",
+            ));
+        };
+        let Some(gaff2) = &self.ff_param_set.small_mol else {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                "Small molecule (GAFF2) parameters aren't loaded",
+            ));
+        };
+
+        let pep_sets: Vec<_> = self.ff_param_set.peptide.iter().collect();
+        let pep_params = nonbonded_params(&pep.common, &pep_sets);
+
+        let job = ScreenJob::start(lib, &pep.common, pep_params, site, gaff2, self.to_save.ph)?;
+
+        handle_success(
+            &mut self.ui,
+            format!(
+                "Screening {} molecules; {} done previously",
+                job.total, job.done
+            ),
+        );
+        self.volatile.screening = Some(job);
+
+        Ok(())
+    }
+}
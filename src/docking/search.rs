@@ -0,0 +1,431 @@
+//! Pose search for docking. Independent Monte Carlo runs sample the ligand's position, orientation,
+//! and rotatable-bond torsions within a docking site. Poses are scored by their ligand-receptor
+//! interaction energy: Lennard-Jones, and Coulomb with a distance-dependent dielectric.
+
+use std::{collections::VecDeque, f64::consts::TAU};
+
+use lin_alg::f64::{Quaternion, Vec3};
+use na_seq::Element;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use rayon::prelude::*;
+
+use crate::{
+    docking::{DockingPose, DockingSite},
+    mol_characterization::PerceivedMol,
+    molecule::MoleculeCommon,
+};
+
+/// Independent Monte Carlo runs; each starts from a random conformer, orientation, and position.
+const NUM_STARTS: usize = 32;
+const MC_STEPS: usize = 300;
+/// kcal/mol. The Metropolis temperature. About kT at 300K.
+const KT: f64 = 0.6;
+/// Å
+const STEP_TRANSLATION: f64 = 0.5;
+/// Radians
+const STEP_ROTATION: f64 = 0.3;
+/// Radians
+const STEP_TORSION: f64 = 1.;
+/// Å. Receptor atoms further than this outside the site are ignored.
+const CUTOFF: f64 = 8.;
+/// Å. Poses within this heavy-atom RMSD of a better-ranked one are considered duplicates.
+const RMSD_DUPLICATE: f64 = 1.;
+const MAX_POSES: usize = 20;
+/// kcal/mol/Å². Keeps the ligand's centroid inside the site.
+const K_SITE: f64 = 10.;
+/// Å. Ligand heavy atoms separated by more than 3 bonds clash if closer than this.
+const CLASH_DIST: f64 = 3.;
+/// kcal/mol/Å²
+const K_CLASH: f64 = 10.;
+/// kcal·Å/(mol·e²)
+const COULOMB: f64 = 332.0636;
+/// kcal/mol. Used for atoms without force field parameters.
+const EPS_DEFAULT: f64 = 0.1;
+
+/// Partial charge, and Lennard-Jones parameters of an atom.
+#[derive(Clone, Copy, Debug)]
+pub struct Nonbonded {
+    /// Elementary charge
+    pub charge: f64,
+    /// Å
+    pub sigma: f64,
+    /// kcal/mol
+    pub eps: f64,
+}
+
+impl Nonbonded {
+    /// For atoms without force field parameters. Uses the VDW radius as half the LJ minimum.
+    pub fn from_element(el: Element) -> Self {
+        Self {
+            charge: 0.,
+            sigma: 2. * el.vdw_radius() as f64 / 2_f64.powf(1. / 6.),
+            eps: EPS_DEFAULT,
+        }
+    }
+}
+
+/// Receptor atoms near a site, with their nonbonded parameters.
+pub struct Receptor {
+    pub posits: Vec<Vec3>,
+    pub params: Vec<Nonbonded>,
+}
+
+impl Receptor {
+    /// Keep only atoms that can interact with a ligand of radius `lig_radius` in the site.
+    pub fn new(posits: &[Vec3], params: &[Nonbonded], site: &DockingSite, lig_radius: f64) -> Self {
+        let dist_max = site.site_radius + lig_radius + CUTOFF;
+
+        let (posits, params) = posits
+            .iter()
+            .zip(params)
+            .filter(|(p, _)| (**p - site.site_center).magnitude() < dist_max)
+            .map(|(p, q)| (*p, *q))
+            .unzip();
+
+        Self { posits, params }
+    }
+
+    /// (Lennard-Jones, electrostatic) interaction energy with the ligand, in kcal/mol.
+    pub fn energy(&self, lig_posits: &[Vec3], lig_params: &[Nonbonded]) -> (f64, f64) {
+        let mut vdw = 0.;
+        let mut elec = 0.;
+
+        for (p_lig, nb_lig) in lig_posits.iter().zip(lig_params) {
+            for (p_rec, nb_rec) in self.posits.iter().zip(&self.params) {
+                let dist_sq = (*p_lig - *p_rec).magnitude_squared();
+                if dist_sq > CUTOFF * CUTOFF {
+                    continue;
+                }
+
+                let sigma = 0.5 * (nb_lig.sigma + nb_rec.sigma);
+                let eps = (nb_lig.eps * nb_rec.eps).sqrt();
+
+                // Clamp so overlapping atoms have a large, but finite energy.
+                let sr_2 = sigma * sigma / dist_sq.max(0.36 * sigma * sigma);
+                let sr_6 = sr_2.powi(3);
+                vdw += 4. * eps * (sr_6 * sr_6 - sr_6);
+
+                // The dielectric is 4r.
+                elec += COULOMB * nb_lig.charge * nb_rec.charge / (4. * dist_sq.max(1.));
+            }
+        }
+
+        (vdw, elec)
+    }
+}
+
+/// A torsion we can rotate; moving the atoms on the smaller side of the bond.
+struct RotBond {
+    pivot: usize,
+    side: usize,
+    moving: Vec<usize>,
+}
+
+/// The ligand's topology, as used in the search.
+struct LigModel<'a> {
+    params: &'a [Nonbonded],
+    rot_bonds: Vec<RotBond>,
+    /// Heavy atom pairs separated by more than 3 bonds.
+    clash_pairs: Vec<(usize, usize)>,
+    heavy: Vec<usize>,
+}
+
+impl<'a> LigModel<'a> {
+    fn new(mol: &MoleculeCommon, params: &'a [Nonbonded]) -> Self {
+        let mut mol = mol.clone();
+        mol.build_adjacency_list();
+        let adj = &mol.adjacency_list;
+
+        // Atoms reachable from `start`, without passing through `block`.
+        let side_of = |start: usize, block: usize| {
+            let mut visited = vec![false; adj.len()];
+            visited[start] = true;
+            visited[block] = true;
+
+            let mut result = Vec::new();
+            let mut queue = VecDeque::from([start]);
+            while let Some(i) = queue.pop_front() {
+                result.push(i);
+                for &j in &adj[i] {
+                    if !visited[j] {
+                        visited[j] = true;
+                        queue.push_back(j);
+                    }
+                }
+            }
+            result
+        };
+
+        let rot_bonds = PerceivedMol::new(&mol)
+            .rotatable_bonds()
+            .into_iter()
+            .map(|i| {
+                let bond = &mol.bonds[i];
+                let side_0 = side_of(bond.atom_0, bond.atom_1);
+                let side_1 = side_of(bond.atom_1, bond.atom_0);
+
+                if side_1.len() <= side_0.len() {
+                    RotBond {
+                        pivot: bond.atom_0,
+                        side: bond.atom_1,
+                        moving: side_1,
+                    }
+                } else {
+                    RotBond {
+                        pivot: bond.atom_1,
+                        side: bond.atom_0,
+                        moving: side_0,
+                    }
+                }
+            })
+            .collect();
+
+        let heavy: Vec<_> = (0..mol.atoms.len())
+            .filter(|&i| mol.atoms[i].element != Element::Hydrogen)
+            .collect();
+
+        // Bond-count distances, up to 3.
+        let mut clash_pairs = Vec::new();
+        for &i in &heavy {
+            let mut dist = vec![usize::MAX; adj.len()];
+            dist[i] = 0;
+            let mut queue = VecDeque::from([i]);
+            while let Some(a) = queue.pop_front() {
+                if dist[a] == 3 {
+                    continue;
+                }
+                for &b in &adj[a] {
+                    if dist[b] == usize::MAX {
+                        dist[b] = dist[a] + 1;
+                        queue.push_back(b);
+                    }
+                }
+            }
+
+            for &j in &heavy {
+                if j > i && dist[j] > 3 {
+                    clash_pairs.push((i, j));
+                }
+            }
+        }
+
+        Self {
+            params,
+            rot_bonds,
+            clash_pairs,
+            heavy,
+        }
+    }
+
+    fn rotate_bond(&self, posits: &mut [Vec3], bond: &RotBond, angle: f64) {
+        let pivot = posits[bond.pivot];
+        let axis = (posits[bond.side] - pivot).to_normalized();
+        let rotator = Quaternion::from_axis_angle(axis, angle);
+
+        for &i in &bond.moving {
+            posits[i] = pivot + rotator.rotate_vec(posits[i] - pivot);
+        }
+    }
+
+    fn clash_energy(&self, posits: &[Vec3]) -> f64 {
+        self.clash_pairs
+            .iter()
+            .map(|&(i, j)| {
+                let d = (posits[i] - posits[j]).magnitude();
+                if d < CLASH_DIST {
+                    K_CLASH * (CLASH_DIST - d).powi(2)
+                } else {
+                    0.
+                }
+            })
+            .sum()
+    }
+
+    /// The score we minimize: Interaction energy, plus penalties for intramolecular clashes, and
+    /// for leaving the site.
+    fn score(&self, posits: &[Vec3], receptor: &Receptor, site: &DockingSite) -> (f64, f64, f64) {
+        let (vdw, elec) = receptor.energy(posits, self.params);
+
+        let dist = (centroid(posits) - site.site_center).magnitude();
+        let site_penalty = if dist > site.site_radius {
+            K_SITE * (dist - site.site_radius).powi(2)
+        } else {
+            0.
+        };
+
+        (
+            vdw + elec + self.clash_energy(posits) + site_penalty,
+            vdw,
+            elec,
+        )
+    }
+}
+
+fn centroid(posits: &[Vec3]) -> Vec3 {
+    posits.iter().fold(Vec3::new_zero(), |a, b| a + *b) / posits.len() as f64
+}
+
+fn rotate_about(posits: &mut [Vec3], center: Vec3, rotator: Quaternion) {
+    for p in posits {
+        *p = center + rotator.rotate_vec(*p - center);
+    }
+}
+
+fn random_unit_vec(rng: &mut StdRng) -> Vec3 {
+    let z: f64 = rng.random_range(-1. ..1.);
+    let theta = rng.random_range(0. ..TAU);
+    let r = (1. - z * z).sqrt();
+    Vec3::new(r * theta.cos(), r * theta.sin(), z)
+}
+
+/// A uniformly-distributed orientation. (Shoemake)
+fn random_orientation(rng: &mut StdRng) -> Quaternion {
+    let (u_0, u_1, u_2): (f64, f64, f64) = (rng.random(), rng.random(), rng.random());
+    let (a, b) = ((1. - u_0).sqrt(), u_0.sqrt());
+
+    Quaternion::new(
+        b * (TAU * u_2).cos(),
+        a * (TAU * u_1).sin(),
+        a * (TAU * u_1).cos(),
+        b * (TAU * u_2).sin(),
+    )
+}
+
+/// Heavy-atom RMSD between two poses of the same molecule, without superposition.
+fn pose_rmsd(heavy: &[usize], a: &[Vec3], b: &[Vec3]) -> f64 {
+    if heavy.is_empty() {
+        return 0.;
+    }
+    let sum: f64 = heavy
+        .iter()
+        .map(|&i| (a[i] - b[i]).magnitude_squared())
+        .sum();
+    (sum / heavy.len() as f64).sqrt()
+}
+
+/// One Monte Carlo run. Returns the lowest-scoring pose found.
+fn mc_run(
+    lig: &LigModel,
+    posits_init: &[Vec3],
+    receptor: &Receptor,
+    site: &DockingSite,
+    seed: u64,
+) -> DockingPose {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    // A random conformer, orientation, and position near the site center.
+    let mut posits = posits_init.to_vec();
+    for bond in &lig.rot_bonds {
+        lig.rotate_bond(&mut posits, bond, rng.random_range(0. ..TAU));
+    }
+    let ctr = centroid(&posits);
+    rotate_about(&mut posits, ctr, random_orientation(&mut rng));
+
+    let offset = random_unit_vec(&mut rng) * rng.random_range(0. ..=0.5 * site.site_radius);
+    let shift = site.site_center + offset - ctr;
+    for p in &mut posits {
+        *p += shift;
+    }
+
+    let mut current = lig.score(&posits, receptor, site);
+    let mut best = (current, posits.clone());
+
+    let num_moves = if lig.rot_bonds.is_empty() { 2 } else { 3 };
+
+    for _ in 0..MC_STEPS {
+        let mut trial = posits.clone();
+
+        match rng.random_range(0..num_moves) {
+            0 => {
+                let shift = random_unit_vec(&mut rng) * rng.random_range(0. ..STEP_TRANSLATION);
+                for p in &mut trial {
+                    *p += shift;
+                }
+            }
+            1 => {
+                let rotator = Quaternion::from_axis_angle(
+                    random_unit_vec(&mut rng),
+                    rng.random_range(-STEP_ROTATION..STEP_ROTATION),
+                );
+                let ctr = centroid(&trial);
+                rotate_about(&mut trial, ctr, rotator);
+            }
+            _ => {
+                let bond = &lig.rot_bonds[rng.random_range(0..lig.rot_bonds.len())];
+                lig.rotate_bond(
+                    &mut trial,
+                    bond,
+                    rng.random_range(-STEP_TORSION..STEP_TORSION),
+                );
+            }
+        }
+
+        let score = lig.score(&trial, receptor, site);
+        let accept =
+            score.0 < current.0 || rng.random::<f64>() < ((current.0 - score.0) / KT).exp();
+
+        if accept {
+            posits = trial;
+            current = score;
+
+            if current.0 < best.0.0 {
+                best = (current, posits.clone());
+            }
+        }
+    }
+
+    let ((_, vdw, elec), posits) = best;
+    DockingPose {
+        lig_atom_posits: posits,
+        potential_energy: vdw + elec,
+        energy_vdw: vdw,
+        energy_elec: elec,
+        // Set by the caller, as it requires a score grid.
+        score: 0.,
+        rmsd_to_best: 0.,
+    }
+}
+
+/// Search for ligand poses in a site. Returns distinct poses, ranked by interaction energy,
+/// lowest first.
+pub fn search_poses(
+    lig: &MoleculeCommon,
+    lig_params: &[Nonbonded],
+    receptor: &Receptor,
+    site: &DockingSite,
+) -> Vec<DockingPose> {
+    if lig.atoms.is_empty() {
+        return Vec::new();
+    }
+
+    let model = LigModel::new(lig, lig_params);
+
+    let mut poses: Vec<_> = (0..NUM_STARTS)
+        .into_par_iter()
+        .map(|i| mc_run(&model, &lig.atom_posits, receptor, site, i as u64))
+        .collect();
+
+    poses.sort_by(|a, b| a.potential_energy.total_cmp(&b.potential_energy));
+
+    let mut result: Vec<DockingPose> = Vec::new();
+    for pose in poses {
+        let duplicate = result.iter().any(|p| {
+            pose_rmsd(&model.heavy, &p.lig_atom_posits, &pose.lig_atom_posits) < RMSD_DUPLICATE
+        });
+
+        if !duplicate {
+            result.push(pose);
+        }
+        if result.len() == MAX_POSES {
+            break;
+        }
+    }
+
+    if let Some(best) = result.first().map(|p| p.lig_atom_posits.clone()) {
+        for pose in &mut result {
+            pose.rmsd_to_best = pose_rmsd(&model.heavy, &best, &pose.lig_atom_posits);
+        }
+    }
+
+    result
+}
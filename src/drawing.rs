@@ -24,6 +24,7 @@ use crate::{
         MESH_DENSITY_SURFACE, MESH_SECONDARY_STRUCTURE, MESH_SOLVENT_SURFACE, MESH_SPHERE_HIGHRES,
         MESH_SPHERE_LOWRES, MESH_SPHERE_MEDRES, WATER_BOND_THICKNESS, WATER_OPACITY,
     },
+    stereo::CipLabel,
     util::{clear_mol_entity_indices, find_neighbor_posit, orbit_center, res_color},
     viridis_lut::VIRIDIS,
 };
@@ -53,6 +54,7 @@ const LABEL_COLOR_ATOM: (u8, u8, u8, u8) = (255, 60, 160, 255);
 const LABEL_COLOR_ATOM_SEL: (u8, u8, u8, u8) = (255, 20, 20, 255);
 const LABEL_COLOR_MOL: (u8, u8, u8, u8) = (255, 120, 150, 255);
 const LABEL_COLOR_MOL_SEL: (u8, u8, u8, u8) = (255, 10, 10, 255);
+const LABEL_COLOR_STEREO: (u8, u8, u8, u8) = (255, 220, 60, 255);
 
 // Hetero residues in protein, so they stand out from the normal protein molecules.
 // Lower blend values mean more of the original color.
@@ -65,11 +67,13 @@ const LIPID_BLEND_AMT: f32 = 0.3;
 
 const COLOR_DOCKING_BOX: Color = (0.3, 0.3, 0.9);
 pub const COLOR_DOCKING_SITE_MESH: Color = (0.5, 0.5, 0.9);
-// const DOCKING_SITE_OPACITY: f32 = 0.1;
+const DOCKING_SITE_OPACITY: f32 = 0.15;
 
 // todo temp while debugging
-const COLOR_SA_SURFACE: Color = (0.3, 0.2, 1.);
+pub const COLOR_SA_SURFACE: Color = (0.3, 0.2, 1.);
 // const COLOR_SA_SURFACE: Color = (1., 0., 0.);
+pub const COLOR_DENSITY_SURFACE: Color = (0., 1., 1.);
+pub const COLOR_SECONDARY_STRUCTURE: Color = (0.7, 0.2, 1.); // todo: Make this customizable etc.
 
 pub const BOND_RADIUS_BASE: f32 = 0.10; // Absolute unit in Å.
 
@@ -200,6 +204,18 @@ fn text_overlay_bonds(
     }
 }
 
+/// CIP stereo labels, e.g. R or E. We draw these over other atom labels.
+fn text_overlay_stereo(entity: &mut Entity, label: Option<CipLabel>) {
+    if let Some(label) = label {
+        entity.overlay_text = Some(TextOverlay {
+            text: label.to_str().to_owned(),
+            size: LABEL_SIZE_ATOM,
+            color: LABEL_COLOR_STEREO,
+            font_family: FontFamily::Proportional,
+        });
+    }
+}
+
 /// We use the Entity's class field to determine which graphics-engine entities to retain and remove.
 /// This affects both local drawing logic, and engine-level entity setup.
 #[derive(Clone, Copy, PartialEq)]
@@ -927,6 +943,14 @@ pub fn draw_mol(
         &ui.selection
     };
 
+    let stereo_labels = match &mol {
+        MolGenericRef::Ligand(l) if ui.labels_stereo => l
+            .common
+            .perceive_stereo()
+            .atom_labels(l.common.atoms.len()),
+        _ => vec![None; mol.common().atoms.len()],
+    };
+
     if matches!(
         ui.mol_view,
         MoleculeView::BallAndStick | MoleculeView::SpaceFill
@@ -1040,6 +1064,7 @@ pub fn draw_mol(
                     mol_active,
                     ui,
                 );
+                text_overlay_stereo(&mut entity, stereo_labels[i_atom]);
             }
 
             entity.class = mol.mol_type().entity_type() as u32;
@@ -1236,6 +1261,13 @@ pub fn draw_mol(
                 mol_active, // todo
                 ui,
             );
+
+            if !matches!(
+                ui.mol_view,
+                MoleculeView::BallAndStick | MoleculeView::SpaceFill
+            ) {
+                text_overlay_stereo(&mut entities[0], stereo_labels[bond.atom_0]);
+            }
         }
 
         result.extend(entities);
@@ -1270,6 +1302,39 @@ pub fn draw_mol(
     result
 }
 
+/// Translucent spheres for detected binding pockets. The selected one is highlighted.
+fn draw_docking_sites(state: &State, scene: &mut Scene) {
+    // Transparent objects don't render well inside the transparent surface.
+    if state.ui.mol_view == MoleculeView::Surface {
+        return;
+    }
+
+    let Some(mol) = &state.peptide else {
+        return;
+    };
+
+    for (i, site) in mol.docking_sites.iter().enumerate() {
+        let color = if state.ui.docking_site_sel == Some(i) {
+            COLOR_SELECTED
+        } else {
+            COLOR_DOCKING_BOX
+        };
+
+        let mut ent = Entity::new(
+            MESH_SPHERE_HIGHRES,
+            site.site.site_center.into(),
+            Quaternion::new_identity(),
+            site.site.site_radius as f32,
+            color,
+            ATOM_SHININESS,
+        );
+        ent.class = EntityClass::DockingSite as u32;
+        ent.opacity = DOCKING_SITE_OPACITY;
+
+        scene.entities.push(ent);
+    }
+}
+
 /// A visual representation of volumetric electron density,
 /// as loaded from .map files or similar. This is our point-based approach; not the isosurface.
 /// We change size based on density, and not linearly, for visual effect.
@@ -1317,7 +1382,7 @@ pub fn draw_density_surface(entities: &mut Vec<Entity>, state: &mut State) {
         Vec3::new_zero(),
         Quaternion::new_identity(),
         1.,
-        (0., 1., 1.),
+        COLOR_DENSITY_SURFACE,
         ATOM_SHININESS,
     );
     ent.class = EntityClass::DensitySurface as u32;
@@ -1398,7 +1463,7 @@ pub fn draw_secondary_structure(update_mesh: &mut bool, mesh_created: bool, scen
         Vec3::new_zero(),
         Quaternion::new_identity(),
         1.,
-        (0.7, 0.2, 1.), // todo: Make this customizable etc.
+        COLOR_SECONDARY_STRUCTURE,
         ATOM_SHININESS,
     );
     ent.class = EntityClass::SecondaryStructure as u32;
@@ -1439,6 +1504,7 @@ pub fn draw_peptide(state: &mut State, scene: &mut Scene) {
         ent.class != EntityClass::Protein as u32
             && ent.class != EntityClass::SaSurface as u32
             && ent.class != EntityClass::SaSurfaceDots as u32
+            && ent.class != EntityClass::DockingSite as u32
     });
 
     // Edit small molecules only; not proteins.
@@ -2000,6 +2066,8 @@ pub fn draw_peptide(state: &mut State, scene: &mut Scene) {
         eprintln!("Uhoh!")
     }
 
+    draw_docking_sites(state, scene);
+
     if scene.entities.len() != initial_ent_count {
         clear_mol_entity_indices(state, None);
     }
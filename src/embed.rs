@@ -0,0 +1,734 @@
+//! Generate 3D coordinates for molecules that only have topology, e.g. ones created from SMILES.
+//!
+//! We use distance geometry: Bounds on interatomic distances are built from ideal bond lengths,
+//! bond angles, and torsion limits. We find coordinates satisfying these by minimizing an error
+//! function; first in 4D, which helps avoid local minima, then in 3D, with planarity and stereo
+//! terms. A GAFF2 energy minimization then cleans up the result.
+
+use std::{collections::HashMap, f64::consts::PI, io, mem};
+
+use bio_files::{BondType, md_params::ForceFieldParams};
+use dynamics::{
+    ComputationDevice, FfMolType, HydrogenConstraint, MdConfig, MdOverrides, MdState, MolDynamics,
+    ParamError, params::FfParamSet,
+};
+use graphics::{EngineUpdates, Scene};
+use lin_alg::f64::Vec3;
+use na_seq::Element::{self, Hydrogen, Nitrogen};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{
+    State,
+    mol_lig::MoleculeSmall,
+    molecule::{Atom, Bond, MoleculeCommon, MoleculeGeneric},
+    smiles::{BondDir, SmilesChirality, parse_smiles},
+    util::{handle_err, handle_success},
+};
+
+/// Embeddings from different random starting coordinates; we keep the best.
+const EMBED_ATTEMPTS: u64 = 6;
+const ITERS_4D: usize = 2_000;
+const ITERS_3D: usize = 2_000;
+/// We accept an embedding with error below this without further attempts.
+const ERR_ACCEPT: f64 = 0.01;
+
+// Bound tolerances, in Å.
+const TOL_BOND: f64 = 0.01;
+const TOL_ANGLE: f64 = 0.03;
+const TOL_TORSION: f64 = 0.05;
+/// Non-bonded atoms may approach this fraction of the sum of their VDW radii.
+const VDW_SCALE: f64 = 0.75;
+/// An upper limit on distance per bond separating two atoms, in Å.
+const MAX_DIST_PER_BOND: f64 = 1.6;
+/// For atoms in disconnected components.
+const MAX_DIST_DISCONNECTED: f64 = 30.;
+
+// Weights of the planarity and chirality terms, relative to distance bounds.
+const W_PLANAR: f64 = 0.1;
+const W_CHIRAL: f64 = 1.;
+/// Minimum signed volume (Å³ × 6) of a chiral center's neighbors.
+const CHIRAL_VOL_MIN: f64 = 1.;
+
+/// Iterations of the force field energy minimization.
+const FF_MIN_ITERS: usize = 500;
+
+/// Stereo to enforce when embedding.
+#[derive(Clone, Debug, Default)]
+pub struct StereoConstraints {
+    /// Four neighbors of a center, and if, looking from the first, the rest are anticlockwise.
+    pub chiral: Vec<([usize; 4], bool)>,
+    /// (x, a, b, y): Substituents x and y across double bond a=b, and if they're cis.
+    pub double_bonds: Vec<(usize, usize, usize, usize, bool)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Hybridization {
+    Sp,
+    Sp2,
+    Sp3,
+}
+
+fn covalent_radius(el: Element) -> f64 {
+    match el.to_letter().as_str() {
+        "H" => 0.31,
+        "B" => 0.84,
+        "C" => 0.76,
+        "N" => 0.71,
+        "O" => 0.66,
+        "F" => 0.57,
+        "Si" => 1.11,
+        "P" => 1.07,
+        "S" => 1.05,
+        "Cl" => 1.02,
+        "Se" => 1.2,
+        "Br" => 1.2,
+        "I" => 1.39,
+        _ => 1.2,
+    }
+}
+
+fn bond_length(el_0: Element, el_1: Element, bond_type: BondType) -> f64 {
+    let single = covalent_radius(el_0) + covalent_radius(el_1);
+
+    single
+        * match bond_type {
+            BondType::Double => 0.87,
+            BondType::Triple => 0.78,
+            BondType::Aromatic => 0.91,
+            _ => 1.,
+        }
+}
+
+/// Add explicit hydrogens for each atom's implicit hydrogen count. Returns, per atom, the indices
+/// of the hydrogens added to it.
+pub fn add_hydrogens(mol: &mut MoleculeCommon) -> Vec<Vec<usize>> {
+    let mut result = vec![Vec::new(); mol.atoms.len()];
+    let mut sn = mol.atoms.iter().map(|a| a.serial_number).max().unwrap_or(0) + 1;
+
+    for i in 0..mol.atoms.len() {
+        let Some(count) = mol.atoms[i].implicit_h else {
+            continue;
+        };
+
+        for _ in 0..count {
+            let i_h = mol.atoms.len();
+            mol.atoms.push(Atom {
+                serial_number: sn,
+                posit: mol.atoms[i].posit,
+                element: Hydrogen,
+                implicit_h: Some(0),
+                ..Default::default()
+            });
+            mol.bonds.push(Bond {
+                bond_type: BondType::Single,
+                atom_0_sn: mol.atoms[i].serial_number,
+                atom_1_sn: sn,
+                atom_0: i,
+                atom_1: i_h,
+                is_backbone: false,
+            });
+            mol.atom_posits.push(mol.atoms[i].posit);
+
+            result[i].push(i_h);
+            sn += 1;
+        }
+        mol.atoms[i].implicit_h = Some(0);
+    }
+
+    mol.build_adjacency_list();
+    result
+}
+
+/// Create a molecule with hydrogens and 3D coordinates from SMILES text, honoring its
+/// stereo markers.
+pub fn mol_from_smiles(text: &str) -> io::Result<MoleculeCommon> {
+    let graph = parse_smiles(text)?;
+
+    let nbr_order = graph.neighbor_order.clone();
+    let chirality = graph.chirality.clone();
+    let bond_dirs = graph.bond_dirs.clone();
+
+    let mut mol = graph.into_molecule(text);
+    let h_added = add_hydrogens(&mut mol);
+
+    let mut stereo = StereoConstraints::default();
+
+    for (i, chir) in chirality.iter().enumerate() {
+        let anticlockwise = match chir {
+            Some(SmilesChirality::Anticlockwise) => true,
+            Some(SmilesChirality::Clockwise) => false,
+            _ => continue,
+        };
+
+        let nbrs: Vec<_> = nbr_order[i]
+            .iter()
+            .filter_map(|n| n.or_else(|| h_added[i].first().copied()))
+            .collect();
+
+        if let Ok(nbrs) = nbrs.try_into() {
+            stereo.chiral.push((nbrs, anticlockwise));
+        }
+    }
+
+    // Is a marked substituent of `a` (not `other`) "up"?
+    let marked = |a: usize, other: usize| {
+        bond_dirs.iter().find_map(|&(from, to, dir)| {
+            if from == a && to != other {
+                Some((to, dir == BondDir::Up))
+            } else if to == a && from != other {
+                Some((from, dir == BondDir::Down))
+            } else {
+                None
+            }
+        })
+    };
+
+    for bond in &mol.bonds {
+        if bond.bond_type != BondType::Double {
+            continue;
+        }
+        let (a, b) = (bond.atom_0, bond.atom_1);
+        if let (Some((x, up_x)), Some((y, up_y))) = (marked(a, b), marked(b, a)) {
+            stereo.double_bonds.push((x, a, b, y, up_x == up_y));
+        }
+    }
+
+    embed_3d(&mut mol, &stereo, 0)?;
+    Ok(mol)
+}
+
+/// Length of the shortest path between two atoms, in bonds, not passing through any of `avoid`,
+/// and no longer than `max`.
+fn path_len(
+    adj: &[Vec<usize>],
+    from: usize,
+    to: usize,
+    avoid: &[usize],
+    max: usize,
+) -> Option<usize> {
+    let mut frontier = vec![from];
+    let mut visited = vec![false; adj.len()];
+    visited[from] = true;
+    for &a in avoid {
+        visited[a] = true;
+    }
+
+    for len in 1..=max {
+        let mut next = Vec::new();
+        for &u in &frontier {
+            for &v in &adj[u] {
+                if v == to {
+                    return Some(len);
+                }
+                if !visited[v] {
+                    visited[v] = true;
+                    next.push(v);
+                }
+            }
+        }
+        frontier = next;
+    }
+    None
+}
+
+/// Number of bonds separating each pair of atoms; `u32::MAX` if they're disconnected.
+fn topological_dists(adj: &[Vec<usize>]) -> Vec<Vec<u32>> {
+    let n = adj.len();
+    let mut result = vec![vec![u32::MAX; n]; n];
+
+    for start in 0..n {
+        let dists = &mut result[start];
+        dists[start] = 0;
+        let mut frontier = vec![start];
+        let mut d = 0;
+
+        while !frontier.is_empty() {
+            d += 1;
+            let mut next = Vec::new();
+            for &u in &frontier {
+                for &v in &adj[u] {
+                    if dists[v] == u32::MAX {
+                        dists[v] = d;
+                        next.push(v);
+                    }
+                }
+            }
+            frontier = next;
+        }
+    }
+
+    result
+}
+
+/// Distance between the ends of x-a-b-y, with bond lengths `l_xa`, `l_ab`, `l_by`, angles
+/// `ang_a` and `ang_b` at a and b, and dihedral `phi`.
+fn torsion_dist(l_xa: f64, l_ab: f64, l_by: f64, ang_a: f64, ang_b: f64, phi: f64) -> f64 {
+    let dx = l_ab - l_by * ang_b.cos() - l_xa * ang_a.cos();
+    let dy = l_by * ang_b.sin() * phi.cos() - l_xa * ang_a.sin();
+    let dz = l_by * ang_b.sin() * phi.sin();
+
+    (dx.powi(2) + dy.powi(2) + dz.powi(2)).sqrt()
+}
+
+/// The geometry model we embed against.
+struct EmbedModel {
+    /// (i, j, lower², upper²)
+    pairs: Vec<(usize, usize, f64, f64)>,
+    /// Sets of 4 atoms which should be coplanar.
+    planar: Vec<[usize; 4]>,
+    chiral: Vec<([usize; 4], bool)>,
+}
+
+impl EmbedModel {
+    fn new(mol: &MoleculeCommon, stereo: &StereoConstraints) -> Self {
+        let n = mol.atoms.len();
+        let adj = &mol.adjacency_list;
+
+        let mut bond_types = HashMap::new();
+        for b in &mol.bonds {
+            bond_types.insert((b.atom_0, b.atom_1), b.bond_type);
+            bond_types.insert((b.atom_1, b.atom_0), b.bond_type);
+        }
+        let bond_type = |a: usize, b: usize| bond_types.get(&(a, b)).copied();
+
+        let unsaturated = |i: usize| {
+            adj[i]
+                .iter()
+                .any(|&j| bond_type(i, j).is_some_and(|bt| bt != BondType::Single))
+        };
+
+        let hybridization: Vec<_> = (0..n)
+            .map(|i| {
+                let count = |bt: BondType| {
+                    adj[i]
+                        .iter()
+                        .filter(|&&j| bond_type(i, j) == Some(bt))
+                        .count()
+                };
+
+                if count(BondType::Triple) > 0 || count(BondType::Double) >= 2 {
+                    Hybridization::Sp
+                } else if count(BondType::Double) > 0 || count(BondType::Aromatic) > 0 {
+                    Hybridization::Sp2
+                } else if mol.atoms[i].element == Nitrogen
+                    && adj[i].len() <= 3
+                    && adj[i].iter().any(|&j| unsaturated(j))
+                {
+                    // Conjugated nitrogens, e.g. in amides and anilines, are planar.
+                    Hybridization::Sp2
+                } else {
+                    Hybridization::Sp3
+                }
+            })
+            .collect();
+
+        let length = |a: usize, b: usize| {
+            bond_length(
+                mol.atoms[a].element,
+                mol.atoms[b].element,
+                bond_type(a, b).unwrap_or(BondType::Single),
+            )
+        };
+
+        // The ideal angle j-i-k.
+        let angle = |j: usize, i: usize, k: usize| {
+            // Small rings force smaller angles.
+            if let Some(len) = path_len(adj, j, k, &[i], 3) {
+                let ring_size = (len + 2) as f64;
+                if ring_size <= 5. {
+                    return PI * (ring_size - 2.) / ring_size;
+                }
+            }
+
+            match hybridization[i] {
+                Hybridization::Sp => PI,
+                Hybridization::Sp2 => 2. * PI / 3.,
+                Hybridization::Sp3 => 109.47_f64.to_radians(),
+            }
+        };
+
+        let topo = topological_dists(adj);
+        let mut pairs = Vec::with_capacity(n * (n - 1) / 2);
+
+        for i in 0..n {
+            for j in i + 1..n {
+                let vdw =
+                    (mol.atoms[i].element.vdw_radius() + mol.atoms[j].element.vdw_radius()) as f64;
+
+                let (lower, upper) = match topo[i][j] {
+                    1 => {
+                        let l = length(i, j);
+                        (l - TOL_BOND, l + TOL_BOND)
+                    }
+                    2 => {
+                        // If there are several paths (4-membered rings), use the shortest distance.
+                        let d = adj[i]
+                            .iter()
+                            .filter(|c| adj[j].contains(c))
+                            .map(|&c| {
+                                let (a, b) = (length(i, c), length(c, j));
+                                (a * a + b * b - 2. * a * b * angle(i, c, j).cos()).sqrt()
+                            })
+                            .fold(f64::MAX, f64::min);
+                        (d - TOL_ANGLE, d + TOL_ANGLE)
+                    }
+                    3 => torsion_bounds(i, j, adj, &hybridization, stereo, &length, &angle),
+                    u32::MAX => (VDW_SCALE * vdw, MAX_DIST_DISCONNECTED),
+                    t => (VDW_SCALE * vdw, t as f64 * MAX_DIST_PER_BOND),
+                };
+
+                pairs.push((i, j, lower.powi(2), upper.max(lower).powi(2)));
+            }
+        }
+
+        let mut planar = Vec::new();
+        for i in 0..n {
+            if hybridization[i] == Hybridization::Sp2 && adj[i].len() == 3 {
+                planar.push([i, adj[i][0], adj[i][1], adj[i][2]]);
+            }
+        }
+        // Substituents across double and aromatic bonds are coplanar.
+        for b in &mol.bonds {
+            if !matches!(b.bond_type, BondType::Double | BondType::Aromatic) {
+                continue;
+            }
+            for &x in adj[b.atom_0].iter().filter(|&&x| x != b.atom_1) {
+                for &y in adj[b.atom_1].iter().filter(|&&y| y != b.atom_0) {
+                    planar.push([x, b.atom_0, b.atom_1, y]);
+                }
+            }
+        }
+
+        Self {
+            pairs,
+            planar,
+            chiral: stereo.chiral.clone(),
+        }
+    }
+
+    /// Error in distance bounds, using the squared-distance form common in distance geometry.
+    /// Accumulates its gradient.
+    fn bounds_err(&self, x: &[f64], dim: usize, grad: &mut [f64]) -> f64 {
+        let mut result = 0.;
+
+        for &(i, j, l2, u2) in &self.pairs {
+            let mut d2 = 0.;
+            for k in 0..dim {
+                d2 += (x[i * dim + k] - x[j * dim + k]).powi(2);
+            }
+
+            // The derivative with respect to d².
+            let de_dd2 = if d2 > u2 {
+                let t = d2 / u2 - 1.;
+                result += t * t;
+                2. * t / u2
+            } else if d2 < l2 {
+                let denom = l2 + d2;
+                let t = 2. * l2 / denom - 1.;
+                result += t * t;
+                2. * t * (-2. * l2 / (denom * denom))
+            } else {
+                continue;
+            };
+
+            for k in 0..dim {
+                let g = de_dd2 * 2. * (x[i * dim + k] - x[j * dim + k]);
+                grad[i * dim + k] += g;
+                grad[j * dim + k] -= g;
+            }
+        }
+
+        result
+    }
+
+    /// Planarity and chirality error, in 3D. Accumulates its gradient.
+    fn stereo_err(&self, x: &[f64], grad: &mut [f64]) -> f64 {
+        let p = |i: usize| Vec3::new(x[i * 3], x[i * 3 + 1], x[i * 3 + 2]);
+
+        let mut add_grad = |i: usize, g: Vec3| {
+            grad[i * 3] += g.x;
+            grad[i * 3 + 1] += g.y;
+            grad[i * 3 + 2] += g.z;
+        };
+
+        let mut result = 0.;
+
+        // Signed volume of 4 points, and its gradients with respect to each.
+        let volume = |ids: &[usize; 4]| {
+            let a = p(ids[1]) - p(ids[0]);
+            let b = p(ids[2]) - p(ids[0]);
+            let c = p(ids[3]) - p(ids[0]);
+
+            let g1 = b.cross(c);
+            let g2 = c.cross(a);
+            let g3 = a.cross(b);
+            let g0 = (g1 + g2 + g3) * -1.;
+
+            (a.dot(g1), [g0, g1, g2, g3])
+        };
+
+        for ids in &self.planar {
+            let (v, g) = volume(ids);
+            result += W_PLANAR * v * v;
+            for (&i, g) in ids.iter().zip(g) {
+                add_grad(i, g * (2. * W_PLANAR * v));
+            }
+        }
+
+        for (ids, anticlockwise) in &self.chiral {
+            let (v, g) = volume(ids);
+            // Anticlockwise is a negative volume.
+            let sign = if *anticlockwise { 1. } else { -1. };
+            let excess = sign * v + CHIRAL_VOL_MIN;
+            if excess > 0. {
+                result += W_CHIRAL * excess * excess;
+                for (&i, g) in ids.iter().zip(g) {
+                    add_grad(i, g * (2. * W_CHIRAL * excess * sign));
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// Lower and upper bounds for atoms separated by 3 bonds.
+fn torsion_bounds(
+    i: usize,
+    j: usize,
+    adj: &[Vec<usize>],
+    hybridization: &[Hybridization],
+    stereo: &StereoConstraints,
+    length: &impl Fn(usize, usize) -> f64,
+    angle: &impl Fn(usize, usize, usize) -> f64,
+) -> (f64, f64) {
+    // Find the path i-a-b-j.
+    let Some((a, b)) = adj[i].iter().find_map(|&a| {
+        adj[a]
+            .iter()
+            .find(|&&b| b != i && adj[b].contains(&j))
+            .map(|&b| (a, b))
+    }) else {
+        return (0., MAX_DIST_PER_BOND * 3.);
+    };
+
+    let dist = |phi: f64| {
+        torsion_dist(
+            length(i, a),
+            length(a, b),
+            length(b, j),
+            angle(i, a, b),
+            angle(a, b, j),
+            phi,
+        )
+    };
+    let (cis, trans) = (dist(0.), dist(PI));
+
+    let specified = stereo
+        .double_bonds
+        .iter()
+        .find_map(|&(x, _, _, y, cis)| ((x == i && y == j) || (x == j && y == i)).then_some(cis));
+
+    // Planar rings of up to 6 atoms.
+    let planar_ring = hybridization[a] == Hybridization::Sp2
+        && hybridization[b] == Hybridization::Sp2
+        && path_len(adj, i, j, &[a, b], 3).is_some();
+
+    match specified {
+        Some(true) => (cis - TOL_TORSION, cis + TOL_TORSION),
+        Some(false) => (trans - TOL_TORSION, trans + TOL_TORSION),
+        None if planar_ring => (cis - TOL_TORSION, cis + TOL_TORSION),
+        None => (cis, trans),
+    }
+}
+
+/// Simple gradient descent, with an adaptive step size. Returns the final error.
+fn minimize(x: &mut Vec<f64>, iters: usize, err_fn: impl Fn(&[f64], &mut [f64]) -> f64) -> f64 {
+    let mut grad = vec![0.; x.len()];
+    let mut err = err_fn(x, &mut grad);
+
+    let mut trial = x.clone();
+    let mut trial_grad = vec![0.; x.len()];
+    let mut step = 0.05;
+
+    for _ in 0..iters {
+        if err < 1e-9 {
+            break;
+        }
+
+        for ((t, x), g) in trial.iter_mut().zip(x.iter()).zip(&grad) {
+            *t = x - step * g;
+        }
+        trial_grad.fill(0.);
+        let trial_err = err_fn(&trial, &mut trial_grad);
+
+        if trial_err < err {
+            mem::swap(x, &mut trial);
+            mem::swap(&mut grad, &mut trial_grad);
+            err = trial_err;
+            step *= 1.2;
+        } else {
+            step *= 0.5;
+            if step < 1e-12 {
+                break;
+            }
+        }
+    }
+
+    err
+}
+
+/// Set atom positions of a molecule (including hydrogens) using distance geometry. Positions are
+/// centered at the origin. `seed` makes the result reproducible.
+pub fn embed_3d(mol: &mut MoleculeCommon, stereo: &StereoConstraints, seed: u64) -> io::Result<()> {
+    let n = mol.atoms.len();
+    if n == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Can't embed a molecule without atoms",
+        ));
+    }
+    if mol.adjacency_list.len() != n {
+        mol.build_adjacency_list();
+    }
+
+    let model = EmbedModel::new(mol, stereo);
+    let box_size = 2. * (n as f64).cbrt() + 2.;
+
+    let mut best: Option<(f64, Vec<f64>)> = None;
+
+    for attempt in 0..EMBED_ATTEMPTS {
+        let mut rng = StdRng::seed_from_u64(seed.wrapping_add(attempt));
+
+        let mut x4: Vec<f64> = (0..n * 4)
+            .map(|_| rng.random_range(-box_size..box_size))
+            .collect();
+        minimize(&mut x4, ITERS_4D, |x, g| model.bounds_err(x, 4, g));
+
+        // Drop the 4th dimension, and refine in 3D with stereo.
+        let mut x3: Vec<f64> = x4.chunks(4).flat_map(|c| [c[0], c[1], c[2]]).collect();
+        let err = minimize(&mut x3, ITERS_3D, |x, g| {
+            model.bounds_err(x, 3, g) + model.stereo_err(x, g)
+        });
+
+        if best.as_ref().is_none_or(|(e, _)| err < *e) {
+            best = Some((err, x3));
+        }
+        if err < ERR_ACCEPT {
+            break;
+        }
+    }
+
+    let Some((_, x)) = best else {
+        return Ok(());
+    };
+
+    let posits: Vec<_> = x.chunks(3).map(|c| Vec3::new(c[0], c[1], c[2])).collect();
+    let centroid = posits.iter().fold(Vec3::new_zero(), |a, b| a + *b) / n as f64;
+
+    for (i, p) in posits.into_iter().enumerate() {
+        mol.atoms[i].posit = p - centroid;
+    }
+    mol.reset_posits();
+
+    Ok(())
+}
+
+/// Relax a small molecule's geometry with an energy minimization using GAFF2. Assigns force field
+/// types, partial charges, and molecule-specific parameters, if not already present.
+pub fn minimize_gaff2(
+    mol: &mut MoleculeSmall,
+    dev: &ComputationDevice,
+    param_set: &FfParamSet,
+    mol_specific_params: &mut HashMap<String, ForceFieldParams>,
+) -> Result<(), ParamError> {
+    let Some(gaff2) = &param_set.small_mol else {
+        return Err(ParamError::new("GAFF2 parameters are not loaded"));
+    };
+    mol.update_ff_related(mol_specific_params, gaff2);
+
+    let common = &mol.common;
+    let mols = vec![MolDynamics {
+        ff_mol_type: FfMolType::SmallOrganic,
+        atoms: common.atoms.iter().map(|a| a.to_generic()).collect(),
+        atom_posits: Some(common.atom_posits.clone()),
+        atom_init_velocities: None,
+        bonds: common.bonds.iter().map(|b| b.to_generic()).collect(),
+        adjacency_list: Some(common.adjacency_list.clone()),
+        static_: false,
+        bonded_only: false,
+        mol_specific_params: mol_specific_params.get(&common.ident).cloned(),
+    }];
+
+    let cfg = MdConfig {
+        overrides: MdOverrides {
+            skip_water: true,
+            skip_water_relaxation: true,
+            long_range_recip_disabled: true,
+            ..Default::default()
+        },
+        max_init_relaxation_iters: None,
+        hydrogen_constraint: HydrogenConstraint::Flexible,
+        ..Default::default()
+    };
+
+    let mut md = MdState::new(dev, &cfg, &mols, param_set)?;
+    md.minimize_energy(dev, FF_MIN_ITERS);
+
+    let n = mol.common.atoms.len();
+    let posits: Vec<Vec3> = md.atoms.iter().take(n).map(|a| a.posit.into()).collect();
+    let centroid = posits.iter().fold(Vec3::new_zero(), |a, b| a + *b) / posits.len() as f64;
+
+    for (atom, p) in mol.common.atoms.iter_mut().zip(posits) {
+        atom.posit = p - centroid;
+    }
+    mol.common.reset_posits();
+
+    Ok(())
+}
+
+impl State {
+    /// Create a ligand from SMILES text: Add hydrogens, generate 3D coordinates, relax these with
+    /// GAFF2, and add it to the scene.
+    pub fn open_smiles(
+        &mut self,
+        text: &str,
+        scene: &mut Scene,
+        engine_updates: &mut EngineUpdates,
+    ) -> io::Result<()> {
+        let common = mol_from_smiles(text)?;
+        let mut mol = MoleculeSmall::new(
+            common.ident,
+            common.atoms,
+            common.bonds,
+            HashMap::new(),
+            None,
+        );
+
+        let msg = match minimize_gaff2(
+            &mut mol,
+            &self.dev,
+            &self.ff_param_set,
+            &mut self.mol_specific_params,
+        ) {
+            Ok(()) => Ok(format!("Created {} from SMILES", mol.common.ident)),
+            Err(e) => Err(format!(
+                "Unable to relax this molecule with GAFF2; using unrelaxed coordinates: {e:?}"
+            )),
+        };
+
+        self.load_mol_to_state(
+            MoleculeGeneric::Ligand(mol),
+            Some(scene),
+            engine_updates,
+            None,
+        );
+
+        match msg {
+            Ok(m) => handle_success(&mut self.ui, m),
+            Err(m) => handle_err(&mut self.ui, m),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,815 @@
+//! Amber topology (prmtop) and coordinate (inpcrd/rst7) files. We export assembled systems to these,
+//! so runs set up here can be continued in pmemd, OpenMM etc. We also import them, keeping their FF types
+//! and partial charges.
+//!
+//! [Format reference](https://ambermd.org/prmtop.pdf)
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use bio_files::md_params::ForceFieldParams;
+use lin_alg::f64::Vec3;
+use na_seq::Element;
+
+use crate::file_io::topology::{
+    SystemTopology, TopAtom, TopBond, TopResidue, WATER_RES_NAME, atomic_number,
+    element_from_atomic_number, element_from_mass,
+};
+
+/// Amber stores charges multiplied by this, so Coulomb energies come out in kcal/mol.
+const AMBER_CHARGE_FACTOR: f64 = 18.2223;
+
+// Standard 1-4 scaling factors for Amber force fields.
+const SCEE: f64 = 1.2;
+const SCNB: f64 = 2.0;
+
+/// Coordinate file extensions we look for next to a prmtop file, in order.
+const COORD_EXTENSIONS: [&str; 4] = ["rst7", "inpcrd", "crd", "restrt"];
+
+/// Format a float as Fortran's E16.8 does; e.g. " 1.00000000E+00".
+fn fortran_e(v: f64) -> String {
+    let s = format!("{v:.8E}");
+    let (mant, exp) = s.split_once('E').unwrap_or((&s, "0"));
+    let exp: i32 = exp.parse().unwrap_or_default();
+    let sign = if exp < 0 { '-' } else { '+' };
+
+    format!("{:>16}", format!("{mant}E{sign}{:02}", exp.abs()))
+}
+
+fn write_section(out: &mut String, flag: &str, format: &str, vals: &[String], per_line: usize) {
+    out.push_str(&format!("%FLAG {flag}\n%FORMAT({format})\n"));
+
+    if vals.is_empty() {
+        // Empty sections still have a blank line.
+        out.push('\n');
+        return;
+    }
+
+    for chunk in vals.chunks(per_line) {
+        out.push_str(&chunk.concat());
+        out.push('\n');
+    }
+}
+
+fn section_str(out: &mut String, flag: &str, vals: &[String]) {
+    let vals: Vec<_> = vals
+        .iter()
+        .map(|v| format!("{:<4}", &v[..v.len().min(4)]))
+        .collect();
+    write_section(out, flag, "20a4", &vals, 20);
+}
+
+fn section_int(out: &mut String, flag: &str, vals: &[i64]) {
+    let vals: Vec<_> = vals.iter().map(|v| format!("{v:>8}")).collect();
+    write_section(out, flag, "10I8", &vals, 10);
+}
+
+fn section_float(out: &mut String, flag: &str, vals: &[f64]) {
+    let vals: Vec<_> = vals.iter().map(|v| fortran_e(*v)).collect();
+    write_section(out, flag, "5E16.8", &vals, 5);
+}
+
+/// Index of a parameter set in a de-duplicated list. 1-based, as Amber uses.
+fn param_index<const N: usize>(
+    params: &mut Vec<[f32; N]>,
+    map: &mut HashMap<[u32; N], usize>,
+    vals: [f32; N],
+) -> i64 {
+    let key = vals.map(|v| v.to_bits());
+    let i = *map.entry(key).or_insert_with(|| {
+        params.push(vals);
+        params.len()
+    });
+    i as i64
+}
+
+/// Approximate modified Bondi radii, used by Amber's implicit solvent models.
+fn mbondi_radius(el: Option<Element>) -> f64 {
+    match el {
+        Some(Element::Hydrogen) => 1.2,
+        Some(Element::Carbon) => 1.7,
+        Some(Element::Nitrogen) => 1.55,
+        Some(Element::Oxygen) => 1.5,
+        Some(Element::Fluorine) => 1.5,
+        Some(Element::Phosphorus) => 1.85,
+        Some(Element::Sulfur) => 1.8,
+        Some(Element::Chlorine) => 1.7,
+        Some(_) => 1.5,
+        None => 0.,
+    }
+}
+
+impl SystemTopology {
+    pub fn to_prmtop(&self) -> String {
+        let n_atoms = self.atoms.len();
+        let n_types = self.lj.len();
+
+        let type_i: HashMap<&str, usize> = self
+            .lj
+            .iter()
+            .enumerate()
+            .map(|(i, (t, _))| (t.as_str(), i))
+            .collect();
+
+        let is_h = |i: usize| self.atoms[i].element == Some(Element::Hydrogen);
+
+        let mut bond_params = Vec::new();
+        let mut bond_map = HashMap::new();
+        let (mut bonds_h, mut bonds_heavy) = (Vec::new(), Vec::new());
+
+        for b in &self.bonds {
+            let (i0, i1) = b.atoms;
+            let t = param_index(&mut bond_params, &mut bond_map, [b.k_b, b.r_0]);
+            let entry = [3 * i0 as i64, 3 * i1 as i64, t];
+
+            if is_h(i0) || is_h(i1) {
+                bonds_h.extend(entry);
+            } else {
+                bonds_heavy.extend(entry);
+            }
+        }
+
+        let mut angle_params = Vec::new();
+        let mut angle_map = HashMap::new();
+        let (mut angles_h, mut angles_heavy) = (Vec::new(), Vec::new());
+
+        for a in &self.angles {
+            let (i0, i1, i2) = a.atoms;
+            let t = param_index(&mut angle_params, &mut angle_map, [a.k, a.theta_0]);
+            let entry = [3 * i0 as i64, 3 * i1 as i64, 3 * i2 as i64, t];
+
+            if is_h(i0) || is_h(i1) || is_h(i2) {
+                angles_h.extend(entry);
+            } else {
+                angles_heavy.extend(entry);
+            }
+        }
+
+        let excl = self.exclusions();
+
+        // 1-2 and 1-3 pairs; 1-4 interactions aren't computed for these. (e.g. in small rings)
+        let mut close_pairs = HashSet::new();
+        for b in &self.bonds {
+            close_pairs.insert((b.atoms.0.min(b.atoms.1), b.atoms.0.max(b.atoms.1)));
+        }
+        for a in &self.angles {
+            close_pairs.insert((a.atoms.0.min(a.atoms.2), a.atoms.0.max(a.atoms.2)));
+        }
+
+        let mut dihe_params = Vec::new();
+        let mut dihe_map = HashMap::new();
+        let (mut dihe_h, mut dihe_heavy) = (Vec::new(), Vec::new());
+        let mut pairs_14 = HashSet::new();
+
+        for d in &self.dihedrals {
+            let (mut i0, mut i1, mut i2, mut i3) = d.atoms;
+
+            // Amber flags dihedrals using negative atom indices in positions 3 and 4, so those
+            // can't be atom 0. Reverse the order if required; this doesn't change the angle, for
+            // impropers too.
+            if i2 == 0 || i3 == 0 {
+                (i0, i1, i2, i3) = (i3, i2, i1, i0);
+            }
+
+            let pair = (i0.min(i3), i0.max(i3));
+            let skip_14 = d.improper || close_pairs.contains(&pair) || !pairs_14.insert(pair);
+
+            let t = param_index(
+                &mut dihe_params,
+                &mut dihe_map,
+                [d.k, d.periodicity as f32, d.phase],
+            );
+
+            let a2 = 3 * i2 as i64;
+            let a3 = 3 * i3 as i64;
+            let entry = [
+                3 * i0 as i64,
+                3 * i1 as i64,
+                if skip_14 { -a2 } else { a2 },
+                if d.improper { -a3 } else { a3 },
+                t,
+            ];
+
+            if is_h(i0) || is_h(i1) || is_h(i2) || is_h(i3) {
+                dihe_h.extend(entry);
+            } else {
+                dihe_heavy.extend(entry);
+            }
+        }
+
+        let mut num_excluded = Vec::with_capacity(n_atoms);
+        let mut excluded_list = Vec::new();
+        for e in &excl {
+            if e.is_empty() {
+                num_excluded.push(1);
+                excluded_list.push(0);
+            } else {
+                num_excluded.push(e.len() as i64);
+                excluded_list.extend(e.iter().map(|j| *j as i64 + 1));
+            }
+        }
+
+        let mut nb_index = vec![0; n_types * n_types];
+        let mut acoef = Vec::new();
+        let mut bcoef = Vec::new();
+
+        for j in 0..n_types {
+            for i in 0..=j {
+                let (lj_i, lj_j) = (self.lj[i].1, self.lj[j].1);
+                let sigma = (lj_i.sigma as f64 + lj_j.sigma as f64) / 2.;
+                let eps = (lj_i.eps as f64 * lj_j.eps as f64).sqrt();
+
+                acoef.push(4. * eps * sigma.powi(12));
+                bcoef.push(4. * eps * sigma.powi(6));
+
+                let idx = acoef.len() as i64;
+                nb_index[i * n_types + j] = idx;
+                nb_index[j * n_types + i] = idx;
+            }
+        }
+
+        let res_pointers: Vec<i64> = self
+            .residues
+            .iter()
+            .map(|r| r.first_atom as i64 + 1)
+            .collect();
+
+        let max_res_atoms = self
+            .residues
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                let end = match self.residues.get(i + 1) {
+                    Some(next) => next.first_atom,
+                    None => n_atoms,
+                };
+                end - r.first_atom
+            })
+            .max()
+            .unwrap_or_default();
+
+        let num_extra = self.atoms.iter().filter(|a| a.element.is_none()).count();
+
+        let n_bond_h = bonds_h.len() / 3;
+        let n_bond_heavy = bonds_heavy.len() / 3;
+        let n_angle_h = angles_h.len() / 4;
+        let n_angle_heavy = angles_heavy.len() / 4;
+        let n_dihe_h = dihe_h.len() / 5;
+        let n_dihe_heavy = dihe_heavy.len() / 5;
+
+        let pointers: Vec<i64> = vec![
+            n_atoms as i64,
+            n_types as i64,
+            n_bond_h as i64,
+            n_bond_heavy as i64,
+            n_angle_h as i64,
+            n_angle_heavy as i64,
+            n_dihe_h as i64,
+            n_dihe_heavy as i64,
+            0,
+            0,
+            excluded_list.len() as i64,
+            self.residues.len() as i64,
+            n_bond_heavy as i64,
+            n_angle_heavy as i64,
+            n_dihe_heavy as i64,
+            bond_params.len() as i64,
+            angle_params.len() as i64,
+            dihe_params.len() as i64,
+            n_types as i64,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            if self.box_size.is_some() { 1 } else { 0 },
+            max_res_atoms as i64,
+            0,
+            num_extra as i64,
+        ];
+
+        let mut out = String::new();
+        out.push_str("%VERSION  VERSION_STAMP = V0001.000\n");
+        write_section(
+            &mut out,
+            "TITLE",
+            "20a4",
+            &[format!("{:<80}", self.title)],
+            1,
+        );
+        section_int(&mut out, "POINTERS", &pointers);
+
+        let names: Vec<_> = self.atoms.iter().map(|a| a.name.clone()).collect();
+        section_str(&mut out, "ATOM_NAME", &names);
+
+        let charges: Vec<_> = self
+            .atoms
+            .iter()
+            .map(|a| a.charge as f64 * AMBER_CHARGE_FACTOR)
+            .collect();
+        section_float(&mut out, "CHARGE", &charges);
+
+        let atomic_nums: Vec<_> = self
+            .atoms
+            .iter()
+            .map(|a| match a.element {
+                Some(el) => atomic_number(el) as i64,
+                None => 0,
+            })
+            .collect();
+        section_int(&mut out, "ATOMIC_NUMBER", &atomic_nums);
+
+        let masses: Vec<_> = self.atoms.iter().map(|a| a.mass as f64).collect();
+        section_float(&mut out, "MASS", &masses);
+
+        let type_indices: Vec<_> = self
+            .atoms
+            .iter()
+            .map(|a| type_i[a.ff_type.as_str()] as i64 + 1)
+            .collect();
+        section_int(&mut out, "ATOM_TYPE_INDEX", &type_indices);
+        section_int(&mut out, "NUMBER_EXCLUDED_ATOMS", &num_excluded);
+        section_int(&mut out, "NONBONDED_PARM_INDEX", &nb_index);
+
+        let res_names: Vec<_> = self.residues.iter().map(|r| r.name.clone()).collect();
+        section_str(&mut out, "RESIDUE_LABEL", &res_names);
+        section_int(&mut out, "RESIDUE_POINTER", &res_pointers);
+
+        let bp: Vec<_> = bond_params.iter().map(|p| p[0] as f64).collect();
+        section_float(&mut out, "BOND_FORCE_CONSTANT", &bp);
+        let bp: Vec<_> = bond_params.iter().map(|p| p[1] as f64).collect();
+        section_float(&mut out, "BOND_EQUIL_VALUE", &bp);
+
+        let ap: Vec<_> = angle_params.iter().map(|p| p[0] as f64).collect();
+        section_float(&mut out, "ANGLE_FORCE_CONSTANT", &ap);
+        let ap: Vec<_> = angle_params.iter().map(|p| p[1] as f64).collect();
+        section_float(&mut out, "ANGLE_EQUIL_VALUE", &ap);
+
+        let dp: Vec<_> = dihe_params.iter().map(|p| p[0] as f64).collect();
+        section_float(&mut out, "DIHEDRAL_FORCE_CONSTANT", &dp);
+        let dp: Vec<_> = dihe_params.iter().map(|p| p[1] as f64).collect();
+        section_float(&mut out, "DIHEDRAL_PERIODICITY", &dp);
+        let dp: Vec<_> = dihe_params.iter().map(|p| p[2] as f64).collect();
+        section_float(&mut out, "DIHEDRAL_PHASE", &dp);
+        section_float(
+            &mut out,
+            "SCEE_SCALE_FACTOR",
+            &vec![SCEE; dihe_params.len()],
+        );
+        section_float(
+            &mut out,
+            "SCNB_SCALE_FACTOR",
+            &vec![SCNB; dihe_params.len()],
+        );
+
+        section_float(&mut out, "SOLTY", &vec![0.; n_types]);
+        section_float(&mut out, "LENNARD_JONES_ACOEF", &acoef);
+        section_float(&mut out, "LENNARD_JONES_BCOEF", &bcoef);
+
+        section_int(&mut out, "BONDS_INC_HYDROGEN", &bonds_h);
+        section_int(&mut out, "BONDS_WITHOUT_HYDROGEN", &bonds_heavy);
+        section_int(&mut out, "ANGLES_INC_HYDROGEN", &angles_h);
+        section_int(&mut out, "ANGLES_WITHOUT_HYDROGEN", &angles_heavy);
+        section_int(&mut out, "DIHEDRALS_INC_HYDROGEN", &dihe_h);
+        section_int(&mut out, "DIHEDRALS_WITHOUT_HYDROGEN", &dihe_heavy);
+        section_int(&mut out, "EXCLUDED_ATOMS_LIST", &excluded_list);
+
+        section_float(&mut out, "HBOND_ACOEF", &[]);
+        section_float(&mut out, "HBOND_BCOEF", &[]);
+        section_float(&mut out, "HBCUT", &[]);
+
+        let types: Vec<_> = self.atoms.iter().map(|a| a.ff_type.clone()).collect();
+        section_str(&mut out, "AMBER_ATOM_TYPE", &types);
+        section_str(
+            &mut out,
+            "TREE_CHAIN_CLASSIFICATION",
+            &vec!["BLA".to_owned(); n_atoms],
+        );
+        section_int(&mut out, "JOIN_ARRAY", &vec![0; n_atoms]);
+        section_int(&mut out, "IROTAT", &vec![0; n_atoms]);
+
+        if let Some(size) = self.box_size {
+            let mol_sizes = self.molecule_sizes();
+
+            let first_solvent_res = self
+                .atoms
+                .iter()
+                .position(|a| self.residues[a.residue].name == WATER_RES_NAME)
+                .map(|i| self.atoms[i].residue);
+
+            // Index of the last solute residue, and of the first solvent molecule. (1-based)
+            let (last_solute_res, first_solvent_mol) = match first_solvent_res {
+                Some(r) => {
+                    let first_atom = self.residues[r].first_atom;
+                    let mut atom_count = 0;
+                    let mut mol_i = mol_sizes.len();
+                    for (i, s) in mol_sizes.iter().enumerate() {
+                        if atom_count >= first_atom {
+                            mol_i = i;
+                            break;
+                        }
+                        atom_count += s;
+                    }
+                    (r as i64, mol_i as i64 + 1)
+                }
+                None => (self.residues.len() as i64, mol_sizes.len() as i64 + 1),
+            };
+
+            section_int(
+                &mut out,
+                "SOLVENT_POINTERS",
+                &[last_solute_res, mol_sizes.len() as i64, first_solvent_mol],
+            );
+            let sizes: Vec<_> = mol_sizes.iter().map(|s| *s as i64).collect();
+            section_int(&mut out, "ATOMS_PER_MOLECULE", &sizes);
+            section_float(&mut out, "BOX_DIMENSIONS", &[90., size.x, size.y, size.z]);
+        }
+
+        let radii_set = "modified Bondi radii (mbondi)";
+        write_section(
+            &mut out,
+            "RADIUS_SET",
+            "1a80",
+            &[format!("{radii_set:<80}")],
+            1,
+        );
+        let radii: Vec<_> = self
+            .atoms
+            .iter()
+            .map(|a| mbondi_radius(a.element))
+            .collect();
+        section_float(&mut out, "RADII", &radii);
+        section_float(&mut out, "SCREEN", &vec![0.8; n_atoms]);
+        section_int(&mut out, "IPOL", &[0]);
+
+        out
+    }
+
+    /// Coordinates, in rst7 format. Includes the box if periodic.
+    pub fn to_inpcrd(&self) -> String {
+        let mut out = format!("{}\n{:>6}\n", self.title, self.atoms.len());
+
+        let vals: Vec<f64> = self
+            .atoms
+            .iter()
+            .flat_map(|a| [a.posit.x, a.posit.y, a.posit.z])
+            .collect();
+
+        for chunk in vals.chunks(6) {
+            for v in chunk {
+                out.push_str(&format!("{v:>12.7}"));
+            }
+            out.push('\n');
+        }
+
+        if let Some(size) = self.box_size {
+            out.push_str(&format!(
+                "{:>12.7}{:>12.7}{:>12.7}{:>12.7}{:>12.7}{:>12.7}\n",
+                size.x, size.y, size.z, 90., 90., 90.
+            ));
+        }
+
+        out
+    }
+
+    /// Save the topology to `path`, and coordinates alongside it, with the rst7 extension.
+    pub fn save_amber(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_prmtop())?;
+        fs::write(path.with_extension("rst7"), self.to_inpcrd())
+    }
+}
+
+/// Raw prmtop data, by section flag.
+#[derive(Clone, Debug, Default)]
+pub struct Prmtop {
+    pub title: String,
+    pub sections: HashMap<String, Vec<String>>,
+}
+
+/// Parse a Fortran format spec like "10I8", "5E16.8" or "20a4" into its field width.
+fn format_width(format: &str) -> Option<usize> {
+    let (_, rest) = format.split_once(|c: char| c.is_ascii_alphabetic())?;
+    let width = rest.split('.').next()?;
+    width.parse().ok()
+}
+
+impl Prmtop {
+    pub fn new(text: &str) -> io::Result<Self> {
+        let mut result = Self::default();
+
+        let mut flag: Option<String> = None;
+        let mut width = 80;
+
+        for line in text.lines() {
+            if let Some(f) = line.strip_prefix("%FLAG") {
+                let f = f.trim().to_owned();
+                result.sections.insert(f.clone(), Vec::new());
+                flag = Some(f);
+                continue;
+            }
+            if let Some(f) = line.strip_prefix("%FORMAT") {
+                let f = f.trim().trim_start_matches('(').trim_end_matches(')');
+                width = format_width(f).unwrap_or(80);
+                continue;
+            }
+            if line.starts_with('%') {
+                continue;
+            }
+
+            let Some(f) = &flag else { continue };
+            let vals = result.sections.get_mut(f).unwrap();
+
+            let mut i = 0;
+            while i < line.len() {
+                let end = (i + width).min(line.len());
+                let v = line.get(i..end).unwrap_or_default().trim();
+                if !v.is_empty() {
+                    vals.push(v.to_owned());
+                }
+                i = end;
+            }
+        }
+
+        if !result.sections.contains_key("POINTERS") {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Not a prmtop file; missing POINTERS",
+            ));
+        }
+
+        if let Some(t) = result.sections.get("TITLE") {
+            result.title = t.join(" ");
+        }
+
+        Ok(result)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::new(&fs::read_to_string(path)?)
+    }
+
+    pub fn strs(&self, flag: &str) -> io::Result<&[String]> {
+        self.sections
+            .get(flag)
+            .map(|v| v.as_slice())
+            .ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Missing prmtop section: {flag}"),
+                )
+            })
+    }
+
+    pub fn ints(&self, flag: &str) -> io::Result<Vec<i64>> {
+        self.strs(flag)?
+            .iter()
+            .map(|v| {
+                v.parse().map_err(|_| {
+                    io::Error::new(ErrorKind::InvalidData, format!("Invalid integer in {flag}"))
+                })
+            })
+            .collect()
+    }
+
+    pub fn floats(&self, flag: &str) -> io::Result<Vec<f64>> {
+        self.strs(flag)?
+            .iter()
+            .map(|v| {
+                v.parse().map_err(|_| {
+                    io::Error::new(ErrorKind::InvalidData, format!("Invalid float in {flag}"))
+                })
+            })
+            .collect()
+    }
+}
+
+/// Load atom coordinates from an inpcrd or rst7 file. Ignores velocities and the box.
+pub fn load_inpcrd(text: &str, n_atoms: usize) -> io::Result<Vec<Vec3>> {
+    let mut vals = Vec::with_capacity(n_atoms * 3);
+
+    // Line 0 is a title. Line 1 is the atom count, and optionally time.
+    for line in text.lines().skip(2) {
+        let mut i = 0;
+        while i + 12 <= line.len() || (i < line.len() && !line[i..].trim().is_empty()) {
+            let end = (i + 12).min(line.len());
+            let v = line[i..end].trim();
+            vals.push(v.parse::<f64>().map_err(|_| {
+                io::Error::new(ErrorKind::InvalidData, "Invalid inpcrd coordinate")
+            })?);
+            i = end;
+        }
+        if vals.len() >= n_atoms * 3 {
+            break;
+        }
+    }
+
+    if vals.len() < n_atoms * 3 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Coordinate file has fewer atoms than the topology",
+        ));
+    }
+
+    Ok(vals[..n_atoms * 3]
+        .chunks(3)
+        .map(|c| Vec3::new(c[0], c[1], c[2]))
+        .collect())
+}
+
+/// Find a coordinate file with the same name as the topology file.
+pub fn find_coord_file(prmtop_path: &Path) -> Option<PathBuf> {
+    COORD_EXTENSIONS
+        .iter()
+        .map(|ext| prmtop_path.with_extension(ext))
+        .find(|p| p.exists())
+}
+
+impl SystemTopology {
+    /// Build from a loaded prmtop file, and coordinates. Angles and dihedrals aren't loaded; we
+    /// only need the bond graph to build molecules.
+    pub fn from_prmtop(top: &Prmtop, posits: &[Vec3]) -> io::Result<Self> {
+        let names = top.strs("ATOM_NAME")?;
+        let n_atoms = names.len();
+
+        let charges = top.floats("CHARGE")?;
+        let ff_types = top.strs("AMBER_ATOM_TYPE")?;
+        let masses = top.floats("MASS")?;
+        let res_labels = top.strs("RESIDUE_LABEL")?;
+        let res_pointers = top.ints("RESIDUE_POINTER")?;
+
+        let elements: Vec<Option<Element>> = match top.ints("ATOMIC_NUMBER") {
+            Ok(nums) => nums
+                .iter()
+                .map(|n| element_from_atomic_number(*n as usize).ok())
+                .collect(),
+            Err(_) => masses.iter().map(|m| element_from_mass(*m)).collect(),
+        };
+
+        if charges.len() != n_atoms
+            || ff_types.len() != n_atoms
+            || masses.len() != n_atoms
+            || elements.len() != n_atoms
+            || posits.len() != n_atoms
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Inconsistent atom counts in prmtop sections",
+            ));
+        }
+
+        let mut result = Self::new(&top.title);
+
+        for (r, (name, start)) in res_labels.iter().zip(&res_pointers).enumerate() {
+            result.residues.push(TopResidue {
+                name: name.clone(),
+                serial_number: r as u32 + 1,
+                first_atom: (*start as usize).saturating_sub(1),
+            });
+        }
+
+        let mut res_i = 0;
+        for i in 0..n_atoms {
+            while res_i + 1 < result.residues.len() && result.residues[res_i + 1].first_atom <= i {
+                res_i += 1;
+            }
+
+            result.atoms.push(TopAtom {
+                name: names[i].clone(),
+                ff_type: ff_types[i].clone(),
+                element: elements[i],
+                charge: (charges[i] / AMBER_CHARGE_FACTOR) as f32,
+                mass: masses[i] as f32,
+                residue: res_i,
+                posit: posits[i],
+            });
+        }
+
+        let k_b = top.floats("BOND_FORCE_CONSTANT")?;
+        let r_0 = top.floats("BOND_EQUIL_VALUE")?;
+
+        for flag in ["BONDS_INC_HYDROGEN", "BONDS_WITHOUT_HYDROGEN"] {
+            for b in top.ints(flag)?.chunks_exact(3) {
+                let (i0, i1) = ((b[0] / 3) as usize, (b[1] / 3) as usize);
+                let t = (b[2] as usize).saturating_sub(1);
+
+                if i0 >= n_atoms || i1 >= n_atoms {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        "Bond index out of range in prmtop",
+                    ));
+                }
+
+                result.bonds.push(TopBond {
+                    atoms: (i0, i1),
+                    k_b: k_b.get(t).copied().unwrap_or_default() as f32,
+                    r_0: r_0.get(t).copied().unwrap_or_default() as f32,
+                });
+            }
+        }
+
+        if let Ok(dims) = top.floats("BOX_DIMENSIONS")
+            && dims.len() == 4
+        {
+            result.box_size = Some(Vec3::new(dims[1], dims[2], dims[3]));
+        }
+
+        Ok(result)
+    }
+}
+
+/// An FF type, padded to the 2-character column width frcmod uses.
+fn frcmod_type(t: &str) -> String {
+    format!("{t:<2}")
+}
+
+/// Serialize parameters in Amber's frcmod format; e.g. molecule-specific parameters.
+/// Masses aren't included; these come from the general parameter set.
+pub fn frcmod_text(params: &ForceFieldParams, title: &str) -> String {
+    let mut out = format!("{title}\nMASS\n\nBOND\n");
+
+    let mut bonds: Vec<_> = params.bond.iter().collect();
+    bonds.sort_by(|a, b| a.0.cmp(b.0));
+    for ((a, b), v) in bonds {
+        out.push_str(&format!(
+            "{}-{}  {:>8.3}  {:>8.4}\n",
+            frcmod_type(a),
+            frcmod_type(b),
+            v.k_b,
+            v.r_0
+        ));
+    }
+
+    out.push_str("\nANGLE\n");
+    let mut angles: Vec<_> = params.angle.iter().collect();
+    angles.sort_by(|a, b| a.0.cmp(b.0));
+    for ((a, b, c), v) in angles {
+        out.push_str(&format!(
+            "{}-{}-{}  {:>8.3}  {:>10.3}\n",
+            frcmod_type(a),
+            frcmod_type(b),
+            frcmod_type(c),
+            v.k,
+            (v.theta_0 as f64).to_degrees()
+        ));
+    }
+
+    for (section, map, improper) in [
+        ("DIHE", &params.dihedral, false),
+        ("IMPROPER", &params.improper, true),
+    ] {
+        out.push_str(&format!("\n{section}\n"));
+
+        let mut dihedrals: Vec<_> = map.iter().collect();
+        dihedrals.sort_by(|a, b| a.0.cmp(b.0));
+
+        for ((a, b, c, d), terms) in dihedrals {
+            let types = [a, b, c, d].map(|t| frcmod_type(t)).join("-");
+
+            for (i, t) in terms.iter().enumerate() {
+                // A negative periodicity indicates more terms follow.
+                let periodicity = if i + 1 < terms.len() {
+                    -(t.periodicity as i32).abs()
+                } else {
+                    t.periodicity as i32
+                };
+                let phase = (t.phase as f64).to_degrees();
+
+                if improper {
+                    out.push_str(&format!(
+                        "{types}  {:>14.6}  {:>10.3}  {:>6.1}\n",
+                        t.barrier_height, phase, periodicity as f64
+                    ));
+                } else {
+                    out.push_str(&format!(
+                        "{types}  {:>3}  {:>14.6}  {:>10.3}  {:>6.1}\n",
+                        t.divider, t.barrier_height, phase, periodicity as f64
+                    ));
+                }
+            }
+        }
+    }
+
+    out.push_str("\nNONBON\n");
+    let mut lj: Vec<_> = params.lennard_jones.iter().collect();
+    lj.sort_by(|a, b| a.0.cmp(b.0));
+    for (t, v) in lj {
+        // Amber uses R_min / 2 instead of σ.
+        let r_star = v.sigma as f64 * 2_f64.powf(1. / 6.) / 2.;
+        out.push_str(&format!(
+            "  {}  {:>12.4}  {:>10.4}\n",
+            frcmod_type(t),
+            r_star,
+            v.eps
+        ));
+    }
+
+    out.push_str("\n\n");
+    out
+}
@@ -0,0 +1,786 @@
+//! GROMACS coordinate (gro) and topology (top, itp) files. We export assembled systems as a gro
+//! and top pair, and import them, keeping FF types and partial charges.
+//!
+//! GROMACS uses nm and kJ/mol, and includes the 1/2 factor in its harmonic force constants; we
+//! convert to and from Amber conventions, which `SystemTopology` uses.
+
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    io::{self, ErrorKind},
+    iter,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use lin_alg::f64::Vec3;
+use na_seq::Element;
+
+use crate::file_io::topology::{
+    FF_TYPE_EXTRA_PT, FF_TYPE_WATER_H, FF_TYPE_WATER_O, OPC_Q_H, OPC_Q_M, OPC_R_HH, OPC_R_OH,
+    OPC_R_OM, SystemTopology, TopAtom, TopBond, TopResidue, WATER_RES_NAME, atomic_number,
+    element_from_atomic_number, element_from_mass,
+};
+
+const NM_PER_ANGSTROM: f64 = 0.1;
+const KJ_PER_KCAL: f64 = 4.184;
+
+/// GROMACS' conventional water residue and molecule name.
+const GMX_WATER_NAME: &str = "SOL";
+
+/// Water molecule names used by GROMACS force field ports, and other packages.
+const GMX_WATER_NAMES: [&str; 3] = [GMX_WATER_NAME, "WAT", "HOH"];
+
+/// Monatomic ion molecule names used by GROMACS force field ports, with charge and mass.
+const GMX_IONS: [(&str, f32, f32); 6] = [
+    ("NA", 1., 22.99),
+    ("CL", -1., 35.45),
+    ("K", 1., 39.098),
+    ("MG", 2., 24.305),
+    ("CA", 2., 40.078),
+    ("ZN", 2., 65.38),
+];
+
+/// Used when exporting a system without a sim box; GROMACS requires one.
+const BOX_PAD: f64 = 10.; // Å
+
+/// An atom, as listed in a gro file.
+#[derive(Clone, Debug)]
+pub struct GroAtom {
+    pub res_sn: u32,
+    pub res_name: String,
+    pub name: String,
+    /// Å
+    pub posit: Vec3,
+}
+
+/// Coordinates, from a gro file.
+#[derive(Clone, Debug, Default)]
+pub struct Gro {
+    pub title: String,
+    pub atoms: Vec<GroAtom>,
+    /// Å. We only support rectangular boxes.
+    pub box_size: Option<Vec3>,
+}
+
+impl Gro {
+    pub fn new(text: &str) -> io::Result<Self> {
+        let mut lines = text.lines();
+
+        let title = lines.next().unwrap_or_default().trim().to_owned();
+        let n_atoms: usize = lines
+            .next()
+            .and_then(|l| l.trim().parse().ok())
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Invalid gro atom count"))?;
+
+        let mut atoms = Vec::with_capacity(n_atoms);
+
+        for _ in 0..n_atoms {
+            let line = lines.next().ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    "gro file has fewer atoms than listed",
+                )
+            })?;
+
+            let field = |start: usize, end: usize| {
+                line.get(start..end.min(line.len()))
+                    .unwrap_or_default()
+                    .trim()
+            };
+            let coord = |start: usize| -> io::Result<f64> {
+                let v: f64 = field(start, start + 8).parse().map_err(|_| {
+                    io::Error::new(ErrorKind::InvalidData, "Invalid gro coordinate")
+                })?;
+                Ok(v / NM_PER_ANGSTROM)
+            };
+
+            atoms.push(GroAtom {
+                res_sn: field(0, 5).parse().unwrap_or_default(),
+                res_name: field(5, 10).to_owned(),
+                name: field(10, 15).to_owned(),
+                posit: Vec3::new(coord(20)?, coord(28)?, coord(36)?),
+            });
+        }
+
+        let box_size = lines.next().and_then(|l| {
+            let v: Vec<f64> = l
+                .split_whitespace()
+                .filter_map(|v| v.parse().ok())
+                .collect();
+            if v.len() >= 3 {
+                Some(Vec3::new(v[0], v[1], v[2]) / NM_PER_ANGSTROM)
+            } else {
+                None
+            }
+        });
+
+        Ok(Self {
+            title,
+            atoms,
+            box_size,
+        })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::new(&fs::read_to_string(path)?)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GmxAtom {
+    pub ff_type: String,
+    pub res_sn: u32,
+    pub res_name: String,
+    pub name: String,
+    pub charge: f32,
+    pub mass: Option<f32>,
+}
+
+/// A `[ moleculetype ]` block.
+#[derive(Clone, Debug, Default)]
+pub struct GmxMolType {
+    pub name: String,
+    pub atoms: Vec<GmxAtom>,
+    /// 0-based atom indices, and (k_b, r_0) in Amber units if listed.
+    pub bonds: Vec<(usize, usize, Option<(f32, f32)>)>,
+}
+
+/// Atom type info from `[ atomtypes ]`.
+#[derive(Clone, Debug, Default)]
+pub struct GmxAtomType {
+    pub atomic_num: Option<usize>,
+    pub mass: Option<f32>,
+    /// Virtual sites and shells.
+    pub is_virtual: bool,
+}
+
+/// A topology from a top file, and any itp files it includes.
+#[derive(Clone, Debug, Default)]
+pub struct GmxTop {
+    pub atom_types: HashMap<String, GmxAtomType>,
+    pub mol_types: Vec<GmxMolType>,
+    /// Molecule type name, and count, in order.
+    pub molecules: Vec<(String, usize)>,
+}
+
+/// Directories GROMACS searches for included files, after the including file's own: Those in
+/// `$GMXLIB`, then the installation's `share/gromacs/top`, from `$GMXDATA`.
+fn include_dirs() -> Vec<PathBuf> {
+    let mut result = Vec::new();
+
+    if let Some(lib) = env::var_os("GMXLIB") {
+        result.extend(env::split_paths(&lib));
+    }
+    if let Some(data) = env::var_os("GMXDATA") {
+        result.push(PathBuf::from(data).join("gromacs").join("top"));
+    }
+
+    result
+}
+
+/// Lines from a top file with `#include`s expanded, comments stripped, and `#ifdef` blocks resolved.
+fn preprocess(path: &Path, defines: &mut HashSet<String>, out: &mut Vec<String>) -> io::Result<()> {
+    let text = fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    // Whether each nested conditional block is active.
+    let mut active: Vec<bool> = Vec::new();
+
+    for line in text.lines() {
+        let line = line.split(';').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let enabled = active.iter().all(|a| *a);
+        let mut words = line.split_whitespace();
+
+        match words.next().unwrap_or_default() {
+            "#ifdef" => active.push(defines.contains(words.next().unwrap_or_default())),
+            "#ifndef" => active.push(!defines.contains(words.next().unwrap_or_default())),
+            "#else" => {
+                if let Some(a) = active.last_mut() {
+                    *a = !*a;
+                }
+            }
+            "#endif" => {
+                active.pop();
+            }
+            "#define" if enabled => {
+                if let Some(d) = words.next() {
+                    defines.insert(d.to_owned());
+                }
+            }
+            "#include" if enabled => {
+                let file = line["#include".len()..].trim().trim_matches('"');
+                let inc_path = iter::once(dir.to_owned())
+                    .chain(include_dirs())
+                    .map(|d| d.join(file))
+                    .find(|p| p.exists());
+
+                // Force field includes are often resolved from the GROMACS installation. If that
+                // isn't available, we handle water and ions without their molecule types.
+                match inc_path {
+                    Some(p) => preprocess(&p, defines, out)?,
+                    None => eprintln!("Skipping GROMACS include not found: {file}"),
+                }
+            }
+            _ if enabled && !line.starts_with('#') => out.push(line.to_owned()),
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+impl GmxTop {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut lines = Vec::new();
+        preprocess(path, &mut HashSet::new(), &mut lines)?;
+
+        let mut result = Self::default();
+        let mut section = String::new();
+
+        let parse_err = |sect: &str| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid line in [ {sect} ]"),
+            )
+        };
+
+        for line in &lines {
+            if line.starts_with('[') {
+                section = line.trim_matches(['[', ']', ' ']).to_lowercase();
+                continue;
+            }
+
+            let cols: Vec<&str> = line.split_whitespace().collect();
+
+            match section.as_str() {
+                "atomtypes" => {
+                    // Columns vary: name [bond_type] [at.num] mass charge ptype sigma epsilon. We
+                    // locate the particle type column to work out which are present.
+                    let Some(p) = cols
+                        .iter()
+                        .rposition(|c| matches!(*c, "A" | "D" | "S" | "V"))
+                    else {
+                        return Err(parse_err(&section));
+                    };
+                    if p < 3 {
+                        return Err(parse_err(&section));
+                    }
+
+                    let atomic_num = if p >= 4 {
+                        cols[p - 3].parse().ok()
+                    } else {
+                        None
+                    };
+
+                    result.atom_types.insert(
+                        cols[0].to_owned(),
+                        GmxAtomType {
+                            atomic_num,
+                            mass: cols[p - 2].parse().ok(),
+                            is_virtual: cols[p] != "A",
+                        },
+                    );
+                }
+                "moleculetype" => result.mol_types.push(GmxMolType {
+                    name: cols[0].to_owned(),
+                    ..Default::default()
+                }),
+                "atoms" => {
+                    let Some(mt) = result.mol_types.last_mut() else {
+                        continue;
+                    };
+                    if cols.len() < 7 {
+                        return Err(parse_err(&section));
+                    }
+
+                    mt.atoms.push(GmxAtom {
+                        ff_type: cols[1].to_owned(),
+                        res_sn: cols[2].parse().map_err(|_| parse_err(&section))?,
+                        res_name: cols[3].to_owned(),
+                        name: cols[4].to_owned(),
+                        charge: cols[6].parse().map_err(|_| parse_err(&section))?,
+                        mass: cols.get(7).and_then(|m| m.parse().ok()),
+                    });
+                }
+                "bonds" => {
+                    let Some(mt) = result.mol_types.last_mut() else {
+                        continue;
+                    };
+                    if cols.len() < 2 {
+                        return Err(parse_err(&section));
+                    }
+
+                    let i0: usize = cols[0].parse().map_err(|_| parse_err(&section))?;
+                    let i1: usize = cols[1].parse().map_err(|_| parse_err(&section))?;
+
+                    let params = match (
+                        cols.get(3).and_then(|v| v.parse::<f64>().ok()),
+                        cols.get(4).and_then(|v| v.parse::<f64>().ok()),
+                    ) {
+                        (Some(b0), Some(kb)) => Some((
+                            (kb * NM_PER_ANGSTROM.powi(2) / (2. * KJ_PER_KCAL)) as f32,
+                            (b0 / NM_PER_ANGSTROM) as f32,
+                        )),
+                        _ => None,
+                    };
+
+                    mt.bonds
+                        .push((i0.saturating_sub(1), i1.saturating_sub(1), params));
+                }
+                "molecules" => {
+                    if cols.len() < 2 {
+                        return Err(parse_err(&section));
+                    }
+                    let count = cols[1].parse().map_err(|_| parse_err(&section))?;
+                    result.molecules.push((cols[0].to_owned(), count));
+                }
+                // Settles, exclusions, angles, dihedrals etc. We only need the bond graph.
+                _ => (),
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Convert names to ones GROMACS expects for water.
+fn gmx_res_name(name: &str) -> &str {
+    if name == WATER_RES_NAME {
+        GMX_WATER_NAME
+    } else {
+        name
+    }
+}
+
+/// A stand-in molecule type for water or an ion, for when its definition is in a force field
+/// include we couldn't find. Water atoms are taken from the gro residue at `gro_i`; their charges
+/// depend on the water model, so are left at 0.
+fn builtin_mol_type(name: &str, gro: &Gro, gro_i: usize) -> Option<GmxMolType> {
+    if let Some((_, charge, mass)) = GMX_IONS.iter().find(|(n, _, _)| *n == name) {
+        return Some(GmxMolType {
+            name: name.to_owned(),
+            atoms: vec![GmxAtom {
+                ff_type: name.to_owned(),
+                res_sn: 1,
+                res_name: name.to_owned(),
+                name: name.to_owned(),
+                charge: *charge,
+                mass: Some(*mass),
+            }],
+            bonds: Vec::new(),
+        });
+    }
+
+    if !GMX_WATER_NAMES.contains(&name) {
+        return None;
+    }
+
+    let first = gro.atoms.get(gro_i)?;
+    let mut result = GmxMolType {
+        name: name.to_owned(),
+        ..Default::default()
+    };
+
+    for (i, gro_atom) in gro.atoms[gro_i..]
+        .iter()
+        .take_while(|a| a.res_sn == first.res_sn && a.res_name == first.res_name)
+        .enumerate()
+    {
+        // e.g. OW, HW1, HW2, and MW for 4-point models' virtual sites.
+        let (ff_type, mass) = match gro_atom.name.chars().next() {
+            Some('O') => (
+                FF_TYPE_WATER_O,
+                Some(Element::Oxygen.atomic_weight() as f32),
+            ),
+            Some('H') => {
+                result.bonds.push((0, i, None));
+                (
+                    FF_TYPE_WATER_H,
+                    Some(Element::Hydrogen.atomic_weight() as f32),
+                )
+            }
+            _ => (FF_TYPE_EXTRA_PT, None),
+        };
+
+        result.atoms.push(GmxAtom {
+            ff_type: ff_type.to_owned(),
+            res_sn: 1,
+            res_name: gro_atom.res_name.clone(),
+            name: gro_atom.name.clone(),
+            charge: 0.,
+            mass,
+        });
+    }
+
+    Some(result)
+}
+
+impl SystemTopology {
+    /// Build from gro coordinates and a GROMACS topology. Angles and dihedrals aren't loaded; we
+    /// only need the bond graph to build molecules.
+    pub fn from_gromacs(gro: &Gro, top: &GmxTop) -> io::Result<Self> {
+        let mut result = Self::new(&gro.title);
+        result.box_size = gro.box_size;
+
+        for (mol_name, count) in &top.molecules {
+            let builtin;
+            let mt = match top.mol_types.iter().find(|m| &m.name == mol_name) {
+                Some(m) => m,
+                None => {
+                    builtin =
+                        builtin_mol_type(mol_name, gro, result.atoms.len()).ok_or_else(|| {
+                            io::Error::new(
+                                ErrorKind::InvalidData,
+                                format!("Missing moleculetype for {mol_name}"),
+                            )
+                        })?;
+                    &builtin
+                }
+            };
+
+            for _ in 0..*count {
+                let start = result.atoms.len();
+                let mut prev_res_sn = None;
+
+                for atom in &mt.atoms {
+                    let Some(gro_atom) = gro.atoms.get(result.atoms.len()) else {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidData,
+                            "gro file has fewer atoms than the topology",
+                        ));
+                    };
+
+                    if prev_res_sn != Some(atom.res_sn) {
+                        result.residues.push(TopResidue {
+                            name: atom.res_name.clone(),
+                            serial_number: gro_atom.res_sn,
+                            first_atom: result.atoms.len(),
+                        });
+                        prev_res_sn = Some(atom.res_sn);
+                    }
+
+                    let atom_type = top.atom_types.get(&atom.ff_type);
+                    let mass = atom
+                        .mass
+                        .or(atom_type.and_then(|t| t.mass))
+                        .unwrap_or_default();
+
+                    let element = match atom_type {
+                        Some(t) if t.is_virtual => None,
+                        Some(GmxAtomType {
+                            atomic_num: Some(n),
+                            ..
+                        }) if *n > 0 => element_from_atomic_number(*n).ok(),
+                        _ => element_from_mass(mass as f64),
+                    };
+
+                    result.atoms.push(TopAtom {
+                        name: atom.name.clone(),
+                        ff_type: atom.ff_type.clone(),
+                        element,
+                        charge: atom.charge,
+                        mass,
+                        residue: result.residues.len() - 1,
+                        posit: gro_atom.posit,
+                    });
+                }
+
+                for (i0, i1, params) in &mt.bonds {
+                    let (k_b, r_0) = params.unwrap_or_default();
+                    result.bonds.push(TopBond {
+                        atoms: (start + i0, start + i1),
+                        k_b,
+                        r_0,
+                    });
+                }
+            }
+        }
+
+        if result.atoms.len() != gro.atoms.len() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Atom counts in the gro and top files don't match",
+            ));
+        }
+
+        Ok(result)
+    }
+
+    /// Coordinates, in gro format. If there's no sim box, we pad the system's extent.
+    pub fn to_gro(&self) -> String {
+        let mut out = format!("{}\n{:>5}\n", self.title, self.atoms.len());
+
+        for (i, atom) in self.atoms.iter().enumerate() {
+            let res = &self.residues[atom.residue];
+            let p = atom.posit * NM_PER_ANGSTROM;
+
+            // Serial numbers wrap in fixed-width fields; GROMACS ignores them.
+            out.push_str(&format!(
+                "{:>5}{:<5}{:>5}{:>5}{:>8.3}{:>8.3}{:>8.3}\n",
+                res.serial_number % 100_000,
+                gmx_res_name(&res.name),
+                atom.name,
+                (i + 1) % 100_000,
+                p.x,
+                p.y,
+                p.z
+            ));
+        }
+
+        let size_a = match self.box_size {
+            Some(s) => s,
+            None => {
+                let mut low = Vec3::new(f64::MAX, f64::MAX, f64::MAX);
+                let mut high = Vec3::new(f64::MIN, f64::MIN, f64::MIN);
+                for a in &self.atoms {
+                    low = Vec3::new(
+                        low.x.min(a.posit.x),
+                        low.y.min(a.posit.y),
+                        low.z.min(a.posit.z),
+                    );
+                    high = Vec3::new(
+                        high.x.max(a.posit.x),
+                        high.y.max(a.posit.y),
+                        high.z.max(a.posit.z),
+                    );
+                }
+                high - low + Vec3::new(BOX_PAD, BOX_PAD, BOX_PAD) * 2.
+            }
+        };
+        let size = size_a * NM_PER_ANGSTROM;
+
+        out.push_str(&format!(
+            "{:>10.5}{:>10.5}{:>10.5}\n",
+            size.x, size.y, size.z
+        ));
+        out
+    }
+
+    /// Topology, in GROMACS top format. Each molecule gets its own molecule type, except water, which
+    /// uses a single rigid OPC type with a virtual site.
+    pub fn to_gmx_top(&self) -> String {
+        let mut out = format!(
+            "; {}\n\n[ defaults ]\n; nbfunc  comb-rule  gen-pairs  fudgeLJ  fudgeQQ\n1  2  yes  0.5  0.8333\n\n",
+            self.title
+        );
+
+        out.push_str("[ atomtypes ]\n; name  at.num  mass  charge  ptype  sigma  epsilon\n");
+        for (ff_type, lj) in &self.lj {
+            let atom = self.atoms.iter().find(|a| &a.ff_type == ff_type);
+            let (at_num, mass, ptype) = match atom {
+                Some(a) => match a.element {
+                    Some(el) => (atomic_number(el), a.mass, "A"),
+                    None => (0, 0., "D"),
+                },
+                None => (0, 0., "A"),
+            };
+
+            out.push_str(&format!(
+                "{ff_type:<6} {at_num:>3} {mass:>10.4} 0.0000 {ptype} {:.6e} {:.6e}\n",
+                lj.sigma as f64 * NM_PER_ANGSTROM,
+                lj.eps as f64 * KJ_PER_KCAL
+            ));
+        }
+        out.push('\n');
+
+        // Molecule type name for each molecule, deconflicted.
+        let mut mol_type_names: Vec<String> = Vec::with_capacity(self.molecules.len());
+        let mut used = HashSet::new();
+
+        for mol in &self.molecules {
+            if mol.is_water {
+                mol_type_names.push(GMX_WATER_NAME.to_owned());
+                continue;
+            }
+
+            let base: String = mol
+                .name
+                .chars()
+                .map(|c| if c.is_whitespace() { '_' } else { c })
+                .collect();
+            let base = if base.is_empty() {
+                "MOL".to_owned()
+            } else {
+                base
+            };
+
+            let mut name = base.clone();
+            let mut i = 2;
+            while used.contains(&name) || name == GMX_WATER_NAME {
+                name = format!("{base}_{i}");
+                i += 1;
+            }
+            used.insert(name.clone());
+
+            self.write_mol_type(&mut out, &name, mol.atoms.clone());
+            mol_type_names.push(name);
+        }
+
+        if self.molecules.iter().any(|m| m.is_water) {
+            write_water_mol_type(&mut out);
+        }
+
+        out.push_str("[ system ]\n");
+        out.push_str(&format!("{}\n\n", self.title));
+
+        out.push_str("[ molecules ]\n; name  count\n");
+        let mut runs: Vec<(&str, usize)> = Vec::new();
+        for name in &mol_type_names {
+            match runs.last_mut() {
+                Some((n, count)) if *n == name => *count += 1,
+                _ => runs.push((name, 1)),
+            }
+        }
+        for (name, count) in runs {
+            out.push_str(&format!("{name:<12} {count}\n"));
+        }
+
+        out
+    }
+
+    fn write_mol_type(&self, out: &mut String, name: &str, range: Range<usize>) {
+        let local = |i: usize| i - range.start + 1;
+        let in_mol = |atoms: &[usize]| atoms.iter().all(|i| range.contains(i));
+
+        out.push_str(&format!("[ moleculetype ]\n; name  nrexcl\n{name}  3\n\n"));
+
+        out.push_str("[ atoms ]\n; nr  type  resnr  residue  atom  cgnr  charge  mass\n");
+        for i in range.clone() {
+            let atom = &self.atoms[i];
+            let res = &self.residues[atom.residue];
+            out.push_str(&format!(
+                "{:>6} {:<6} {:>6} {:<5} {:<5} {:>6} {:>10.6} {:>10.4}\n",
+                local(i),
+                atom.ff_type,
+                res.serial_number,
+                gmx_res_name(&res.name),
+                atom.name,
+                local(i),
+                atom.charge,
+                atom.mass
+            ));
+        }
+        out.push('\n');
+
+        let bonds: Vec<_> = self
+            .bonds
+            .iter()
+            .filter(|b| in_mol(&[b.atoms.0, b.atoms.1]))
+            .collect();
+
+        out.push_str("[ bonds ]\n; ai  aj  funct  b0  kb\n");
+        for b in &bonds {
+            out.push_str(&format!(
+                "{:>6} {:>6} 1 {:.5} {:.2}\n",
+                local(b.atoms.0),
+                local(b.atoms.1),
+                b.r_0 as f64 * NM_PER_ANGSTROM,
+                b.k_b as f64 * 2. * KJ_PER_KCAL / NM_PER_ANGSTROM.powi(2)
+            ));
+        }
+        out.push('\n');
+
+        let angles: Vec<_> = self
+            .angles
+            .iter()
+            .filter(|a| in_mol(&[a.atoms.0, a.atoms.1, a.atoms.2]))
+            .collect();
+
+        let dihedrals: Vec<_> = self
+            .dihedrals
+            .iter()
+            .filter(|d| in_mol(&[d.atoms.0, d.atoms.1, d.atoms.2, d.atoms.3]))
+            .collect();
+
+        // 1-4 pairs; skip ones also separated by fewer bonds, e.g. in small rings.
+        let mut close = HashSet::new();
+        for b in &bonds {
+            close.insert((b.atoms.0.min(b.atoms.1), b.atoms.0.max(b.atoms.1)));
+        }
+        for a in &angles {
+            close.insert((a.atoms.0.min(a.atoms.2), a.atoms.0.max(a.atoms.2)));
+        }
+
+        let mut pairs = Vec::new();
+        for d in dihedrals.iter().filter(|d| !d.improper) {
+            let pair = (d.atoms.0.min(d.atoms.3), d.atoms.0.max(d.atoms.3));
+            if !close.contains(&pair) && !pairs.contains(&pair) {
+                pairs.push(pair);
+            }
+        }
+
+        out.push_str("[ pairs ]\n; ai  aj  funct\n");
+        for (i0, i1) in pairs {
+            out.push_str(&format!("{:>6} {:>6} 1\n", local(i0), local(i1)));
+        }
+        out.push('\n');
+
+        out.push_str("[ angles ]\n; ai  aj  ak  funct  theta0  k\n");
+        for a in &angles {
+            out.push_str(&format!(
+                "{:>6} {:>6} {:>6} 1 {:.4} {:.4}\n",
+                local(a.atoms.0),
+                local(a.atoms.1),
+                local(a.atoms.2),
+                (a.theta_0 as f64).to_degrees(),
+                a.k as f64 * 2. * KJ_PER_KCAL
+            ));
+        }
+        out.push('\n');
+
+        // Function 9 allows multiple terms per atom set; 4 is a periodic improper.
+        out.push_str("[ dihedrals ]\n; ai  aj  ak  al  funct  phase  kd  pn\n");
+        for d in &dihedrals {
+            out.push_str(&format!(
+                "{:>6} {:>6} {:>6} {:>6} {} {:.4} {:.5} {}\n",
+                local(d.atoms.0),
+                local(d.atoms.1),
+                local(d.atoms.2),
+                local(d.atoms.3),
+                if d.improper { 4 } else { 9 },
+                (d.phase as f64).to_degrees(),
+                d.k as f64 * KJ_PER_KCAL,
+                d.periodicity
+            ));
+        }
+        out.push('\n');
+    }
+
+    /// Save coordinates and topology, as gro and top files with the same name.
+    pub fn save_gromacs(&self, path: &Path) -> io::Result<()> {
+        fs::write(path.with_extension("gro"), self.to_gro())?;
+        fs::write(path.with_extension("top"), self.to_gmx_top())
+    }
+}
+
+/// Rigid OPC water, using SETTLE, and a virtual site for the negative charge. Atom order matches
+/// `SystemTopology::add_water`.
+fn write_water_mol_type(out: &mut String) {
+    // The virtual site is a linear combination of the O-H vectors.
+    let r_oh = OPC_R_OH as f64;
+    let half_hh = OPC_R_HH as f64 / 2.;
+    let a = OPC_R_OM as f64 / (2. * (r_oh.powi(2) - half_hh.powi(2)).sqrt());
+
+    let m_o = Element::Oxygen.atomic_weight();
+    let m_h = Element::Hydrogen.atomic_weight();
+    let w = GMX_WATER_NAME;
+
+    out.push_str(&format!(
+        "[ moleculetype ]\n; name  nrexcl\n{w}  2\n\n\
+         [ atoms ]\n; nr  type  resnr  residue  atom  cgnr  charge  mass\n\
+         1  {FF_TYPE_WATER_O}  1  {w}  OW   1  0.0  {m_o:.4}\n\
+         2  {FF_TYPE_WATER_H}  1  {w}  HW1  1  {OPC_Q_H}  {m_h:.4}\n\
+         3  {FF_TYPE_WATER_H}  1  {w}  HW2  1  {OPC_Q_H}  {m_h:.4}\n\
+         4  {FF_TYPE_EXTRA_PT}  1  {w}  MW   1  {OPC_Q_M}  0.0\n\n\
+         [ settles ]\n; OW  funct  doh  dhh\n1  1  {:.5}  {:.5}\n\n\
+         [ virtual_sites3 ]\n; site  from  funct  a  b\n4  1  2  3  1  {a:.8}  {a:.8}\n\n\
+         [ exclusions ]\n1  2  3  4\n2  1  3  4\n3  1  2  4\n4  1  2  3\n\n",
+        r_oh * NM_PER_ANGSTROM,
+        OPC_R_HH as f64 * NM_PER_ANGSTROM,
+    ));
+}
@@ -0,0 +1,500 @@
+//! Export of generated meshes (Solvent-accessible surface, electron density isosurface, and
+//! secondary structure cartoon) to common 3D formats: OBJ, PLY, binary glTF (GLB), and STL.
+//! These are useful for 3D printing, figures rendered in other programs, and web viewers.
+//!
+//! Meshes are in world coordinates (Å) at export. Per-vertex colors are used where set, e.g. by
+//! `update_sas_mesh_coloring`; otherwise, we use the color the mesh is drawn with.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use graphics::{Mesh, Scene};
+use lin_alg::f32::Vec3;
+
+use crate::{
+    Selection, State,
+    drawing::{
+        COLOR_DENSITY_SURFACE, COLOR_SA_SURFACE, COLOR_SECONDARY_STRUCTURE, DENSITY_ISO_OPACITY,
+        SAS_ISO_OPACITY,
+    },
+    render::{MESH_DENSITY_SURFACE, MESH_SECONDARY_STRUCTURE, MESH_SOLVENT_SURFACE},
+    util::handle_success,
+};
+
+/// Vertices further than this from any atom aren't included in selection-only exports. Å.
+const SEL_MAX_DIST: f32 = 6.;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum MeshSource {
+    #[default]
+    SaSurface,
+    DensitySurface,
+    Cartoon,
+}
+
+impl MeshSource {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::SaSurface => "Surface",
+            Self::DensitySurface => "Density sfc",
+            Self::Cartoon => "Cartoon",
+        }
+    }
+
+    fn mesh_i(self) -> usize {
+        match self {
+            Self::SaSurface => MESH_SOLVENT_SURFACE,
+            Self::DensitySurface => MESH_DENSITY_SURFACE,
+            Self::Cartoon => MESH_SECONDARY_STRUCTURE,
+        }
+    }
+
+    /// RGBA; used for vertices without their own color.
+    fn default_color(self) -> [f32; 4] {
+        let ((r, g, b), a) = match self {
+            Self::SaSurface => (COLOR_SA_SURFACE, SAS_ISO_OPACITY),
+            Self::DensitySurface => (COLOR_DENSITY_SURFACE, DENSITY_ISO_OPACITY),
+            Self::Cartoon => (COLOR_SECONDARY_STRUCTURE, 1.),
+        };
+        [r, g, b, a]
+    }
+
+    /// Mesh sources which have been generated.
+    pub fn available(state: &State) -> Vec<Self> {
+        let mut result = Vec::new();
+        let Some(mol) = &state.peptide else {
+            return result;
+        };
+
+        if state.volatile.flags.sas_mesh_created {
+            result.push(Self::SaSurface);
+        }
+        if mol.elec_density.is_some() {
+            result.push(Self::DensitySurface);
+        }
+        if state.volatile.flags.ss_mesh_created {
+            result.push(Self::Cartoon);
+        }
+        result
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MeshFormat {
+    Obj,
+    Ply,
+    Glb,
+    Stl,
+}
+
+impl MeshFormat {
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let ext = path.extension().unwrap_or_default().to_ascii_lowercase();
+
+        match ext.to_str().unwrap_or_default() {
+            "obj" => Ok(Self::Obj),
+            "ply" => Ok(Self::Ply),
+            "glb" => Ok(Self::Glb),
+            "stl" => Ok(Self::Stl),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Unsupported mesh format; use OBJ, PLY, GLB, or STL",
+            )),
+        }
+    }
+}
+
+/// A triangle mesh prepared for export.
+pub(crate) struct ExportMesh {
+    posits: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    /// RGBA, 0 to 1, sRGB.
+    colors: Vec<[f32; 4]>,
+    tris: Vec<[u32; 3]>,
+}
+
+impl ExportMesh {
+    /// `keep` is a per-vertex mask; triangles are kept if all of their vertices are.
+    pub(crate) fn new(mesh: &Mesh, default_color: [f32; 4], keep: Option<&[bool]>, scale: f32) -> Self {
+        let mut index_map = vec![u32::MAX; mesh.vertices.len()];
+        let mut result = Self {
+            posits: Vec::new(),
+            normals: Vec::new(),
+            colors: Vec::new(),
+            tris: Vec::new(),
+        };
+
+        for tri in mesh.indices.chunks_exact(3) {
+            if let Some(k) = keep
+                && !tri.iter().all(|&i| k[i])
+            {
+                continue;
+            }
+
+            let mut tri_out = [0; 3];
+            for (j, &i) in tri.iter().enumerate() {
+                if index_map[i] == u32::MAX {
+                    let v = &mesh.vertices[i];
+                    index_map[i] = result.posits.len() as u32;
+
+                    let p = v.position;
+                    result.posits.push(Vec3::new(p[0], p[1], p[2]) * scale);
+
+                    let n = v.normal;
+                    result.normals.push(if n.magnitude_squared() > 0. {
+                        n.to_normalized()
+                    } else {
+                        Vec3::new(0., 0., 1.)
+                    });
+
+                    result.colors.push(match v.color {
+                        Some((r, g, b, a)) => [
+                            r as f32 / 255.,
+                            g as f32 / 255.,
+                            b as f32 / 255.,
+                            a as f32 / 255.,
+                        ],
+                        None => default_color,
+                    });
+                }
+                tri_out[j] = index_map[i];
+            }
+            result.tris.push(tri_out);
+        }
+
+        result
+    }
+
+    fn tri_normal(&self, tri: &[u32; 3]) -> Vec3 {
+        let [a, b, c] = tri.map(|i| self.posits[i as usize]);
+        let n = (b - a).cross(c - a);
+        if n.magnitude_squared() > 0. {
+            n.to_normalized()
+        } else {
+            Vec3::new_zero()
+        }
+    }
+
+    /// Wavefront OBJ, with the common vertex-color extension (RGB after XYZ).
+    fn to_obj(&self) -> Vec<u8> {
+        let mut s = String::from("# Exported from Molchanica\n");
+
+        for (p, c) in self.posits.iter().zip(&self.colors) {
+            let _ = writeln!(
+                s,
+                "v {:.4} {:.4} {:.4} {:.4} {:.4} {:.4}",
+                p.x, p.y, p.z, c[0], c[1], c[2]
+            );
+        }
+        for n in &self.normals {
+            let _ = writeln!(s, "vn {:.4} {:.4} {:.4}", n.x, n.y, n.z);
+        }
+        for t in &self.tris {
+            let (a, b, c) = (t[0] + 1, t[1] + 1, t[2] + 1);
+            let _ = writeln!(s, "f {a}//{a} {b}//{b} {c}//{c}");
+        }
+
+        s.into_bytes()
+    }
+
+    /// Binary little-endian PLY, with normals and RGBA vertex colors.
+    pub(crate) fn to_ply(&self) -> Vec<u8> {
+        let header = format!(
+            "ply\nformat binary_little_endian 1.0\ncomment Exported from Molchanica\n\
+            element vertex {}\nproperty float x\nproperty float y\nproperty float z\n\
+            property float nx\nproperty float ny\nproperty float nz\n\
+            property uchar red\nproperty uchar green\nproperty uchar blue\nproperty uchar alpha\n\
+            element face {}\nproperty list uchar uint vertex_indices\nend_header\n",
+            self.posits.len(),
+            self.tris.len()
+        );
+
+        let mut result = header.into_bytes();
+        for i in 0..self.posits.len() {
+            let (p, n, c) = (self.posits[i], self.normals[i], self.colors[i]);
+            for v in [p.x, p.y, p.z, n.x, n.y, n.z] {
+                result.extend(v.to_le_bytes());
+            }
+            result.extend(c.map(|v| (v * 255.).round() as u8));
+        }
+        for t in &self.tris {
+            result.push(3);
+            for i in t {
+                result.extend(i.to_le_bytes());
+            }
+        }
+
+        result
+    }
+
+    /// Binary STL. This format doesn't support colors.
+    pub(crate) fn to_stl(&self) -> Vec<u8> {
+        let mut result = vec![0; 80];
+        let header = b"Exported from Molchanica";
+        result[..header.len()].copy_from_slice(header);
+
+        result.extend((self.tris.len() as u32).to_le_bytes());
+        for t in &self.tris {
+            let n = self.tri_normal(t);
+            let mut vals = vec![n.x, n.y, n.z];
+            for i in t {
+                let p = self.posits[*i as usize];
+                vals.extend([p.x, p.y, p.z]);
+            }
+            for v in vals {
+                result.extend(v.to_le_bytes());
+            }
+            result.extend(0_u16.to_le_bytes());
+        }
+
+        result
+    }
+
+    /// Binary glTF 2.0, with a single mesh and material.
+    pub(crate) fn to_glb(&self, name: &str) -> Vec<u8> {
+        const ARRAY_BUFFER: u32 = 34_962;
+        const ELEMENT_ARRAY_BUFFER: u32 = 34_963;
+        const FLOAT: u32 = 5_126;
+        const UNSIGNED_INT: u32 = 5_125;
+
+        let n = self.posits.len();
+
+        let mut bin = Vec::new();
+        for p in &self.posits {
+            for v in [p.x, p.y, p.z] {
+                bin.extend(v.to_le_bytes());
+            }
+        }
+        for nrm in &self.normals {
+            for v in [nrm.x, nrm.y, nrm.z] {
+                bin.extend(v.to_le_bytes());
+            }
+        }
+        // glTF vertex colors are linear.
+        for c in &self.colors {
+            for v in [
+                srgb_to_linear(c[0]),
+                srgb_to_linear(c[1]),
+                srgb_to_linear(c[2]),
+                c[3],
+            ] {
+                bin.extend(v.to_le_bytes());
+            }
+        }
+        for t in &self.tris {
+            for i in t {
+                bin.extend(i.to_le_bytes());
+            }
+        }
+
+        let (len_posit, len_color, len_indices) = (n * 12, n * 16, self.tris.len() * 12);
+
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for p in &self.posits {
+            for (j, v) in [p.x, p.y, p.z].into_iter().enumerate() {
+                min[j] = min[j].min(v);
+                max[j] = max[j].max(v);
+            }
+        }
+        if n == 0 {
+            (min, max) = ([0.; 3], [0.; 3]);
+        }
+
+        let translucent = self.colors.iter().any(|c| c[3] < 1.);
+        let alpha_mode = if translucent { "BLEND" } else { "OPAQUE" };
+
+        let json = format!(
+            r#"{{"asset":{{"version":"2.0","generator":"Molchanica"}},"scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0,"name":"{name}"}}],"meshes":[{{"name":"{name}","primitives":[{{"attributes":{{"POSITION":0,"NORMAL":1,"COLOR_0":2}},"indices":3,"material":0}}]}}],"materials":[{{"pbrMetallicRoughness":{{"baseColorFactor":[1,1,1,1],"metallicFactor":0,"roughnessFactor":0.6}},"alphaMode":"{alpha_mode}","doubleSided":true}}],"buffers":[{{"byteLength":{}}}],"bufferViews":[{{"buffer":0,"byteOffset":0,"byteLength":{len_posit},"target":{ARRAY_BUFFER}}},{{"buffer":0,"byteOffset":{},"byteLength":{len_posit},"target":{ARRAY_BUFFER}}},{{"buffer":0,"byteOffset":{},"byteLength":{len_color},"target":{ARRAY_BUFFER}}},{{"buffer":0,"byteOffset":{},"byteLength":{len_indices},"target":{ELEMENT_ARRAY_BUFFER}}}],"accessors":[{{"bufferView":0,"componentType":{FLOAT},"count":{n},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}},{{"bufferView":1,"componentType":{FLOAT},"count":{n},"type":"VEC3"}},{{"bufferView":2,"componentType":{FLOAT},"count":{n},"type":"VEC4"}},{{"bufferView":3,"componentType":{UNSIGNED_INT},"count":{},"type":"SCALAR"}}]}}"#,
+            bin.len(),
+            len_posit,
+            len_posit * 2,
+            len_posit * 2 + len_color,
+            min[0],
+            min[1],
+            min[2],
+            max[0],
+            max[1],
+            max[2],
+            self.tris.len() * 3,
+        );
+
+        // Chunks must be 4-byte aligned; JSON is padded with spaces, and binary with zeros.
+        let mut json = json.into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        while bin.len() % 4 != 0 {
+            bin.push(0);
+        }
+
+        let total_len = 12 + 8 + json.len() + 8 + bin.len();
+
+        let mut result = Vec::with_capacity(total_len);
+        result.extend(b"glTF");
+        result.extend(2_u32.to_le_bytes());
+        result.extend((total_len as u32).to_le_bytes());
+
+        result.extend((json.len() as u32).to_le_bytes());
+        result.extend(b"JSON");
+        result.extend(json);
+
+        result.extend((bin.len() as u32).to_le_bytes());
+        result.extend(b"BIN\0");
+        result.extend(bin);
+
+        result
+    }
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Peptide atom indices in the current selection.
+fn selected_peptide_atoms(state: &State) -> io::Result<Vec<usize>> {
+    let Some(mol) = &state.peptide else {
+        return Err(io::Error::new(ErrorKind::InvalidData, "No protein loaded"));
+    };
+
+    let result = match &state.ui.selection {
+        Selection::AtomPeptide(i) => vec![*i],
+        Selection::AtomsPeptide(v) => v.clone(),
+        Selection::Residue(i) => mol.residues[*i].atoms.clone(),
+        Selection::BondPeptide(i) => {
+            let bond = &mol.common.bonds[*i];
+            vec![bond.atom_0, bond.atom_1]
+        }
+        _ => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Selection-only mesh export requires a protein atom, residue, or bond selection",
+            ));
+        }
+    };
+
+    Ok(result)
+}
+
+/// For each vertex, whether the atom nearest to it is selected.
+fn vertices_near_sel(mesh: &Mesh, atom_posits: &[Vec3], selected: &[usize]) -> Vec<bool> {
+    let cell_key = |p: Vec3| {
+        (
+            (p.x / SEL_MAX_DIST).floor() as i32,
+            (p.y / SEL_MAX_DIST).floor() as i32,
+            (p.z / SEL_MAX_DIST).floor() as i32,
+        )
+    };
+
+    let mut grid: HashMap<(i32, i32, i32), Vec<usize>> = HashMap::new();
+    for (i, p) in atom_posits.iter().enumerate() {
+        grid.entry(cell_key(*p)).or_default().push(i);
+    }
+
+    let mut is_sel = vec![false; atom_posits.len()];
+    for &i in selected {
+        if i < is_sel.len() {
+            is_sel[i] = true;
+        }
+    }
+
+    mesh.vertices
+        .iter()
+        .map(|v| {
+            let vp = Vec3::new(v.position[0], v.position[1], v.position[2]);
+            let (cx, cy, cz) = cell_key(vp);
+
+            let mut nearest = None;
+            let mut nearest_dist = SEL_MAX_DIST * SEL_MAX_DIST;
+
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let Some(cands) = grid.get(&(cx + dx, cy + dy, cz + dz)) else {
+                            continue;
+                        };
+                        for &i in cands {
+                            let dist = (atom_posits[i] - vp).magnitude_squared();
+                            if dist < nearest_dist {
+                                nearest_dist = dist;
+                                nearest = Some(i);
+                            }
+                        }
+                    }
+                }
+            }
+
+            nearest.is_some_and(|i| is_sel[i])
+        })
+        .collect()
+}
+
+impl State {
+    /// Save a generated mesh to file. The format is determined by the path's extension.
+    /// `scale` converts from Å to output units, e.g. 0.1 for nm.
+    pub fn export_mesh(
+        &mut self,
+        path: &Path,
+        scene: &Scene,
+        source: MeshSource,
+        selection_only: bool,
+        scale: f32,
+    ) -> io::Result<()> {
+        let format = MeshFormat::from_path(path)?;
+
+        if !MeshSource::available(self).contains(&source) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("The {} mesh hasn't been generated", source.to_str()),
+            ));
+        }
+        let mesh = &scene.meshes[source.mesh_i()];
+
+        let keep = if selection_only {
+            let selected = selected_peptide_atoms(self)?;
+            // Checked above, in `available`.
+            let mol = self.peptide.as_ref().unwrap();
+            let atom_posits: Vec<Vec3> = mol.common.atoms.iter().map(|a| a.posit.into()).collect();
+
+            Some(vertices_near_sel(mesh, &atom_posits, &selected))
+        } else {
+            None
+        };
+
+        let export = ExportMesh::new(mesh, source.default_color(), keep.as_deref(), scale);
+        if export.tris.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "No mesh triangles to export",
+            ));
+        }
+
+        let data = match format {
+            MeshFormat::Obj => export.to_obj(),
+            MeshFormat::Ply => export.to_ply(),
+            MeshFormat::Glb => export.to_glb(source.to_str()),
+            MeshFormat::Stl => export.to_stl(),
+        };
+        fs::write(path, data)?;
+
+        handle_success(
+            &mut self.ui,
+            format!(
+                "Exported {} triangles to {}",
+                export.tris.len(),
+                path.file_name().unwrap_or_default().to_string_lossy()
+            ),
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+//! Writes proteins to mmCIF. We load mmCIF files using `bio_files`; this serializes the current state
+//! of a `MoleculePeptide`, so edits, added hydrogens, and MD-relaxed positions are preserved.
+
+use std::{fs, io, path::Path};
+
+use bio_files::{ResidueType, SecondaryStructure};
+
+use crate::{
+    file_io::pdb::{atom_name, atom_res_chain, res_name, ss_atom_ranges},
+    molecule::{Atom, MoleculePeptide},
+};
+
+/// Format a value for a CIF loop, quoting it if required.
+pub(super) fn cif_val(val: &str) -> String {
+    if val.is_empty() {
+        return "?".to_owned();
+    }
+
+    let needs_quote = val.contains(char::is_whitespace)
+        || val.starts_with(['_', '#', '$', '\'', '"', ';', '[', ']']);
+
+    if !needs_quote {
+        val.to_owned()
+    } else if val.contains('"') {
+        format!("'{val}'")
+    } else {
+        format!("\"{val}\"")
+    }
+}
+
+impl MoleculePeptide {
+    /// Serialize to mmCIF, with positions from `atom_posits`. Includes secondary structure
+    /// if present.
+    pub fn to_mmcif(&self) -> String {
+        let ident = if self.common.ident.is_empty() {
+            "molecule".to_owned()
+        } else {
+            self.common.ident.replace(char::is_whitespace, "_")
+        };
+
+        let mut result = format!("data_{ident}\n#\n_entry.id {}\n#\n", cif_val(&ident));
+
+        if let Some(title) = self.common.metadata.get("title") {
+            result.push_str(&format!("_struct.title {}\n#\n", cif_val(title)));
+        }
+
+        // Each chain is its own entity; we don't attempt to deduplicate identical sequences.
+        let entity_id = |chain_id: &str| match self.chains.iter().position(|c| c.id == chain_id) {
+            Some(i) => i + 1,
+            None => 1,
+        };
+
+        // Label sequence IDs number the polymer residues of each entity from 1. Per convention,
+        // non-polymer residues have none.
+        let mut label_seq_ids = vec![None; self.residues.len()];
+        for chain in &self.chains {
+            let mut seq_id = 0;
+            for &res_i in &chain.residues {
+                if matches!(self.residues[res_i].res_type, ResidueType::AminoAcid(_)) {
+                    seq_id += 1;
+                    label_seq_ids[res_i] = Some(seq_id);
+                }
+            }
+        }
+        let label_seq_id = |atom: &Atom| match atom.residue.and_then(|i| label_seq_ids[i]) {
+            Some(id) => id.to_string(),
+            None => ".".to_owned(),
+        };
+
+        let ss = ss_atom_ranges(self);
+
+        let helices: Vec<_> = ss
+            .iter()
+            .filter(|(_, _, s)| matches!(s, SecondaryStructure::Helix))
+            .collect();
+        let strands: Vec<_> = ss
+            .iter()
+            .filter(|(_, _, s)| matches!(s, SecondaryStructure::Sheet))
+            .collect();
+
+        // Residue name, chain, and label and author sequence numbers at each end of a secondary
+        // structure range.
+        let ends = |start: usize, end: usize| {
+            let (atom_0, atom_1) = (&self.common.atoms[start], &self.common.atoms[end]);
+            let (res_0, chain_0) = atom_res_chain(self, atom_0);
+            let (res_1, chain_1) = atom_res_chain(self, atom_1);
+            let (res_0, res_1) = (res_0?, res_1?);
+
+            Some((
+                (cif_val(&res_name(&res_0.res_type)), chain_0),
+                (label_seq_id(atom_0), res_0.seq_num),
+                (cif_val(&res_name(&res_1.res_type)), chain_1),
+                (label_seq_id(atom_1), res_1.seq_num),
+            ))
+        };
+
+        if !helices.is_empty() {
+            result.push_str(
+                "loop_\n_struct_conf.conf_type_id\n_struct_conf.id\n\
+                 _struct_conf.beg_label_comp_id\n_struct_conf.beg_label_asym_id\n_struct_conf.beg_label_seq_id\n\
+                 _struct_conf.end_label_comp_id\n_struct_conf.end_label_asym_id\n_struct_conf.end_label_seq_id\n\
+                 _struct_conf.beg_auth_comp_id\n_struct_conf.beg_auth_asym_id\n_struct_conf.beg_auth_seq_id\n\
+                 _struct_conf.end_auth_comp_id\n_struct_conf.end_auth_asym_id\n_struct_conf.end_auth_seq_id\n",
+            );
+
+            for (i, (start, end, _)) in helices.iter().enumerate() {
+                let Some(((name_0, chain_0), (seq_0, sn_0), (name_1, chain_1), (seq_1, sn_1))) =
+                    ends(*start, *end)
+                else {
+                    continue;
+                };
+
+                result.push_str(&format!(
+                    "HELX_P HELX_P{} {name_0} {chain_0} {seq_0} {name_1} {chain_1} {seq_1} {name_0} {chain_0} {sn_0} {name_1} {chain_1} {sn_1}\n",
+                    i + 1
+                ));
+            }
+            result.push_str("#\n");
+        }
+
+        if !strands.is_empty() {
+            result.push_str(
+                "loop_\n_struct_sheet_range.sheet_id\n_struct_sheet_range.id\n\
+                 _struct_sheet_range.beg_label_comp_id\n_struct_sheet_range.beg_label_asym_id\n_struct_sheet_range.beg_label_seq_id\n\
+                 _struct_sheet_range.end_label_comp_id\n_struct_sheet_range.end_label_asym_id\n_struct_sheet_range.end_label_seq_id\n\
+                 _struct_sheet_range.beg_auth_comp_id\n_struct_sheet_range.beg_auth_asym_id\n_struct_sheet_range.beg_auth_seq_id\n\
+                 _struct_sheet_range.end_auth_comp_id\n_struct_sheet_range.end_auth_asym_id\n_struct_sheet_range.end_auth_seq_id\n",
+            );
+
+            for (i, (start, end, _)) in strands.iter().enumerate() {
+                let Some(((name_0, chain_0), (seq_0, sn_0), (name_1, chain_1), (seq_1, sn_1))) =
+                    ends(*start, *end)
+                else {
+                    continue;
+                };
+
+                // We don't track strand pairing, so each strand is listed as its own sheet.
+                result.push_str(&format!(
+                    "S{0} 1 {name_0} {chain_0} {seq_0} {name_1} {chain_1} {seq_1} {name_0} {chain_0} {sn_0} {name_1} {chain_1} {sn_1}\n",
+                    i + 1
+                ));
+            }
+            result.push_str("#\n");
+        }
+
+        result.push_str(
+            "loop_\n_atom_site.group_PDB\n_atom_site.id\n_atom_site.type_symbol\n\
+             _atom_site.label_atom_id\n_atom_site.label_alt_id\n_atom_site.label_comp_id\n\
+             _atom_site.label_asym_id\n_atom_site.label_entity_id\n_atom_site.label_seq_id\n\
+             _atom_site.pdbx_PDB_ins_code\n_atom_site.Cartn_x\n_atom_site.Cartn_y\n_atom_site.Cartn_z\n\
+             _atom_site.occupancy\n_atom_site.B_iso_or_equiv\n_atom_site.pdbx_formal_charge\n\
+             _atom_site.auth_seq_id\n_atom_site.auth_comp_id\n_atom_site.auth_asym_id\n\
+             _atom_site.auth_atom_id\n_atom_site.pdbx_PDB_model_num\n",
+        );
+
+        for (i, atom) in self.common.atoms.iter().enumerate() {
+            let (res, chain_id) = atom_res_chain(self, atom);
+            let (comp_id, res_sn, ins_code) = match res {
+                Some(r) => (
+                    cif_val(&res_name(&r.res_type)),
+                    r.seq_num.to_string(),
+                    match r.ins_code {
+                        Some(ic) => ic.to_string(),
+                        None => "?".to_owned(),
+                    },
+                ),
+                None => ("UNK".to_owned(), ".".to_owned(), "?".to_owned()),
+            };
+            let label_seq = label_seq_id(atom);
+
+            let group = if atom.hetero { "HETATM" } else { "ATOM" };
+            let name = cif_val(&atom_name(atom));
+            let alt = atom.alt_conformation_id.as_deref().unwrap_or(".");
+            let posit = self.common.atom_posits.get(i).unwrap_or(&atom.posit);
+
+            result.push_str(&format!(
+                "{group} {} {} {name} {alt} {comp_id} {chain_id} {} {label_seq} {ins_code} {:.3} {:.3} {:.3} {:.2} {:.2} ? {res_sn} {comp_id} {chain_id} {name} 1\n",
+                atom.serial_number,
+                atom.element.to_letter().to_uppercase(),
+                entity_id(chain_id),
+                posit.x,
+                posit.y,
+                posit.z,
+                atom.occupancy.unwrap_or(1.),
+                atom.b_factor.unwrap_or_default(),
+            ));
+        }
+        result.push_str("#\n");
+
+        result
+    }
+
+    pub fn save_mmcif(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_mmcif())
+    }
+}
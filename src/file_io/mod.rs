@@ -1,8 +1,16 @@
-use std::{fs, io, io::ErrorKind, path::Path, sync::mpsc, thread, time::Instant};
+pub mod amber;
+pub mod gromacs;
+pub mod mesh_export;
+mod mmcif;
+pub mod pdb;
+pub mod session;
+pub mod topology;
+pub mod trajectory;
+
+use std::{fs, io, io::ErrorKind, path::Path, time::Instant};
 
-use bio_apis::pubchem;
 use bio_files::{
-    DensityMap, MmCif, Mol2, Pdbqt, Xyz, cif_sf::CifStructureFactors, gemmi_sf_to_map,
+    DensityMap, MmCif, Mol2, Xyz, cif_sf::CifStructureFactors, gemmi_sf_to_map,
     md_params::ForceFieldParams, sdf::Sdf,
 };
 use chrono::Utc;
@@ -15,15 +23,27 @@ use rand::Rng;
 use crate::{
     Selection, State,
     cam_misc::move_mol_to_cam,
+    docking::external,
     download_mols,
     drawing::draw_peptide,
     drawing_wrappers,
+    file_io::{
+        amber::{self, Prmtop},
+        gromacs::{GmxTop, Gro},
+        pdb::Pdb,
+        topology::SystemTopology,
+    },
+    mol_characterization::Descriptors,
     mol_lig::MoleculeSmall,
+    mol_library,
     molecule::{
-        MolGenericTrait, MolIdent, MolType, MoleculeCommon, MoleculeGeneric, MoleculePeptide,
+        MolGenericRef, MolGenericTrait, MolIdent, MolType, MoleculeCommon, MoleculeGeneric,
+        MoleculePeptide,
     },
     prefs::{OpenHistory, OpenType},
     reflection::{DENSITY_CELL_MARGIN, DENSITY_MAX_DIST, DensityPt, DensityRect},
+    selfies::smiles_to_selfies,
+    stereo::SdfStereo,
     util::{handle_err, handle_success},
 };
 
@@ -50,14 +70,16 @@ impl State {
             "sdf" | "mol2" | "xyz" | "pdbqt" | "pdb" | "cif" | "xyz" => {
                 self.open_mol_from_file(path, scene, engine_updates)?
             }
-            "prmtop" => {
-                // todo
-            }
+            "prmtop" => self.open_prmtop(path, scene, engine_updates)?,
+            "gro" | "top" => self.open_gromacs(path, scene, engine_updates)?,
             "map" => self.open_map(path)?,
             "mtz" => self.open_mtz(path)?,
             // todo: lib, .dat etc as required. Using Amber force fields and its format
             // todo to start. We assume it'll be generalizable later.
             "frcmod" | "dat" => self.open_force_field(path)?,
+            "mcs" => self.open_session(path, scene, engine_updates)?,
+            // SMILES files are always treated as libraries.
+            "smi" | "smiles" => self.open_library(path)?,
             _ => {
                 return Err(io::Error::new(
                     ErrorKind::InvalidData,
@@ -90,10 +112,34 @@ impl State {
         let binding = path.extension().unwrap_or_default().to_ascii_lowercase();
         let extension = binding;
 
+        // Multi-record files, e.g. vendor libraries, go to the library panel instead of the scene.
+        // Multi-record SDF output from docking programs is loaded as one ligand with its poses.
+        if extension == "sdf" || extension == "mol2" {
+            let text = fs::read_to_string(path)?;
+            if mol_library::num_records(&text, extension == "mol2") > 1 {
+                if extension == "sdf"
+                    && let Some(mut m) = external::load_sdf_poses(&text)?
+                {
+                    m.common.path = Some(path.to_owned());
+                    self.load_mol_to_state(
+                        MoleculeGeneric::Ligand(m),
+                        scene,
+                        engine_updates,
+                        Some(path),
+                    );
+                    return Ok(());
+                }
+                return self.open_library(path);
+            }
+        }
+
         let molecule = match extension.to_str().unwrap() {
             "sdf" => {
                 let mut m: MoleculeSmall = Sdf::load(path)?.try_into()?;
                 m.common.path = Some(path.to_owned());
+                // `bio_files` doesn't keep wedge bonds and parities; flat structures need them.
+                m.common
+                    .apply_sdf_stereo(&SdfStereo::from_text(&fs::read_to_string(path)?));
                 Ok(MoleculeGeneric::Ligand(m))
             }
             "mol2" => {
@@ -106,7 +152,8 @@ impl State {
                 Ok(MoleculeGeneric::Ligand(m))
             }
             "pdbqt" => {
-                let mut m: MoleculeSmall = Pdbqt::load(path)?.try_into()?;
+                // Handles multi-model docking output, e.g. from Vina.
+                let mut m = external::load_pdbqt(&fs::read_to_string(path)?)?;
                 m.common.path = Some(path.to_owned());
                 Ok(MoleculeGeneric::Ligand(m))
             }
@@ -159,6 +206,40 @@ impl State {
 
                 Ok(MoleculeGeneric::Peptide(mol))
             }
+            "pdb" => {
+                let data_str = fs::read_to_string(path)?;
+                let mut pdb_data = Pdb::new(&data_str)?;
+
+                if pdb_data.ident.is_empty() {
+                    pdb_data.ident = path
+                        .file_stem()
+                        .and_then(|s| s.to_str())
+                        .unwrap_or_default()
+                        .to_owned();
+                }
+
+                if pdb_data.is_ligand_only() {
+                    let m = MoleculeSmall::from_pdb(&pdb_data, Some(path.to_owned()))?;
+                    Ok(MoleculeGeneric::Ligand(m))
+                } else {
+                    let Some(ff_map) = &self.ff_param_set.peptide_ff_q_map else {
+                        return Err(io::Error::new(
+                            ErrorKind::Other,
+                            "Missing FF map when opening a protein; can't validate H",
+                        ));
+                    };
+
+                    let mol = MoleculePeptide::from_pdb(
+                        &pdb_data,
+                        ff_map,
+                        Some(path.to_owned()),
+                        self.to_save.ph,
+                    )?;
+                    self.cif_pdb_raw = Some(data_str);
+
+                    Ok(MoleculeGeneric::Peptide(mol))
+                }
+            }
             _ => Err(io::Error::new(
                 ErrorKind::InvalidData,
                 "Invalid file extension",
@@ -250,6 +331,98 @@ impl State {
         Ok(())
     }
 
+    /// Open an Amber topology, with coordinates from an inpcrd or rst7 file of the same name.
+    pub fn open_prmtop(
+        &mut self,
+        path: &Path,
+        scene: Option<&mut Scene>,
+        engine_updates: &mut EngineUpdates,
+    ) -> io::Result<()> {
+        let top = Prmtop::load(path)?;
+
+        let Some(coord_path) = amber::find_coord_file(path) else {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                "No inpcrd or rst7 file found with the same name as the prmtop file",
+            ));
+        };
+
+        let n_atoms = top.strs("ATOM_NAME")?.len();
+        let posits = amber::load_inpcrd(&fs::read_to_string(coord_path)?, n_atoms)?;
+
+        let system = SystemTopology::from_prmtop(&top, &posits)?;
+        self.load_system(&system, path, scene, engine_updates)
+    }
+
+    /// Open a GROMACS system from a gro and top file with the same name. Either can be selected.
+    /// Included itp files are loaded if they're present locally.
+    pub fn open_gromacs(
+        &mut self,
+        path: &Path,
+        scene: Option<&mut Scene>,
+        engine_updates: &mut EngineUpdates,
+    ) -> io::Result<()> {
+        let (gro_path, top_path) = (path.with_extension("gro"), path.with_extension("top"));
+
+        if !gro_path.exists() || !top_path.exists() {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                "GROMACS import requires gro and top files with the same name",
+            ));
+        }
+
+        let gro = Gro::load(&gro_path)?;
+        let top = GmxTop::load(&top_path)?;
+
+        let system = SystemTopology::from_gromacs(&gro, &top)?;
+        self.load_system(&system, &gro_path, scene, engine_updates)
+    }
+
+    /// Load molecules from an imported MD system, e.g. from Amber or GROMACS files. FF types
+    /// and partial charges are taken from the topology. Water is skipped; we add our own during MD.
+    fn load_system(
+        &mut self,
+        system: &SystemTopology,
+        path: &Path,
+        mut scene: Option<&mut Scene>,
+        engine_updates: &mut EngineUpdates,
+    ) -> io::Result<()> {
+        let ident = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        let mut open_type = OpenType::Ligand;
+
+        for mol in system.to_mols(&ident)? {
+            match mol {
+                MoleculeGeneric::Ligand(mut mol) => {
+                    // We skip `load_mol_to_state` here, so as not to move ligands relative to
+                    // the rest of the system.
+                    if let Some(p) = &self.ff_param_set.small_mol {
+                        mol.update_ff_related(&mut self.mol_specific_params, p);
+                    }
+                    self.ligands.push(mol);
+                }
+                m => {
+                    open_type = OpenType::Peptide;
+                    self.load_mol_to_state(m, scene.as_deref_mut(), engine_updates, Some(path));
+                }
+            }
+        }
+
+        self.mol_dynamics = None;
+
+        if let Some(s) = scene {
+            drawing_wrappers::draw_all_ligs(self, s);
+        }
+
+        self.update_history(path, open_type);
+
+        Ok(())
+    }
+
     /// Open Amber force field parameters, e.g. dat and frcmod.
     pub fn open_force_field(&mut self, path: &Path) -> io::Result<()> {
         let binding = path.extension().unwrap_or_default().to_ascii_lowercase();
@@ -346,40 +519,36 @@ impl State {
         let extension = binding;
 
         match extension.to_str().unwrap_or_default() {
-            "pdb" | "cif" => {
-                // todo: Eval how you want to handle this. For now, the raw CIF or PDB.
-                // if let Some(pdb) = &mut self.pdb {
-                //     save_pdb(pdb, path)?;
-                //     self.to_save.last_opened = Some(path.to_owned());
-                //     self.update_save_prefs()
-                // }
-                // We don't allow editing the protein files yet, so save the raw CIF.
-                if let Some(data) = &mut self.cif_pdb_raw {
-                    fs::write(path, data)?;
-
-                    let ident = match &self.peptide {
-                        Some(mol) => mol.common.ident.clone(),
-                        None => String::new(),
-                    };
+            "pdb" | "cif" => match &self.peptide {
+                Some(mol) => {
+                    // Serialize the current state, vice the file as loaded; this includes
+                    // hydrogens we've added, MD-relaxed positions etc.
+                    if extension == "pdb" {
+                        mol.save_pdb(path)?;
+                    } else {
+                        mol.save_mmcif(path)?;
+                    }
 
-                    // self.to_save.last_peptide_opened = Some(path.to_owned());
-                    // self.update_history(path, OpenType::Peptide, &ident);
                     self.update_history(path, OpenType::Peptide);
 
                     // Save the open history.
                     self.update_save_prefs(false);
                 }
-            }
+                None => return Err(io::Error::new(ErrorKind::InvalidData, "No protein to save")),
+            },
             "sdf" => match self.active_mol() {
-                Some(lig) => {
-                    lig.to_sdf().save(path)?;
+                Some(MolGenericRef::Ligand(lig)) => {
+                    // Includes stereo parities, charges, and isotopes.
+                    let mut text = String::new();
+                    mol_library::sdf_record(lig, &mut text)?;
+                    fs::write(path, text)?;
 
                     self.update_history(path, OpenType::Ligand);
 
                     // Save the open history.
                     self.update_save_prefs(false);
                 }
-                None => return Err(io::Error::new(ErrorKind::InvalidData, "No ligand to save")),
+                _ => return Err(io::Error::new(ErrorKind::InvalidData, "No ligand to save")),
             },
             "mol2" => match self.active_mol() {
                 Some(lig) => {
@@ -401,7 +570,14 @@ impl State {
                 }
                 None => return Err(io::Error::new(ErrorKind::InvalidData, "No ligand to save")),
             },
-            "prmtop" => (), // todo
+            "prmtop" => {
+                SystemTopology::from_state(self)?.save_amber(path)?;
+                self.update_save_prefs(false);
+            }
+            "gro" | "top" => {
+                SystemTopology::from_state(self)?.save_gromacs(path)?;
+                self.update_save_prefs(false);
+            }
             "pdbqt" => match self.active_mol() {
                 Some(lig) => {
                     lig.to_pdbqt().save(path)?;
@@ -607,12 +783,37 @@ impl State {
                 }
             }
             MoleculeGeneric::Ligand(mut mol) => {
-                if let Some(ref mut s) = scene {
+                // Docked poses are positioned relative to the receptor, and their atoms must
+                // match the imported poses; keep the file's positions and protonation state.
+                let docked = !mol.ext_poses.is_empty();
+
+                if !docked && let Some(ref mut s) = scene {
                     move_mol_to_cam(&mut mol.common_mut(), &s.camera);
                 }
 
                 self.mol_dynamics = None;
 
+                // If enabled, molecules without parameters from the file get the dominant
+                // protonation state at our pH before assigning them; keep the file's state for
+                // parameterized ones.
+                if self.ui.protonate_on_open
+                    && !docked
+                    && mol
+                        .common
+                        .atoms
+                        .iter()
+                        .any(|a| a.force_field_type.is_none() || a.partial_charge.is_none())
+                    && mol.protonate(self.to_save.ph)
+                {
+                    handle_success(
+                        &mut self.ui,
+                        format!(
+                            "Set {} to its dominant protonation state at pH {}",
+                            mol.common.ident, self.to_save.ph
+                        ),
+                    );
+                }
+
                 if let Some(p) = &self.ff_param_set.small_mol {
                     mol.update_ff_related(&mut self.mol_specific_params, p);
 
@@ -625,7 +826,7 @@ impl State {
                     )
                 }
 
-                if let Some(ref mut s) = scene {
+                if !docked && let Some(ref mut s) = scene {
                     let centroid = mol.common.centroid();
                     // If there is already a molecule here, offset.
                     // todo: Apply this logic to other mol types A/R
@@ -645,31 +846,16 @@ impl State {
                     }
                 }
 
+                // We generate canonical SMILES locally, and cache them by identifier.
+                let smiles = mol.common.to_smiles();
                 for ident in &mol.idents {
-                    // todo: Should we use the pubchem ID? Be flexible? Check both?
-                    if !matches!(ident, MolIdent::PdbeAmber(_)) {
-                        continue;
-                    }
-
-                    match self.to_save.smiles_map.get(&ident) {
-                        Some(v) => {
-                            println!("Loaded smiles for {ident:?} from our local DB: {v}");
-                            mol.smiles = Some(v.clone());
-                            break;
-                        }
-                        None => {
-                            let (tx, rx) = mpsc::channel(); // one-shot channel
-                            let ident_for_thread = ident.clone();
-
-                            thread::spawn(move || {
-                                let data = pubchem::get_smiles(&ident_for_thread.to_str());
-                                let _ = tx.send((ident_for_thread, data));
-                            });
-
-                            self.volatile.smiles_pending_data_avail = Some(rx);
-                        }
+                    if matches!(ident, MolIdent::PdbeAmber(_)) {
+                        self.to_save.smiles_map.insert(ident.clone(), smiles.clone());
                     }
                 }
+                mol.selfies = smiles_to_selfies(&smiles).ok();
+                mol.smiles = Some(smiles);
+                mol.descriptors = Some(Descriptors::new(&mol.common));
 
                 centroid = mol.common.centroid();
                 ident = mol.common.ident.clone();
@@ -0,0 +1,825 @@
+//! Loads legacy PDB-format coordinate files. We convert these into the same generic structures
+//! mmCIF files load into, so proteins go through the same pipeline. (Hydrogens, FF types, partial charges
+//! etc.)
+//!
+//! [Format reference](https://www.wwpdb.org/documentation/file-format-content/format33/v3.3.html)
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use bio_files::{
+    AtomGeneric, BackboneSS, BondGeneric, BondType, ChainGeneric, MmCif, ResidueEnd,
+    ResidueGeneric, ResidueType, SecondaryStructure, create_bonds,
+};
+use dynamics::params::ProtFfChargeMapSet;
+use lin_alg::f64::Vec3;
+use na_seq::{AaIdent, AminoAcid, AtomTypeInRes, Element};
+
+use crate::{
+    mol_lig::MoleculeSmall,
+    molecule::{Atom, Bond, MoleculePeptide, Residue},
+};
+
+/// Data parsed from a PDB file. Only the first model is kept for multi-model files (e.g. NMR
+/// ensembles); the number of models is recorded in metadata.
+#[derive(Clone, Debug, Default)]
+pub struct Pdb {
+    pub ident: String,
+    pub metadata: HashMap<String, String>,
+    pub atoms: Vec<AtomGeneric>,
+    /// From CONECT records. Repeated entries are interpreted as higher bond orders, per the
+    /// convention some tools use.
+    pub bonds: Vec<BondGeneric>,
+    pub chains: Vec<ChainGeneric>,
+    pub residues: Vec<ResidueGeneric>,
+    pub secondary_structure: Vec<BackboneSS>,
+    /// Chain ID, and the residue names listed in its SEQRES records.
+    pub seq_res: Vec<(String, Vec<String>)>,
+    /// Per-atom values not stored by `AtomGeneric`. Keyed by serial number.
+    pub b_factors: HashMap<u32, f32>,
+    pub occupancies: HashMap<u32, f32>,
+    /// Per-residue values not stored by `ResidueGeneric`: The sequence number, which may be
+    /// negative, and the insertion code. Keyed by residue serial number.
+    pub res_seq: HashMap<u32, (i32, Option<char>)>,
+    pub num_models: usize,
+}
+
+/// Extract a fixed-width column range. `start` and `end` are 1-indexed and inclusive, as in the
+/// format spec. Returns an empty string for short lines.
+fn col(line: &str, start: usize, end: usize) -> &str {
+    let len = line.len();
+    if start > len {
+        return "";
+    }
+    line.get(start - 1..end.min(len)).unwrap_or("").trim()
+}
+
+fn parse_f64(line: &str, start: usize, end: usize) -> io::Result<f64> {
+    col(line, start, end).parse().map_err(|_| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid number in PDB line: {line}"),
+        )
+    })
+}
+
+/// Infer the element from the atom name, for files with an empty element column. Per the spec, the
+/// element symbol is right-justified in columns 13-14 of the name field.
+fn element_from_name(name_field: &str) -> io::Result<Element> {
+    let two: String = name_field
+        .chars()
+        .take(2)
+        .filter(|c| c.is_ascii_alphabetic())
+        .collect();
+
+    if name_field.starts_with(' ') || two.len() < 2 {
+        let first: String = name_field
+            .chars()
+            .filter(|c| c.is_ascii_alphabetic())
+            .take(1)
+            .collect();
+        return Element::from_letter(&first);
+    }
+
+    Element::from_letter(&two).or_else(|_| Element::from_letter(&two[..1]))
+}
+
+fn res_type_from_name(name: &str, hetero: bool) -> ResidueType {
+    match name {
+        "HOH" | "WAT" | "DOD" | "H2O" | "SOL" => ResidueType::Water,
+        _ => {
+            if !hetero && let Ok(aa) = AminoAcid::from_str(name) {
+                ResidueType::AminoAcid(aa)
+            } else {
+                ResidueType::Other(name.to_owned())
+            }
+        }
+    }
+}
+
+/// Residues are identified by chain, sequence number and insertion code.
+type ResKey = (String, i32, String);
+
+/// Nucleotide residue names, of RNA and DNA.
+const NUCLEOTIDE_NAMES: [&str; 10] = ["A", "C", "G", "U", "I", "DA", "DC", "DG", "DT", "DI"];
+
+impl Pdb {
+    pub fn new(text: &str) -> io::Result<Self> {
+        let mut result = Self::default();
+
+        // Index into `result.residues`, and the residue's chain.
+        let mut res_map: HashMap<ResKey, usize> = HashMap::new();
+        let mut res_keys: Vec<ResKey> = Vec::new();
+        let mut chain_map: HashMap<String, usize> = HashMap::new();
+
+        // Raw CONECT entries, (from, to), including duplicates.
+        let mut conect: Vec<(u32, u32)> = Vec::new();
+        // (start, end, type) residue keys for HELIX and SHEET records.
+        let mut ss_records: Vec<(ResKey, ResKey, SecondaryStructure)> = Vec::new();
+
+        let mut past_first_model = false;
+        let mut title = String::new();
+
+        for line in text.lines() {
+            let record = col(line, 1, 6);
+
+            match record {
+                "HEADER" => {
+                    result.ident = col(line, 63, 66).to_owned();
+                    let classification = col(line, 11, 50);
+                    if !classification.is_empty() {
+                        result
+                            .metadata
+                            .insert("classification".to_owned(), classification.to_owned());
+                    }
+                }
+                "TITLE" => {
+                    if !title.is_empty() {
+                        title.push(' ');
+                    }
+                    title.push_str(col(line, 11, 80));
+                }
+                "EXPDTA" => {
+                    result
+                        .metadata
+                        .insert("experimental_method".to_owned(), col(line, 11, 79).to_owned());
+                }
+                "SEQRES" => {
+                    let chain_id = col(line, 12, 12).to_owned();
+                    let names = col(line, 20, 80)
+                        .split_whitespace()
+                        .map(|s| s.to_owned());
+
+                    match result.seq_res.iter_mut().find(|(c, _)| *c == chain_id) {
+                        Some((_, seq)) => seq.extend(names),
+                        None => result.seq_res.push((chain_id, names.collect())),
+                    }
+                }
+                "HELIX" => {
+                    let start = (
+                        col(line, 20, 20).to_owned(),
+                        col(line, 22, 25).parse().unwrap_or_default(),
+                        col(line, 26, 26).to_owned(),
+                    );
+                    let end = (
+                        col(line, 32, 32).to_owned(),
+                        col(line, 34, 37).parse().unwrap_or_default(),
+                        col(line, 38, 38).to_owned(),
+                    );
+                    ss_records.push((start, end, SecondaryStructure::Helix));
+                }
+                "SHEET" => {
+                    let start = (
+                        col(line, 22, 22).to_owned(),
+                        col(line, 23, 26).parse().unwrap_or_default(),
+                        col(line, 27, 27).to_owned(),
+                    );
+                    let end = (
+                        col(line, 33, 33).to_owned(),
+                        col(line, 34, 37).parse().unwrap_or_default(),
+                        col(line, 38, 38).to_owned(),
+                    );
+                    ss_records.push((start, end, SecondaryStructure::Sheet));
+                }
+                "MODEL" => {
+                    result.num_models += 1;
+                    if result.num_models > 1 {
+                        past_first_model = true;
+                    }
+                }
+                "ATOM" | "HETATM" => {
+                    if past_first_model {
+                        continue;
+                    }
+
+                    let hetero = record == "HETATM";
+
+                    // Files with more than 99,999 atoms sometimes use hexadecimal, or overflow
+                    // characters here. Fall back to sequential numbering in that case.
+                    let serial_number = col(line, 7, 11).parse::<u32>().unwrap_or_else(|_| {
+                        result.atoms.last().map(|a| a.serial_number + 1).unwrap_or(1)
+                    });
+
+                    let name = col(line, 13, 16);
+                    let alt_loc = col(line, 17, 17);
+                    let res_name = col(line, 18, 20);
+                    let chain_id = col(line, 22, 22).to_owned();
+                    let res_seq: i32 = col(line, 23, 26).parse().unwrap_or_default();
+                    let i_code = col(line, 27, 27).to_owned();
+
+                    let posit = Vec3::new(
+                        parse_f64(line, 31, 38)?,
+                        parse_f64(line, 39, 46)?,
+                        parse_f64(line, 47, 54)?,
+                    );
+
+                    if let Ok(occ) = col(line, 55, 60).parse::<f32>() {
+                        result.occupancies.insert(serial_number, occ);
+                    }
+                    if let Ok(b) = col(line, 61, 66).parse::<f32>() {
+                        result.b_factors.insert(serial_number, b);
+                    }
+
+                    let element_col = col(line, 77, 78);
+                    let element = if element_col.is_empty() {
+                        element_from_name(line.get(12..16).unwrap_or(name))?
+                    } else {
+                        // E.g. "FE" -> "Fe".
+                        let mut el = element_col.to_ascii_lowercase();
+                        el[..1].make_ascii_uppercase();
+                        Element::from_letter(&el)?
+                    };
+
+                    let res_type = res_type_from_name(res_name, hetero);
+
+                    let (type_in_res, type_in_res_general) = match &res_type {
+                        ResidueType::AminoAcid(_) => match AtomTypeInRes::from_str(name) {
+                            Ok(tir) => (Some(tir), None),
+                            Err(_) => (None, Some(name.to_owned())),
+                        },
+                        _ => (None, Some(name.to_owned())),
+                    };
+
+                    result.atoms.push(AtomGeneric {
+                        serial_number,
+                        posit,
+                        element,
+                        type_in_res,
+                        type_in_res_general,
+                        hetero,
+                        alt_conformation_id: if alt_loc.is_empty() {
+                            None
+                        } else {
+                            Some(alt_loc.to_owned())
+                        },
+                        ..Default::default()
+                    });
+
+                    let chain_i = match chain_map.get(&chain_id) {
+                        Some(i) => *i,
+                        None => {
+                            result.chains.push(ChainGeneric {
+                                id: chain_id.clone(),
+                                residue_sns: Vec::new(),
+                                atom_sns: Vec::new(),
+                            });
+                            chain_map.insert(chain_id.clone(), result.chains.len() - 1);
+                            result.chains.len() - 1
+                        }
+                    };
+                    result.chains[chain_i].atom_sns.push(serial_number);
+
+                    let key = (chain_id, res_seq, i_code);
+                    let res_i = match res_map.get(&key) {
+                        Some(i) => *i,
+                        None => {
+                            // Sequence numbers aren't unique with insertion codes, and may be
+                            // negative, so we number residues in file order, and keep the
+                            // sequence number separately.
+                            let res_sn = result.residues.len() as u32 + 1;
+                            result
+                                .res_seq
+                                .insert(res_sn, (res_seq, key.2.chars().next()));
+
+                            result.residues.push(ResidueGeneric {
+                                serial_number: res_sn,
+                                res_type,
+                                atom_sns: Vec::new(),
+                                end: ResidueEnd::Internal,
+                            });
+                            result.chains[chain_i].residue_sns.push(res_sn);
+
+                            res_map.insert(key.clone(), result.residues.len() - 1);
+                            res_keys.push(key);
+                            result.residues.len() - 1
+                        }
+                    };
+                    result.residues[res_i].atom_sns.push(serial_number);
+                }
+                "CONECT" => {
+                    let Ok(from) = col(line, 7, 11).parse::<u32>() else {
+                        continue;
+                    };
+                    for (start, end) in [(12, 16), (17, 21), (22, 26), (27, 31)] {
+                        if let Ok(to) = col(line, start, end).parse::<u32>() {
+                            conect.push((from, to));
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if result.num_models == 0 {
+            result.num_models = 1;
+        }
+
+        if result.atoms.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "No atoms found in PDB file",
+            ));
+        }
+
+        if !title.is_empty() {
+            result.metadata.insert("title".to_owned(), title);
+        }
+        result
+            .metadata
+            .insert("num_models".to_owned(), result.num_models.to_string());
+
+        for (chain_id, seq) in &result.seq_res {
+            result
+                .metadata
+                .insert(format!("seqres_{chain_id}"), seq.join(" "));
+        }
+
+        result.bonds = bonds_from_conect(&conect);
+        result.set_residue_ends(&res_keys);
+        result.secondary_structure = result.make_ss(&ss_records, &res_map);
+
+        Ok(result)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data_str = fs::read_to_string(path)?;
+        Self::new(&data_str)
+    }
+
+    /// Files without amino acid or nucleotide residues; e.g. a single ligand exported from a
+    /// docking program, or extracted from a complex. These often use ATOM records.
+    pub fn is_ligand_only(&self) -> bool {
+        !self.residues.iter().any(|r| match &r.res_type {
+            ResidueType::AminoAcid(_) => true,
+            ResidueType::Other(name) => NUCLEOTIDE_NAMES.contains(&name.as_str()),
+            ResidueType::Water => false,
+        })
+    }
+
+    pub fn has_hydrogens(&self) -> bool {
+        self.atoms.iter().any(|a| a.element == Element::Hydrogen)
+    }
+
+    /// Mark the first and last amino acid residue of each chain as terminal.
+    fn set_residue_ends(&mut self, res_keys: &[ResKey]) {
+        let mut first_last: HashMap<&str, (usize, usize)> = HashMap::new();
+
+        for (i, key) in res_keys.iter().enumerate() {
+            if !matches!(self.residues[i].res_type, ResidueType::AminoAcid(_)) {
+                continue;
+            }
+            first_last
+                .entry(&key.0)
+                .and_modify(|(_, last)| *last = i)
+                .or_insert((i, i));
+        }
+
+        for (first, last) in first_last.values() {
+            if first == last {
+                continue;
+            }
+            self.residues[*first].end = ResidueEnd::NTerminus;
+            self.residues[*last].end = ResidueEnd::CTerminus;
+        }
+    }
+
+    /// Convert HELIX and SHEET residue ranges to the atom serial number ranges our secondary-structure
+    /// mesh uses.
+    fn make_ss(
+        &self,
+        records: &[(ResKey, ResKey, SecondaryStructure)],
+        res_map: &HashMap<ResKey, usize>,
+    ) -> Vec<BackboneSS> {
+        let mut result = Vec::new();
+
+        for (start, end, sec_struct) in records {
+            let (Some(res_start), Some(res_end)) = (res_map.get(start), res_map.get(end)) else {
+                continue;
+            };
+
+            let start_sn = self.residues[*res_start].atom_sns.iter().min();
+            let end_sn = self.residues[*res_end].atom_sns.iter().max();
+
+            if let (Some(start_sn), Some(end_sn)) = (start_sn, end_sn) {
+                result.push(BackboneSS {
+                    start_sn: *start_sn,
+                    end_sn: *end_sn,
+                    sec_struct: sec_struct.clone(),
+                });
+            }
+        }
+
+        result
+    }
+
+    /// Used to load proteins through the mmCIF pipeline.
+    pub fn to_mmcif(&self) -> MmCif {
+        MmCif {
+            ident: self.ident.clone(),
+            metadata: self.metadata.clone(),
+            atoms: self.atoms.clone(),
+            chains: self.chains.clone(),
+            residues: self.residues.clone(),
+            secondary_structure: self.secondary_structure.clone(),
+            experimental_method: None,
+        }
+    }
+
+    /// Apply data we don't pass through the generic atom type. Matches by serial number, so
+    /// it's safe to run on atom sets that have had hydrogens added.
+    pub fn apply_atom_props(&self, atoms: &mut [Atom]) {
+        for atom in atoms {
+            if let Some(b) = self.b_factors.get(&atom.serial_number) {
+                atom.b_factor = Some(*b);
+            }
+            if let Some(occ) = self.occupancies.get(&atom.serial_number) {
+                atom.occupancy = Some(*occ);
+            }
+        }
+    }
+
+    /// Apply sequence numbers and insertion codes, which generic residues don't have. Matches by
+    /// serial number.
+    pub fn apply_res_props(&self, residues: &mut [Residue]) {
+        for res in residues {
+            if let Some(&(seq_num, ins_code)) = self.res_seq.get(&res.serial_number) {
+                res.seq_num = seq_num;
+                res.ins_code = ins_code;
+            }
+        }
+    }
+}
+
+/// Build bonds from CONECT entries. Each bond is usually listed under both of its atoms; we count
+/// repeats within a single atom's records to infer bond order.
+fn bonds_from_conect(conect: &[(u32, u32)]) -> Vec<BondGeneric> {
+    let mut counts: HashMap<(u32, u32), usize> = HashMap::new();
+    for (from, to) in conect {
+        *counts.entry((*from, *to)).or_default() += 1;
+    }
+
+    let mut orders: HashMap<(u32, u32), usize> = HashMap::new();
+    for ((from, to), count) in &counts {
+        if from == to {
+            continue;
+        }
+        let key = (*from.min(to), *from.max(to));
+        let entry = orders.entry(key).or_default();
+        *entry = (*entry).max(*count);
+    }
+
+    let mut keys: Vec<_> = orders.keys().copied().collect();
+    keys.sort();
+
+    keys.into_iter()
+        .map(|(atom_0_sn, atom_1_sn)| BondGeneric {
+            bond_type: match orders[&(atom_0_sn, atom_1_sn)] {
+                2 => BondType::Double,
+                3 => BondType::Triple,
+                _ => BondType::Single,
+            },
+            atom_0_sn,
+            atom_1_sn,
+        })
+        .collect()
+}
+
+impl MoleculePeptide {
+    /// Loads through the same pipeline as mmCIF files. If the file included hydrogens, we replace
+    /// them with our own, so their naming is consistent with our force fields, and our pH.
+    pub fn from_pdb(
+        pdb: &Pdb,
+        ff_map: &ProtFfChargeMapSet,
+        path: Option<PathBuf>,
+        ph: f32,
+    ) -> io::Result<Self> {
+        let mut result = Self::from_mmcif(pdb.to_mmcif(), ff_map, path, ph)?;
+
+        if pdb.has_hydrogens() {
+            result.reassign_hydrogens(ph, ff_map)?;
+        }
+
+        pdb.apply_atom_props(&mut result.common.atoms);
+        pdb.apply_res_props(&mut result.residues);
+
+        // Bonds explicitly listed for hetero groups may not all be inferred from geometry.
+        let mut added = false;
+        for bond in &pdb.bonds {
+            let exists = result.common.bonds.iter().any(|b| {
+                (b.atom_0_sn == bond.atom_0_sn && b.atom_1_sn == bond.atom_1_sn)
+                    || (b.atom_0_sn == bond.atom_1_sn && b.atom_1_sn == bond.atom_0_sn)
+            });
+            if exists {
+                continue;
+            }
+
+            // Atoms removed as alternate conformations won't be present.
+            if let Ok(b) = Bond::from_generic(bond, &result.common.atoms) {
+                result.common.bonds.push(b);
+                added = true;
+            }
+        }
+
+        if added {
+            result.common.build_adjacency_list();
+        }
+
+        Ok(result)
+    }
+}
+
+impl MoleculeSmall {
+    /// For PDB files that contain only hetero atoms. Uses CONECT records for bonds if present;
+    /// otherwise infers them from geometry.
+    pub fn from_pdb(pdb: &Pdb, path: Option<PathBuf>) -> io::Result<Self> {
+        // Keep the first alternate location only.
+        let first_alt = pdb
+            .atoms
+            .iter()
+            .find_map(|a| a.alt_conformation_id.clone());
+
+        let atoms_gen: Vec<_> = pdb
+            .atoms
+            .iter()
+            .filter(|a| a.alt_conformation_id.is_none() || a.alt_conformation_id == first_alt)
+            .cloned()
+            .collect();
+
+        let mut atoms: Vec<Atom> = atoms_gen.iter().map(|a| a.into()).collect();
+        pdb.apply_atom_props(&mut atoms);
+
+        let bonds_gen = if pdb.bonds.is_empty() {
+            create_bonds(&atoms_gen)
+        } else {
+            pdb.bonds.clone()
+        };
+
+        let bonds: Vec<Bond> = bonds_gen
+            .iter()
+            .filter_map(|b| Bond::from_generic(b, &atoms).ok())
+            .collect();
+
+        // Use the residue name as the identifier if the header doesn't have one.
+        let ident = if pdb.ident.is_empty() {
+            match pdb.residues.first().map(|r| &r.res_type) {
+                Some(ResidueType::Other(name)) => name.clone(),
+                _ => String::new(),
+            }
+        } else {
+            pdb.ident.clone()
+        };
+
+        Ok(Self::new(ident, atoms, bonds, pdb.metadata.clone(), path))
+    }
+}
+
+/// Residue name, as used in PDB and mmCIF files.
+pub(super) fn res_name(res_type: &ResidueType) -> String {
+    match res_type {
+        ResidueType::AminoAcid(aa) => aa.to_str(AaIdent::ThreeLetters).to_uppercase(),
+        ResidueType::Water => "HOH".to_owned(),
+        ResidueType::Other(name) => name.clone(),
+    }
+}
+
+/// Atom name, e.g. "CA", "HB2", or "O1". Falls back to the element symbol.
+pub(super) fn atom_name(atom: &Atom) -> String {
+    if let Some(tir) = &atom.type_in_res {
+        return tir.to_string();
+    }
+    if let Some(name) = &atom.type_in_res_general {
+        return name.clone();
+    }
+    atom.element.to_letter()
+}
+
+/// Residue and chain ID for an atom, from our cached indices.
+pub(super) fn atom_res_chain<'a>(
+    mol: &'a MoleculePeptide,
+    atom: &Atom,
+) -> (Option<&'a Residue>, &'a str) {
+    let res = atom.residue.and_then(|i| mol.residues.get(i));
+    let chain_id = match atom.chain.and_then(|i| mol.chains.get(i)) {
+        Some(c) => c.id.as_str(),
+        None => "A",
+    };
+    (res, chain_id)
+}
+
+/// Map secondary structure atom serial number ranges back to the residues at their ends. Returns
+/// (start atom index, end atom index, type).
+pub(super) fn ss_atom_ranges(mol: &MoleculePeptide) -> Vec<(usize, usize, SecondaryStructure)> {
+    let sn_map: HashMap<u32, usize> = mol
+        .common
+        .atoms
+        .iter()
+        .enumerate()
+        .map(|(i, a)| (a.serial_number, i))
+        .collect();
+
+    mol.secondary_structure
+        .iter()
+        .filter_map(|ss| {
+            let start = sn_map.get(&ss.start_sn)?;
+            let end = sn_map.get(&ss.end_sn)?;
+            Some((*start, *end, ss.sec_struct.clone()))
+        })
+        .collect()
+}
+
+/// Atom names are aligned so the element symbol lands in columns 13-14.
+pub(super) fn pdb_atom_name(name: &str, element: Element) -> String {
+    if name.len() >= 4 || element.to_letter().len() == 2 {
+        format!("{name:<4}")
+    } else {
+        format!(" {name:<3}")
+    }
+}
+
+/// PDB chain IDs are a single character. Longer ones, e.g. from mmCIF files, are truncated.
+fn pdb_chain_id(id: &str) -> char {
+    id.chars().next().unwrap_or(' ')
+}
+
+/// Sequence numbers outside the 4-column field wrap around, as we do for atom serial numbers.
+fn pdb_seq_num(seq_num: i32) -> i32 {
+    if (-999..=9_999).contains(&seq_num) {
+        seq_num
+    } else {
+        seq_num.rem_euclid(10_000)
+    }
+}
+
+impl MoleculePeptide {
+    /// Serialize the current state of this molecule, including positions from `atom_posits`, e.g.
+    /// after an MD run. Writes SEQRES records for each chain's polymer residues, HELIX and SHEET
+    /// records if secondary structure is present, TER records at the end of each chain, and CONECT
+    /// records for bonds involving hetero atoms. Atoms are renumbered sequentially, as TER records
+    /// take a serial number.
+    pub fn to_pdb(&self) -> String {
+        let mut result = String::new();
+
+        let ident = if self.common.ident.is_empty() {
+            "XXXX"
+        } else {
+            &self.common.ident
+        };
+        result.push_str(&format!("HEADER    {:<40}{:>9}   {:<4}\n", "", "", ident));
+        if let Some(title) = self.common.metadata.get("title") {
+            result.push_str(&format!("TITLE     {title}\n"));
+        }
+
+        for chain in &self.chains {
+            let names: Vec<_> = chain
+                .residues
+                .iter()
+                .filter_map(|&i| self.residues.get(i))
+                .filter(|r| matches!(r.res_type, ResidueType::AminoAcid(_)))
+                .map(|r| res_name(&r.res_type))
+                .collect();
+            let chain_id = pdb_chain_id(&chain.id);
+
+            // Up to 13 residues per line.
+            for (line_i, chunk) in names.chunks(13).enumerate() {
+                result.push_str(&format!(
+                    "SEQRES {:>3} {chain_id:1} {:>4}  {}\n",
+                    line_i + 1,
+                    names.len(),
+                    chunk.join(" ")
+                ));
+            }
+        }
+
+        let mut helix_i = 0;
+        let mut sheet_i = 0;
+        for (start, end, sec_struct) in ss_atom_ranges(self) {
+            let (Some(res_0), chain_0) = atom_res_chain(self, &self.common.atoms[start]) else {
+                continue;
+            };
+            let (Some(res_1), chain_1) = atom_res_chain(self, &self.common.atoms[end]) else {
+                continue;
+            };
+
+            let name_0 = res_name(&res_0.res_type);
+            let name_1 = res_name(&res_1.res_type);
+            let (chain_0, chain_1) = (pdb_chain_id(chain_0), pdb_chain_id(chain_1));
+            let (sn_0, sn_1) = (pdb_seq_num(res_0.seq_num), pdb_seq_num(res_1.seq_num));
+            let ic_0 = res_0.ins_code.unwrap_or(' ');
+            let ic_1 = res_1.ins_code.unwrap_or(' ');
+
+            match sec_struct {
+                SecondaryStructure::Helix => {
+                    helix_i += 1;
+                    let len = res_1.serial_number.saturating_sub(res_0.serial_number) + 1;
+                    result.push_str(&format!(
+                        "HELIX  {helix_i:>3} {helix_i:>3} {name_0:>3} {chain_0:1} {sn_0:>4}{ic_0:1} {name_1:>3} {chain_1:1} {sn_1:>4}{ic_1:1} 1{:>30} {len:>5}\n",
+                        ""
+                    ));
+                }
+                SecondaryStructure::Sheet => {
+                    sheet_i += 1;
+                    result.push_str(&format!(
+                        "SHEET  {sheet_i:>3} {sheet_i:>3} 1 {name_0:>3} {chain_0:1}{sn_0:>4}{ic_0:1} {name_1:>3} {chain_1:1}{sn_1:>4}{ic_1:1} 0\n"
+                    ));
+                }
+                SecondaryStructure::Coil => (),
+            }
+        }
+
+        // The last polymer atom of each chain, which we follow with a TER record.
+        let chain_ends: HashSet<usize> = self
+            .chains
+            .iter()
+            .filter_map(|c| {
+                c.atoms
+                    .iter()
+                    .copied()
+                    .filter(|&i| !self.common.atoms[i].hetero)
+                    .max()
+            })
+            .collect();
+
+        // Serial numbers as written, by atom index. These are sequential, including TER records.
+        let mut out_sns = vec![0; self.common.atoms.len()];
+        let mut out_sn = 0;
+
+        for (i, atom) in self.common.atoms.iter().enumerate() {
+            out_sn += 1;
+            out_sns[i] = out_sn;
+
+            let (res, chain_id) = atom_res_chain(self, atom);
+            let (res_name, res_sn, ins_code) = match res {
+                Some(r) => (
+                    res_name(&r.res_type),
+                    pdb_seq_num(r.seq_num),
+                    r.ins_code.unwrap_or(' '),
+                ),
+                None => ("UNK".to_owned(), 0, ' '),
+            };
+            // Longer hetero names don't fit the fixed-width format.
+            let res_name = &res_name[..res_name.len().min(3)];
+            let chain_id = pdb_chain_id(chain_id);
+
+            let posit = self.common.atom_posits.get(i).unwrap_or(&atom.posit);
+            let record = if atom.hetero { "HETATM" } else { "ATOM" };
+            let alt = atom.alt_conformation_id.as_deref().unwrap_or(" ");
+
+            result.push_str(&format!(
+                "{record:<6}{:>5} {}{alt:1}{res_name:>3} {chain_id:1}{res_sn:>4}{ins_code:1}   {:>8.3}{:>8.3}{:>8.3}{:>6.2}{:>6.2}          {:>2}\n",
+                out_sn % 100_000,
+                pdb_atom_name(&atom_name(atom), atom.element),
+                posit.x,
+                posit.y,
+                posit.z,
+                atom.occupancy.unwrap_or(1.),
+                atom.b_factor.unwrap_or_default(),
+                atom.element.to_letter().to_uppercase(),
+            ));
+
+            if chain_ends.contains(&i) {
+                out_sn += 1;
+                result.push_str(&format!(
+                    "TER   {:>5}      {res_name:>3} {chain_id:1}{res_sn:>4}{ins_code:1}\n",
+                    out_sn % 100_000
+                ));
+            }
+        }
+
+        // CONECT records are only expected for hetero groups; standard residue connectivity is implied.
+        let mut conect: HashMap<u32, Vec<u32>> = HashMap::new();
+        for bond in &self.common.bonds {
+            if !self.common.atoms[bond.atom_0].hetero && !self.common.atoms[bond.atom_1].hetero {
+                continue;
+            }
+            let (sn_0, sn_1) = (out_sns[bond.atom_0], out_sns[bond.atom_1]);
+            conect.entry(sn_0).or_default().push(sn_1);
+            conect.entry(sn_1).or_default().push(sn_0);
+        }
+
+        let mut conect_sns: Vec<_> = conect.keys().copied().collect();
+        conect_sns.sort();
+
+        for sn in conect_sns {
+            // Up to 4 bonded atoms per line.
+            for chunk in conect[&sn].chunks(4) {
+                result.push_str(&format!("CONECT{:>5}", sn % 100_000));
+                for other in chunk {
+                    result.push_str(&format!("{:>5}", other % 100_000));
+                }
+                result.push('\n');
+            }
+        }
+
+        result.push_str("END\n");
+        result
+    }
+
+    pub fn save_pdb(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_pdb())
+    }
+}
@@ -0,0 +1,1115 @@
+//! Session files (.mcs): A self-contained snapshot of the workspace. This includes all molecules with
+//! their current atom positions, molecule-specific force field parameters, selections, camera
+//! snapshots, density maps, and optionally MD snapshots. Unlike the prefs file, this doesn't
+//! depend on the original molecule files, so it can be shared.
+//!
+//! Files start with a magic number and format version, followed by the bincode-encoded session.
+//! When changing `Session`, increment `SESSION_VERSION`, and keep a decoder for older versions.
+//! The session only contains types defined here, e.g. vice `Selection` or `MdConfig`, so changes
+//! to app and library types don't change the format; we convert at the boundary.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Cursor, ErrorKind},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use bincode::{Decode, Encode, config};
+use bio_files::{
+    AtomGeneric, BackboneSS, BondGeneric, BondType, ChainGeneric, DensityMap, ResidueEnd,
+    ResidueGeneric, ResidueType, SecondaryStructure, md_params::ForceFieldParams,
+};
+use dynamics::{HydrogenConstraint, Integrator, MdConfig, MdState, SimBoxInit, snapshot::Snapshot};
+use graphics::{EngineUpdates, EntityUpdate, Scene};
+use lin_alg::{f32::Quaternion as QuaternionF32, f64::Vec3};
+use na_seq::{AminoAcid, AtomTypeInRes, Element, seq_from_str};
+
+use crate::{
+    CamSnapshot, ResColoring, Selection, State, ViewSelLevel, Visibility,
+    docking::DockingSite,
+    drawing::{MoleculeView, draw_peptide},
+    drawing_wrappers::{draw_all_ligs, draw_all_lipids, draw_all_nucleic_acids},
+    file_io::{amber::frcmod_text, pdb::res_name},
+    lipid::MoleculeLipid,
+    mol_characterization::Descriptors,
+    mol_lig::MoleculeSmall,
+    molecule::{
+        Atom, Bond, Chain, MoleculeCommon, MoleculeGeneric, MoleculePeptide, Residue,
+        init_bonds_chains_res,
+    },
+    nucleic_acid::MoleculeNucleicAcid,
+    prefs::PerMolToSave,
+    selfies::smiles_to_selfies,
+    util::{close_peptide, handle_success, load_snap},
+};
+
+pub const SESSION_MAGIC: &[u8; 4] = b"MCS\0";
+pub const SESSION_VERSION: u16 = 1;
+
+#[derive(Encode, Decode)]
+pub(crate) struct SessionAtom {
+    pub serial_number: u32,
+    pub posit: Vec3,
+    pub element: String,
+    pub type_in_res: Option<String>,
+    pub type_in_res_general: Option<String>,
+    pub force_field_type: Option<String>,
+    pub partial_charge: Option<f32>,
+    pub hetero: bool,
+    pub occupancy: Option<f32>,
+    pub b_factor: Option<f32>,
+    pub formal_charge: i8,
+    pub implicit_h: Option<u8>,
+    pub isotope: Option<u16>,
+    pub alt_conformation_id: Option<String>,
+}
+
+#[derive(Encode, Decode)]
+pub(crate) struct SessionBond {
+    bond_type: u8,
+    atom_0_sn: u32,
+    atom_1_sn: u32,
+}
+
+#[derive(Encode, Decode)]
+pub(crate) struct SessionResidue {
+    serial_number: u32,
+    seq_num: i32,
+    ins_code: Option<char>,
+    /// 0: Amino acid, 1: Water, 2: Other.
+    res_kind: u8,
+    res_name: String,
+    atom_sns: Vec<u32>,
+    end: u8,
+}
+
+#[derive(Encode, Decode)]
+pub(crate) struct SessionChain {
+    id: String,
+    residue_sns: Vec<u32>,
+    atom_sns: Vec<u32>,
+    visible: bool,
+}
+
+/// Fields common to all molecule types.
+#[derive(Encode, Decode)]
+pub(crate) struct SessionMol {
+    pub ident: String,
+    pub atoms: Vec<SessionAtom>,
+    pub bonds: Vec<SessionBond>,
+    pub residues: Vec<SessionResidue>,
+    pub chains: Vec<SessionChain>,
+    /// Current positions, e.g. after docking, MD, or manipulation.
+    pub atom_posits: Vec<Vec3>,
+    pub metadata: HashMap<String, String>,
+    pub path: Option<PathBuf>,
+    pub visible: bool,
+    pub selected_for_md: bool,
+}
+
+/// Per-peptide display and docking settings. See `PerMolToSave`.
+#[derive(Encode, Decode)]
+struct SessionPerMol {
+    chain_to_pick_res: Option<usize>,
+    show_docking_tools: bool,
+    /// 0: Amino acid, 1: Position, 2: Hydrophobicity.
+    res_coloring: u8,
+    atom_color_by_charge: bool,
+    show_aa_seq: bool,
+    docking_site_center: Vec3,
+    docking_site_radius: f64,
+}
+
+#[derive(Encode, Decode)]
+struct SessionPeptide {
+    mol: SessionMol,
+    /// (start SN, end SN, 0: Helix, 1: Sheet, 2: Coil)
+    secondary_structure: Vec<(u32, u32, u8)>,
+    per_mol: SessionPerMol,
+}
+
+#[derive(Encode, Decode)]
+pub(crate) struct SessionLigand {
+    pub mol: SessionMol,
+    smiles: Option<String>,
+}
+
+#[derive(Encode, Decode)]
+struct SessionNucleicAcid {
+    mol: SessionMol,
+    seq: String,
+    features: Vec<(String, (usize, usize))>,
+}
+
+#[derive(Encode, Decode)]
+struct SessionLipid {
+    mol: SessionMol,
+    lmsd_id: String,
+    hmdb_id: String,
+    kegg_id: String,
+    common_name: String,
+}
+
+#[derive(Encode, Decode)]
+struct SessionSnapshot {
+    time: f64,
+    atom_posits: Vec<[f32; 3]>,
+    water_o_posits: Vec<[f32; 3]>,
+    water_h0_posits: Vec<[f32; 3]>,
+    water_h1_posits: Vec<[f32; 3]>,
+    energy_kinetic: f64,
+    energy_potential: f64,
+    temperature: f64,
+    pressure: f64,
+}
+
+/// See `Selection`.
+#[derive(Encode, Decode)]
+enum SessionSelection {
+    None,
+    AtomPeptide(usize),
+    Residue(usize),
+    AtomsPeptide(Vec<usize>),
+    AtomLig((usize, usize)),
+    AtomsLig((usize, Vec<usize>)),
+    AtomNucleicAcid((usize, usize)),
+    AtomLipid((usize, usize)),
+    BondPeptide(usize),
+    BondLig((usize, usize)),
+    BondsLig((usize, Vec<usize>)),
+    BondNucleicAcid((usize, usize)),
+    BondLipid((usize, usize)),
+}
+
+/// See `CamSnapshot`.
+#[derive(Encode, Decode)]
+struct SessionCam {
+    position: [f32; 3],
+    /// w, x, y, z
+    orientation: [f32; 4],
+    far: f32,
+    name: String,
+}
+
+/// See `Visibility`.
+#[derive(Encode, Decode)]
+struct SessionVisibility {
+    hide_sidechains: bool,
+    hide_water: bool,
+    hide_hetero: bool,
+    hide_protein: bool,
+    hide_ligand: bool,
+    hide_nucleic_acids: bool,
+    hide_lipids: bool,
+    hide_hydrogen: bool,
+    hide_h_bonds: bool,
+    dim_peptide: bool,
+    hide_density_point_cloud: bool,
+    hide_density_surface: bool,
+    labels_mol: bool,
+    labels_atom_sn: bool,
+    labels_atom_q: bool,
+    labels_atom_detailed: bool,
+    labels_bond: bool,
+}
+
+#[derive(Encode, Decode)]
+enum SessionIntegrator {
+    LangevinMiddle { gamma: f32 },
+    VerletVelocity { thermostat: Option<f64> },
+}
+
+/// The MD settings editable in the UI. See `MdConfig`; others use its defaults.
+#[derive(Encode, Decode)]
+struct SessionMdConfig {
+    integrator: SessionIntegrator,
+    h_constrained: bool,
+    /// K
+    temp_target: f32,
+    /// bar
+    pressure_target: f32,
+    /// Å. `None` for a fixed simulation box, which we don't save.
+    solvent_pad: Option<f32>,
+    relax: bool,
+}
+
+#[derive(Encode, Decode)]
+pub(crate) struct Session {
+    peptide: Option<SessionPeptide>,
+    pub ligands: Vec<SessionLigand>,
+    nucleic_acids: Vec<SessionNucleicAcid>,
+    lipids: Vec<SessionLipid>,
+    /// Molecule ident, and parameters in frcmod format.
+    mol_specific_params: Vec<(String, String)>,
+    /// The peptide's density map, in CCP4/MRC (.map) format.
+    density: Option<Vec<u8>>,
+    selection: SessionSelection,
+    /// The camera at save time.
+    camera: SessionCam,
+    cam_snapshots: Vec<SessionCam>,
+    /// See `mol_view_to_u8()`.
+    mol_view: u8,
+    /// 0: Atom, 1: Bond, 2: Residue.
+    view_sel_level: u8,
+    visibility: SessionVisibility,
+    near_sel_only: bool,
+    near_lig_only: bool,
+    nearby_dist_thresh: u16,
+    md_config: SessionMdConfig,
+    num_md_steps: u32,
+    md_dt: f32,
+    ph: f32,
+    md_snapshots: Vec<SessionSnapshot>,
+}
+
+fn bond_type_to_u8(bt: BondType) -> u8 {
+    match bt {
+        BondType::Double => 2,
+        BondType::Triple => 3,
+        BondType::Aromatic => 4,
+        _ => 1,
+    }
+}
+
+fn bond_type_from_u8(v: u8) -> BondType {
+    match v {
+        2 => BondType::Double,
+        3 => BondType::Triple,
+        4 => BondType::Aromatic,
+        _ => BondType::Single,
+    }
+}
+
+fn res_end_to_u8(end: ResidueEnd) -> u8 {
+    match end {
+        ResidueEnd::Internal => 0,
+        ResidueEnd::NTerminus => 1,
+        ResidueEnd::CTerminus => 2,
+        ResidueEnd::Hetero => 3,
+    }
+}
+
+fn res_end_from_u8(v: u8) -> ResidueEnd {
+    match v {
+        1 => ResidueEnd::NTerminus,
+        2 => ResidueEnd::CTerminus,
+        3 => ResidueEnd::Hetero,
+        _ => ResidueEnd::Internal,
+    }
+}
+
+fn mol_view_to_u8(view: MoleculeView) -> u8 {
+    match view {
+        MoleculeView::Backbone => 0,
+        MoleculeView::Sticks => 1,
+        MoleculeView::BallAndStick => 2,
+        MoleculeView::SpaceFill => 3,
+        MoleculeView::Ribbon => 4,
+        MoleculeView::Surface => 5,
+        MoleculeView::Dots => 6,
+    }
+}
+
+fn mol_view_from_u8(v: u8) -> MoleculeView {
+    match v {
+        0 => MoleculeView::Backbone,
+        2 => MoleculeView::BallAndStick,
+        3 => MoleculeView::SpaceFill,
+        4 => MoleculeView::Ribbon,
+        5 => MoleculeView::Surface,
+        6 => MoleculeView::Dots,
+        _ => MoleculeView::Sticks,
+    }
+}
+
+fn view_sel_level_to_u8(level: ViewSelLevel) -> u8 {
+    match level {
+        ViewSelLevel::Atom => 0,
+        ViewSelLevel::Bond => 1,
+        ViewSelLevel::Residue => 2,
+    }
+}
+
+fn view_sel_level_from_u8(v: u8) -> ViewSelLevel {
+    match v {
+        1 => ViewSelLevel::Bond,
+        2 => ViewSelLevel::Residue,
+        _ => ViewSelLevel::Atom,
+    }
+}
+
+fn res_coloring_to_u8(coloring: ResColoring) -> u8 {
+    match coloring {
+        ResColoring::AminoAcid => 0,
+        ResColoring::Position => 1,
+        ResColoring::Hydrophobicity => 2,
+    }
+}
+
+fn res_coloring_from_u8(v: u8) -> ResColoring {
+    match v {
+        1 => ResColoring::Position,
+        2 => ResColoring::Hydrophobicity,
+        _ => ResColoring::AminoAcid,
+    }
+}
+
+impl From<&Selection> for SessionSelection {
+    fn from(sel: &Selection) -> Self {
+        match sel.clone() {
+            Selection::None => Self::None,
+            Selection::AtomPeptide(i) => Self::AtomPeptide(i),
+            Selection::Residue(i) => Self::Residue(i),
+            Selection::AtomsPeptide(v) => Self::AtomsPeptide(v),
+            Selection::AtomLig(v) => Self::AtomLig(v),
+            Selection::AtomsLig(v) => Self::AtomsLig(v),
+            Selection::AtomNucleicAcid(v) => Self::AtomNucleicAcid(v),
+            Selection::AtomLipid(v) => Self::AtomLipid(v),
+            Selection::BondPeptide(i) => Self::BondPeptide(i),
+            Selection::BondLig(v) => Self::BondLig(v),
+            Selection::BondsLig(v) => Self::BondsLig(v),
+            Selection::BondNucleicAcid(v) => Self::BondNucleicAcid(v),
+            Selection::BondLipid(v) => Self::BondLipid(v),
+        }
+    }
+}
+
+impl From<SessionSelection> for Selection {
+    fn from(sel: SessionSelection) -> Self {
+        match sel {
+            SessionSelection::None => Self::None,
+            SessionSelection::AtomPeptide(i) => Self::AtomPeptide(i),
+            SessionSelection::Residue(i) => Self::Residue(i),
+            SessionSelection::AtomsPeptide(v) => Self::AtomsPeptide(v),
+            SessionSelection::AtomLig(v) => Self::AtomLig(v),
+            SessionSelection::AtomsLig(v) => Self::AtomsLig(v),
+            SessionSelection::AtomNucleicAcid(v) => Self::AtomNucleicAcid(v),
+            SessionSelection::AtomLipid(v) => Self::AtomLipid(v),
+            SessionSelection::BondPeptide(i) => Self::BondPeptide(i),
+            SessionSelection::BondLig(v) => Self::BondLig(v),
+            SessionSelection::BondsLig(v) => Self::BondsLig(v),
+            SessionSelection::BondNucleicAcid(v) => Self::BondNucleicAcid(v),
+            SessionSelection::BondLipid(v) => Self::BondLipid(v),
+        }
+    }
+}
+
+impl From<&CamSnapshot> for SessionCam {
+    fn from(cam: &CamSnapshot) -> Self {
+        let (p, o) = (&cam.position, &cam.orientation);
+        Self {
+            position: [p.x, p.y, p.z],
+            orientation: [o.w, o.x, o.y, o.z],
+            far: cam.far,
+            name: cam.name.clone(),
+        }
+    }
+}
+
+impl From<SessionCam> for CamSnapshot {
+    fn from(cam: SessionCam) -> Self {
+        let [w, x, y, z] = cam.orientation;
+        Self {
+            position: arr_to_vec3(&cam.position),
+            orientation: QuaternionF32::new(w, x, y, z),
+            far: cam.far,
+            name: cam.name,
+        }
+    }
+}
+
+impl From<&Visibility> for SessionVisibility {
+    fn from(v: &Visibility) -> Self {
+        Self {
+            hide_sidechains: v.hide_sidechains,
+            hide_water: v.hide_water,
+            hide_hetero: v.hide_hetero,
+            hide_protein: v.hide_protein,
+            hide_ligand: v.hide_ligand,
+            hide_nucleic_acids: v.hide_nucleic_acids,
+            hide_lipids: v.hide_lipids,
+            hide_hydrogen: v.hide_hydrogen,
+            hide_h_bonds: v.hide_h_bonds,
+            dim_peptide: v.dim_peptide,
+            hide_density_point_cloud: v.hide_density_point_cloud,
+            hide_density_surface: v.hide_density_surface,
+            labels_mol: v.labels_mol,
+            labels_atom_sn: v.labels_atom_sn,
+            labels_atom_q: v.labels_atom_q,
+            labels_atom_detailed: v.labels_atom_detailed,
+            labels_bond: v.labels_bond,
+        }
+    }
+}
+
+impl From<SessionVisibility> for Visibility {
+    fn from(v: SessionVisibility) -> Self {
+        Self {
+            hide_sidechains: v.hide_sidechains,
+            hide_water: v.hide_water,
+            hide_hetero: v.hide_hetero,
+            hide_protein: v.hide_protein,
+            hide_ligand: v.hide_ligand,
+            hide_nucleic_acids: v.hide_nucleic_acids,
+            hide_lipids: v.hide_lipids,
+            hide_hydrogen: v.hide_hydrogen,
+            hide_h_bonds: v.hide_h_bonds,
+            dim_peptide: v.dim_peptide,
+            hide_density_point_cloud: v.hide_density_point_cloud,
+            hide_density_surface: v.hide_density_surface,
+            labels_mol: v.labels_mol,
+            labels_atom_sn: v.labels_atom_sn,
+            labels_atom_q: v.labels_atom_q,
+            labels_atom_detailed: v.labels_atom_detailed,
+            labels_bond: v.labels_bond,
+        }
+    }
+}
+
+impl From<&MdConfig> for SessionMdConfig {
+    fn from(cfg: &MdConfig) -> Self {
+        Self {
+            integrator: match cfg.integrator {
+                Integrator::LangevinMiddle { gamma } => SessionIntegrator::LangevinMiddle { gamma },
+                Integrator::VerletVelocity { thermostat } => {
+                    SessionIntegrator::VerletVelocity { thermostat }
+                }
+            },
+            h_constrained: matches!(cfg.hydrogen_constraint, HydrogenConstraint::Constrained),
+            temp_target: cfg.temp_target,
+            pressure_target: cfg.pressure_target,
+            solvent_pad: match cfg.sim_box {
+                SimBoxInit::Pad(pad) => Some(pad),
+                SimBoxInit::Fixed(_) => None,
+            },
+            relax: cfg.max_init_relaxation_iters.is_some(),
+        }
+    }
+}
+
+impl From<SessionMdConfig> for MdConfig {
+    fn from(cfg: SessionMdConfig) -> Self {
+        let default = MdConfig::default();
+
+        Self {
+            integrator: match cfg.integrator {
+                SessionIntegrator::LangevinMiddle { gamma } => Integrator::LangevinMiddle { gamma },
+                SessionIntegrator::VerletVelocity { thermostat } => {
+                    Integrator::VerletVelocity { thermostat }
+                }
+            },
+            hydrogen_constraint: if cfg.h_constrained {
+                HydrogenConstraint::Constrained
+            } else {
+                HydrogenConstraint::Flexible
+            },
+            temp_target: cfg.temp_target,
+            pressure_target: cfg.pressure_target,
+            sim_box: match cfg.solvent_pad {
+                Some(pad) => SimBoxInit::Pad(pad),
+                None => default.sim_box.clone(),
+            },
+            max_init_relaxation_iters: if cfg.relax {
+                default.max_init_relaxation_iters
+            } else {
+                None
+            },
+            ..default
+        }
+    }
+}
+
+impl SessionPerMol {
+    fn new(state: &State, ident: &str) -> Self {
+        let site = state
+            .to_save
+            .per_mol
+            .get(ident)
+            .map(|d| d.docking_site.clone())
+            .unwrap_or_default();
+
+        Self {
+            chain_to_pick_res: state.ui.chain_to_pick_res,
+            show_docking_tools: state.ui.show_docking_tools,
+            res_coloring: res_coloring_to_u8(state.ui.res_coloring),
+            atom_color_by_charge: state.ui.atom_color_by_charge,
+            show_aa_seq: state.ui.ui_vis.aa_seq,
+            docking_site_center: site.site_center,
+            docking_site_radius: site.site_radius,
+        }
+    }
+
+    /// Apply to the UI, and to the peptide's prefs entry.
+    fn apply(&self, state: &mut State, ident: &str) {
+        state.ui.chain_to_pick_res = self.chain_to_pick_res;
+        state.ui.show_docking_tools = self.show_docking_tools;
+        state.ui.res_coloring = res_coloring_from_u8(self.res_coloring);
+        state.ui.atom_color_by_charge = self.atom_color_by_charge;
+        state.ui.ui_vis.aa_seq = self.show_aa_seq;
+
+        let mut data = PerMolToSave::from_state(state, false);
+        data.docking_site = DockingSite {
+            site_center: self.docking_site_center,
+            site_radius: self.docking_site_radius,
+        };
+        state.to_save.per_mol.insert(ident.to_owned(), data);
+    }
+}
+
+fn vec3_to_arr(v: &lin_alg::f32::Vec3) -> [f32; 3] {
+    [v.x, v.y, v.z]
+}
+
+fn arr_to_vec3(v: &[f32; 3]) -> lin_alg::f32::Vec3 {
+    lin_alg::f32::Vec3::new(v[0], v[1], v[2])
+}
+
+impl SessionMol {
+    fn new(common: &MoleculeCommon, residues: &[Residue]) -> Self {
+        Self {
+            ident: common.ident.clone(),
+            atoms: common
+                .atoms
+                .iter()
+                .map(|a| SessionAtom {
+                    serial_number: a.serial_number,
+                    posit: a.posit,
+                    element: a.element.to_letter(),
+                    type_in_res: a.type_in_res.as_ref().map(|t| t.to_string()),
+                    type_in_res_general: a.type_in_res_general.clone(),
+                    force_field_type: a.force_field_type.clone(),
+                    partial_charge: a.partial_charge,
+                    hetero: a.hetero,
+                    occupancy: a.occupancy,
+                    b_factor: a.b_factor,
+                    formal_charge: a.formal_charge,
+                    implicit_h: a.implicit_h,
+                    isotope: a.isotope,
+                    alt_conformation_id: a.alt_conformation_id.clone(),
+                })
+                .collect(),
+            bonds: common
+                .bonds
+                .iter()
+                .map(|b| SessionBond {
+                    bond_type: bond_type_to_u8(b.bond_type),
+                    atom_0_sn: b.atom_0_sn,
+                    atom_1_sn: b.atom_1_sn,
+                })
+                .collect(),
+            residues: residues
+                .iter()
+                .map(|r| SessionResidue {
+                    serial_number: r.serial_number,
+                    seq_num: r.seq_num,
+                    ins_code: r.ins_code,
+                    res_kind: match r.res_type {
+                        ResidueType::AminoAcid(_) => 0,
+                        ResidueType::Water => 1,
+                        ResidueType::Other(_) => 2,
+                    },
+                    res_name: res_name(&r.res_type),
+                    atom_sns: r.atom_sns.clone(),
+                    end: res_end_to_u8(r.end),
+                })
+                .collect(),
+            chains: Vec::new(),
+            atom_posits: common.atom_posits.clone(),
+            metadata: common.metadata.clone(),
+            path: common.path.clone(),
+            visible: common.visible,
+            selected_for_md: common.selected_for_md,
+        }
+    }
+
+    /// Rebuild atoms, bonds, residues and chains, with their index references.
+    fn to_parts(&self) -> io::Result<(Vec<Atom>, Vec<Bond>, Vec<Residue>, Vec<Chain>)> {
+        let mut atoms = Vec::with_capacity(self.atoms.len());
+        for a in &self.atoms {
+            atoms.push(AtomGeneric {
+                serial_number: a.serial_number,
+                posit: a.posit,
+                element: Element::from_letter(&a.element)?,
+                type_in_res: a
+                    .type_in_res
+                    .as_ref()
+                    .and_then(|t| AtomTypeInRes::from_str(t).ok()),
+                type_in_res_general: a.type_in_res_general.clone(),
+                force_field_type: a.force_field_type.clone(),
+                partial_charge: a.partial_charge,
+                hetero: a.hetero,
+                alt_conformation_id: a.alt_conformation_id.clone(),
+                ..Default::default()
+            });
+        }
+
+        let bonds: Vec<_> = self
+            .bonds
+            .iter()
+            .map(|b| BondGeneric {
+                bond_type: bond_type_from_u8(b.bond_type),
+                atom_0_sn: b.atom_0_sn,
+                atom_1_sn: b.atom_1_sn,
+            })
+            .collect();
+
+        let residues: Vec<_> = self
+            .residues
+            .iter()
+            .map(|r| ResidueGeneric {
+                serial_number: r.serial_number,
+                res_type: match r.res_kind {
+                    0 => match AminoAcid::from_str(&r.res_name) {
+                        Ok(aa) => ResidueType::AminoAcid(aa),
+                        Err(_) => ResidueType::Other(r.res_name.clone()),
+                    },
+                    1 => ResidueType::Water,
+                    _ => ResidueType::Other(r.res_name.clone()),
+                },
+                atom_sns: r.atom_sns.clone(),
+                end: res_end_from_u8(r.end),
+            })
+            .collect();
+
+        let chains: Vec<_> = self
+            .chains
+            .iter()
+            .map(|c| ChainGeneric {
+                id: c.id.clone(),
+                residue_sns: c.residue_sns.clone(),
+                atom_sns: c.atom_sns.clone(),
+            })
+            .collect();
+
+        let (mut atoms, bonds, mut residues, mut chains) =
+            init_bonds_chains_res(&atoms, &bonds, &residues, &chains, &[])?;
+
+        for (atom, a) in atoms.iter_mut().zip(&self.atoms) {
+            atom.occupancy = a.occupancy;
+            atom.b_factor = a.b_factor;
+            atom.formal_charge = a.formal_charge;
+            atom.implicit_h = a.implicit_h;
+            atom.isotope = a.isotope;
+        }
+        for (res, r) in residues.iter_mut().zip(&self.residues) {
+            res.seq_num = r.seq_num;
+            res.ins_code = r.ins_code;
+        }
+        for (chain, c) in chains.iter_mut().zip(&self.chains) {
+            chain.visible = c.visible;
+        }
+
+        Ok((atoms, bonds, residues, chains))
+    }
+
+    /// Apply fields not set by molecule constructors.
+    fn apply_to(&self, common: &mut MoleculeCommon) -> io::Result<()> {
+        if self.atom_posits.len() != common.atoms.len() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Atom count mismatch in session molecule {}", self.ident),
+            ));
+        }
+
+        common.atom_posits = self.atom_posits.clone();
+        common.visible = self.visible;
+        common.selected_for_md = self.selected_for_md;
+        Ok(())
+    }
+}
+
+impl Session {
+    pub fn from_state(state: &State, camera: CamSnapshot, include_md: bool) -> io::Result<Self> {
+        let peptide = state.peptide.as_ref().map(|mol| {
+            let mut sm = SessionMol::new(&mol.common, &mol.residues);
+            sm.chains = mol
+                .chains
+                .iter()
+                .map(|c| SessionChain {
+                    id: c.id.clone(),
+                    residue_sns: c.residue_sns.clone(),
+                    atom_sns: c.atom_sns.clone(),
+                    visible: c.visible,
+                })
+                .collect();
+
+            SessionPeptide {
+                mol: sm,
+                secondary_structure: mol
+                    .secondary_structure
+                    .iter()
+                    .map(|ss| {
+                        let kind = match ss.sec_struct {
+                            SecondaryStructure::Helix => 0,
+                            SecondaryStructure::Sheet => 1,
+                            _ => 2,
+                        };
+                        (ss.start_sn, ss.end_sn, kind)
+                    })
+                    .collect(),
+                per_mol: SessionPerMol::new(state, &mol.common.ident),
+            }
+        });
+
+        let mut mol_specific_params: Vec<_> = state
+            .mol_specific_params
+            .iter()
+            .map(|(ident, p)| (ident.clone(), frcmod_text(p, ident)))
+            .collect();
+        mol_specific_params.sort();
+
+        // `DensityMap` only saves to a path, so we round-trip through a temporary file.
+        let mut density = None;
+        if let Some(mol) = &state.peptide
+            && let Some(dm) = &mol.density_map
+        {
+            let tmp = std::env::temp_dir().join("molchanica_session_density.map");
+            dm.save(&tmp)?;
+            density = Some(fs::read(&tmp)?);
+            let _ = fs::remove_file(&tmp);
+        }
+
+        let md_snapshots = match &state.mol_dynamics {
+            Some(md) if include_md => md
+                .snapshots
+                .iter()
+                .map(|s| SessionSnapshot {
+                    time: s.time,
+                    atom_posits: s.atom_posits.iter().map(vec3_to_arr).collect(),
+                    water_o_posits: s.water_o_posits.iter().map(vec3_to_arr).collect(),
+                    water_h0_posits: s.water_h0_posits.iter().map(vec3_to_arr).collect(),
+                    water_h1_posits: s.water_h1_posits.iter().map(vec3_to_arr).collect(),
+                    energy_kinetic: s.energy_kinetic as f64,
+                    energy_potential: s.energy_potential as f64,
+                    temperature: s.temperature as f64,
+                    pressure: s.pressure as f64,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(Self {
+            peptide,
+            ligands: state
+                .ligands
+                .iter()
+                .map(|m| SessionLigand {
+                    mol: SessionMol::new(&m.common, &[]),
+                    smiles: m.smiles.clone(),
+                })
+                .collect(),
+            nucleic_acids: state
+                .nucleic_acids
+                .iter()
+                .map(|m| SessionNucleicAcid {
+                    mol: SessionMol::new(&m.common, &m.residues),
+                    seq: m.seq.iter().map(|nt| nt.to_str_upper()).collect(),
+                    features: m.features.clone(),
+                })
+                .collect(),
+            lipids: state
+                .lipids
+                .iter()
+                .map(|m| SessionLipid {
+                    mol: SessionMol::new(&m.common, &m.residues),
+                    lmsd_id: m.lmsd_id.clone(),
+                    hmdb_id: m.hmdb_id.clone(),
+                    kegg_id: m.kegg_id.clone(),
+                    common_name: m.common_name.clone(),
+                })
+                .collect(),
+            mol_specific_params,
+            density,
+            selection: (&state.ui.selection).into(),
+            camera: (&camera).into(),
+            cam_snapshots: state.cam_snapshots.iter().map(Into::into).collect(),
+            mol_view: mol_view_to_u8(state.ui.mol_view),
+            view_sel_level: view_sel_level_to_u8(state.ui.view_sel_level),
+            visibility: (&state.ui.visibility).into(),
+            near_sel_only: state.ui.show_near_sel_only,
+            near_lig_only: state.ui.show_near_lig_only,
+            nearby_dist_thresh: state.ui.nearby_dist_thresh,
+            md_config: (&state.to_save.md_config).into(),
+            num_md_steps: state.to_save.num_md_steps,
+            md_dt: state.to_save.md_dt,
+            ph: state.to_save.ph,
+            md_snapshots,
+        })
+    }
+
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut result = SESSION_MAGIC.to_vec();
+        result.extend(SESSION_VERSION.to_le_bytes());
+
+        let payload = bincode::encode_to_vec(self, config::standard())
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        result.extend(payload);
+
+        Ok(result)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        if data.len() < 6 || &data[..4] != SESSION_MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Not a Molchanica session file",
+            ));
+        }
+
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        let payload = &data[6..];
+
+        let decode_err = |e: bincode::error::DecodeError| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Unable to decode session: {e}"),
+            )
+        };
+
+        // When adding a version, freeze the previous layout, decode older versions with it, and
+        // convert.
+        match version {
+            SESSION_VERSION => Ok(bincode::decode_from_slice(payload, config::standard())
+                .map_err(decode_err)?
+                .0),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Session file version {version} is newer than this program supports \
+                    ({SESSION_VERSION}). Please update Molchanica."
+                ),
+            )),
+        }
+    }
+}
+
+impl State {
+    /// Save the whole workspace to a session file.
+    pub fn save_session(&mut self, path: &Path, scene: &Scene, include_md: bool) -> io::Result<()> {
+        let camera = CamSnapshot::from_cam(&scene.camera, "Session".to_owned());
+        let session = Session::from_state(self, camera, include_md)?;
+        fs::write(path, session.to_bytes()?)?;
+
+        handle_success(
+            &mut self.ui,
+            format!(
+                "Saved session to {}",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            ),
+        );
+        Ok(())
+    }
+
+    /// Replace the current workspace with one loaded from a session file.
+    pub fn open_session(
+        &mut self,
+        path: &Path,
+        mut scene: Option<&mut Scene>,
+        engine_updates: &mut EngineUpdates,
+    ) -> io::Result<()> {
+        let session = Session::from_bytes(&fs::read(path)?)?;
+
+        // Build everything before modifying state, so a bad file doesn't leave us half-loaded.
+        let mut mol_specific_params = HashMap::new();
+        for (ident, text) in &session.mol_specific_params {
+            let params = ForceFieldParams::from_frcmod(text)
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{e:?}")))?;
+            mol_specific_params.insert(ident.clone(), params);
+        }
+
+        let peptide = match &session.peptide {
+            Some(sp) => {
+                let (atoms, bonds, residues, chains) = sp.mol.to_parts()?;
+                let mut mol = MoleculePeptide::new(
+                    sp.mol.ident.clone(),
+                    atoms,
+                    bonds,
+                    chains,
+                    residues,
+                    sp.mol.metadata.clone(),
+                    sp.mol.path.clone(),
+                );
+                sp.mol.apply_to(&mut mol.common)?;
+
+                mol.secondary_structure = sp
+                    .secondary_structure
+                    .iter()
+                    .map(|(start_sn, end_sn, kind)| BackboneSS {
+                        start_sn: *start_sn,
+                        end_sn: *end_sn,
+                        sec_struct: match kind {
+                            0 => SecondaryStructure::Helix,
+                            1 => SecondaryStructure::Sheet,
+                            _ => SecondaryStructure::Coil,
+                        },
+                    })
+                    .collect();
+
+                Some(mol)
+            }
+            None => None,
+        };
+
+        let mut ligands = Vec::with_capacity(session.ligands.len());
+        for sl in &session.ligands {
+            let (atoms, bonds, _, _) = sl.mol.to_parts()?;
+            let mut mol = MoleculeSmall::new(
+                sl.mol.ident.clone(),
+                atoms,
+                bonds,
+                sl.mol.metadata.clone(),
+                sl.mol.path.clone(),
+            );
+            sl.mol.apply_to(&mut mol.common)?;
+            mol.smiles = sl.smiles.clone();
+            mol.selfies = sl.smiles.as_ref().and_then(|s| smiles_to_selfies(s).ok());
+            mol.descriptors = Some(Descriptors::new(&mol.common));
+            ligands.push(mol);
+        }
+
+        let mut nucleic_acids = Vec::with_capacity(session.nucleic_acids.len());
+        for sn in &session.nucleic_acids {
+            let (atoms, bonds, residues, _) = sn.mol.to_parts()?;
+            let mut mol = MoleculeNucleicAcid {
+                common: MoleculeCommon::new(
+                    sn.mol.ident.clone(),
+                    atoms,
+                    bonds,
+                    sn.mol.metadata.clone(),
+                    sn.mol.path.clone(),
+                ),
+                residues,
+                seq: seq_from_str(&sn.seq),
+                features: sn.features.clone(),
+            };
+            sn.mol.apply_to(&mut mol.common)?;
+            nucleic_acids.push(mol);
+        }
+
+        let mut lipids = Vec::with_capacity(session.lipids.len());
+        for sl in &session.lipids {
+            let (atoms, bonds, residues, _) = sl.mol.to_parts()?;
+            let mut mol = MoleculeLipid {
+                common: MoleculeCommon::new(
+                    sl.mol.ident.clone(),
+                    atoms,
+                    bonds,
+                    sl.mol.metadata.clone(),
+                    sl.mol.path.clone(),
+                ),
+                lmsd_id: sl.lmsd_id.clone(),
+                hmdb_id: sl.hmdb_id.clone(),
+                kegg_id: sl.kegg_id.clone(),
+                common_name: sl.common_name.clone(),
+                residues,
+            };
+            sl.mol.apply_to(&mut mol.common)?;
+            lipids.push(mol);
+        }
+
+        // Clear the existing workspace.
+        match scene.as_deref_mut() {
+            Some(s) => close_peptide(self, s, engine_updates),
+            None => self.peptide = None,
+        }
+        self.ligands.clear();
+        self.nucleic_acids.clear();
+        self.lipids.clear();
+        self.mol_dynamics = None;
+        self.volatile.active_mol = None;
+
+        self.mol_specific_params.extend(mol_specific_params);
+
+        if let Some(mol) = peptide {
+            self.load_mol_to_state(
+                MoleculeGeneric::Peptide(mol),
+                scene.as_deref_mut(),
+                engine_updates,
+                None,
+            );
+        }
+        // After loading the peptide, as that overwrites this from the UI state.
+        if let Some(sp) = &session.peptide {
+            sp.per_mol.apply(self, &sp.mol.ident);
+        }
+
+        for mut mol in ligands {
+            if let Some(p) = &self.ff_param_set.small_mol {
+                mol.update_ff_related(&mut self.mol_specific_params, p);
+                mol.update_aux(&self.volatile.active_mol);
+            }
+            self.ligands.push(mol);
+        }
+        self.nucleic_acids = nucleic_acids;
+        self.lipids = lipids;
+
+        if let Some(data) = &session.density {
+            let dm = DensityMap::open(&mut Cursor::new(data))?;
+            self.load_density(dm);
+        }
+
+        if !session.md_snapshots.is_empty() {
+            let mut md = MdState::default();
+            md.snapshots = session
+                .md_snapshots
+                .iter()
+                .map(|s| Snapshot {
+                    time: s.time,
+                    atom_posits: s.atom_posits.iter().map(arr_to_vec3).collect(),
+                    atom_velocities: Vec::new(),
+                    water_o_posits: s.water_o_posits.iter().map(arr_to_vec3).collect(),
+                    water_h0_posits: s.water_h0_posits.iter().map(arr_to_vec3).collect(),
+                    water_h1_posits: s.water_h1_posits.iter().map(arr_to_vec3).collect(),
+                    water_velocities: Vec::new(),
+                    energy_kinetic: s.energy_kinetic as _,
+                    energy_potential: s.energy_potential as _,
+                    energy_potential_between_mols: Vec::new(),
+                    hydrogen_bonds: Vec::new(),
+                    temperature: s.temperature as _,
+                    pressure: s.pressure as _,
+                })
+                .collect();
+
+            self.mol_dynamics = Some(md);
+            self.ui.current_snapshot = 0;
+        }
+
+        self.to_save.selection = session.selection.into();
+        self.to_save.cam_snapshots = session.cam_snapshots.into_iter().map(Into::into).collect();
+        self.to_save.mol_view = mol_view_from_u8(session.mol_view);
+        self.to_save.view_sel_level = view_sel_level_from_u8(session.view_sel_level);
+        self.to_save.visibility = session.visibility.into();
+        self.to_save.near_sel_only = session.near_sel_only;
+        self.to_save.near_lig_only = session.near_lig_only;
+        self.to_save.nearby_dist_thresh = session.nearby_dist_thresh;
+        self.to_save.md_config = session.md_config.into();
+        self.to_save.num_md_steps = session.num_md_steps;
+        self.to_save.md_dt = session.md_dt;
+        self.to_save.ph = session.ph;
+
+        self.update_from_prefs();
+
+        if let Some(s) = scene {
+            draw_all_ligs(self, s);
+            draw_all_nucleic_acids(self, s);
+            draw_all_lipids(self, s);
+            draw_peptide(self, s);
+
+            // Restore the camera, using the snapshot machinery.
+            self.cam_snapshots.push(session.camera.into());
+            self.ui.cam_snapshot = Some(self.cam_snapshots.len() - 1);
+            load_snap(self, s, engine_updates);
+            self.cam_snapshots.pop();
+            self.ui.cam_snapshot = None;
+        }
+
+        self.volatile.flags.new_mol_loaded = true;
+        engine_updates.entities = EntityUpdate::All;
+
+        self.update_save_prefs(false);
+
+        Ok(())
+    }
+}
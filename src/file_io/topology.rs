@@ -0,0 +1,994 @@
+//! Assembles a fully-parameterized system from open molecules, for export to the topology
+//! formats of other MD packages. (Amber prmtop, GROMACS top) We look up parameters the same
+//! way our own MD does: Molecule-specific overrides (e.g. from FRCMOD files) first, then the general
+//! set for the molecule's type.
+//!
+//! Importers for these formats also build a `SystemTopology`, which we convert to molecules.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    io::ErrorKind,
+    ops::Range,
+    str::FromStr,
+};
+
+use bio_files::{
+    AtomGeneric, BondGeneric, BondType, ChainGeneric, ResidueEnd, ResidueGeneric, ResidueType,
+    md_params::ForceFieldParams,
+};
+use dynamics::{FfMolType, params::FfParamSet};
+use lin_alg::f64::Vec3;
+use na_seq::{AminoAcid, AtomTypeInRes, Element};
+
+use crate::{
+    State,
+    file_io::pdb::{atom_name, res_name},
+    mol_lig::MoleculeSmall,
+    molecule::{
+        Atom, Bond, MoleculeCommon, MoleculeGeneric, MoleculePeptide, Residue,
+        init_bonds_chains_res,
+    },
+};
+
+// OPC water model parameters. This is the water model our MD uses.
+pub const OPC_R_OH: f32 = 0.8724; // Å
+pub const OPC_R_HH: f32 = 1.3712; // Å
+/// Distance from O to the virtual charge site (M), along the H-O-H bisector.
+pub const OPC_R_OM: f32 = 0.1594; // Å
+pub const OPC_Q_H: f32 = 0.6791;
+pub const OPC_Q_M: f32 = -1.3582;
+pub const OPC_SIGMA_O: f32 = 3.16655; // Å
+pub const OPC_EPS_O: f32 = 0.2128; // kcal/mol
+// Used for rigid water in packages that constrain via bonds. (e.g. Amber's SETTLE via a H-H bond)
+const WATER_K_B: f32 = 553.;
+
+pub const WATER_RES_NAME: &str = "WAT";
+pub const FF_TYPE_WATER_O: &str = "OW";
+pub const FF_TYPE_WATER_H: &str = "HW";
+pub const FF_TYPE_EXTRA_PT: &str = "EP";
+
+/// Element symbols, indexed by atomic number - 1.
+pub const ELEMENT_SYMBOLS: [&str; 54] = [
+    "H", "He", "Li", "Be", "B", "C", "N", "O", "F", "Ne", "Na", "Mg", "Al", "Si", "P", "S", "Cl",
+    "Ar", "K", "Ca", "Sc", "Ti", "V", "Cr", "Mn", "Fe", "Co", "Ni", "Cu", "Zn", "Ga", "Ge", "As",
+    "Se", "Br", "Kr", "Rb", "Sr", "Y", "Zr", "Nb", "Mo", "Tc", "Ru", "Rh", "Pd", "Ag", "Cd", "In",
+    "Sn", "Sb", "Te", "I", "Xe",
+];
+
+/// Returns 0 for elements not in our table.
+pub fn atomic_number(el: Element) -> usize {
+    let sym = el.to_letter();
+    match ELEMENT_SYMBOLS
+        .iter()
+        .position(|s| s.eq_ignore_ascii_case(&sym))
+    {
+        Some(i) => i + 1,
+        None => 0,
+    }
+}
+
+pub fn element_from_atomic_number(num: usize) -> io::Result<Element> {
+    if num == 0 || num > ELEMENT_SYMBOLS.len() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Unsupported atomic number: {num}"),
+        ));
+    }
+    Element::from_letter(ELEMENT_SYMBOLS[num - 1])
+}
+
+#[derive(Clone, Debug)]
+pub struct TopAtom {
+    pub name: String,
+    pub ff_type: String,
+    /// `None` for virtual sites, e.g. the OPC water charge site.
+    pub element: Option<Element>,
+    /// Elementary charge.
+    pub charge: f32,
+    /// Daltons.
+    pub mass: f32,
+    /// Index into `SystemTopology::residues`.
+    pub residue: usize,
+    pub posit: Vec3,
+}
+
+#[derive(Clone, Debug)]
+pub struct TopResidue {
+    pub name: String,
+    pub serial_number: u32,
+    pub first_atom: usize,
+}
+
+/// A named collection of atoms, e.g. a ligand or a water molecule. Atom indices are contiguous.
+#[derive(Clone, Debug)]
+pub struct TopMolecule {
+    pub name: String,
+    pub atoms: Range<usize>,
+    pub is_water: bool,
+}
+
+/// Amber units and conventions: kcal/mol/Å², with no 1/2 factor.
+#[derive(Clone, Debug)]
+pub struct TopBond {
+    pub atoms: (usize, usize),
+    pub k_b: f32,
+    /// Å
+    pub r_0: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct TopAngle {
+    pub atoms: (usize, usize, usize),
+    /// kcal/mol/rad²
+    pub k: f32,
+    /// Radians
+    pub theta_0: f32,
+}
+
+/// One Fourier term. Dihedrals with multiple terms are represented by one of these per term.
+#[derive(Clone, Debug)]
+pub struct TopDihedral {
+    pub atoms: (usize, usize, usize, usize),
+    /// kcal/mol; barrier height divided by the Amber divider.
+    pub k: f32,
+    pub periodicity: i32,
+    /// Radians
+    pub phase: f32,
+    /// For impropers, the hub atom is in position 2. (0-indexed)
+    pub improper: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TopLj {
+    /// Å
+    pub sigma: f32,
+    /// kcal/mol
+    pub eps: f32,
+}
+
+/// A parameterized system, ready for export.
+#[derive(Clone, Debug, Default)]
+pub struct SystemTopology {
+    pub title: String,
+    pub atoms: Vec<TopAtom>,
+    pub residues: Vec<TopResidue>,
+    pub molecules: Vec<TopMolecule>,
+    pub bonds: Vec<TopBond>,
+    pub angles: Vec<TopAngle>,
+    pub dihedrals: Vec<TopDihedral>,
+    /// By FF type, in order of first appearance.
+    pub lj: Vec<(String, TopLj)>,
+    /// Periodic box side lengths, in Å.
+    pub box_size: Option<Vec3>,
+}
+
+/// Parameter sets to search, in priority order.
+struct ParamSources<'a> {
+    sets: Vec<&'a ForceFieldParams>,
+}
+
+impl<'a> ParamSources<'a> {
+    fn bond(&self, a: &str, b: &str) -> Option<(f32, f32)> {
+        for p in &self.sets {
+            for key in [(a, b), (b, a)] {
+                if let Some(v) = p.bond.get(&(key.0.to_owned(), key.1.to_owned())) {
+                    return Some((v.k_b as f32, v.r_0 as f32));
+                }
+            }
+        }
+        None
+    }
+
+    fn angle(&self, a: &str, b: &str, c: &str) -> Option<(f32, f32)> {
+        for p in &self.sets {
+            for key in [(a, b, c), (c, b, a)] {
+                if let Some(v) =
+                    p.angle
+                        .get(&(key.0.to_owned(), key.1.to_owned(), key.2.to_owned()))
+                {
+                    return Some((v.k as f32, v.theta_0 as f32));
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns (k, periodicity, phase) for each term. Handles "X" wildcards in the outer positions.
+    fn dihedral(&self, a: &str, b: &str, c: &str, d: &str, improper: bool) -> Vec<(f32, i32, f32)> {
+        let keys = if improper {
+            // The hub is in position 2; the order of the others varies between sources.
+            vec![
+                (a, b, c, d),
+                (b, a, c, d),
+                (a, d, c, b),
+                (d, a, c, b),
+                (b, d, c, a),
+                (d, b, c, a),
+                ("X", b, c, d),
+                ("X", a, c, d),
+                ("X", d, c, b),
+                ("X", "X", c, d),
+                ("X", "X", c, b),
+                ("X", "X", c, a),
+            ]
+        } else {
+            vec![
+                (a, b, c, d),
+                (d, c, b, a),
+                ("X", b, c, "X"),
+                ("X", c, b, "X"),
+            ]
+        };
+
+        for p in &self.sets {
+            let map = if improper { &p.improper } else { &p.dihedral };
+
+            for key in &keys {
+                let key = (
+                    key.0.to_owned(),
+                    key.1.to_owned(),
+                    key.2.to_owned(),
+                    key.3.to_owned(),
+                );
+
+                if let Some(terms) = map.get(&key) {
+                    return terms
+                        .iter()
+                        .map(|t| {
+                            let divider = t.divider as f32;
+                            let divider = if divider == 0. { 1. } else { divider };
+                            (
+                                t.barrier_height as f32 / divider,
+                                t.periodicity as i32,
+                                t.phase as f32,
+                            )
+                        })
+                        .collect();
+                }
+            }
+        }
+        Vec::new()
+    }
+
+    fn lj(&self, ff_type: &str) -> Option<TopLj> {
+        for p in &self.sets {
+            if let Some(v) = p.lennard_jones.get(ff_type) {
+                return Some(TopLj {
+                    sigma: v.sigma as f32,
+                    eps: v.eps as f32,
+                });
+            }
+        }
+        None
+    }
+}
+
+/// General parameters for a molecule type.
+pub fn general_params(param_set: &FfParamSet, ff_mol_type: FfMolType) -> Option<&ForceFieldParams> {
+    match ff_mol_type {
+        FfMolType::Peptide => param_set.peptide.as_ref(),
+        FfMolType::SmallOrganic => param_set.small_mol.as_ref(),
+        FfMolType::Dna => param_set.dna.as_ref(),
+        FfMolType::Lipid => param_set.lipids.as_ref(),
+        _ => None,
+    }
+}
+
+impl SystemTopology {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Add a molecule. `include` filters atoms by index, e.g. to skip hetero atoms in a protein.
+    /// Bonded terms are derived from the bond graph; any that are missing parameters cause
+    /// an error, as the result wouldn't be usable by other MD packages.
+    pub fn add_mol(
+        &mut self,
+        mol: &MoleculeCommon,
+        residues: &[Residue],
+        include: Option<&[usize]>,
+        general: Option<&ForceFieldParams>,
+        specific: Option<&ForceFieldParams>,
+    ) -> io::Result<()> {
+        let mut sets = Vec::new();
+        if let Some(s) = specific {
+            sets.push(s);
+        }
+        if let Some(g) = general {
+            sets.push(g);
+        }
+        let params = ParamSources { sets };
+
+        let atom_is: Vec<usize> = match include {
+            Some(v) => v.to_vec(),
+            None => (0..mol.atoms.len()).collect(),
+        };
+
+        let start = self.atoms.len();
+
+        // Local atom index to index in this system.
+        let mut index_map: HashMap<usize, usize> = HashMap::with_capacity(atom_is.len());
+        // Residue index in the molecule to residue index in this system.
+        let mut res_map: HashMap<Option<usize>, usize> = HashMap::new();
+
+        // Used for atoms not part of a residue; e.g. small molecules.
+        let default_res_name: String = {
+            let n: String = mol
+                .ident
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .take(3)
+                .collect::<String>()
+                .to_uppercase();
+            if n.is_empty() { "MOL".to_owned() } else { n }
+        };
+
+        for &i in &atom_is {
+            let atom = &mol.atoms[i];
+
+            let (Some(ff_type), Some(charge)) = (&atom.force_field_type, atom.partial_charge)
+            else {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Atom {} in {} is missing its force field type or partial charge",
+                        atom.serial_number, mol.ident
+                    ),
+                ));
+            };
+
+            let res_i = match res_map.get(&atom.residue) {
+                Some(r) => *r,
+                None => {
+                    let (name, serial_number) = match atom.residue.and_then(|r| residues.get(r)) {
+                        Some(r) => (res_name(&r.res_type), r.serial_number),
+                        None => (default_res_name.clone(), self.residues.len() as u32 + 1),
+                    };
+
+                    self.residues.push(TopResidue {
+                        name,
+                        serial_number,
+                        first_atom: self.atoms.len(),
+                    });
+                    res_map.insert(atom.residue, self.residues.len() - 1);
+                    self.residues.len() - 1
+                }
+            };
+
+            if !self.lj.iter().any(|(t, _)| t == ff_type) {
+                let lj = params.lj(ff_type).ok_or_else(|| {
+                    io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Missing Lennard-Jones parameters for type {ff_type}"),
+                    )
+                })?;
+                self.lj.push((ff_type.clone(), lj));
+            }
+
+            index_map.insert(i, self.atoms.len());
+
+            self.atoms.push(TopAtom {
+                name: atom_name(atom),
+                ff_type: ff_type.clone(),
+                element: Some(atom.element),
+                charge,
+                mass: atom.element.atomic_weight() as f32,
+                residue: res_i,
+                posit: mol.atom_posits.get(i).copied().unwrap_or(atom.posit),
+            });
+        }
+
+        let bonds: Vec<&Bond> = mol
+            .bonds
+            .iter()
+            .filter(|b| index_map.contains_key(&b.atom_0) && index_map.contains_key(&b.atom_1))
+            .collect();
+
+        // Adjacency in system indices, for atoms in this molecule.
+        let mut adj: HashMap<usize, Vec<usize>> = HashMap::new();
+
+        for bond in &bonds {
+            let (i0, i1) = (index_map[&bond.atom_0], index_map[&bond.atom_1]);
+            adj.entry(i0).or_default().push(i1);
+            adj.entry(i1).or_default().push(i0);
+
+            let (t0, t1) = (&self.atoms[i0].ff_type, &self.atoms[i1].ff_type);
+            let (k_b, r_0) = params.bond(t0, t1).ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Missing bond parameters for {t0}-{t1}"),
+                )
+            })?;
+
+            self.bonds.push(TopBond {
+                atoms: (i0, i1),
+                k_b,
+                r_0,
+            });
+        }
+
+        let mut centers: Vec<_> = adj.keys().copied().collect();
+        centers.sort();
+
+        for &ctr in &centers {
+            let nbrs = &adj[&ctr];
+            for a in 0..nbrs.len() {
+                for b in a + 1..nbrs.len() {
+                    let (i0, i2) = (nbrs[a], nbrs[b]);
+                    let types = (
+                        &self.atoms[i0].ff_type,
+                        &self.atoms[ctr].ff_type,
+                        &self.atoms[i2].ff_type,
+                    );
+
+                    let (k, theta_0) =
+                        params.angle(types.0, types.1, types.2).ok_or_else(|| {
+                            io::Error::new(
+                                ErrorKind::InvalidData,
+                                format!(
+                                    "Missing angle parameters for {}-{}-{}",
+                                    types.0, types.1, types.2
+                                ),
+                            )
+                        })?;
+
+                    self.angles.push(TopAngle {
+                        atoms: (i0, ctr, i2),
+                        k,
+                        theta_0,
+                    });
+                }
+            }
+        }
+
+        // Proper dihedrals, around each bond.
+        for bond in &bonds {
+            let (i1, i2) = (index_map[&bond.atom_0], index_map[&bond.atom_1]);
+
+            for &i0 in adj[&i1].iter().filter(|&&x| x != i2) {
+                for &i3 in adj[&i2].iter().filter(|&&x| x != i1) {
+                    if i0 == i3 {
+                        continue;
+                    }
+
+                    let t = [i0, i1, i2, i3].map(|i| self.atoms[i].ff_type.as_str());
+                    let terms = params.dihedral(t[0], t[1], t[2], t[3], false);
+
+                    // Unlike with impropers, a missing proper dihedral is an error in Amber.
+                    if terms.is_empty() {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "Missing dihedral parameters for {}-{}-{}-{}",
+                                t[0], t[1], t[2], t[3]
+                            ),
+                        ));
+                    }
+
+                    for (k, periodicity, phase) in terms {
+                        self.dihedrals.push(TopDihedral {
+                            atoms: (i0, i1, i2, i3),
+                            k,
+                            periodicity,
+                            phase,
+                            improper: false,
+                        });
+                    }
+                }
+            }
+        }
+
+        // Impropers. These are only defined for some planar hubs, so missing ones are expected.
+        for &ctr in &centers {
+            let nbrs = &adj[&ctr];
+            if nbrs.len() != 3 {
+                continue;
+            }
+            let t = [nbrs[0], nbrs[1], ctr, nbrs[2]].map(|i| self.atoms[i].ff_type.as_str());
+
+            for (k, periodicity, phase) in params.dihedral(t[0], t[1], t[2], t[3], true) {
+                self.dihedrals.push(TopDihedral {
+                    atoms: (nbrs[0], nbrs[1], ctr, nbrs[2]),
+                    k,
+                    periodicity,
+                    phase,
+                    improper: true,
+                });
+            }
+        }
+
+        let is_water = !residues.is_empty()
+            && atom_is.iter().all(|&i| match mol.atoms[i].residue {
+                Some(r) => residues.get(r).map(|r| &r.res_type) == Some(&ResidueType::Water),
+                None => false,
+            });
+
+        self.molecules.push(TopMolecule {
+            name: mol.ident.clone(),
+            atoms: start..self.atoms.len(),
+            is_water,
+        });
+
+        Ok(())
+    }
+
+    /// Add an OPC water molecule, including its virtual charge site.
+    pub fn add_water(&mut self, o: Vec3, h0: Vec3, h1: Vec3) {
+        let start = self.atoms.len();
+
+        for (ff_type, lj) in [
+            (
+                FF_TYPE_WATER_O,
+                TopLj {
+                    sigma: OPC_SIGMA_O,
+                    eps: OPC_EPS_O,
+                },
+            ),
+            (FF_TYPE_WATER_H, TopLj::default()),
+            (FF_TYPE_EXTRA_PT, TopLj::default()),
+        ] {
+            if !self.lj.iter().any(|(t, _)| t == ff_type) {
+                self.lj.push((ff_type.to_owned(), lj));
+            }
+        }
+
+        self.residues.push(TopResidue {
+            name: WATER_RES_NAME.to_owned(),
+            serial_number: self.residues.len() as u32 + 1,
+            first_atom: start,
+        });
+        let residue = self.residues.len() - 1;
+
+        let bisector = ((h0 - o) + (h1 - o)).to_normalized();
+        let m = o + bisector * OPC_R_OM as f64;
+
+        let h_mass = Element::Hydrogen.atomic_weight() as f32;
+
+        for (name, ff_type, element, charge, mass, posit) in [
+            (
+                "O",
+                FF_TYPE_WATER_O,
+                Some(Element::Oxygen),
+                0.,
+                Element::Oxygen.atomic_weight() as f32,
+                o,
+            ),
+            (
+                "H1",
+                FF_TYPE_WATER_H,
+                Some(Element::Hydrogen),
+                OPC_Q_H,
+                h_mass,
+                h0,
+            ),
+            (
+                "H2",
+                FF_TYPE_WATER_H,
+                Some(Element::Hydrogen),
+                OPC_Q_H,
+                h_mass,
+                h1,
+            ),
+            ("EPW", FF_TYPE_EXTRA_PT, None, OPC_Q_M, 0., m),
+        ] {
+            self.atoms.push(TopAtom {
+                name: name.to_owned(),
+                ff_type: ff_type.to_owned(),
+                element,
+                charge,
+                mass,
+                residue,
+                posit,
+            });
+        }
+
+        let (i_o, i_h0, i_h1, i_m) = (start, start + 1, start + 2, start + 3);
+
+        for (atoms, r_0) in [
+            ((i_o, i_h0), OPC_R_OH),
+            ((i_o, i_h1), OPC_R_OH),
+            ((i_h0, i_h1), OPC_R_HH),
+            ((i_o, i_m), OPC_R_OM),
+        ] {
+            self.bonds.push(TopBond {
+                atoms,
+                k_b: WATER_K_B,
+                r_0,
+            });
+        }
+
+        self.molecules.push(TopMolecule {
+            name: WATER_RES_NAME.to_owned(),
+            atoms: start..self.atoms.len(),
+            is_water: true,
+        });
+    }
+
+    /// Build from molecules selected for MD, including water and the sim box from the current MD
+    /// snapshot, if available.
+    pub fn from_state(state: &State) -> io::Result<Self> {
+        let mut result = Self::new("Molchanica system");
+        let param_set = &state.ff_param_set;
+
+        if let Some(pep) = &state.peptide
+            && pep.common.selected_for_md
+        {
+            // We assume hetero atoms are ligands, water etc, and are not part of the protein;
+            // this is consistent with our MD setup.
+            let include: Vec<_> = pep
+                .common
+                .atoms
+                .iter()
+                .enumerate()
+                .filter(|(_, a)| !a.hetero)
+                .map(|(i, _)| i)
+                .collect();
+
+            result.add_mol(
+                &pep.common,
+                &pep.residues,
+                Some(&include),
+                general_params(param_set, FfMolType::Peptide),
+                None,
+            )?;
+        }
+
+        let mut mols = Vec::new();
+        for m in state.ligands.iter().filter(|m| m.common.selected_for_md) {
+            mols.push((FfMolType::SmallOrganic, &m.common, &[][..]));
+        }
+        for m in state.lipids.iter().filter(|m| m.common.selected_for_md) {
+            mols.push((FfMolType::Lipid, &m.common, &m.residues[..]));
+        }
+        for m in state
+            .nucleic_acids
+            .iter()
+            .filter(|m| m.common.selected_for_md)
+        {
+            mols.push((FfMolType::Dna, &m.common, &m.residues[..]));
+        }
+
+        for (ff_mol_type, mol, residues) in mols {
+            result.add_mol(
+                mol,
+                residues,
+                None,
+                general_params(param_set, ff_mol_type),
+                state.mol_specific_params.get(&mol.ident),
+            )?;
+        }
+
+        if result.atoms.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "No molecules are selected for MD",
+            ));
+        }
+
+        if let Some(md) = &state.mol_dynamics {
+            if let Some(snap) = md.snapshots.get(state.ui.current_snapshot) {
+                for (i, o) in snap.water_o_posits.iter().enumerate() {
+                    let (Some(h0), Some(h1)) =
+                        (snap.water_h0_posits.get(i), snap.water_h1_posits.get(i))
+                    else {
+                        continue;
+                    };
+                    result.add_water((*o).into(), (*h0).into(), (*h1).into());
+                }
+            }
+
+            let size = md.cell.bounds_high - md.cell.bounds_low;
+            result.box_size = Some(Vec3::new(size.x as f64, size.y as f64, size.z as f64));
+        }
+
+        Ok(result)
+    }
+
+    /// Atoms in each connected component, in order. Used by formats that need molecule
+    /// boundaries, e.g. for pressure scaling.
+    pub fn molecule_sizes(&self) -> Vec<usize> {
+        let mut parent: Vec<usize> = (0..self.atoms.len()).collect();
+
+        fn find(parent: &mut [usize], i: usize) -> usize {
+            let mut root = i;
+            while parent[root] != root {
+                root = parent[root];
+            }
+            parent[i] = root;
+            root
+        }
+
+        for bond in &self.bonds {
+            let r0 = find(&mut parent, bond.atoms.0);
+            let r1 = find(&mut parent, bond.atoms.1);
+            if r0 != r1 {
+                parent[r0.max(r1)] = r0.min(r1);
+            }
+        }
+
+        let mut result: Vec<usize> = Vec::new();
+        let mut prev_root = None;
+        for i in 0..self.atoms.len() {
+            let root = find(&mut parent, i);
+            if Some(root) == prev_root {
+                *result.last_mut().unwrap() += 1;
+            } else {
+                result.push(1);
+                prev_root = Some(root);
+            }
+        }
+
+        result
+    }
+
+    /// Atom pairs separated by 1, 2, or 3 bonds, and all pairs within each water molecule. Each
+    /// pair is listed once, with the lower index first.
+    pub fn exclusions(&self) -> Vec<Vec<usize>> {
+        let mut result = vec![Vec::new(); self.atoms.len()];
+
+        let mut add = |a: usize, b: usize| {
+            let (lo, hi) = (a.min(b), a.max(b));
+            if lo != hi && !result[lo].contains(&hi) {
+                result[lo].push(hi);
+            }
+        };
+
+        for b in &self.bonds {
+            add(b.atoms.0, b.atoms.1);
+        }
+        for a in &self.angles {
+            add(a.atoms.0, a.atoms.2);
+        }
+        for d in self.dihedrals.iter().filter(|d| !d.improper) {
+            add(d.atoms.0, d.atoms.3);
+        }
+
+        // Virtual sites of 4-point water models, e.g. OPC's EP, have no bonds to exclude them
+        // from their molecule's other atoms.
+        let mut waters: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (i, atom) in self.atoms.iter().enumerate() {
+            if is_water_res(&self.residues[atom.residue].name) {
+                waters.entry(atom.residue).or_default().push(i);
+            }
+        }
+        for atoms in waters.values() {
+            for (j, &a) in atoms.iter().enumerate() {
+                for &b in &atoms[j + 1..] {
+                    add(a, b);
+                }
+            }
+        }
+
+        for r in &mut result {
+            r.sort();
+        }
+        result
+    }
+}
+
+/// Guess the element from mass, for topologies that don't list atomic numbers.
+pub fn element_from_mass(mass: f64) -> Option<Element> {
+    ELEMENT_SYMBOLS
+        .iter()
+        .filter_map(|s| Element::from_letter(s).ok())
+        .map(|el| (el, (el.atomic_weight() as f64 - mass).abs()))
+        .filter(|(_, diff)| *diff < 0.5)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(el, _)| el)
+}
+
+/// Map residue names used by Amber force fields for protonation states and termini to standard
+/// amino acids. e.g. "HIE", "CYX", "NALA".
+fn res_name_to_aa(name: &str) -> Option<AminoAcid> {
+    let name = if name.len() == 4 && (name.starts_with('N') || name.starts_with('C')) {
+        &name[1..]
+    } else {
+        name
+    };
+
+    let std_name = match name {
+        "HIE" | "HID" | "HIP" => "HIS",
+        "CYX" | "CYM" => "CYS",
+        "ASH" => "ASP",
+        "GLH" => "GLU",
+        "LYN" => "LYS",
+        _ => name,
+    };
+
+    AminoAcid::from_str(std_name).ok()
+}
+
+fn is_water_res(name: &str) -> bool {
+    matches!(name, "WAT" | "HOH" | "SOL")
+}
+
+impl SystemTopology {
+    /// Build molecules from an imported topology. Amino acid residues make up the protein; other
+    /// non-water molecules are returned as small molecules, by connected component. FF types and
+    /// partial charges are kept. Water and virtual sites are skipped.
+    pub fn to_mols(&self, ident: &str) -> io::Result<Vec<MoleculeGeneric>> {
+        let res_aa: Vec<Option<AminoAcid>> = self
+            .residues
+            .iter()
+            .map(|r| res_name_to_aa(&r.name))
+            .collect();
+
+        let atom_gen = |i: usize, hetero: bool| -> Option<AtomGeneric> {
+            let atom = &self.atoms[i];
+            let type_in_res = if hetero {
+                None
+            } else {
+                AtomTypeInRes::from_str(&atom.name).ok()
+            };
+
+            Some(AtomGeneric {
+                serial_number: i as u32 + 1,
+                posit: atom.posit,
+                element: atom.element?,
+                type_in_res,
+                type_in_res_general: Some(atom.name.clone()),
+                force_field_type: Some(atom.ff_type.clone()),
+                partial_charge: Some(atom.charge),
+                hetero,
+                ..Default::default()
+            })
+        };
+
+        let mut result = Vec::new();
+
+        // The protein.
+        let pep_atoms: Vec<AtomGeneric> = (0..self.atoms.len())
+            .filter(|i| res_aa[self.atoms[*i].residue].is_some())
+            .filter_map(|i| atom_gen(i, false))
+            .collect();
+
+        if !pep_atoms.is_empty() {
+            let pep_sns: HashSet<u32> = pep_atoms.iter().map(|a| a.serial_number).collect();
+
+            let bonds: Vec<BondGeneric> = self
+                .bonds
+                .iter()
+                .map(|b| (b.atoms.0 as u32 + 1, b.atoms.1 as u32 + 1))
+                .filter(|(a, b)| pep_sns.contains(a) && pep_sns.contains(b))
+                .map(|(a, b)| BondGeneric {
+                    bond_type: BondType::Single,
+                    atom_0_sn: a,
+                    atom_1_sn: b,
+                })
+                .collect();
+
+            let mut residues = Vec::new();
+            let mut chains: Vec<ChainGeneric> = Vec::new();
+            let mut prev_res_sns: Option<Vec<u32>> = None;
+
+            for (r, aa) in res_aa.iter().enumerate() {
+                let Some(aa) = aa else {
+                    continue;
+                };
+
+                let atom_sns: Vec<u32> = pep_atoms
+                    .iter()
+                    .filter(|a| self.atoms[a.serial_number as usize - 1].residue == r)
+                    .map(|a| a.serial_number)
+                    .collect();
+
+                // Start a new chain if this residue isn't bonded to the previous one.
+                let continues = match &prev_res_sns {
+                    Some(prev) => bonds.iter().any(|b| {
+                        (prev.contains(&b.atom_0_sn) && atom_sns.contains(&b.atom_1_sn))
+                            || (prev.contains(&b.atom_1_sn) && atom_sns.contains(&b.atom_0_sn))
+                    }),
+                    None => false,
+                };
+
+                if !continues {
+                    let id = char::from(b'A' + (chains.len() % 26) as u8).to_string();
+                    chains.push(ChainGeneric {
+                        id,
+                        residue_sns: Vec::new(),
+                        atom_sns: Vec::new(),
+                    });
+                }
+
+                let serial_number = self.residues[r].serial_number;
+
+                let chain = chains.last_mut().unwrap();
+                chain.residue_sns.push(serial_number);
+                chain.atom_sns.extend(&atom_sns);
+
+                residues.push(ResidueGeneric {
+                    serial_number,
+                    res_type: ResidueType::AminoAcid(*aa),
+                    atom_sns: atom_sns.clone(),
+                    end: ResidueEnd::Internal,
+                });
+
+                prev_res_sns = Some(atom_sns);
+            }
+
+            let (atoms, bonds, residues, chains) =
+                init_bonds_chains_res(&pep_atoms, &bonds, &residues, &chains, &[])?;
+
+            result.push(MoleculeGeneric::Peptide(MoleculePeptide::new(
+                ident.to_owned(),
+                atoms,
+                bonds,
+                chains,
+                residues,
+                HashMap::new(),
+                None,
+            )));
+        }
+
+        // Other molecules, by connected component.
+        let is_other = |i: usize| {
+            let atom = &self.atoms[i];
+            res_aa[atom.residue].is_none()
+                && !is_water_res(&self.residues[atom.residue].name)
+                && atom.element.is_some()
+        };
+
+        let mut parent: Vec<usize> = (0..self.atoms.len()).collect();
+
+        fn find(parent: &mut [usize], i: usize) -> usize {
+            let mut root = i;
+            while parent[root] != root {
+                root = parent[root];
+            }
+            parent[i] = root;
+            root
+        }
+
+        for b in &self.bonds {
+            let (i0, i1) = b.atoms;
+            if !is_other(i0) || !is_other(i1) {
+                continue;
+            }
+            let (r0, r1) = (find(&mut parent, i0), find(&mut parent, i1));
+            if r0 != r1 {
+                parent[r0.max(r1)] = r0.min(r1);
+            }
+        }
+
+        let mut groups: Vec<(usize, Vec<usize>)> = Vec::new();
+        for i in (0..self.atoms.len()).filter(|i| is_other(*i)) {
+            let root = find(&mut parent, i);
+            match groups.iter_mut().find(|(r, _)| *r == root) {
+                Some((_, g)) => g.push(i),
+                None => groups.push((root, vec![i])),
+            }
+        }
+
+        for (_, group) in groups {
+            let atoms: Vec<Atom> = group
+                .iter()
+                .filter_map(|i| atom_gen(*i, true))
+                .map(|a| (&a).into())
+                .collect();
+
+            let bonds: Vec<Bond> = self
+                .bonds
+                .iter()
+                .filter(|b| group.contains(&b.atoms.0) && group.contains(&b.atoms.1))
+                .filter_map(|b| {
+                    let bond = BondGeneric {
+                        bond_type: BondType::Single,
+                        atom_0_sn: b.atoms.0 as u32 + 1,
+                        atom_1_sn: b.atoms.1 as u32 + 1,
+                    };
+                    Bond::from_generic(&bond, &atoms).ok()
+                })
+                .collect();
+
+            let name = self.residues[self.atoms[group[0]].residue].name.clone();
+            let mol = MoleculeSmall::new(name, atoms, bonds, HashMap::new(), None);
+            result.push(MoleculeGeneric::Ligand(mol));
+        }
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,1507 @@
+//! MD trajectories: DCD (CHARMM/NAMD), XTC (GROMACS), and multi-model PDB and mmCIF. We write
+//! snapshots from `MdState` to these, and read them back for playback.
+//!
+//! Atoms are in snapshot order: Molecules selected for MD, then water as O, H, H triplets.
+
+use std::{
+    fmt::Write,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use dynamics::{MdState, snapshot::Snapshot};
+use graphics::{EngineUpdates, EntityUpdate, Scene};
+use lin_alg::f64::Vec3;
+use na_seq::Element;
+
+use crate::{
+    State,
+    drawing::{draw_peptide, draw_water},
+    drawing_wrappers::{draw_all_ligs, draw_all_lipids, draw_all_nucleic_acids},
+    file_io::{
+        mmcif::cif_val,
+        pdb::{atom_name, atom_res_chain, pdb_atom_name, res_name},
+    },
+    md::change_snapshot,
+    molecule::{MoleculeCommon, Residue},
+    util::handle_success,
+};
+
+/// DCD stores time in AKMA units.
+const AKMA_PER_PS: f64 = 1. / 0.048_888_21;
+const NM_PER_ANGSTROM: f64 = 0.1;
+
+const XTC_MAGIC: i32 = 1995;
+/// Positions are stored as integers in units of 1/precision nm.
+const XTC_PRECISION: f32 = 1_000.;
+
+/// Bits for a given run of small, delta-encoded integers in XTC. From xdrfile.
+const XTC_MAGIC_INTS: [i32; 73] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 8, 10, 12, 16, 20, 25, 32, 40, 50, 64, 80, 101, 128, 161, 203, 256,
+    322, 406, 512, 645, 812, 1024, 1290, 1625, 2048, 2580, 3250, 4096, 5060, 6501, 8192, 10321,
+    13003, 16384, 20642, 26007, 32768, 41285, 52015, 65536, 82570, 104031, 131072, 165140, 208063,
+    262144, 330280, 416127, 524287, 660561, 832255, 1048576, 1321122, 1664510, 2097152, 2642245,
+    3329021, 4194304, 5284491, 6658042, 8388607, 10568983, 13316085, 16777216,
+];
+const XTC_FIRST_IDX: usize = 9;
+
+/// Which atoms to include when exporting a trajectory.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TrajAtomSet {
+    #[default]
+    All,
+    /// Everything except water.
+    Solute,
+    Peptide,
+    Ligands,
+}
+
+impl TrajAtomSet {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::All => "All",
+            Self::Solute => "No water",
+            Self::Peptide => "Protein",
+            Self::Ligands => "Ligands",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrajAtomSource {
+    Peptide,
+    Ligand,
+    Other,
+    Water,
+}
+
+/// Atom details used for text formats, and for filtering.
+#[derive(Clone, Debug)]
+pub struct TrajAtom {
+    pub name: String,
+    pub res_name: String,
+    pub res_sn: u32,
+    pub chain_id: String,
+    pub element: Element,
+    pub hetero: bool,
+    pub source: TrajAtomSource,
+}
+
+impl TrajAtom {
+    pub fn included(&self, set: TrajAtomSet) -> bool {
+        match set {
+            TrajAtomSet::All => true,
+            TrajAtomSet::Solute => self.source != TrajAtomSource::Water,
+            TrajAtomSet::Peptide => self.source == TrajAtomSource::Peptide,
+            TrajAtomSet::Ligands => self.source == TrajAtomSource::Ligand,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TrajFrame {
+    pub step: u32,
+    /// ps
+    pub time: f64,
+    /// Å
+    pub posits: Vec<Vec3>,
+    /// Rectangular box side lengths, in Å.
+    pub box_size: Option<Vec3>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrajFormat {
+    Dcd,
+    Xtc,
+    Pdb,
+    Mmcif,
+}
+
+impl TrajFormat {
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let ext = path
+            .extension()
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+            .to_string_lossy()
+            .to_string();
+
+        match ext.as_str() {
+            "dcd" => Ok(Self::Dcd),
+            "xtc" => Ok(Self::Xtc),
+            "pdb" => Ok(Self::Pdb),
+            "cif" => Ok(Self::Mmcif),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Unsupported trajectory format. Use DCD, XTC, PDB, or mmCIF.",
+            )),
+        }
+    }
+}
+
+/// Serialize frames. `atoms` is required by the text formats, for atom and residue names.
+pub fn write_traj(format: TrajFormat, frames: &[TrajFrame], atoms: &[TrajAtom]) -> Vec<u8> {
+    match format {
+        TrajFormat::Dcd => write_dcd(frames),
+        TrajFormat::Xtc => write_xtc(frames),
+        TrajFormat::Pdb => write_pdb_models(frames, atoms).into_bytes(),
+        TrajFormat::Mmcif => write_mmcif_models(frames, atoms).into_bytes(),
+    }
+}
+
+pub fn read_traj(format: TrajFormat, data: &[u8]) -> io::Result<Vec<TrajFrame>> {
+    let text = || {
+        str::from_utf8(data)
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Trajectory file isn't valid text"))
+    };
+
+    let frames = match format {
+        TrajFormat::Dcd => read_dcd(data)?,
+        TrajFormat::Xtc => read_xtc(data)?,
+        TrajFormat::Pdb => read_pdb_models(text()?)?,
+        TrajFormat::Mmcif => read_mmcif_models(text()?)?,
+    };
+
+    if frames.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "No frames found in the trajectory",
+        ));
+    }
+
+    let n_atoms = frames[0].posits.len();
+    if frames.iter().any(|f| f.posits.len() != n_atoms) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Trajectory frames have differing atom counts",
+        ));
+    }
+
+    Ok(frames)
+}
+
+/// Atoms for non-peptide molecules. Uses residues if available, e.g. for lipid head groups and nucleotides.
+fn mol_traj_atoms(
+    common: &MoleculeCommon,
+    residues: &[Residue],
+    default_res_name: &str,
+    chain_id: &str,
+    source: TrajAtomSource,
+    result: &mut Vec<TrajAtom>,
+) {
+    for atom in &common.atoms {
+        let res = atom.residue.and_then(|i| residues.get(i));
+
+        result.push(TrajAtom {
+            name: atom_name(atom),
+            res_name: match res {
+                Some(r) => res_name(&r.res_type),
+                None => default_res_name.to_owned(),
+            },
+            res_sn: res.map(|r| r.serial_number).unwrap_or(1),
+            chain_id: chain_id.to_owned(),
+            element: atom.element,
+            hetero: true,
+            source,
+        });
+    }
+}
+
+impl State {
+    /// Atoms in snapshot order, not including water. The peptide is included if the snapshots
+    /// contain it. This must be kept in sync with `reassign_snapshot_indices`.
+    fn traj_solute_atoms(&self, snap_atom_count: usize) -> io::Result<Vec<TrajAtom>> {
+        let mut result = Vec::new();
+
+        for mol in self.ligands.iter().filter(|m| m.common.selected_for_md) {
+            mol_traj_atoms(
+                &mol.common,
+                &[],
+                "LIG",
+                "L",
+                TrajAtomSource::Ligand,
+                &mut result,
+            );
+        }
+        for mol in self.lipids.iter().filter(|m| m.common.selected_for_md) {
+            mol_traj_atoms(
+                &mol.common,
+                &mol.residues,
+                "LIP",
+                "M",
+                TrajAtomSource::Other,
+                &mut result,
+            );
+        }
+        for mol in self
+            .nucleic_acids
+            .iter()
+            .filter(|m| m.common.selected_for_md)
+        {
+            mol_traj_atoms(
+                &mol.common,
+                &mol.residues,
+                "NA",
+                "N",
+                TrajAtomSource::Other,
+                &mut result,
+            );
+        }
+
+        if let Some(mol) = &self.peptide
+            && snap_atom_count == result.len() + mol.common.atoms.len()
+        {
+            for atom in &mol.common.atoms {
+                let (res, chain_id) = atom_res_chain(mol, atom);
+
+                result.push(TrajAtom {
+                    name: atom_name(atom),
+                    res_name: match res {
+                        Some(r) => res_name(&r.res_type),
+                        None => "UNK".to_owned(),
+                    },
+                    res_sn: res.map(|r| r.serial_number).unwrap_or(1),
+                    chain_id: chain_id.to_owned(),
+                    element: atom.element,
+                    hetero: atom.hetero,
+                    source: TrajAtomSource::Peptide,
+                });
+            }
+        }
+
+        if result.len() != snap_atom_count {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Snapshots have {snap_atom_count} atoms, but the molecules selected for MD have {}",
+                    result.len()
+                ),
+            ));
+        }
+
+        Ok(result)
+    }
+
+    /// Save MD snapshots as a trajectory. Every `stride`th snapshot is included, limited to
+    /// the atoms in `atom_set`.
+    pub fn save_trajectory(
+        &mut self,
+        path: &Path,
+        stride: usize,
+        atom_set: TrajAtomSet,
+    ) -> io::Result<()> {
+        let format = TrajFormat::from_path(path)?;
+
+        let Some(md) = &self.mol_dynamics else {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                "No MD snapshots to save",
+            ));
+        };
+        let Some(snap_0) = md.snapshots.first() else {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                "No MD snapshots to save",
+            ));
+        };
+
+        let mut atoms = self.traj_solute_atoms(snap_0.atom_posits.len())?;
+
+        for i in 0..snap_0.water_o_posits.len() {
+            for (name, element) in [
+                ("O", Element::Oxygen),
+                ("H1", Element::Hydrogen),
+                ("H2", Element::Hydrogen),
+            ] {
+                atoms.push(TrajAtom {
+                    name: name.to_owned(),
+                    res_name: "HOH".to_owned(),
+                    res_sn: i as u32 + 1,
+                    chain_id: "W".to_owned(),
+                    element,
+                    hetero: true,
+                    source: TrajAtomSource::Water,
+                });
+            }
+        }
+
+        let included: Vec<bool> = atoms.iter().map(|a| a.included(atom_set)).collect();
+
+        let size = md.cell.bounds_high - md.cell.bounds_low;
+        let box_size = Some(Vec3::new(size.x as f64, size.y as f64, size.z as f64));
+
+        let mut frames = Vec::new();
+        for snap in md.snapshots.iter().step_by(stride.max(1)) {
+            let water = snap
+                .water_o_posits
+                .iter()
+                .zip(&snap.water_h0_posits)
+                .zip(&snap.water_h1_posits)
+                .flat_map(|((o, h0), h1)| [o, h0, h1]);
+
+            let posits = snap
+                .atom_posits
+                .iter()
+                .chain(water)
+                .zip(&included)
+                .filter(|(_, inc)| **inc)
+                .map(|(p, _)| (*p).into())
+                .collect();
+
+            frames.push(TrajFrame {
+                step: (snap.time / self.to_save.md_dt as f64).round() as u32,
+                time: snap.time,
+                posits,
+                box_size,
+            });
+        }
+
+        let atoms: Vec<_> = atoms.into_iter().filter(|a| a.included(atom_set)).collect();
+        fs::write(path, write_traj(format, &frames, &atoms))?;
+
+        handle_success(
+            &mut self.ui,
+            format!(
+                "Saved {} frames with {} atoms to {}",
+                frames.len(),
+                atoms.len(),
+                path.file_name().unwrap_or_default().to_string_lossy()
+            ),
+        );
+
+        Ok(())
+    }
+
+    /// Load a trajectory as MD snapshots, for playback. Atoms must be in snapshot order, as
+    /// written by `save_trajectory` with all atoms: Molecules selected for MD, then water.
+    pub fn open_trajectory(
+        &mut self,
+        path: &Path,
+        scene: &mut Scene,
+        engine_updates: &mut EngineUpdates,
+    ) -> io::Result<()> {
+        let frames = read_traj(TrajFormat::from_path(path)?, &fs::read(path)?)?;
+        let n_atoms = frames[0].posits.len();
+
+        let non_pep_count: usize = self
+            .ligands
+            .iter()
+            .map(|m| &m.common)
+            .chain(self.lipids.iter().map(|m| &m.common))
+            .chain(self.nucleic_acids.iter().map(|m| &m.common))
+            .filter(|c| c.selected_for_md)
+            .map(|c| c.atoms.len())
+            .sum();
+
+        let pep_count = self.peptide.as_ref().map(|p| p.common.atoms.len());
+
+        // Whatever follows the molecules is water, as O, H, H.
+        let solute_count = match pep_count {
+            Some(pc)
+                if n_atoms >= non_pep_count + pc && (n_atoms - non_pep_count - pc) % 3 == 0 =>
+            {
+                non_pep_count + pc
+            }
+            _ if n_atoms >= non_pep_count && (n_atoms - non_pep_count) % 3 == 0 => non_pep_count,
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Trajectory has {n_atoms} atoms, which doesn't match the molecules selected for MD \
+                        ({non_pep_count} atoms, plus the protein if present)"
+                    ),
+                ));
+            }
+        };
+
+        // Let the player move the peptide if it's in the trajectory.
+        if let Some(mol) = &mut self.peptide {
+            mol.common.selected_for_md = solute_count > non_pep_count;
+        }
+
+        let snapshots: Vec<_> = frames
+            .iter()
+            .map(|frame| {
+                let to_f32 = |p: &Vec3| -> lin_alg::f32::Vec3 { (*p).into() };
+                let water = &frame.posits[solute_count..];
+
+                Snapshot {
+                    time: frame.time,
+                    atom_posits: frame.posits[..solute_count].iter().map(to_f32).collect(),
+                    atom_velocities: Vec::new(),
+                    water_o_posits: water.iter().step_by(3).map(to_f32).collect(),
+                    water_h0_posits: water.iter().skip(1).step_by(3).map(to_f32).collect(),
+                    water_h1_posits: water.iter().skip(2).step_by(3).map(to_f32).collect(),
+                    water_velocities: Vec::new(),
+                    energy_kinetic: 0.,
+                    energy_potential: 0.,
+                    energy_potential_between_mols: Vec::new(),
+                    hydrogen_bonds: Vec::new(),
+                    temperature: 0.,
+                    pressure: 0.,
+                }
+            })
+            .collect();
+
+        let n_frames = snapshots.len();
+        match &mut self.mol_dynamics {
+            Some(md) => md.snapshots = snapshots,
+            None => {
+                let mut md = MdState::default();
+                md.snapshots = snapshots;
+                self.mol_dynamics = Some(md);
+            }
+        }
+
+        self.ui.current_snapshot = 0;
+
+        {
+            let snap = &self.mol_dynamics.as_ref().unwrap().snapshots[0];
+
+            let ligs: Vec<_> = self
+                .ligands
+                .iter_mut()
+                .filter(|l| l.common.selected_for_md)
+                .collect();
+            let lipids: Vec<_> = self
+                .lipids
+                .iter_mut()
+                .filter(|l| l.common.selected_for_md)
+                .collect();
+            let nucleic_acids: Vec<_> = self
+                .nucleic_acids
+                .iter_mut()
+                .filter(|l| l.common.selected_for_md)
+                .collect();
+            let peptide = self.peptide.as_mut().filter(|p| p.common.selected_for_md);
+
+            change_snapshot(peptide, ligs, lipids, nucleic_acids, snap);
+
+            draw_water(
+                scene,
+                &snap.water_o_posits,
+                &snap.water_h0_posits,
+                &snap.water_h1_posits,
+                self.ui.visibility.hide_water,
+            );
+        }
+
+        draw_all_ligs(self, scene);
+        draw_all_lipids(self, scene);
+        draw_all_nucleic_acids(self, scene);
+        draw_peptide(self, scene);
+
+        engine_updates.entities = EntityUpdate::All;
+
+        handle_success(
+            &mut self.ui,
+            format!("Loaded a trajectory with {n_frames} frames and {n_atoms} atoms"),
+        );
+
+        Ok(())
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_owned())
+}
+
+// ---------- DCD ----------
+
+/// Write a Fortran unformatted record: The payload, bracketed by its length.
+fn dcd_record(out: &mut Vec<u8>, payload: &[u8]) {
+    out.extend((payload.len() as i32).to_le_bytes());
+    out.extend(payload);
+    out.extend((payload.len() as i32).to_le_bytes());
+}
+
+/// CHARMM-style DCD, with unit cell info if the first frame has a box.
+pub fn write_dcd(frames: &[TrajFrame]) -> Vec<u8> {
+    let n_atoms = frames.first().map(|f| f.posits.len()).unwrap_or_default();
+    let has_cell = frames
+        .first()
+        .map(|f| f.box_size.is_some())
+        .unwrap_or_default();
+
+    let dt = if frames.len() >= 2 {
+        frames[1].time - frames[0].time
+    } else {
+        0.
+    };
+    let step_interval = if frames.len() >= 2 {
+        frames[1].step.saturating_sub(frames[0].step).max(1)
+    } else {
+        1
+    };
+    // DELTA is per integration step; the frame spacing is DELTA * NSAVC.
+    let delta = dt / step_interval as f64 * AKMA_PER_PS;
+
+    let first_step = frames.first().map(|f| f.step).unwrap_or_default();
+    let last_step = frames.last().map(|f| f.step).unwrap_or_default();
+
+    let mut header = Vec::with_capacity(84);
+    header.extend(b"CORD");
+    for v in [
+        frames.len() as i32,
+        first_step as i32,
+        step_interval as i32,
+        last_step as i32,
+        0,
+        0,
+        0,
+        0,
+        0,
+    ] {
+        header.extend(v.to_le_bytes());
+    }
+    header.extend((delta as f32).to_le_bytes());
+    header.extend((has_cell as i32).to_le_bytes());
+    header.extend([0; 8 * 4]);
+    // Identify as CHARMM version 24, as VMD and MDAnalysis do.
+    header.extend(24_i32.to_le_bytes());
+
+    let mut out = Vec::new();
+    dcd_record(&mut out, &header);
+
+    let mut title = Vec::with_capacity(4 + 80 * 2);
+    title.extend(2_i32.to_le_bytes());
+    for line in [
+        "REMARKS Trajectory written by Molchanica",
+        "REMARKS Units: Å",
+    ] {
+        let mut l = line.as_bytes().to_vec();
+        l.resize(80, b' ');
+        title.extend(l);
+    }
+    dcd_record(&mut out, &title);
+
+    dcd_record(&mut out, &(n_atoms as i32).to_le_bytes());
+
+    for frame in frames {
+        if has_cell {
+            let size = frame.box_size.unwrap_or_default();
+            // Order is A, gamma, B, beta, alpha, C.
+            let mut cell = Vec::with_capacity(48);
+            for v in [size.x, 90., size.y, 90., 90., size.z] {
+                cell.extend(v.to_le_bytes());
+            }
+            dcd_record(&mut out, &cell);
+        }
+
+        for axis in 0..3 {
+            let mut coords = Vec::with_capacity(n_atoms * 4);
+            for p in &frame.posits {
+                let v = match axis {
+                    0 => p.x,
+                    1 => p.y,
+                    _ => p.z,
+                };
+                coords.extend((v as f32).to_le_bytes());
+            }
+            dcd_record(&mut out, &coords);
+        }
+    }
+
+    out
+}
+
+struct DcdReader<'a> {
+    data: &'a [u8],
+    i: usize,
+    big_endian: bool,
+}
+
+impl<'a> DcdReader<'a> {
+    fn i32_at(&self, i: usize) -> io::Result<i32> {
+        let b: [u8; 4] = self
+            .data
+            .get(i..i + 4)
+            .ok_or_else(|| invalid("Unexpected end of DCD file"))?
+            .try_into()
+            .unwrap();
+        Ok(if self.big_endian {
+            i32::from_be_bytes(b)
+        } else {
+            i32::from_le_bytes(b)
+        })
+    }
+
+    /// Returns the payload of the next Fortran record.
+    fn record(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.i32_at(self.i)? as usize;
+        let start = self.i + 4;
+        let payload = self
+            .data
+            .get(start..start + len)
+            .ok_or_else(|| invalid("Unexpected end of DCD file"))?;
+
+        if self.i32_at(start + len)? as usize != len {
+            return Err(invalid("Corrupt DCD record"));
+        }
+
+        self.i = start + len + 4;
+        Ok(payload)
+    }
+
+    fn f32s(&self, payload: &[u8]) -> Vec<f32> {
+        payload
+            .chunks_exact(4)
+            .map(|c| {
+                let b: [u8; 4] = c.try_into().unwrap();
+                if self.big_endian {
+                    f32::from_be_bytes(b)
+                } else {
+                    f32::from_le_bytes(b)
+                }
+            })
+            .collect()
+    }
+
+    fn f64s(&self, payload: &[u8]) -> Vec<f64> {
+        payload
+            .chunks_exact(8)
+            .map(|c| {
+                let b: [u8; 8] = c.try_into().unwrap();
+                if self.big_endian {
+                    f64::from_be_bytes(b)
+                } else {
+                    f64::from_le_bytes(b)
+                }
+            })
+            .collect()
+    }
+}
+
+pub fn read_dcd(data: &[u8]) -> io::Result<Vec<TrajFrame>> {
+    let mut reader = DcdReader {
+        data,
+        i: 0,
+        big_endian: false,
+    };
+
+    if reader.i32_at(0)? != 84 {
+        reader.big_endian = true;
+        if reader.i32_at(0)? != 84 {
+            return Err(invalid("Not a DCD file"));
+        }
+    }
+
+    let header = reader.record()?;
+    if &header[..4] != b"CORD" {
+        return Err(invalid("Not a DCD coordinate file"));
+    }
+    let icntrl: Vec<i32> = (0..20)
+        .map(|i| reader.i32_at(4 + 4 + i * 4))
+        .collect::<io::Result<_>>()?;
+
+    let n_frames_hdr = icntrl[0].max(0) as usize;
+    let first_step = icntrl[1].max(0) as u32;
+    let step_interval = icntrl[2].max(1) as u32;
+    let charmm = icntrl[19] != 0;
+
+    // CHARMM files store DELTA as a float, and flag unit cells. X-PLOR files use a double.
+    let (delta, has_cell) = if charmm {
+        let d = reader.f32s(&header[40..44])[0] as f64;
+        (d, icntrl[10] != 0)
+    } else {
+        (reader.f64s(&header[40..48])[0], false)
+    };
+
+    if icntrl[8] != 0 {
+        return Err(invalid("DCD files with fixed atoms aren't supported"));
+    }
+    if charmm && icntrl[11] != 0 {
+        return Err(invalid("4D DCD files aren't supported"));
+    }
+
+    let dt_ps = delta * step_interval as f64 / AKMA_PER_PS;
+
+    // Title.
+    reader.record()?;
+
+    let n_atoms_rec = reader.record()?;
+    if n_atoms_rec.len() != 4 {
+        return Err(invalid("Invalid DCD atom count"));
+    }
+    let n_atoms = reader.i32_at(reader.i - 8)? as usize;
+
+    let mut frames = Vec::with_capacity(n_frames_hdr);
+
+    // Some writers leave the frame count at 0; read until the end of the file.
+    while reader.i < data.len() {
+        let box_size = if has_cell {
+            let rec = reader.record()?;
+            let cell = reader.f64s(rec);
+            if cell.len() != 6 {
+                return Err(invalid("Invalid DCD unit cell"));
+            }
+            Some(Vec3::new(cell[0], cell[2], cell[5]))
+        } else {
+            None
+        };
+
+        let mut axes = Vec::with_capacity(3);
+        for _ in 0..3 {
+            let rec = reader.record()?;
+            let v = reader.f32s(rec);
+            if v.len() != n_atoms {
+                return Err(invalid("DCD frame has the wrong number of atoms"));
+            }
+            axes.push(v);
+        }
+
+        let frame_i = frames.len() as u32;
+        frames.push(TrajFrame {
+            step: first_step + frame_i * step_interval,
+            time: frame_i as f64 * dt_ps,
+            posits: (0..n_atoms)
+                .map(|i| Vec3::new(axes[0][i] as f64, axes[1][i] as f64, axes[2][i] as f64))
+                .collect(),
+            box_size,
+        });
+    }
+
+    Ok(frames)
+}
+
+// ---------- XTC ----------
+
+/// Number of bits required to store values up to `size`.
+fn xtc_size_of_int(size: u32) -> u32 {
+    32 - size.leading_zeros()
+}
+
+/// Number of bits required to store 3 values packed into their product.
+fn xtc_size_of_ints(sizes: [u32; 3]) -> u32 {
+    let prod = sizes.iter().fold(1_u128, |acc, s| acc * *s as u128);
+    128 - prod.leading_zeros()
+}
+
+/// Writes bits most-significant first, as xdrfile does.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit_len: usize,
+}
+
+impl BitWriter {
+    fn send_bits(&mut self, num_bits: u32, val: u32) {
+        for i in (0..num_bits).rev() {
+            let bit = (val >> i) & 1;
+            if self.bit_len % 8 == 0 {
+                self.bytes.push(0);
+            }
+            if bit != 0 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bit_len % 8);
+            }
+            self.bit_len += 1;
+        }
+    }
+
+    /// Packs 3 integers into their mixed-radix product, then writes it in little-endian byte order.
+    fn send_ints(&mut self, num_bits: u32, sizes: [u32; 3], nums: [u32; 3]) {
+        let v = (nums[0] as u128 * sizes[1] as u128 + nums[1] as u128) * sizes[2] as u128
+            + nums[2] as u128;
+
+        let full_bytes = num_bits / 8;
+        for i in 0..full_bytes {
+            self.send_bits(8, ((v >> (8 * i)) & 0xff) as u32);
+        }
+        let rem = num_bits % 8;
+        if rem > 0 {
+            self.send_bits(rem, ((v >> (8 * full_bytes)) & 0xff) as u32);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit_i: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn receive_bits(&mut self, num_bits: u32) -> io::Result<u32> {
+        let mut result = 0;
+        for _ in 0..num_bits {
+            let byte = self
+                .bytes
+                .get(self.bit_i / 8)
+                .ok_or_else(|| invalid("Unexpected end of XTC coordinate data"))?;
+            let bit = (byte >> (7 - self.bit_i % 8)) & 1;
+            result = (result << 1) | bit as u32;
+            self.bit_i += 1;
+        }
+        Ok(result)
+    }
+
+    fn receive_ints(&mut self, num_bits: u32, sizes: [u32; 3]) -> io::Result<[i32; 3]> {
+        let mut v: u128 = 0;
+        let mut shift = 0;
+        let mut remaining = num_bits;
+
+        while remaining > 0 {
+            let n = remaining.min(8);
+            v |= (self.receive_bits(n)? as u128) << shift;
+            shift += 8;
+            remaining -= n;
+        }
+
+        let n2 = v % sizes[2] as u128;
+        v /= sizes[2] as u128;
+        let n1 = v % sizes[1] as u128;
+        v /= sizes[1] as u128;
+
+        Ok([v as i32, n1 as i32, n2 as i32])
+    }
+}
+
+fn xdr_i32(out: &mut Vec<u8>, v: i32) {
+    out.extend(v.to_be_bytes());
+}
+
+fn xdr_f32(out: &mut Vec<u8>, v: f32) {
+    out.extend(v.to_be_bytes());
+}
+
+fn magic_int(i: usize) -> i32 {
+    XTC_MAGIC_INTS[i.min(XTC_MAGIC_INTS.len() - 1)]
+}
+
+/// Compress coordinates using the xdr3dfcoord algorithm. `coords` are in nm.
+fn xtc_compress(out: &mut Vec<u8>, coords: &[[f32; 3]]) {
+    let n = coords.len();
+    xdr_i32(out, n as i32);
+
+    // Small systems are stored uncompressed.
+    if n <= 9 {
+        for c in coords {
+            for v in c {
+                xdr_f32(out, *v);
+            }
+        }
+        return;
+    }
+
+    xdr_f32(out, XTC_PRECISION);
+
+    let mut ints: Vec<[i32; 3]> = Vec::with_capacity(n);
+    let mut min_int = [i32::MAX; 3];
+    let mut max_int = [i32::MIN; 3];
+    let mut min_diff = i32::MAX;
+
+    for (i, c) in coords.iter().enumerate() {
+        let mut v = [0; 3];
+        for k in 0..3 {
+            let lf = c[k] * XTC_PRECISION;
+            v[k] = if lf >= 0. { lf + 0.5 } else { lf - 0.5 } as i32;
+            min_int[k] = min_int[k].min(v[k]);
+            max_int[k] = max_int[k].max(v[k]);
+        }
+
+        if i > 0 {
+            let prev = ints[i - 1];
+            let diff = (0..3).map(|k| (prev[k] - v[k]).abs()).sum();
+            min_diff = min_diff.min(diff);
+        }
+        ints.push(v);
+    }
+
+    for v in min_int.iter().chain(&max_int) {
+        xdr_i32(out, *v);
+    }
+
+    let size_int = [0, 1, 2].map(|k| (max_int[k] - min_int[k] + 1) as u32);
+
+    // Large ranges are written per-axis.
+    let (bit_size, bit_size_int) = if (size_int[0] | size_int[1] | size_int[2]) > 0xff_ffff {
+        (0, size_int.map(xtc_size_of_int))
+    } else {
+        (xtc_size_of_ints(size_int), [0; 3])
+    };
+
+    let mut small_idx = XTC_FIRST_IDX;
+    while small_idx < XTC_MAGIC_INTS.len() - 1 && XTC_MAGIC_INTS[small_idx] < min_diff {
+        small_idx += 1;
+    }
+    xdr_i32(out, small_idx as i32);
+
+    let max_idx = (small_idx + 8).min(XTC_MAGIC_INTS.len());
+    let min_idx = max_idx - 8;
+    let mut smaller = magic_int(XTC_FIRST_IDX.max(small_idx - 1)) / 2;
+    let mut small_num = magic_int(small_idx) / 2;
+    let mut size_small = [magic_int(small_idx) as u32; 3];
+    let larger = magic_int(max_idx) / 2;
+
+    let mut bits = BitWriter::default();
+    let mut prev = [0; 3];
+    let mut prev_run: i32 = -1;
+    let mut i = 0;
+
+    let near = |a: [i32; 3], b: [i32; 3], lim: i32| (0..3).all(|k| (a[k] - b[k]).abs() < lim);
+
+    while i < n {
+        let mut is_small = false;
+
+        let mut is_smaller: i32 = if small_idx < max_idx && i >= 1 && near(ints[i], prev, larger) {
+            1
+        } else if small_idx > min_idx {
+            -1
+        } else {
+            0
+        };
+
+        if i + 1 < n && near(ints[i], ints[i + 1], small_num) {
+            // Swap the first and second atoms, for better compression of water.
+            ints.swap(i, i + 1);
+            is_small = true;
+        }
+
+        let this = ints[i];
+        let tmp = [0, 1, 2].map(|k| (this[k] - min_int[k]) as u32);
+        if bit_size == 0 {
+            for k in 0..3 {
+                bits.send_bits(bit_size_int[k], tmp[k]);
+            }
+        } else {
+            bits.send_ints(bit_size, size_int, tmp);
+        }
+
+        prev = this;
+        i += 1;
+
+        let mut run = Vec::new();
+        if !is_small && is_smaller == -1 {
+            is_smaller = 0;
+        }
+
+        while is_small && run.len() < 8 {
+            let this = ints[i];
+            let dist_sq: i64 = (0..3).map(|k| ((this[k] - prev[k]) as i64).pow(2)).sum();
+            if is_smaller == -1 && dist_sq >= (smaller as i64).pow(2) {
+                is_smaller = 0;
+            }
+
+            run.push([0, 1, 2].map(|k| (this[k] - prev[k] + small_num) as u32));
+            prev = this;
+            i += 1;
+
+            is_small = i < n && near(ints[i], prev, small_num);
+        }
+
+        let run_len = run.len() as i32 * 3;
+        if run_len != prev_run || is_smaller != 0 {
+            prev_run = run_len;
+            bits.send_bits(1, 1);
+            bits.send_bits(5, (run_len + is_smaller + 1) as u32);
+        } else {
+            bits.send_bits(1, 0);
+        }
+
+        for r in run {
+            bits.send_ints(small_idx as u32, size_small, r);
+        }
+
+        if is_smaller != 0 {
+            small_idx = (small_idx as i32 + is_smaller) as usize;
+            if is_smaller < 0 {
+                small_num = smaller;
+                smaller = if small_idx > XTC_FIRST_IDX {
+                    magic_int(small_idx - 1) / 2
+                } else {
+                    0
+                };
+            } else {
+                smaller = small_num;
+                small_num = magic_int(small_idx) / 2;
+            }
+            size_small = [magic_int(small_idx) as u32; 3];
+        }
+    }
+
+    xdr_i32(out, bits.bytes.len() as i32);
+    out.extend(&bits.bytes);
+    // XDR opaque data is padded to 4 bytes.
+    out.resize(out.len().next_multiple_of(4), 0);
+}
+
+pub fn write_xtc(frames: &[TrajFrame]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    for frame in frames {
+        xdr_i32(&mut out, XTC_MAGIC);
+        xdr_i32(&mut out, frame.posits.len() as i32);
+        xdr_i32(&mut out, frame.step as i32);
+        xdr_f32(&mut out, frame.time as f32);
+
+        let size = frame.box_size.unwrap_or_default() * NM_PER_ANGSTROM;
+        for (i, v) in [size.x, size.y, size.z].iter().enumerate() {
+            for j in 0..3 {
+                xdr_f32(&mut out, if i == j { *v as f32 } else { 0. });
+            }
+        }
+
+        let coords: Vec<[f32; 3]> = frame
+            .posits
+            .iter()
+            .map(|p| {
+                let p = *p * NM_PER_ANGSTROM;
+                [p.x as f32, p.y as f32, p.z as f32]
+            })
+            .collect();
+        xtc_compress(&mut out, &coords);
+    }
+
+    out
+}
+
+struct XdrReader<'a> {
+    data: &'a [u8],
+    i: usize,
+}
+
+impl<'a> XdrReader<'a> {
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let v = self
+            .data
+            .get(self.i..self.i + n)
+            .ok_or_else(|| invalid("Unexpected end of XTC file"))?;
+        self.i += n;
+        Ok(v)
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+}
+
+/// Returns coordinates in nm.
+fn xtc_decompress(reader: &mut XdrReader, n: usize) -> io::Result<Vec<[f32; 3]>> {
+    if reader.i32()? as usize != n {
+        return Err(invalid("XTC atom counts don't match"));
+    }
+
+    if n <= 9 {
+        return (0..n)
+            .map(|_| Ok([reader.f32()?, reader.f32()?, reader.f32()?]))
+            .collect();
+    }
+
+    let precision = reader.f32()?;
+    if precision <= 0. {
+        return Err(invalid("Invalid XTC precision"));
+    }
+
+    let mut min_int = [0; 3];
+    let mut max_int = [0; 3];
+    for v in min_int.iter_mut().chain(max_int.iter_mut()) {
+        *v = reader.i32()?;
+    }
+
+    let size_int = [0, 1, 2].map(|k| (max_int[k] as i64 - min_int[k] as i64 + 1) as u32);
+    let (bit_size, bit_size_int) = if (size_int[0] | size_int[1] | size_int[2]) > 0xff_ffff {
+        (0, size_int.map(xtc_size_of_int))
+    } else {
+        (xtc_size_of_ints(size_int), [0; 3])
+    };
+
+    let mut small_idx = reader.i32()? as usize;
+    if !(XTC_FIRST_IDX..XTC_MAGIC_INTS.len()).contains(&small_idx) {
+        return Err(invalid("Invalid XTC compression index"));
+    }
+
+    let mut smaller = magic_int(XTC_FIRST_IDX.max(small_idx - 1)) / 2;
+    let mut small_num = magic_int(small_idx) / 2;
+    let mut size_small = [magic_int(small_idx) as u32; 3];
+
+    let byte_count = reader.i32()? as usize;
+    let bytes = reader.bytes(byte_count)?;
+    reader.bytes(byte_count.next_multiple_of(4) - byte_count)?;
+
+    let mut bits = BitReader { bytes, bit_i: 0 };
+
+    let inv_precision = 1. / precision;
+    let to_nm = |v: [i32; 3]| v.map(|c| c as f32 * inv_precision);
+
+    let mut result = Vec::with_capacity(n);
+    let mut run = 0;
+
+    while result.len() < n {
+        let mut this = if bit_size == 0 {
+            [
+                bits.receive_bits(bit_size_int[0])? as i32,
+                bits.receive_bits(bit_size_int[1])? as i32,
+                bits.receive_bits(bit_size_int[2])? as i32,
+            ]
+        } else {
+            bits.receive_ints(bit_size, size_int)?
+        };
+
+        for k in 0..3 {
+            this[k] += min_int[k];
+        }
+        let mut prev = this;
+
+        let mut is_smaller = 0;
+        if bits.receive_bits(1)? == 1 {
+            run = bits.receive_bits(5)? as i32;
+            is_smaller = run % 3;
+            run -= is_smaller;
+            is_smaller -= 1;
+        }
+
+        if run > 0 {
+            for k in (0..run).step_by(3) {
+                let mut cur = bits.receive_ints(small_idx as u32, size_small)?;
+                for j in 0..3 {
+                    cur[j] += prev[j] - small_num;
+                }
+
+                if k == 0 {
+                    // Undo the swap of the first and second atoms.
+                    std::mem::swap(&mut cur, &mut prev);
+                    result.push(to_nm(prev));
+                } else {
+                    prev = cur;
+                }
+                result.push(to_nm(cur));
+            }
+        } else {
+            result.push(to_nm(this));
+        }
+
+        small_idx = (small_idx as i32 + is_smaller) as usize;
+        if is_smaller < 0 {
+            small_num = smaller;
+            smaller = if small_idx > XTC_FIRST_IDX {
+                magic_int(small_idx - 1) / 2
+            } else {
+                0
+            };
+        } else if is_smaller > 0 {
+            smaller = small_num;
+            small_num = magic_int(small_idx) / 2;
+        }
+        size_small = [magic_int(small_idx) as u32; 3];
+    }
+
+    if result.len() != n {
+        return Err(invalid("XTC frame has the wrong number of atoms"));
+    }
+
+    Ok(result)
+}
+
+pub fn read_xtc(data: &[u8]) -> io::Result<Vec<TrajFrame>> {
+    let mut reader = XdrReader { data, i: 0 };
+    let mut frames = Vec::new();
+
+    while reader.i < data.len() {
+        if reader.i32()? != XTC_MAGIC {
+            return Err(invalid("Not an XTC file"));
+        }
+
+        let n = reader.i32()? as usize;
+        let step = reader.i32()?.max(0) as u32;
+        let time = reader.f32()? as f64;
+
+        let mut bx = [0.; 9];
+        for v in &mut bx {
+            *v = reader.f32()?;
+        }
+        let box_size = if bx.iter().any(|v| *v != 0.) {
+            Some(Vec3::new(bx[0] as f64, bx[4] as f64, bx[8] as f64) / NM_PER_ANGSTROM)
+        } else {
+            None
+        };
+
+        let posits = xtc_decompress(&mut reader, n)?
+            .iter()
+            .map(|c| Vec3::new(c[0] as f64, c[1] as f64, c[2] as f64) / NM_PER_ANGSTROM)
+            .collect();
+
+        frames.push(TrajFrame {
+            step,
+            time,
+            posits,
+            box_size,
+        });
+    }
+
+    Ok(frames)
+}
+
+// ---------- Multi-model PDB and mmCIF ----------
+
+pub fn write_pdb_models(frames: &[TrajFrame], atoms: &[TrajAtom]) -> String {
+    let mut out = String::from("REMARK   1 TRAJECTORY WRITTEN BY MOLCHANICA\n");
+
+    if let Some(size) = frames.first().and_then(|f| f.box_size) {
+        let _ = writeln!(
+            out,
+            "CRYST1{:>9.3}{:>9.3}{:>9.3}{:>7.2}{:>7.2}{:>7.2} P 1           1",
+            size.x, size.y, size.z, 90., 90., 90.
+        );
+    }
+
+    for (model_i, frame) in frames.iter().enumerate() {
+        let _ = writeln!(out, "MODEL     {:>4}", model_i + 1);
+        let _ = writeln!(
+            out,
+            "REMARK   2 STEP {} TIME {:.3} PS",
+            frame.step, frame.time
+        );
+
+        for (i, (atom, p)) in atoms.iter().zip(&frame.posits).enumerate() {
+            let record = if atom.hetero { "HETATM" } else { "ATOM" };
+            let _ = writeln!(
+                out,
+                "{record:<6}{:>5} {} {:>3} {:1}{:>4}    {:>8.3}{:>8.3}{:>8.3}{:>6.2}{:>6.2}          {:>2}",
+                (i + 1) % 100_000,
+                pdb_atom_name(&atom.name, atom.element),
+                &atom.res_name[..atom.res_name.len().min(3)],
+                atom.chain_id,
+                atom.res_sn % 10_000,
+                p.x,
+                p.y,
+                p.z,
+                1.,
+                0.,
+                atom.element.to_letter().to_uppercase(),
+            );
+        }
+        out.push_str("ENDMDL\n");
+    }
+    out.push_str("END\n");
+
+    out
+}
+
+pub fn write_mmcif_models(frames: &[TrajFrame], atoms: &[TrajAtom]) -> String {
+    let mut out = String::from("data_trajectory\n#\n");
+
+    if let Some(size) = frames.first().and_then(|f| f.box_size) {
+        let _ = write!(
+            out,
+            "_cell.length_a {:.3}\n_cell.length_b {:.3}\n_cell.length_c {:.3}\n\
+             _cell.angle_alpha 90.0\n_cell.angle_beta 90.0\n_cell.angle_gamma 90.0\n#\n",
+            size.x, size.y, size.z
+        );
+    }
+
+    out.push_str(
+        "loop_\n_atom_site.group_PDB\n_atom_site.id\n_atom_site.type_symbol\n\
+         _atom_site.label_atom_id\n_atom_site.label_comp_id\n_atom_site.label_asym_id\n\
+         _atom_site.label_seq_id\n_atom_site.Cartn_x\n_atom_site.Cartn_y\n_atom_site.Cartn_z\n\
+         _atom_site.pdbx_PDB_model_num\n",
+    );
+
+    for (model_i, frame) in frames.iter().enumerate() {
+        for (i, (atom, p)) in atoms.iter().zip(&frame.posits).enumerate() {
+            let group = if atom.hetero { "HETATM" } else { "ATOM" };
+            let _ = writeln!(
+                out,
+                "{group} {} {} {} {} {} {} {:.3} {:.3} {:.3} {}",
+                i + 1,
+                atom.element.to_letter().to_uppercase(),
+                cif_val(&atom.name),
+                cif_val(&atom.res_name),
+                cif_val(&atom.chain_id),
+                atom.res_sn,
+                p.x,
+                p.y,
+                p.z,
+                model_i + 1,
+            );
+        }
+    }
+    out.push_str("#\n");
+
+    out
+}
+
+fn parse_coord(s: &str) -> io::Result<f64> {
+    s.trim()
+        .parse()
+        .map_err(|_| invalid("Invalid coordinate in trajectory"))
+}
+
+pub fn read_pdb_models(text: &str) -> io::Result<Vec<TrajFrame>> {
+    let mut frames = Vec::new();
+    let mut current = TrajFrame::default();
+    let mut box_size = None;
+
+    for line in text.lines() {
+        let record = line.get(..6).unwrap_or(line).trim_end();
+
+        match record {
+            "CRYST1" => {
+                let a = parse_coord(line.get(6..15).unwrap_or_default())?;
+                let b = parse_coord(line.get(15..24).unwrap_or_default())?;
+                let c = parse_coord(line.get(24..33).unwrap_or_default())?;
+                box_size = Some(Vec3::new(a, b, c));
+            }
+            "ATOM" | "HETATM" => {
+                current.posits.push(Vec3::new(
+                    parse_coord(line.get(30..38).unwrap_or_default())?,
+                    parse_coord(line.get(38..46).unwrap_or_default())?,
+                    parse_coord(line.get(46..54).unwrap_or_default())?,
+                ));
+            }
+            // Written by us; lets step and time survive a round trip.
+            "REMARK" if line.get(6..10) == Some("   2") => {
+                let cols: Vec<_> = line.split_whitespace().collect();
+                if let ["REMARK", "2", "STEP", step, "TIME", time, ..] = cols.as_slice() {
+                    current.step = step.parse().unwrap_or_default();
+                    current.time = time.parse().unwrap_or_default();
+                }
+            }
+            "ENDMDL" => {
+                let mut frame = std::mem::take(&mut current);
+                if frame.step == 0 && frame.time == 0. {
+                    frame.step = frames.len() as u32;
+                }
+                frame.box_size = box_size;
+                frames.push(frame);
+            }
+            _ => (),
+        }
+    }
+
+    // Single-model files may omit MODEL and ENDMDL.
+    if !current.posits.is_empty() {
+        current.step = frames.len() as u32;
+        current.box_size = box_size;
+        frames.push(current);
+    }
+
+    Ok(frames)
+}
+
+/// Split a CIF line into tokens, respecting quotes.
+fn cif_tokens(line: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let bytes = line.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i].is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let q = bytes[i];
+        if q == b'\'' || q == b'"' {
+            // A closing quote must be followed by whitespace or the line end.
+            let start = i + 1;
+            let mut end = start;
+            while end < bytes.len()
+                && !(bytes[end] == q && bytes.get(end + 1).is_none_or(|b| b.is_ascii_whitespace()))
+            {
+                end += 1;
+            }
+            result.push(&line[start..end.min(line.len())]);
+            i = end + 1;
+        } else {
+            let start = i;
+            while i < bytes.len() && !bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            result.push(&line[start..i]);
+        }
+    }
+
+    result
+}
+
+pub fn read_mmcif_models(text: &str) -> io::Result<Vec<TrajFrame>> {
+    let mut frames: Vec<TrajFrame> = Vec::new();
+    let mut box_size = [None; 3];
+
+    let mut headers: Vec<&str> = Vec::new();
+    let mut in_atom_loop = false;
+
+    let col = |headers: &[&str], name: &str| headers.iter().position(|h| *h == name);
+
+    for line in text.lines() {
+        let line = line.trim();
+
+        for (i, key) in ["_cell.length_a", "_cell.length_b", "_cell.length_c"]
+            .iter()
+            .enumerate()
+        {
+            if let Some(v) = line.strip_prefix(key) {
+                box_size[i] = v.trim().parse::<f64>().ok();
+            }
+        }
+
+        if line == "loop_" {
+            headers.clear();
+            in_atom_loop = false;
+            continue;
+        }
+
+        if let Some(h) = line.strip_prefix("_atom_site.") {
+            headers.push(h);
+            in_atom_loop = true;
+            continue;
+        }
+
+        if !in_atom_loop || line.is_empty() {
+            continue;
+        }
+        if line.starts_with('#') || line.starts_with('_') || line.starts_with("data_") {
+            in_atom_loop = false;
+            continue;
+        }
+
+        let (Some(x), Some(y), Some(z)) = (
+            col(&headers, "Cartn_x"),
+            col(&headers, "Cartn_y"),
+            col(&headers, "Cartn_z"),
+        ) else {
+            return Err(invalid("mmCIF atom_site loop is missing coordinates"));
+        };
+        let model_col = col(&headers, "pdbx_PDB_model_num");
+
+        let tokens = cif_tokens(line);
+        if tokens.len() != headers.len() {
+            return Err(invalid("Invalid mmCIF atom_site row"));
+        }
+
+        let model: usize = match model_col {
+            Some(m) => tokens[m]
+                .parse()
+                .map_err(|_| invalid("Invalid mmCIF model number"))?,
+            None => 1,
+        };
+
+        // Models are numbered from 1, and are contiguous in practice.
+        let frame_i = model.saturating_sub(1);
+        while frames.len() <= frame_i {
+            let step = frames.len() as u32;
+            frames.push(TrajFrame {
+                step,
+                ..Default::default()
+            });
+        }
+
+        frames[frame_i].posits.push(Vec3::new(
+            parse_coord(tokens[x])?,
+            parse_coord(tokens[y])?,
+            parse_coord(tokens[z])?,
+        ));
+    }
+
+    if let [Some(a), Some(b), Some(c)] = box_size {
+        for f in &mut frames {
+            f.box_size = Some(Vec3::new(a, b, c));
+        }
+    }
+
+    Ok(frames)
+}
@@ -0,0 +1,489 @@
+//! Molecular fingerprints, and Tanimoto similarity search against open ligands, and the loaded
+//! library. This runs locally; it doesn't require network access.
+//!
+//! Morgan fingerprints are circular, and hashed: Each atom's identifier is iteratively updated
+//! from its neighbors', out to a radius, then folded into a fixed number of bits. Radius 2
+//! corresponds to ECFP4. Our structural keys are in the style of MACCS: Each bit is a
+//! predefined feature, e.g. an element, ring size, or functional group.
+
+use bio_files::BondType;
+use na_seq::Element::{
+    self, Bromine, Carbon, Chlorine, Fluorine, Hydrogen, Iodine, Nitrogen, Oxygen, Phosphorus,
+    Sulfur,
+};
+use rayon::prelude::*;
+
+use crate::{
+    State,
+    mol_characterization::{HeavyAtom, PerceivedMol},
+    molecule::MoleculeCommon,
+};
+
+/// ECFP4
+pub const MORGAN_RADIUS: usize = 2;
+pub const MORGAN_BITS: usize = 2_048;
+
+/// The maximum number of hits returned from a similarity search.
+pub const MAX_SIMILARITY_HITS: usize = 50;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FpKind {
+    #[default]
+    Morgan,
+    Maccs,
+}
+
+impl FpKind {
+    pub fn to_str(self) -> String {
+        match self {
+            Self::Morgan => "Morgan (ECFP4)",
+            Self::Maccs => "MACCS keys",
+        }
+        .to_owned()
+    }
+}
+
+/// A fixed-length bit vector.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fingerprint {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl Fingerprint {
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+            len,
+        }
+    }
+
+    pub fn set(&mut self, i: usize) {
+        self.words[i / 64] |= 1 << (i % 64);
+    }
+
+    /// Shared bits over bits set in either. 0 if neither has any set, or if lengths differ.
+    pub fn tanimoto(&self, other: &Self) -> f32 {
+        if self.len != other.len {
+            return 0.;
+        }
+
+        let (mut both, mut either) = (0, 0);
+        for (a, b) in self.words.iter().zip(&other.words) {
+            both += (a & b).count_ones();
+            either += (a | b).count_ones();
+        }
+
+        if either == 0 {
+            0.
+        } else {
+            both as f32 / either as f32
+        }
+    }
+}
+
+impl MoleculeCommon {
+    pub fn fingerprint(&self, kind: FpKind) -> Fingerprint {
+        match kind {
+            FpKind::Morgan => self.morgan_fp(MORGAN_RADIUS, MORGAN_BITS),
+            FpKind::Maccs => self.maccs_fp(),
+        }
+    }
+
+    /// A circular fingerprint. Hydrogens, explicit or implicit, are folded into their heavy
+    /// atom's identifier.
+    pub fn morgan_fp(&self, radius: usize, n_bits: usize) -> Fingerprint {
+        let p = PerceivedMol::new(self);
+        let heavy = p.heavy();
+        let mut result = Fingerprint::new(n_bits);
+
+        let mut ids = vec![0; p.atoms.len()];
+        for &i in &heavy {
+            ids[i] = atom_invariant(&p.atoms[i]);
+            result.set((ids[i] % n_bits as u64) as usize);
+        }
+
+        for r in 0..radius {
+            let mut next = ids.clone();
+            for &i in &heavy {
+                let mut env: Vec<(u64, u64)> = p.atoms[i]
+                    .nbrs
+                    .iter()
+                    .map(|&(j, bt)| (bond_invariant(bt), ids[j]))
+                    .collect();
+                env.sort_unstable();
+
+                let mut h = hash_combine(r as u64 + 1, ids[i]);
+                for (b, id) in env {
+                    h = hash_combine(hash_combine(h, b), id);
+                }
+                next[i] = h;
+                result.set((h % n_bits as u64) as usize);
+            }
+            ids = next;
+        }
+
+        result
+    }
+
+    /// Structural keys, in the style of MACCS.
+    pub fn maccs_fp(&self) -> Fingerprint {
+        let keys = maccs_keys(&PerceivedMol::new(self));
+
+        let mut result = Fingerprint::new(keys.len());
+        for (i, key) in keys.into_iter().enumerate() {
+            if key {
+                result.set(i);
+            }
+        }
+        result
+    }
+}
+
+/// Mix bits; the splitmix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn hash_combine(seed: u64, v: u64) -> u64 {
+    mix(seed ^ mix(v.wrapping_add(0x9e37_79b9_7f4a_7c15)))
+}
+
+/// Element, heavy degree, hydrogen count, charge, and ring membership.
+fn atom_invariant(a: &HeavyAtom) -> u64 {
+    let mut h = 0;
+    for byte in a.element.to_letter().bytes() {
+        h = hash_combine(h, byte as u64);
+    }
+    for v in [
+        a.nbrs.len() as u64,
+        a.h as u64,
+        a.charge as i64 as u64,
+        a.in_ring as u64,
+    ] {
+        h = hash_combine(h, v);
+    }
+    h
+}
+
+fn bond_invariant(bond_type: BondType) -> u64 {
+    match bond_type {
+        BondType::Single => 1,
+        BondType::Double => 2,
+        BondType::Triple => 3,
+        BondType::Aromatic => 4,
+        _ => 5,
+    }
+}
+
+type AtomPred<'a> = dyn Fn(&HeavyAtom) -> bool + 'a;
+
+/// Evaluate each structural key, in a fixed order.
+fn maccs_keys(p: &PerceivedMol) -> Vec<bool> {
+    let heavy = p.heavy();
+    let atoms = &p.atoms;
+
+    let count = |pred: &AtomPred| heavy.iter().filter(|&&i| pred(&atoms[i])).count();
+    let letter = |a: &HeavyAtom, el: &str| a.element.to_letter() == el;
+    let is_halogen = |a: &HeavyAtom| matches!(a.element, Fluorine | Chlorine | Bromine | Iodine);
+
+    // A bond between atoms matching the two predicates.
+    let bond = |bt: BondType, p0: &AtomPred, p1: &AtomPred| {
+        heavy
+            .iter()
+            .any(|&i| p0(&atoms[i]) && atoms[i].nbrs.iter().any(|&(j, b)| b == bt && p1(&atoms[j])))
+    };
+    // Neighbors of `a` matching a predicate, by bond type.
+    let nbrs_of = |a: &HeavyAtom, bt: BondType, pred: &AtomPred| {
+        a.nbrs
+            .iter()
+            .filter(|&&(j, b)| b == bt && pred(&atoms[j]))
+            .count()
+    };
+
+    let el = |e: Element| move |a: &HeavyAtom| a.element == e;
+    let c = el(Carbon);
+    let n = el(Nitrogen);
+    let o = el(Oxygen);
+    let s = el(Sulfur);
+    let any = |_: &HeavyAtom| true;
+
+    let carbonyl_c = |a: &HeavyAtom| a.element == Carbon && nbrs_of(a, BondType::Double, &o) > 0;
+    let hydroxyl = |a: &HeavyAtom| a.element == Oxygen && a.h > 0 && a.nbrs.len() == 1;
+
+    let ring_sizes: Vec<usize> = p.rings.iter().map(|r| r.len()).collect();
+    let aromatic_rings: Vec<&Vec<usize>> = p
+        .rings
+        .iter()
+        .filter(|r| r.iter().all(|&i| atoms[i].aromatic))
+        .collect();
+    let ring_has = |pred: &AtomPred| p.rings.iter().any(|r| r.iter().any(|&i| pred(&atoms[i])));
+    let fused = p.rings.iter().enumerate().any(|(i, r0)| {
+        p.rings[i + 1..]
+            .iter()
+            .any(|r1| r0.iter().filter(|a| r1.contains(a)).count() >= 2)
+    });
+
+    let standard = |a: &HeavyAtom| {
+        matches!(
+            a.element,
+            Carbon
+                | Nitrogen
+                | Oxygen
+                | Sulfur
+                | Phosphorus
+                | Fluorine
+                | Chlorine
+                | Bromine
+                | Iodine
+        ) || letter(a, "B")
+            || letter(a, "Si")
+            || letter(a, "Se")
+    };
+
+    vec![
+        // Elements
+        count(&n) > 0,
+        count(&o) > 0,
+        count(&s) > 0,
+        count(&el(Phosphorus)) > 0,
+        count(&el(Fluorine)) > 0,
+        count(&el(Chlorine)) > 0,
+        count(&el(Bromine)) > 0,
+        count(&el(Iodine)) > 0,
+        count(&|a| letter(a, "B")) > 0,
+        count(&|a| letter(a, "Si")) > 0,
+        count(&|a| letter(a, "Se")) > 0,
+        count(&|a| !standard(a) && a.element != Hydrogen) > 0,
+        count(&is_halogen) > 0,
+        count(&is_halogen) > 1,
+        // Element counts
+        count(&n) > 1,
+        count(&n) > 2,
+        count(&o) > 1,
+        count(&o) > 2,
+        count(&o) > 3,
+        count(&s) > 1,
+        // Charges
+        count(&|a| a.charge > 0) > 0,
+        count(&|a| a.charge < 0) > 0,
+        // Rings
+        !p.rings.is_empty(),
+        p.rings.len() > 1,
+        p.rings.len() > 2,
+        ring_sizes.contains(&3),
+        ring_sizes.contains(&4),
+        ring_sizes.contains(&5),
+        ring_sizes.contains(&6),
+        ring_sizes.contains(&7),
+        ring_sizes.iter().any(|&l| l >= 8),
+        fused,
+        !aromatic_rings.is_empty(),
+        aromatic_rings.len() > 1,
+        aromatic_rings
+            .iter()
+            .any(|r| r.iter().any(|&i| atoms[i].element != Carbon)),
+        ring_has(&n),
+        ring_has(&o),
+        ring_has(&s),
+        // Non-aromatic heterocycle
+        p.rings.iter().any(|r| {
+            r.iter().any(|&i| atoms[i].element != Carbon) && !r.iter().all(|&i| atoms[i].aromatic)
+        }),
+        count(&|a| a.element == Nitrogen && a.aromatic) > 0,
+        count(&|a| matches!(a.element, Oxygen | Sulfur) && a.aromatic) > 0,
+        count(&|a| a.element == Nitrogen && a.aromatic && a.h > 0) > 0,
+        // Bonds
+        bond(
+            BondType::Double,
+            &|a| a.element == Carbon && !a.aromatic,
+            &|a| a.element == Carbon && !a.aromatic,
+        ),
+        bond(BondType::Triple, &c, &c),
+        bond(BondType::Triple, &c, &n),
+        bond(BondType::Double, &c, &n),
+        bond(BondType::Double, &n, &n),
+        bond(BondType::Double, &c, &o),
+        bond(BondType::Double, &c, &s),
+        bond(BondType::Double, &s, &o),
+        bond(BondType::Double, &el(Phosphorus), &o),
+        bond(BondType::Double, &n, &o),
+        bond(BondType::Single, &n, &n),
+        bond(BondType::Single, &n, &o),
+        bond(BondType::Single, &s, &s),
+        bond(BondType::Single, &s, &n),
+        bond(BondType::Single, &|a| a.aromatic, &is_halogen),
+        bond(BondType::Single, &|a| a.aromatic, &|a| a.aromatic),
+        // Hydroxyls
+        count(&hydroxyl) > 0,
+        count(&hydroxyl) > 1,
+        bond(BondType::Single, &hydroxyl, &|a| {
+            a.element == Carbon && a.aromatic
+        }),
+        bond(BondType::Single, &hydroxyl, &|a| {
+            a.element == Carbon && a.nbrs.iter().all(|(_, bt)| *bt == BondType::Single)
+        }),
+        // Carboxylic acid, or carboxylate
+        count(&|a| {
+            carbonyl_c(a)
+                && nbrs_of(a, BondType::Single, &|o| {
+                    o.element == Oxygen && o.nbrs.len() == 1 && (o.h > 0 || o.charge < 0)
+                }) > 0
+        }) > 0,
+        // Ester
+        count(&|a| {
+            carbonyl_c(a)
+                && nbrs_of(a, BondType::Single, &|o| {
+                    o.element == Oxygen && o.nbrs.len() == 2
+                }) > 0
+        }) > 0,
+        // Amide
+        count(&|a| carbonyl_c(a) && nbrs_of(a, BondType::Single, &n) > 0) > 0,
+        // Urea, or carbamate
+        count(&|a| {
+            carbonyl_c(a)
+                && nbrs_of(a, BondType::Single, &n) > 0
+                && nbrs_of(a, BondType::Single, &|x| {
+                    matches!(x.element, Nitrogen | Oxygen)
+                }) > 1
+        }) > 0,
+        // Ketone
+        count(&|a| carbonyl_c(a) && nbrs_of(a, BondType::Single, &c) == 2) > 0,
+        // Aldehyde
+        count(&|a| carbonyl_c(a) && a.h > 0) > 0,
+        // Amines: primary, secondary, tertiary, quaternary, and aromatic
+        count(&|a| a.element == Nitrogen && !a.aromatic && a.h == 2 && a.nbrs.len() == 1) > 0,
+        count(&|a| {
+            a.element == Nitrogen
+                && a.h == 1
+                && a.nbrs.len() == 2
+                && nbrs_of(a, BondType::Single, &carbonyl_c) == 0
+                && a.num_bonds(BondType::Single) == 2
+        }) > 0,
+        count(&|a| {
+            a.element == Nitrogen && a.h == 0 && a.num_bonds(BondType::Single) == 3 && a.charge == 0
+        }) > 0,
+        count(&|a| a.element == Nitrogen && a.charge > 0 && a.nbrs.len() == 4) > 0,
+        bond(
+            BondType::Single,
+            &|a| a.element == Nitrogen && a.h > 0 && !a.aromatic,
+            &|a| a.aromatic,
+        ),
+        // Nitro
+        count(&|a| {
+            a.element == Nitrogen
+                && nbrs_of(a, BondType::Single, &o) + nbrs_of(a, BondType::Double, &o) >= 2
+                && !a.aromatic
+        }) > 0,
+        // Ethers, and thioethers
+        count(&|a| {
+            a.element == Oxygen
+                && a.nbrs.len() == 2
+                && !a.aromatic
+                && nbrs_of(a, BondType::Single, &|x| {
+                    x.element == Carbon && !carbonyl_c(x)
+                }) == 2
+        }) > 0,
+        count(&|a| a.element == Sulfur && a.h > 0) > 0,
+        count(&|a| {
+            a.element == Sulfur
+                && !a.aromatic
+                && nbrs_of(a, BondType::Single, &c) == 2
+                && a.nbrs.len() == 2
+        }) > 0,
+        // Sulfonyl, and sulfonamide
+        count(&|a| a.element == Sulfur && nbrs_of(a, BondType::Double, &o) >= 2) > 0,
+        count(&|a| {
+            a.element == Sulfur
+                && nbrs_of(a, BondType::Double, &o) >= 2
+                && nbrs_of(a, BondType::Single, &n) > 0
+        }) > 0,
+        // Carbon environments
+        count(&|a| a.element == Carbon && a.h == 3) > 0,
+        count(&|a| a.element == Carbon && a.h == 3) > 2,
+        count(&|a| a.element == Carbon && a.h == 2 && !a.in_ring) > 0,
+        bond(
+            BondType::Single,
+            &|a| a.element == Carbon && a.h == 2 && !a.in_ring,
+            &|a| a.element == Carbon && a.h == 2 && !a.in_ring,
+        ),
+        count(&|a| a.element == Carbon && a.h == 0 && a.num_bonds(BondType::Single) == 4) > 0,
+        count(&|a| a.element == Carbon && nbrs_of(a, BondType::Single, &el(Fluorine)) >= 3) > 0,
+        count(&|a| a.nbrs.len() >= 4) > 0,
+        // Any heavy atom bonded to 3 or more heteroatoms.
+        count(&|a| {
+            nbrs_of(a, BondType::Single, &|x| x.element != Carbon)
+                + nbrs_of(a, BondType::Double, &|x| x.element != Carbon)
+                >= 3
+        }) > 0,
+        // Heteroatom bonded to an aromatic atom
+        bond(BondType::Single, &|a| a.aromatic, &|a| {
+            a.element != Carbon && !a.aromatic
+        }),
+        count(&any) >= 16,
+        count(&any) >= 32,
+    ]
+}
+
+/// Where a similarity hit is from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HitSource {
+    /// Index into `State::ligands`.
+    Ligand(usize),
+    /// Index into the loaded library's molecules.
+    Library(usize),
+}
+
+#[derive(Clone, Debug)]
+pub struct SimilarityHit {
+    pub source: HitSource,
+    pub ident: String,
+    /// Tanimoto coefficient, from 0 to 1.
+    pub similarity: f32,
+}
+
+/// The results of a similarity search, for display.
+#[derive(Clone, Debug)]
+pub struct SimilaritySearch {
+    /// Index into `State::ligands`.
+    pub query: usize,
+    pub kind: FpKind,
+    pub hits: Vec<SimilarityHit>,
+}
+
+impl State {
+    /// Find the molecules most similar to a ligand, from other open ligands, and the loaded
+    /// library. Returns the hits with the highest Tanimoto coefficients, in descending order.
+    pub fn find_similar(&self, lig_i: usize, kind: FpKind) -> Vec<SimilarityHit> {
+        let Some(query) = self.ligands.get(lig_i) else {
+            return Vec::new();
+        };
+        let query_fp = query.common.fingerprint(kind);
+
+        let mut hits: Vec<_> = self
+            .ligands
+            .par_iter()
+            .enumerate()
+            .filter(|(i, _)| *i != lig_i)
+            .map(|(i, m)| SimilarityHit {
+                source: HitSource::Ligand(i),
+                ident: m.common.ident.clone(),
+                similarity: query_fp.tanimoto(&m.common.fingerprint(kind)),
+            })
+            .collect();
+
+        if let Some(lib) = &self.library {
+            hits.par_extend(lib.mols.par_iter().enumerate().map(|(i, m)| SimilarityHit {
+                source: HitSource::Library(i),
+                ident: m.common.ident.clone(),
+                similarity: query_fp.tanimoto(&m.common.fingerprint(kind)),
+            }));
+        }
+
+        hits.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        hits.truncate(MAX_SIMILARITY_HITS);
+        hits
+    }
+}
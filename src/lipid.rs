@@ -325,6 +325,8 @@ fn combine_head_tail(
     {
         head.residues.push(Residue {
             serial_number: 0,
+            seq_num: 0,
+            ins_code: None,
             res_type: ResidueType::Other("Head".to_string()),
             atom_sns: head.common.atoms[..head_len]
                 .iter()
@@ -336,6 +338,8 @@ fn combine_head_tail(
         });
         head.residues.push(Residue {
             serial_number: 1,
+            seq_num: 1,
+            ins_code: None,
             res_type: ResidueType::Other(chain_0_name.to_owned()),
             atom_sns: head.common.atoms[head_len..offset_t1]
                 .iter()
@@ -347,6 +351,8 @@ fn combine_head_tail(
         });
         head.residues.push(Residue {
             serial_number: 2,
+            seq_num: 2,
+            ins_code: None,
             res_type: ResidueType::Other(chain_1_name.to_owned()),
             atom_sns: head.common.atoms[offset_t1..total_len]
                 .iter()
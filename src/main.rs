@@ -15,19 +15,25 @@
 //! [S3 Gemmi link](https://daedalus-mols.s3.us-east-1.amazonaws.com/gemmi.exe)
 //! [S3 Geostd link](https://daedalus-mols.s3.us-east-1.amazonaws.com/amber_geostd)
 
+mod align;
 mod bond_inference;
 // mod docking;
 mod docking;
 mod download_mols;
 mod drawing;
+mod embed;
 mod file_io;
+mod fingerprint;
 mod forces;
 mod inputs;
 mod molecule;
 mod prefs;
+mod protonation;
 mod render;
 mod ribbon_mesh;
+mod rings;
 mod sa_surface;
+mod scaffold;
 mod ui;
 mod util;
 
@@ -42,11 +48,15 @@ mod md;
 mod mol_characterization;
 mod mol_editor;
 mod mol_lig;
+mod mol_library;
 mod mol_manip;
 mod nucleic_acid;
 mod orca;
 mod selection;
+mod selfies;
+mod smarts;
 mod smiles;
+mod stereo;
 #[cfg(test)]
 mod tests;
 mod viridis_lut;
@@ -90,13 +100,19 @@ use mol_manip::MolManip;
 use molecule::MoleculePeptide;
 
 use crate::{
+    docking::{DockingState, external::PoseComparison, screen::ScreenJob},
+    file_io::{mesh_export::MeshSource, trajectory::TrajAtomSet},
+    fingerprint::SimilaritySearch,
     lipid::{LipidShape, MoleculeLipid, load_lipid_templates},
     mol_editor::MolEditorState,
-    molecule::{MoGenericRefMut, MolGenericRef, MolIdent, MolType},
+    mol_library::MolLibrary,
+    molecule::{MoGenericRefMut, MolGenericRef, MolType},
     nucleic_acid::{MoleculeNucleicAcid, NucleicAcidType, Strands, load_na_templates},
     orca::StateOrca,
     prefs::ToSave,
+    protonation::ProtonationStates,
     render::render,
+    scaffold::Clustering,
     ui::cam::{FOG_DIST_DEFAULT, VIEW_DEPTH_NEAR_MIN},
     util::handle_err,
 };
@@ -168,6 +184,14 @@ impl Display for ViewSelLevel {
 struct FileDialogs {
     load: FileDialog,
     save: FileDialog,
+    /// MD trajectories. These are separate, as they share extensions with molecule files.
+    traj_load: FileDialog,
+    traj_save: FileDialog,
+    /// Exports a filtered subset of the molecule library.
+    library_save: FileDialog,
+    /// Exports descriptors of the filtered library molecules.
+    library_csv_save: FileDialog,
+    mesh_save: FileDialog,
     // todo: Add these A/R.
     // load_editor: FileDialog,
     // save_editor: FileDialog,
@@ -179,34 +203,76 @@ impl Default for FileDialogs {
             .add_file_filter_extensions(
                 "All",
                 vec![
-                    "cif", "mol2", "sdf", "xyz", "pdbqt", "map", "mtz", "frcmod", "dat", "prmtop",
+                    "cif", "pdb", "mol2", "sdf", "xyz", "pdbqt", "map", "mtz", "frcmod", "dat",
+                    "prmtop", "gro", "top", "mcs", "smi",
                 ],
             )
             .add_file_filter_extensions(
                 "Molecule (small)",
                 vec!["mol2", "sdf", "xyz", "pdbqt", "prmtop"],
             )
-            .add_file_filter_extensions("Protein (CIF)", vec!["cif"])
+            .add_file_filter_extensions("Protein (CIF)", vec!["cif", "pdb"])
             .add_file_filter_extensions("Density", vec!["map", "mtz", "cif"])
-            .add_file_filter_extensions("Mol dynamics", vec!["frcmod", "dat", "lib", "prmtop"])
+            .add_file_filter_extensions("Session", vec!["mcs"])
+            .add_file_filter_extensions("Library (SMILES)", vec!["smi", "smiles"])
+            .add_file_filter_extensions(
+                "Mol dynamics",
+                vec!["frcmod", "dat", "lib", "prmtop", "gro", "top"],
+            )
             //
             .add_file_filter_extensions(
                 "Molecule (small)",
                 vec!["mol2", "sdf", "xyz", "pdbqt", "prmtop"],
             )
             .add_save_extension("Protein (CIF)", "cif")
+            .add_save_extension("Protein (PDB)", "pdb")
             .add_save_extension("Mol2", "mol2")
             .add_save_extension("SDF", "sdf")
             .add_save_extension("XYZ", "xyz")
             .add_save_extension("Pdbqt", "pdbqt")
             .add_save_extension("Map", "map")
             .add_save_extension("MTZ", "mtz")
-            .add_save_extension("Prmtop", "prmtop");
+            .add_save_extension("Prmtop", "prmtop")
+            .add_save_extension("GROMACS (gro + top)", "gro")
+            .add_save_extension("Session", "mcs");
 
         let load = FileDialog::with_config(cfg_all.clone()).default_file_filter("All");
         let save = FileDialog::with_config(cfg_all).default_save_extension("Protein");
 
-        Self { load, save }
+        let cfg_traj = FileDialogConfig::default()
+            .add_file_filter_extensions("Trajectory", vec!["dcd", "xtc", "pdb", "cif"])
+            .add_save_extension("DCD", "dcd")
+            .add_save_extension("XTC", "xtc")
+            .add_save_extension("PDB (multi-model)", "pdb")
+            .add_save_extension("mmCIF (multi-model)", "cif");
+
+        let traj_load =
+            FileDialog::with_config(cfg_traj.clone()).default_file_filter("Trajectory");
+        let traj_save = FileDialog::with_config(cfg_traj).default_save_extension("DCD");
+
+        let cfg_library = FileDialogConfig::default().add_save_extension("SDF", "sdf");
+        let library_save = FileDialog::with_config(cfg_library).default_save_extension("SDF");
+
+        let cfg_library_csv = FileDialogConfig::default().add_save_extension("CSV", "csv");
+        let library_csv_save =
+            FileDialog::with_config(cfg_library_csv).default_save_extension("CSV");
+
+        let cfg_mesh = FileDialogConfig::default()
+            .add_save_extension("glTF (binary)", "glb")
+            .add_save_extension("OBJ", "obj")
+            .add_save_extension("PLY", "ply")
+            .add_save_extension("STL", "stl");
+        let mesh_save = FileDialog::with_config(cfg_mesh).default_save_extension("glTF (binary)");
+
+        Self {
+            load,
+            save,
+            traj_load,
+            traj_save,
+            library_save,
+            library_csv_save,
+            mesh_save,
+        }
     }
 }
 
@@ -262,10 +328,10 @@ struct StateVolatile {
             Result<FilesAvailable, ReqError>,
         )>,
     >,
-    /// Receives thread data upon an HTTP result completion.
-    smiles_pending_data_avail: Option<Receiver<(MolIdent, Result<String, ReqError>)>>,
     /// The first param is the index.
     amber_geostd_data_avail: Option<Receiver<(usize, Result<GeostdData, ReqError>)>>,
+    /// A virtual screen of the library, running in the background.
+    screening: Option<ScreenJob>,
     /// We may change CWD during CLI navigation; keep prefs directory constant.
     prefs_dir: PathBuf,
     /// Entered by the user, for this session.
@@ -305,9 +371,9 @@ impl Default for StateVolatile {
         Self {
             dialogs: Default::default(),
             inputs_commanded: Default::default(),
-            smiles_pending_data_avail: Default::default(),
             mol_pending_data_avail: Default::default(),
             amber_geostd_data_avail: Default::default(),
+            screening: Default::default(),
             prefs_dir: env::current_dir().unwrap(), // This is why we can't derive.
             cli_input_history: Default::default(),
             cli_input_selected: Default::default(),
@@ -407,6 +473,17 @@ struct PopupState {
     rama_plot: bool,
     recent_files: bool,
     metadata: Option<(MolType, usize)>,
+    library: bool,
+    /// Results of a fingerprint similarity search, if displayed.
+    similar: Option<SimilaritySearch>,
+    /// Ligand or library series, if displayed.
+    clusters: Option<Clustering>,
+    /// A ligand's protonation states and tautomers, if displayed.
+    protonation: Option<ProtonationStates>,
+    /// Poses from the docking run.
+    docking_poses: bool,
+    /// Interactions of a ligand's imported docking poses, if displayed.
+    pose_comparison: Option<PoseComparison>,
 }
 
 struct StateUiMd {
@@ -420,6 +497,9 @@ struct StateUiMd {
     peptide_only_near_ligs: bool,
     /// Peptide atoms don't move, but exert forces.
     peptide_static: bool,
+    /// Save every nth snapshot when exporting trajectories.
+    traj_stride: usize,
+    traj_atom_set: TrajAtomSet,
 }
 
 impl Default for StateUiMd {
@@ -432,6 +512,26 @@ impl Default for StateUiMd {
             langevin_γ: Default::default(),
             peptide_only_near_ligs: true,
             peptide_static: true,
+            traj_stride: 1,
+            traj_atom_set: Default::default(),
+        }
+    }
+}
+
+struct StateUiMeshExport {
+    source: MeshSource,
+    /// Only include parts of the mesh closest to selected atoms.
+    selection_only: bool,
+    /// Output units per Å. e.g. 0.1 for nm, or 1 for 1mm per Å when 3D printing.
+    scale_input: String,
+}
+
+impl Default for StateUiMeshExport {
+    fn default() -> Self {
+        Self {
+            source: Default::default(),
+            selection_only: false,
+            scale_input: "1".to_owned(),
         }
     }
 }
@@ -526,6 +626,8 @@ struct StateUi {
     docking_site_y: String,
     docking_site_z: String,
     docking_site_size: String,
+    /// Index into the peptide's detected docking sites.
+    docking_site_sel: Option<usize>,
     /// For the arc/orbit cam only.
     orbit_selected_atom: bool,
     // todo: Re-implement A/R
@@ -553,9 +655,27 @@ struct StateUi {
     popup: PopupState,
     md: StateUiMd,
     ph_input: String,
+    /// If true, ligands opened without force field types and partial charges are set to their
+    /// dominant protonation state at the configured pH.
+    protonate_on_open: bool,
     /// If true, the surface mesh is colored according to the atom or residue colors closest to
     /// it. (E.g. CPK, by partial charge, by hydrophobicity etc). If false, it's a solid color.
     color_surface_mesh: bool,
+    /// Include MD snapshots when saving a session file.
+    session_include_md: bool,
+    /// Library property filter bounds. Stored as text, for the input fields.
+    lib_min_input: String,
+    lib_max_input: String,
+    lib_smarts_input: String,
+    /// The parse error from the last SMARTS applied, if any. The library keeps filtering by the
+    /// last valid pattern meanwhile.
+    lib_smarts_err: Option<String>,
+    /// For selecting ligand atoms by substructure.
+    smarts_input: String,
+    mesh_export: StateUiMeshExport,
+    /// CIP labels of stereocenters (R/S) and double bonds (E/Z) on small molecules. Not part of
+    /// `Visibility`, as that's persisted, and adding to it would invalidate saved files.
+    labels_stereo: bool,
 }
 
 /// For showing and hiding UI sections.
@@ -563,6 +683,7 @@ pub struct UiVisibility {
     aa_seq: bool,
     smiles: bool,
     selfies: bool,
+    descriptors: bool,
     lipids: bool,
     nucleic_acids: bool,
     amino_acids: bool,
@@ -576,6 +697,7 @@ impl Default for UiVisibility {
             aa_seq: false,
             smiles: false,
             selfies: false,
+            descriptors: false,
             lipids: false,
             nucleic_acids: false,
             amino_acids: false,
@@ -663,6 +785,8 @@ struct State {
     pub ligands: Vec<MoleculeSmall>,
     pub nucleic_acids: Vec<MoleculeNucleicAcid>,
     pub lipids: Vec<MoleculeLipid>,
+    /// Molecules loaded from multi-record files, e.g. vendor libraries. These aren't drawn.
+    pub library: Option<MolLibrary>,
     pub cam_snapshots: Vec<CamSnapshot>,
     /// This allows us to keep in-memory data for other molecules.
     pub to_save: ToSave,
@@ -675,6 +799,8 @@ struct State {
     #[cfg(feature = "cuda")]
     pub kernel_reflections: Option<CudaFunction>,
     pub mol_dynamics: Option<MdState>,
+    /// Ranked poses from the most recent docking run, until one is kept or discarded.
+    pub docking: Option<DockingState>,
     // todo: Combine these params in a single struct.
     pub ff_param_set: FfParamSet,
     pub mol_specific_params: HashMap<String, ForceFieldParams>,
@@ -701,6 +827,7 @@ impl Default for State {
             ligands: Default::default(),
             nucleic_acids: Default::default(),
             lipids: Default::default(),
+            library: Default::default(),
             cam_snapshots: Default::default(),
             to_save: Default::default(),
             to_save_prev: Default::default(),
@@ -708,6 +835,7 @@ impl Default for State {
             #[cfg(feature = "cuda")]
             kernel_reflections: None,
             mol_dynamics: Default::default(),
+            docking: Default::default(),
             ff_param_set: Default::default(),
             mol_specific_params: Default::default(),
             templates: Default::default(),
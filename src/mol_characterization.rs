@@ -1,9 +1,830 @@
-//! An experiment to categorize small molecules, and find similar ones
+//! Categorize small molecules, and compute descriptors used to assess drug-likeness.
+
+use std::collections::HashSet;
+
+use bio_files::BondType;
+use na_seq::Element::{
+    self, Bromine, Carbon, Chlorine, Fluorine, Hydrogen, Iodine, Nitrogen, Oxygen, Phosphorus,
+    Sulfur,
+};
+
+use crate::{
+    molecule::MoleculeCommon,
+    smiles::{bond_order, default_implicit_h},
+};
 
 pub struct Classification {
     pub num_atoms: usize,
+    /// From the smallest set of smallest rings.
     pub num_rings: usize,
     pub num_amines: usize,
     pub num_nitrogens: usize,
     pub num_oxygens: usize,
 }
+
+impl Classification {
+    pub fn new(mol: &MoleculeCommon) -> Self {
+        let count = |el| mol.atoms.iter().filter(|a| a.element == el).count();
+
+        // Amines: Nitrogens with only single bonds, which aren't bonded to a carbonyl or
+        // sulfonyl group. (i.e. excluding amides and sulfonamides)
+        let double_bonded_to_o_s = |i: usize| {
+            mol.bonds.iter().any(|b| {
+                let other = if b.atom_0 == i {
+                    b.atom_1
+                } else if b.atom_1 == i {
+                    b.atom_0
+                } else {
+                    return false;
+                };
+                b.bond_type == BondType::Double
+                    && matches!(mol.atoms[other].element, Oxygen | Sulfur)
+            })
+        };
+
+        let num_amines = mol
+            .atoms
+            .iter()
+            .enumerate()
+            .filter(|(i, a)| {
+                a.element == Nitrogen
+                    && mol.bonds.iter().all(|b| {
+                        (b.atom_0 != *i && b.atom_1 != *i) || b.bond_type == BondType::Single
+                    })
+                    && mol.adjacency_list[*i].iter().all(|&j| {
+                        !(matches!(mol.atoms[j].element, Carbon | Sulfur)
+                            && double_bonded_to_o_s(j))
+                    })
+            })
+            .count();
+
+        Self {
+            num_atoms: mol.atoms.len(),
+            num_rings: mol.find_rings().len(),
+            num_amines,
+            num_nitrogens: count(Nitrogen),
+            num_oxygens: count(Oxygen),
+        }
+    }
+}
+
+/// Physicochemical descriptors of a small molecule, and the drug-likeness rules that use them.
+/// Hydrogens may be explicit atoms, or implicit counts, e.g. from SMILES.
+#[derive(Clone, Debug, Default)]
+pub struct Descriptors {
+    /// Average molecular weight, in Daltons.
+    pub mol_weight: f64,
+    /// Monoisotopic mass, in Daltons.
+    pub exact_mass: f64,
+    /// In Hill order, with net charge appended. e.g. "C9H8O4".
+    pub formula: String,
+    /// Wildman-Crippen estimate of the octanol-water partition coefficient.
+    pub log_p: f64,
+    /// Topological polar surface area (Ertl), from nitrogens and oxygens. Å².
+    pub tpsa: f64,
+    /// Nitrogens and oxygens with at least one hydrogen.
+    pub h_donors: usize,
+    /// Nitrogens and oxygens, as in Lipinski's rule of five.
+    pub h_acceptors: usize,
+    /// Non-ring single bonds between non-terminal heavy atoms. Excludes amide C-N bonds, and
+    /// bonds to triple-bonded atoms.
+    pub rotatable_bonds: usize,
+    pub formal_charge: i32,
+    pub heavy_atoms: usize,
+    /// The fraction of carbons which are sp3 hybridized.
+    pub fraction_sp3: f64,
+}
+
+/// A heavy atom, with hydrogens folded into a count, and aromaticity perceived.
+pub struct HeavyAtom {
+    pub element: Element,
+    pub charge: i8,
+    /// Explicit and implicit hydrogens.
+    pub h: u8,
+    pub aromatic: bool,
+    /// Bond order sum, including hydrogens, from the Kekulé form.
+    pub valence: u8,
+    pub in_ring: bool,
+    /// In a 3-membered ring; Ertl's TPSA treats these separately.
+    pub ring_3: bool,
+    /// Heavy atom neighbors, by index, and the bond to each.
+    pub nbrs: Vec<(usize, BondType)>,
+}
+
+impl HeavyAtom {
+    pub fn num_bonds(&self, bond_type: BondType) -> usize {
+        self.nbrs.iter().filter(|(_, bt)| *bt == bond_type).count()
+    }
+
+    pub fn has_bond(&self, bond_type: BondType) -> bool {
+        self.nbrs.iter().any(|(_, bt)| *bt == bond_type)
+    }
+}
+
+/// A molecule with rings, aromaticity, and hydrogen counts perceived. Shared by descriptors and
+/// fingerprints. `atoms` is indexed the same as `mol.atoms`; explicit hydrogens are included,
+/// but aren't listed as neighbors.
+pub struct PerceivedMol {
+    /// A copy of the molecule, with aromatic bonds set from Hückel's rule.
+    pub mol: MoleculeCommon,
+    pub atoms: Vec<HeavyAtom>,
+    pub rings: Vec<Vec<usize>>,
+    /// Atom index pairs, low index first.
+    pub ring_bonds: HashSet<(usize, usize)>,
+    pub implicit_h: Vec<u8>,
+}
+
+impl PerceivedMol {
+    pub fn new(mol: &MoleculeCommon) -> Self {
+        let mut mol = mol.clone();
+        mol.build_adjacency_list();
+
+        // If there's no hydrogen information at all (e.g. a heavy-atom-only PDB ligand), infer
+        // implicit counts from standard valences, using the Kekulé form.
+        let h_known = mol
+            .atoms
+            .iter()
+            .any(|a| a.element == Hydrogen || a.implicit_h.is_some());
+        let _ = mol.kekulize();
+
+        let implicit_h: Vec<u8> = (0..mol.atoms.len())
+            .map(|i| {
+                let atom = &mol.atoms[i];
+                if h_known || atom.element == Hydrogen || atom.formal_charge != 0 {
+                    return atom.implicit_h.unwrap_or(0);
+                }
+                let (sum, aromatic) = bonds_of(&mol, i).fold((0, false), |(sum, ar), bt| {
+                    (sum + bond_order(bt), ar || bt == BondType::Aromatic)
+                });
+                default_implicit_h(atom.element, sum, aromatic)
+            })
+            .collect();
+
+        // Aromatic bonds remain if kekulization failed; count these as 1, plus 1 per atom.
+        let valence: Vec<u8> = (0..mol.atoms.len())
+            .map(|i| {
+                let (sum, aromatic) = bonds_of(&mol, i).fold((0, false), |(sum, ar), bt| {
+                    (sum + bond_order(bt), ar || bt == BondType::Aromatic)
+                });
+                sum + implicit_h[i] + aromatic as u8
+            })
+            .collect();
+
+        // Leaves existing aromatic bonds as they are if this fails.
+        let _ = mol.perceive_aromaticity();
+
+        let rings = mol.find_rings();
+        let mut ring_bonds = HashSet::new();
+        for ring in &rings {
+            for (j, &a) in ring.iter().enumerate() {
+                let b = ring[(j + 1) % ring.len()];
+                ring_bonds.insert((a.min(b), a.max(b)));
+            }
+        }
+
+        let atoms = mol
+            .atoms
+            .iter()
+            .enumerate()
+            .map(|(i, atom)| {
+                let explicit_h = mol.adjacency_list[i]
+                    .iter()
+                    .filter(|&&j| mol.atoms[j].element == Hydrogen)
+                    .count() as u8;
+
+                let nbrs: Vec<_> = mol
+                    .bonds
+                    .iter()
+                    .filter_map(|b| {
+                        let other = if b.atom_0 == i {
+                            b.atom_1
+                        } else if b.atom_1 == i {
+                            b.atom_0
+                        } else {
+                            return None;
+                        };
+                        (mol.atoms[other].element != Hydrogen).then_some((other, b.bond_type))
+                    })
+                    .collect();
+
+                HeavyAtom {
+                    element: atom.element,
+                    charge: atom.formal_charge,
+                    h: explicit_h + implicit_h[i],
+                    aromatic: nbrs.iter().any(|(_, bt)| *bt == BondType::Aromatic),
+                    valence: valence[i],
+                    in_ring: rings.iter().any(|r| r.contains(&i)),
+                    ring_3: rings.iter().any(|r| r.len() == 3 && r.contains(&i)),
+                    nbrs,
+                }
+            })
+            .collect();
+
+        Self {
+            mol,
+            atoms,
+            rings,
+            ring_bonds,
+            implicit_h,
+        }
+    }
+
+    /// Indices of non-hydrogen atoms.
+    pub fn heavy(&self) -> Vec<usize> {
+        (0..self.atoms.len())
+            .filter(|&i| self.atoms[i].element != Hydrogen)
+            .collect()
+    }
+
+    pub fn is_ring_bond(&self, a: usize, b: usize) -> bool {
+        self.ring_bonds.contains(&(a.min(b), a.max(b)))
+    }
+
+    /// Indices of rotatable bonds: Acyclic single bonds between non-terminal heavy atoms,
+    /// excluding amides, and bonds next to triple bonds.
+    pub fn rotatable_bonds(&self) -> Vec<usize> {
+        let atoms = &self.atoms;
+
+        (0..self.mol.bonds.len())
+            .filter(|&i| {
+                let b = &self.mol.bonds[i];
+                let (a0, a1) = (&atoms[b.atom_0], &atoms[b.atom_1]);
+                b.bond_type == BondType::Single
+                    && a0.element != Hydrogen
+                    && a1.element != Hydrogen
+                    && a0.nbrs.len() > 1
+                    && a1.nbrs.len() > 1
+                    && !self.is_ring_bond(b.atom_0, b.atom_1)
+                    && !a0.has_bond(BondType::Triple)
+                    && !a1.has_bond(BondType::Triple)
+                    && !is_amide(atoms, b.atom_0, b.atom_1)
+                    && !is_amide(atoms, b.atom_1, b.atom_0)
+            })
+            .collect()
+    }
+}
+
+impl Descriptors {
+    pub fn new(mol: &MoleculeCommon) -> Self {
+        let perceived = PerceivedMol::new(mol);
+        let PerceivedMol {
+            mol,
+            atoms,
+            implicit_h,
+            ..
+        } = &perceived;
+
+        let heavy = perceived.heavy();
+
+        // Composition. Explicit hydrogens are counted as atoms; implicit ones from the counts.
+        let num_implicit_h: usize = implicit_h.iter().map(|&h| h as usize).sum();
+        let h_weight = Hydrogen.atomic_weight() as f64;
+
+        let mol_weight = mol
+            .atoms
+            .iter()
+            .map(|a| a.element.atomic_weight() as f64)
+            .sum::<f64>()
+            + num_implicit_h as f64 * h_weight;
+
+        let exact_mass = mol
+            .atoms
+            .iter()
+            .map(|a| monoisotopic_mass(a.element))
+            .sum::<f64>()
+            + num_implicit_h as f64 * monoisotopic_mass(Hydrogen);
+
+        let formal_charge = mol.atoms.iter().map(|a| a.formal_charge as i32).sum();
+
+        let n_o = |i: &&usize| matches!(atoms[**i].element, Nitrogen | Oxygen);
+        let h_donors = heavy
+            .iter()
+            .filter(n_o)
+            .filter(|&&i| atoms[i].h > 0)
+            .count();
+        let h_acceptors = heavy.iter().filter(n_o).count();
+
+        let rotatable_bonds = perceived.rotatable_bonds().len();
+
+        let carbons: Vec<_> = heavy
+            .iter()
+            .filter(|&&i| atoms[i].element == Carbon)
+            .collect();
+        let sp3 = carbons
+            .iter()
+            .filter(|&&&i| atoms[i].nbrs.iter().all(|(_, bt)| *bt == BondType::Single))
+            .count();
+        let fraction_sp3 = if carbons.is_empty() {
+            0.
+        } else {
+            sp3 as f64 / carbons.len() as f64
+        };
+
+        Self {
+            mol_weight,
+            exact_mass,
+            formula: formula(mol, num_implicit_h, formal_charge),
+            log_p: heavy.iter().map(|&i| crippen(atoms, i)).sum(),
+            tpsa: heavy.iter().map(|&i| tpsa(&atoms[i])).sum(),
+            h_donors,
+            h_acceptors,
+            rotatable_bonds,
+            formal_charge,
+            heavy_atoms: heavy.len(),
+            fraction_sp3,
+        }
+    }
+
+    /// The number of Lipinski rule-of-five criteria violated: MW ≤ 500, logP ≤ 5, H-bond
+    /// donors ≤ 5, and H-bond acceptors ≤ 10.
+    pub fn lipinski_violations(&self) -> usize {
+        [
+            self.mol_weight > 500.,
+            self.log_p > 5.,
+            self.h_donors > 5,
+            self.h_acceptors > 10,
+        ]
+        .iter()
+        .filter(|v| **v)
+        .count()
+    }
+
+    /// Passes the rule of five, allowing for a single violation.
+    pub fn lipinski(&self) -> bool {
+        self.lipinski_violations() <= 1
+    }
+
+    /// Veber's oral bioavailability criteria: ≤ 10 rotatable bonds, and TPSA ≤ 140 Å².
+    pub fn veber(&self) -> bool {
+        self.rotatable_bonds <= 10 && self.tpsa <= 140.
+    }
+
+    /// Lead-likeness, per Teague et al: 250 ≤ MW ≤ 350, logP ≤ 3.5, and ≤ 7 rotatable bonds.
+    pub fn lead_like(&self) -> bool {
+        (250. ..=350.).contains(&self.mol_weight) && self.log_p <= 3.5 && self.rotatable_bonds <= 7
+    }
+}
+
+/// Bond types of all bonds to an atom.
+fn bonds_of(mol: &MoleculeCommon, i: usize) -> impl Iterator<Item = BondType> {
+    mol.bonds
+        .iter()
+        .filter(move |b| b.atom_0 == i || b.atom_1 == i)
+        .map(|b| b.bond_type)
+}
+
+/// `c` is a carbonyl carbon, single-bonded to nitrogen `n`.
+fn is_amide(atoms: &[HeavyAtom], c: usize, n: usize) -> bool {
+    atoms[c].element == Carbon
+        && atoms[n].element == Nitrogen
+        && atoms[c]
+            .nbrs
+            .iter()
+            .any(|&(j, bt)| bt == BondType::Double && atoms[j].element == Oxygen)
+}
+
+/// The mass of an element's most abundant isotope. Falls back to the average weight for
+/// elements uncommon in small organic molecules.
+fn monoisotopic_mass(el: Element) -> f64 {
+    match el.to_letter().as_str() {
+        "H" => 1.007_825,
+        "B" => 11.009_305,
+        "C" => 12.,
+        "N" => 14.003_074,
+        "O" => 15.994_915,
+        "F" => 18.998_403,
+        "Na" => 22.989_770,
+        "Si" => 27.976_927,
+        "P" => 30.973_762,
+        "S" => 31.972_071,
+        "Cl" => 34.968_853,
+        "K" => 38.963_707,
+        "Se" => 79.916_522,
+        "Br" => 78.918_338,
+        "I" => 126.904_473,
+        _ => el.atomic_weight() as f64,
+    }
+}
+
+/// The molecular formula in Hill order: Carbon, hydrogen, then other elements alphabetically.
+/// Without carbon, all elements are alphabetical.
+fn formula(mol: &MoleculeCommon, num_implicit_h: usize, charge: i32) -> String {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    let mut add = |el: String, n: usize| match counts.iter_mut().find(|(e, _)| *e == el) {
+        Some((_, count)) => *count += n,
+        None => counts.push((el, n)),
+    };
+
+    for atom in &mol.atoms {
+        add(atom.element.to_letter(), 1);
+    }
+    if num_implicit_h > 0 {
+        add("H".to_owned(), num_implicit_h);
+    }
+
+    let has_carbon = counts.iter().any(|(e, _)| e == "C");
+    counts.sort_by_key(|(e, _)| {
+        let rank = match e.as_str() {
+            "C" if has_carbon => 0,
+            "H" if has_carbon => 1,
+            _ => 2,
+        };
+        (rank, e.clone())
+    });
+
+    let mut result = String::new();
+    for (el, count) in counts {
+        result += &el;
+        if count > 1 {
+            result += &count.to_string();
+        }
+    }
+
+    match charge {
+        0 => (),
+        1 => result.push('+'),
+        -1 => result.push('-'),
+        c if c > 0 => result += &format!("+{c}"),
+        c => result += &format!("-{}", -c),
+    }
+
+    result
+}
+
+/// An atom's contribution to logP, including its hydrogens, from the atom types of Wildman and
+/// Crippen, J. Chem. Inf. Comput. Sci. 1999, 39, 868–873.
+fn crippen(atoms: &[HeavyAtom], i: usize) -> f64 {
+    let a = &atoms[i];
+    let h = a.h as f64;
+
+    match a.element {
+        Carbon => crippen_carbon(atoms, i) + 0.1230 * h,
+        Nitrogen => crippen_nitrogen(atoms, i) + 0.2142 * h,
+        Oxygen => crippen_oxygen(atoms, i) + crippen_hydroxyl_h(atoms, i) * h,
+        Sulfur => {
+            let v = if a.aromatic {
+                0.6237
+            } else if a.charge != 0 {
+                -0.0024
+            } else {
+                0.6482
+            };
+            v - 0.2677 * h
+        }
+        Phosphorus => 0.8612 - 0.2677 * h,
+        Fluorine | Chlorine | Bromine | Iodine if a.charge != 0 => -2.996,
+        Fluorine => 0.4202,
+        Chlorine => 0.6895,
+        Bromine => 0.8456,
+        Iodine => 0.8857,
+        // Elements without a Crippen type here contribute nothing.
+        _ => 0.,
+    }
+}
+
+/// Elements which aliphatic carbons with heteroatom substituents (C3, C4) may bond to.
+fn is_crippen_hetero(el: Element) -> bool {
+    matches!(
+        el,
+        Nitrogen | Oxygen | Phosphorus | Sulfur | Fluorine | Chlorine | Bromine | Iodine
+    )
+}
+
+fn crippen_carbon(atoms: &[HeavyAtom], i: usize) -> f64 {
+    let a = &atoms[i];
+    let nbrs = &a.nbrs;
+    let x = nbrs.len() + a.h as usize;
+
+    let aliphatic_c = |j: usize| atoms[j].element == Carbon && !atoms[j].aromatic;
+    let all_single_aliphatic_c = nbrs
+        .iter()
+        .all(|&(j, bt)| bt == BondType::Single && aliphatic_c(j));
+    let all_aliphatic = nbrs.iter().all(|&(j, _)| !atoms[j].aromatic);
+    let any_aromatic = nbrs.iter().any(|&(j, _)| atoms[j].aromatic);
+    let any_hetero = nbrs
+        .iter()
+        .any(|&(j, _)| !atoms[j].aromatic && is_crippen_hetero(atoms[j].element));
+
+    if a.aromatic {
+        let exotic = |j: usize| {
+            !atoms[j].aromatic
+                && !matches!(
+                    atoms[j].element,
+                    Carbon | Nitrogen | Oxygen | Sulfur | Fluorine | Chlorine | Bromine | Iodine
+                )
+        };
+        let halogen = |el| nbrs.iter().any(|&(j, _)| atoms[j].element == el);
+
+        if a.h == 0
+            && nbrs
+                .iter()
+                .any(|&(j, bt)| bt == BondType::Single && exotic(j))
+        {
+            return -0.5443;
+        }
+        if halogen(Fluorine) {
+            return 0.;
+        }
+        if halogen(Chlorine) {
+            return 0.2450;
+        }
+        if halogen(Bromine) {
+            return 0.1980;
+        }
+        if halogen(Iodine) {
+            return 0.;
+        }
+        if a.h == 1 {
+            return 0.1581;
+        }
+        if a.num_bonds(BondType::Aromatic) >= 3 {
+            return 0.2955;
+        }
+
+        // Substituted aromatic carbons, by their exocyclic neighbor.
+        for &(j, bt) in nbrs {
+            let other = &atoms[j];
+            match bt {
+                BondType::Single if other.aromatic => return 0.2713,
+                BondType::Single => match other.element {
+                    Carbon => return 0.1360,
+                    Nitrogen => return 0.4619,
+                    Oxygen => return 0.5437,
+                    Sulfur => return 0.1893,
+                    _ => (),
+                },
+                BondType::Double if matches!(other.element, Carbon | Nitrogen | Oxygen) => {
+                    return -0.8186;
+                }
+                _ => (),
+            }
+        }
+        return 0.08129;
+    }
+
+    // Aliphatic hydrocarbon.
+    if a.h == 4
+        || (a.h == 3 && nbrs.len() == 1 && all_single_aliphatic_c)
+        || (a.h == 2 && nbrs.len() == 2 && all_single_aliphatic_c)
+    {
+        return 0.1441;
+    }
+    if ((a.h == 1 && nbrs.len() == 3) || (a.h == 0 && nbrs.len() == 4)) && all_single_aliphatic_c {
+        return 0.;
+    }
+
+    // Aliphatic, with heteroatom substituents.
+    if x == 4 && all_aliphatic && any_hetero {
+        match a.h {
+            3 | 2 => return -0.2035,
+            1 | 0 => return -0.2051,
+            _ => (),
+        }
+    }
+
+    let double_to = |pred: &dyn Fn(&HeavyAtom) -> bool| {
+        nbrs.iter()
+            .any(|&(j, bt)| bt == BondType::Double && pred(&atoms[j]))
+    };
+
+    // C = heteroatom.
+    if double_to(&|o| !o.aromatic && o.element != Carbon) {
+        return -0.2783;
+    }
+
+    // C = C, aliphatic.
+    if double_to(&|o| !o.aromatic && o.element == Carbon) && all_aliphatic {
+        return 0.1551;
+    }
+    // Acetylene, and nitrile carbons.
+    if x == 2
+        && nbrs
+            .iter()
+            .any(|&(j, bt)| bt == BondType::Triple && !atoms[j].aromatic)
+    {
+        return 0.0017;
+    }
+
+    // Aliphatic, attached to an aromatic atom.
+    if x == 4 && any_aromatic {
+        let on_aromatic_c = nbrs
+            .iter()
+            .any(|&(j, _)| atoms[j].aromatic && atoms[j].element == Carbon);
+        match a.h {
+            3 if on_aromatic_c => return 0.08452,
+            3 => return -0.1444,
+            2 => return -0.0516,
+            1 => return 0.1193,
+            0 => return -0.0967,
+            _ => (),
+        }
+    }
+
+    // C = C, aromatic.
+    if (double_to(&|o| o.element == Carbon) && any_aromatic) || double_to(&|o| o.aromatic) {
+        return 0.2640;
+    }
+
+    if x == 4
+        && nbrs.iter().any(|&(j, _)| {
+            !atoms[j].aromatic && !is_crippen_hetero(atoms[j].element) && atoms[j].element != Carbon
+        })
+    {
+        return 0.2148;
+    }
+
+    0.08129
+}
+
+fn crippen_nitrogen(atoms: &[HeavyAtom], i: usize) -> f64 {
+    let a = &atoms[i];
+
+    if a.aromatic {
+        return if a.charge > 0 { -1.119 } else { -0.3239 };
+    }
+
+    if a.charge > 0 {
+        if a.h > 0 {
+            return -1.950;
+        }
+        // Quaternary, nitro, and iminium nitrogens, vs e.g. diazonium and azide.
+        let azide = a.num_bonds(BondType::Double) == 2;
+        return if a.has_bond(BondType::Triple) || azide {
+            0.2887
+        } else {
+            -0.3396
+        };
+    }
+    if a.charge < 0 {
+        return 0.2887;
+    }
+
+    let all_single = a.nbrs.iter().all(|(_, bt)| *bt == BondType::Single);
+    let any_aromatic = a.nbrs.iter().any(|&(j, _)| atoms[j].aromatic);
+
+    match (a.h, a.nbrs.len()) {
+        _ if a.has_bond(BondType::Triple) => 0.01508,
+        // Primary and secondary amines, aliphatic, and aromatic.
+        (2, 1) if all_single && any_aromatic => -1.027,
+        (2, 1) if all_single => -1.019,
+        (1, 2) if all_single && any_aromatic => -0.5188,
+        (1, 2) if all_single => -0.7096,
+        // Imines
+        (1, 1) if a.has_bond(BondType::Double) => 0.08387,
+        (0, 2) if a.has_bond(BondType::Double) => 0.1836,
+        // Tertiary amines
+        (0, 3) if all_single && any_aromatic => -0.4458,
+        (0, 3) if all_single => -0.3187,
+        _ => -0.4806,
+    }
+}
+
+fn crippen_oxygen(atoms: &[HeavyAtom], i: usize) -> f64 {
+    let a = &atoms[i];
+
+    if a.aromatic {
+        return 0.1552;
+    }
+
+    if a.charge == 0 && a.h > 0 {
+        return -0.2893;
+    }
+
+    if a.charge == 0 && a.nbrs.len() == 2 && a.num_bonds(BondType::Single) == 2 {
+        let aromatic = a.nbrs.iter().any(|&(j, _)| atoms[j].aromatic);
+        return if aromatic { -0.4195 } else { -0.0684 };
+    }
+
+    let Some(&(j, bt)) = a.nbrs.first() else {
+        return -0.1188;
+    };
+    let other = &atoms[j];
+
+    // Oxides, by element, and carboxylates.
+    match other.element {
+        Nitrogen | Oxygen => return 0.0335,
+        Sulfur => return -0.3339,
+        Carbon => (),
+        _ => return -1.189,
+    }
+
+    if a.charge < 0 {
+        let carboxylate = other
+            .nbrs
+            .iter()
+            .any(|&(k, bt)| bt == BondType::Double && atoms[k].element == Oxygen);
+        return if carboxylate { -1.326 } else { -0.1188 };
+    }
+
+    if bt != BondType::Double {
+        return -0.1188;
+    }
+    if other.aromatic {
+        return 0.1788;
+    }
+
+    // Carbonyls: Aliphatic, aromatic, and between two heteroatoms.
+    let subst: Vec<&HeavyAtom> = other
+        .nbrs
+        .iter()
+        .filter(|&&(k, _)| k != i)
+        .map(|&(k, _)| &atoms[k])
+        .collect();
+
+    match subst.as_slice() {
+        [] => -0.1526,
+        [s] if s.aromatic => 0.1129,
+        [s] if other.h == 1 || s.element == Oxygen => -0.1526,
+        [s0, s1] => {
+            let carbon = |s: &HeavyAtom| s.element == Carbon;
+            if (carbon(s0) && !s0.aromatic && !s1.aromatic)
+                || (carbon(s1) && !s1.aromatic && !s0.aromatic)
+            {
+                -0.1526
+            } else if (carbon(s0) || carbon(s1)) && (s0.aromatic || s1.aromatic) {
+                0.1129
+            } else if !carbon(s0) && !carbon(s1) {
+                0.4833
+            } else {
+                -0.1188
+            }
+        }
+        _ => -0.1188,
+    }
+}
+
+/// The contribution of each hydrogen on an oxygen: Alcohols, acids, and N-hydroxyls.
+fn crippen_hydroxyl_h(atoms: &[HeavyAtom], i: usize) -> f64 {
+    let a = &atoms[i];
+    let Some(&(j, _)) = a.nbrs.first() else {
+        return -0.2677;
+    };
+    let other = &atoms[j];
+
+    match other.element {
+        Nitrogen => 0.2142,
+        Oxygen | Sulfur => 0.2980,
+        Carbon if !other.aromatic => {
+            let acid = other.nbrs.iter().any(|&(k, bt)| {
+                bt == BondType::Double
+                    && matches!(atoms[k].element, Carbon | Nitrogen | Oxygen | Sulfur)
+            });
+            if acid { 0.2980 } else { -0.2677 }
+        }
+        _ => -0.2677,
+    }
+}
+
+/// An atom's contribution to the topological polar surface area, from Ertl et al, J. Med. Chem.
+/// 2000, 43, 3714–3717.
+fn tpsa(a: &HeavyAtom) -> f64 {
+    let single = a.num_bonds(BondType::Single);
+    let double = a.num_bonds(BondType::Double);
+    let triple = a.num_bonds(BondType::Triple);
+    let aromatic = a.num_bonds(BondType::Aromatic);
+
+    match a.element {
+        Nitrogen if a.aromatic => match (a.charge, a.h, aromatic, single, double) {
+            (0, 0, 2, 0, 0) => 12.89,
+            (0, 0, 3, 0, 0) => 4.41,
+            (0, 0, 2, 1, 0) => 4.93,
+            (0, 0, 2, 0, 1) => 8.39,
+            (0, 1, 2, 0, 0) => 15.79,
+            (1, 0, 3, 0, 0) => 4.10,
+            (1, 0, 2, 1, 0) => 3.88,
+            (1, 1, 2, 0, 0) => 14.14,
+            _ => 0.,
+        },
+        Nitrogen => match (a.charge, a.h, single, double, triple) {
+            (0, 0, 3, 0, 0) if a.ring_3 => 3.01,
+            (0, 0, 3, 0, 0) => 3.24,
+            (0, 0, 1, 1, 0) => 12.36,
+            (0, 0, 0, 0, 1) => 23.79,
+            (0, 0, 1, 2, 0) => 11.68,
+            (0, 0, 0, 1, 1) => 13.60,
+            (0, 1, 2, 0, 0) if a.ring_3 => 21.94,
+            (0, 1, 2, 0, 0) => 12.03,
+            (0, 1, 0, 1, 0) => 23.85,
+            (0, 2, 1, 0, 0) => 26.02,
+            (1, 0, 4, 0, 0) => 0.,
+            (1, 0, 2, 1, 0) => 3.01,
+            (1, 0, 1, 0, 1) => 4.36,
+            (1, 1, 3, 0, 0) => 4.44,
+            (1, 1, 1, 1, 0) => 13.97,
+            (1, 2, 2, 0, 0) => 16.61,
+            (1, 2, 0, 1, 0) => 25.59,
+            (1, 3, 1, 0, 0) => 27.64,
+            _ => 0.,
+        },
+        Oxygen if a.aromatic => 13.14,
+        Oxygen => match (a.charge, a.h, single, double) {
+            (0, 0, 2, 0) if a.ring_3 => 12.53,
+            (0, 0, 2, 0) => 9.23,
+            (0, 0, 0, 1) => 17.07,
+            (0, 1, 1, 0) => 20.23,
+            (-1, 0, 1, 0) => 23.06,
+            _ => 0.,
+        },
+        _ => 0.,
+    }
+}
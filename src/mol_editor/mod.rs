@@ -3,6 +3,7 @@ pub mod templates;
 
 use std::{
     collections::HashMap,
+    fs,
     io,
     io::ErrorKind,
     path::Path,
@@ -33,14 +34,19 @@ use crate::{
         bond_entities, draw_mol, draw_peptide,
     },
     drawing_wrappers::{draw_all_ligs, draw_all_lipids, draw_all_nucleic_acids},
+    file_io::topology::SystemTopology,
     md::change_snapshot_helper,
+    mol_characterization::Descriptors,
     mol_editor,
+    mol_library::sdf_record,
     mol_lig::MoleculeSmall,
     mol_manip::{ManipMode, MolManip},
     molecule::{Atom, Bond, MolGenericRef, MolType, MoleculeCommon},
     render::{
         ATOM_SHININESS, BALL_STICK_RADIUS, BALL_STICK_RADIUS_H, set_flashlight, set_static_light,
     },
+    selfies::smiles_to_selfies,
+    stereo::SdfStereo,
     util::find_neighbor_posit,
 };
 
@@ -157,6 +163,8 @@ impl MolEditorState {
             "sdf" => {
                 let mut m: MoleculeSmall = Sdf::load(path)?.try_into()?;
                 m.common.path = Some(path.to_owned());
+                m.common
+                    .apply_sdf_stereo(&SdfStereo::from_text(&fs::read_to_string(path)?));
                 m
             }
             "mol2" => MoleculeSmall::from_xyz(Xyz::load(path)?, path)?,
@@ -275,7 +283,10 @@ impl MolEditorState {
         // Load the initial relaxation into atom positions.
         self.load_atom_posits_from_md(&mut scene.entities, state_ui, engine_updates, manip_mode);
 
-        self.mol.smiles = Some(self.mol.common.to_smiles());
+        let smiles = self.mol.common.to_smiles();
+        self.mol.selfies = smiles_to_selfies(&smiles).ok();
+        self.mol.smiles = Some(smiles);
+        self.mol.descriptors = Some(Descriptors::new(&self.mol.common));
 
         self.move_to_origin();
         scene.input_settings.control_scheme = ControlScheme::Arc {
@@ -685,10 +696,26 @@ pub fn save(state: &mut State, path: &Path) -> io::Result<()> {
     let extension = binding;
 
     match extension.to_str().unwrap_or_default() {
-        "sdf" => mol.to_sdf().save(path)?,
+        "sdf" => {
+            let mut text = String::new();
+            sdf_record(&state.mol_editor.mol, &mut text)?;
+            fs::write(path, text)?;
+        }
         "mol2" => mol.to_mol2().save(path)?,
         "xyz" => mol.to_xyz().save(path)?,
-        "prmtop" => (), // todo
+        "prmtop" => {
+            let common = &state.mol_editor.mol.common;
+
+            let mut top = SystemTopology::new(&common.ident);
+            top.add_mol(
+                common,
+                &[],
+                None,
+                state.ff_param_set.small_mol.as_ref(),
+                Some(&state.mol_editor.mol_specific_params),
+            )?;
+            top.save_amber(path)?
+        }
         "pdbqt" => mol.to_pdbqt().save(path)?,
         _ => unimplemented!(),
     }
@@ -0,0 +1,525 @@
+//! Multi-record small molecule files (e.g. vendor libraries in SDF, Mol2, or SMILES format). We load
+//! these into a library, separate from `State::ligands`, so they can be browsed, filtered, and
+//! individually added to the scene, without drawing thousands of molecules at once.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use bio_files::{BondType, Mol2, Sdf};
+use graphics::{EngineUpdates, Scene};
+use na_seq::Element::Hydrogen;
+use rayon::prelude::*;
+
+use crate::{
+    State,
+    embed::mol_from_smiles,
+    mol_characterization::Descriptors,
+    mol_lig::MoleculeSmall,
+    molecule::{Atom, MoleculeGeneric},
+    smarts::SmartsPattern,
+    stereo::SdfStereo,
+    util::{handle_err, handle_success},
+};
+
+/// Number of library entries to display at once.
+pub const LIB_PAGE_SIZE: usize = 50;
+
+pub const SDF_RECORD_END: &str = "$$$$";
+const MOL2_RECORD_START: &str = "@<TRIPOS>MOLECULE";
+/// V2000 counts and indices are 3 characters wide.
+const SDF_MAX_ATOMS: usize = 999;
+/// V2000 property lines, e.g. `M  CHG`, hold at most this many entries.
+const SDF_PROP_ENTRIES: usize = 8;
+
+/// A property we can filter library entries by.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LibProperty {
+    NumAtoms,
+    NumHeavyAtoms,
+    MolWeight,
+    /// A numerical metadata field, e.g. an SDF tag like "MW" or "logP" from the vendor.
+    Tag(String),
+}
+
+impl LibProperty {
+    pub fn to_str(&self) -> String {
+        match self {
+            Self::NumAtoms => "Atoms".to_owned(),
+            Self::NumHeavyAtoms => "Heavy atoms".to_owned(),
+            Self::MolWeight => "Mol weight".to_owned(),
+            Self::Tag(t) => t.clone(),
+        }
+    }
+
+    pub fn value(&self, mol: &MoleculeSmall) -> Option<f64> {
+        let atoms = &mol.common.atoms;
+        match self {
+            Self::NumAtoms => Some(atoms.len() as f64),
+            Self::NumHeavyAtoms => {
+                Some(atoms.iter().filter(|a| a.element != Hydrogen).count() as f64)
+            }
+            Self::MolWeight => Some(atoms.iter().map(|a| a.element.atomic_weight() as f64).sum()),
+            Self::Tag(t) => mol.common.metadata.get(t)?.trim().parse().ok(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LibFilter {
+    /// Case-insensitive substring of the molecule's identifier.
+    pub name: String,
+    pub property: Option<LibProperty>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Only include molecules containing this substructure.
+    pub smarts: Option<SmartsPattern>,
+}
+
+impl LibFilter {
+    pub fn matches(&self, mol: &MoleculeSmall) -> bool {
+        if !self.name.is_empty()
+            && !mol
+                .common
+                .ident
+                .to_lowercase()
+                .contains(&self.name.to_lowercase())
+        {
+            return false;
+        }
+
+        if let Some(prop) = &self.property
+            && (self.min.is_some() || self.max.is_some())
+        {
+            let Some(v) = prop.value(mol) else {
+                return false;
+            };
+            if self.min.is_some_and(|min| v < min) || self.max.is_some_and(|max| v > max) {
+                return false;
+            }
+        }
+
+        if let Some(pattern) = &self.smarts
+            && !mol.common.has_substruct(pattern)
+        {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// A set of molecules loaded from a multi-record file.
+#[derive(Debug, Default)]
+pub struct MolLibrary {
+    pub path: PathBuf,
+    pub mols: Vec<MoleculeSmall>,
+    /// Records we were unable to parse.
+    pub num_failed: usize,
+    pub filter: LibFilter,
+    /// Indices into `mols` which pass the filter.
+    pub filtered: Vec<usize>,
+    /// Index of the page of `filtered` currently displayed.
+    pub page: usize,
+}
+
+/// The number of molecule records in an SDF or Mol2 file's text.
+pub fn num_records(text: &str, mol2: bool) -> usize {
+    if mol2 {
+        text.lines()
+            .filter(|l| l.trim() == MOL2_RECORD_START)
+            .count()
+    } else {
+        text.lines().filter(|l| l.trim() == SDF_RECORD_END).count()
+    }
+}
+
+/// Parse a SMILES file: One molecule per line, as SMILES text, optionally followed by whitespace
+/// and a name. Blank lines, and lines starting with `#` are skipped.
+fn smiles_record(line: &str, line_i: usize) -> io::Result<MoleculeSmall> {
+    let (smiles, name) = match line.split_once(char::is_whitespace) {
+        Some((s, n)) => (s, n.trim()),
+        None => (line, ""),
+    };
+
+    let common = mol_from_smiles(smiles)?;
+    let ident = if name.is_empty() {
+        format!("Mol {}", line_i + 1)
+    } else {
+        name.to_owned()
+    };
+
+    let metadata = HashMap::from([("SMILES".to_owned(), smiles.to_owned())]);
+    Ok(MoleculeSmall::new(
+        ident,
+        common.atoms,
+        common.bonds,
+        metadata,
+        None,
+    ))
+}
+
+/// Parse one SDF record, including stereo parities, charges, and isotopes.
+pub fn sdf_mol(rec: &str) -> io::Result<MoleculeSmall> {
+    let mut result: MoleculeSmall = Sdf::new(rec)?.try_into()?;
+    result.common.apply_sdf_stereo(&SdfStereo::from_text(rec));
+    apply_sdf_props(rec, &mut result.common.atoms);
+
+    Ok(result)
+}
+
+/// Split file text into one string per record.
+pub fn split_records(text: &str, mol2: bool) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+
+    for line in text.lines() {
+        if mol2 && line.trim() == MOL2_RECORD_START {
+            // Discards comments etc. preceding the first record.
+            if current.contains(MOL2_RECORD_START) {
+                result.push(std::mem::take(&mut current));
+            }
+            current.clear();
+        }
+
+        current.push_str(line);
+        current.push('\n');
+
+        if !mol2 && line.trim() == SDF_RECORD_END {
+            result.push(std::mem::take(&mut current));
+        }
+    }
+
+    if !current.trim().is_empty() {
+        result.push(current);
+    }
+
+    result
+}
+
+impl MolLibrary {
+    /// Load all records from an SDF, Mol2, or SMILES file. Records which fail to parse are skipped.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let ext = path.extension().unwrap_or_default().to_ascii_lowercase();
+        let mol2 = ext == "mol2";
+        let smiles = ext == "smi" || ext == "smiles";
+
+        let text = fs::read_to_string(path)?;
+        let records = if smiles {
+            text.lines()
+                .map(|l| l.trim().to_owned())
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .collect()
+        } else {
+            split_records(&text, mol2)
+        };
+
+        // Parsing is independent per record, and may be slow for large libraries. For SMILES,
+        // this includes generating coordinates.
+        let parsed: Vec<_> = records
+            .par_iter()
+            .enumerate()
+            .map(|(i, rec)| {
+                let mut mol: MoleculeSmall = if smiles {
+                    smiles_record(rec, i)?
+                } else if mol2 {
+                    Mol2::new(rec)?.try_into()?
+                } else {
+                    sdf_mol(rec)?
+                };
+                mol.common.path = Some(path.to_owned());
+                Ok::<_, io::Error>(mol)
+            })
+            .collect();
+
+        let num_failed = parsed.iter().filter(|m| m.is_err()).count();
+        let mols: Vec<_> = parsed.into_iter().filter_map(Result::ok).collect();
+
+        if mols.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "No valid molecules found in this library",
+            ));
+        }
+
+        let mut result = Self {
+            path: path.to_owned(),
+            mols,
+            num_failed,
+            ..Default::default()
+        };
+        result.apply_filter();
+
+        Ok(result)
+    }
+
+    /// Update the filtered indices. Run this after changing the filter.
+    pub fn apply_filter(&mut self) {
+        // Substructure matching is slow enough to be worth parallelizing on large libraries.
+        self.filtered = self
+            .mols
+            .par_iter()
+            .enumerate()
+            .filter(|(_, m)| self.filter.matches(m))
+            .map(|(i, _)| i)
+            .collect();
+
+        self.page = self.page.min(self.num_pages().saturating_sub(1));
+    }
+
+    pub fn num_pages(&self) -> usize {
+        self.filtered.len().div_ceil(LIB_PAGE_SIZE)
+    }
+
+    /// Indices into `mols` of the current page.
+    pub fn page_indices(&self) -> &[usize] {
+        let start = (self.page * LIB_PAGE_SIZE).min(self.filtered.len());
+        let end = (start + LIB_PAGE_SIZE).min(self.filtered.len());
+        &self.filtered[start..end]
+    }
+
+    /// All metadata keys present in the library, e.g. SDF tags. For use as filter properties.
+    pub fn tags(&self) -> Vec<String> {
+        let keys: BTreeSet<_> = self
+            .mols
+            .iter()
+            .flat_map(|m| m.common.metadata.keys())
+            .collect();
+
+        keys.into_iter().cloned().collect()
+    }
+
+    /// Save the molecules that pass the filter to a multi-record SDF file.
+    pub fn save_filtered_sdf(&self, path: &Path) -> io::Result<()> {
+        let mut text = String::new();
+        for &i in &self.filtered {
+            sdf_record(&self.mols[i], &mut text)?;
+        }
+
+        fs::write(path, text)
+    }
+
+    /// Save descriptors and drug-likeness verdicts of the molecules that pass the filter to a
+    /// CSV file, one row per molecule.
+    pub fn save_descriptors_csv(&self, path: &Path) -> io::Result<()> {
+        let rows: Vec<String> = self
+            .filtered
+            .par_iter()
+            .map(|&i| {
+                let mol = &self.mols[i];
+                let d = match &mol.descriptors {
+                    Some(d) => d.clone(),
+                    None => Descriptors::new(&mol.common),
+                };
+
+                format!(
+                    "{},{},{:.3},{:.4},{:.3},{:.2},{},{},{},{},{},{:.3},{},{},{},{}",
+                    csv_field(&mol.common.ident),
+                    csv_field(&d.formula),
+                    d.mol_weight,
+                    d.exact_mass,
+                    d.log_p,
+                    d.tpsa,
+                    d.h_donors,
+                    d.h_acceptors,
+                    d.rotatable_bonds,
+                    d.formal_charge,
+                    d.heavy_atoms,
+                    d.fraction_sp3,
+                    d.lipinski_violations(),
+                    d.lipinski(),
+                    d.veber(),
+                    d.lead_like(),
+                )
+            })
+            .collect();
+
+        let mut text = "Name,Formula,MW,Exact mass,logP,TPSA,HBD,HBA,Rotatable bonds,Charge,\
+            Heavy atoms,Fsp3,Lipinski violations,Lipinski,Veber,Lead-like\n"
+            .to_owned();
+        for row in rows {
+            text += &row;
+            text.push('\n');
+        }
+
+        fs::write(path, text)
+    }
+}
+
+/// Quote a CSV field if it contains a delimiter, quote, or line break.
+pub fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Set formal charges and isotopes from a V2000 record's `M  CHG` and `M  ISO` lines.
+fn apply_sdf_props(text: &str, atoms: &mut [Atom]) {
+    for line in text.lines() {
+        let (is_charge, rest) = if let Some(r) = line.strip_prefix("M  CHG") {
+            (true, r)
+        } else if let Some(r) = line.strip_prefix("M  ISO") {
+            (false, r)
+        } else {
+            continue;
+        };
+
+        // The entry count, then (atom number, value) pairs.
+        let vals: Vec<i32> = rest
+            .split_whitespace()
+            .filter_map(|v| v.parse().ok())
+            .collect();
+
+        for pair in vals.get(1..).unwrap_or_default().chunks_exact(2) {
+            let Some(atom) = atoms.get_mut((pair[0] as usize).wrapping_sub(1)) else {
+                continue;
+            };
+            if is_charge {
+                atom.formal_charge = pair[1] as i8;
+            } else {
+                atom.isotope = u16::try_from(pair[1]).ok();
+            }
+        }
+    }
+}
+
+/// Write V2000 property lines, e.g. `M  CHG`, for (atom index, value) entries.
+fn write_sdf_props(buf: &mut String, name: &str, entries: &[(usize, i32)]) {
+    for chunk in entries.chunks(SDF_PROP_ENTRIES) {
+        let _ = write!(buf, "M  {name}{:>3}", chunk.len());
+        for (i, v) in chunk {
+            let _ = write!(buf, " {:>3} {v:>3}", i + 1);
+        }
+        buf.push('\n');
+    }
+}
+
+/// Append a single V2000 record, including tags, to `buf`. Formal charges and isotopes are
+/// written as `M  CHG` and `M  ISO` lines.
+pub fn sdf_record(mol: &MoleculeSmall, buf: &mut String) -> io::Result<()> {
+    let sdf = mol.to_sdf();
+
+    if sdf.atoms.len() > SDF_MAX_ATOMS || sdf.bonds.len() > SDF_MAX_ATOMS {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} has too many atoms or bonds for V2000 SDF (max {SDF_MAX_ATOMS})",
+                sdf.ident
+            ),
+        ));
+    }
+
+    // The header block: Name, program, and comment.
+    let _ = writeln!(buf, "{}\n  Molchanica\n", sdf.ident);
+    let _ = writeln!(
+        buf,
+        "{:>3}{:>3}  0  0  0  0  0  0  0  0999 V2000",
+        sdf.atoms.len(),
+        sdf.bonds.len()
+    );
+
+    let parities = mol.common.sdf_parities();
+
+    for (atom, parity) in sdf.atoms.iter().zip(parities) {
+        let _ = writeln!(
+            buf,
+            "{:>10.4}{:>10.4}{:>10.4} {:<3} 0  0{parity:>3}  0  0  0  0  0  0  0  0  0",
+            atom.posit.x,
+            atom.posit.y,
+            atom.posit.z,
+            atom.element.to_letter()
+        );
+    }
+
+    let index: HashMap<_, _> = sdf
+        .atoms
+        .iter()
+        .enumerate()
+        .map(|(i, a)| (a.serial_number, i))
+        .collect();
+
+    for bond in &sdf.bonds {
+        let (Some(i0), Some(i1)) = (index.get(&bond.atom_0_sn), index.get(&bond.atom_1_sn)) else {
+            continue;
+        };
+
+        let bond_type = match bond.bond_type {
+            BondType::Double => 2,
+            BondType::Triple => 3,
+            BondType::Aromatic => 4,
+            _ => 1,
+        };
+        let _ = writeln!(buf, "{:>3}{:>3}{:>3}  0", i0 + 1, i1 + 1, bond_type);
+    }
+
+    let atoms = &mol.common.atoms;
+    let charges: Vec<_> = atoms
+        .iter()
+        .enumerate()
+        .filter(|(_, a)| a.formal_charge != 0)
+        .map(|(i, a)| (i, a.formal_charge as i32))
+        .collect();
+    let isotopes: Vec<_> = atoms
+        .iter()
+        .enumerate()
+        .filter_map(|(i, a)| Some((i, a.isotope? as i32)))
+        .collect();
+    write_sdf_props(buf, "CHG", &charges);
+    write_sdf_props(buf, "ISO", &isotopes);
+    buf.push_str("M  END\n");
+
+    let mut keys: Vec<_> = sdf.metadata.keys().collect();
+    keys.sort();
+    for key in keys {
+        let _ = writeln!(buf, "> <{key}>\n{}\n", sdf.metadata[key]);
+    }
+
+    buf.push_str(SDF_RECORD_END);
+    buf.push('\n');
+
+    Ok(())
+}
+
+impl State {
+    /// Load a multi-record file into the library panel.
+    pub fn open_library(&mut self, path: &Path) -> io::Result<()> {
+        let lib = MolLibrary::load(path)?;
+
+        let mut msg = format!("Loaded {} molecules into the library", lib.mols.len());
+        if lib.num_failed > 0 {
+            msg += &format!(" ({} records failed to parse)", lib.num_failed);
+        }
+
+        self.library = Some(lib);
+        self.ui.popup.library = true;
+        handle_success(&mut self.ui, msg);
+
+        Ok(())
+    }
+
+    /// Add a library molecule to `ligands`, and draw it.
+    pub fn promote_library_mol(
+        &mut self,
+        i: usize,
+        scene: &mut Scene,
+        engine_updates: &mut EngineUpdates,
+    ) {
+        let Some(mol) = self.library.as_ref().and_then(|l| l.mols.get(i)).cloned() else {
+            handle_err(&mut self.ui, "Library molecule not found".to_owned());
+            return;
+        };
+
+        self.load_mol_to_state(
+            MoleculeGeneric::Ligand(mol),
+            Some(scene),
+            engine_updates,
+            None,
+        );
+    }
+}
@@ -21,7 +21,8 @@ use dynamics::{
 use na_seq::Element;
 
 use crate::{
-    docking::{DockingSite, Pose},
+    docking::{DockingSite, Pose, external::ExternalPose},
+    mol_characterization::Descriptors,
     molecule::{
         Atom, Bond, Chain, MolGenericRef, MolGenericTrait, MolIdent, MolType as Mt, MoleculeCommon,
         Residue,
@@ -46,6 +47,16 @@ pub struct MoleculeSmall {
     /// Simplified Molecular Input Line Entry System
     /// A cache for display as required. This is a text representation of a molecular formula.
     pub smiles: Option<String>,
+    /// Self-Referencing Embedded Strings. A cache for display, as with SMILES. `None` if the
+    /// molecule can't be encoded.
+    pub selfies: Option<String>,
+    /// Physicochemical descriptors and drug-likeness. A cache for display, as with SMILES.
+    pub descriptors: Option<Descriptors>,
+    /// Poses from another docking program's output, e.g. Vina or Gnina. Empty unless loaded
+    /// from such a file.
+    pub ext_poses: Vec<ExternalPose>,
+    /// The imported pose applied to the atom positions.
+    pub ext_pose_i: usize,
 }
 
 impl MoleculeSmall {
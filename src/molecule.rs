@@ -39,6 +39,7 @@ use rayon::prelude::*;
 use crate::{
     Selection,
     bond_inference::create_hydrogen_bonds,
+    docking::find_sites::DetectedSite,
     drawing::EntityClass,
     lipid::MoleculeLipid,
     mol_editor::NEXT_ATOM_SN,
@@ -509,6 +510,8 @@ pub struct MoleculePeptide {
     pub experimental_method: Option<ExperimentalMethod>,
     /// E.g: ["A", "B"]. Inferred from atoms.
     pub alternate_conformations: Option<Vec<String>>,
+    /// Detected binding pockets, ranked by score. Empty until pocket detection is run.
+    pub docking_sites: Vec<DetectedSite>,
     // pub ff_params: Option<ForceFieldParamsIndexed>,
 }
 
@@ -814,9 +817,14 @@ pub struct HydrogenBond {
 
 #[derive(Debug, Clone)]
 pub struct Residue {
-    /// We use serial number of display, search etc, and array index to select. Residue serial number is not
-    /// unique in the molecule; only in the chain.
+    /// We use serial number to reference residues from chains, and array index to select. Residue
+    /// serial number is not unique in the molecule; only in the chain.
     pub serial_number: u32,
+    /// The sequence number from the source file; we use this for display and search. In PDB files,
+    /// this may be negative, or repeated with different insertion codes.
+    pub seq_num: i32,
+    /// E.g. the "A" in residue "52A".
+    pub ins_code: Option<char>,
     pub res_type: ResidueType,
     /// Serial number
     pub atom_sns: Vec<u32>,
@@ -834,6 +842,14 @@ impl Residue {
             end: self.end,
         }
     }
+
+    /// The sequence number, and insertion code if present. E.g. "52" or "52A".
+    pub fn seq_label(&self) -> String {
+        match self.ins_code {
+            Some(ic) => format!("{}{ic}", self.seq_num),
+            None => self.seq_num.to_string(),
+        }
+    }
 }
 
 impl Residue {
@@ -854,6 +870,8 @@ impl Residue {
 
         Ok(Self {
             serial_number: res.serial_number,
+            seq_num: res.serial_number as i32,
+            ins_code: None,
             res_type: res.res_type.clone(),
             atom_sns: res.atom_sns.clone(),
             atoms,
@@ -956,7 +974,7 @@ fn res_sns_to_indices(sn_tgt: u32, res_set: &[Residue]) -> Option<usize> {
 
 impl Display for Residue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "#{}: {}", self.serial_number, self.res_type)?;
+        write!(f, "#{}: {}", self.seq_label(), self.res_type)?;
 
         if let Some(dihedral) = &self.dihedral {
             write!(f, "   {}", dihedral)?;
@@ -996,8 +1014,17 @@ pub struct Atom {
     pub hetero: bool,
     /// For docking.
     pub occupancy: Option<f32>,
+    /// Isotropic temperature factor, e.g. as loaded from PDB files.
+    pub b_factor: Option<f32>,
     /// Elementary charge. (Charge of a proton)
     pub partial_charge: Option<f32>,
+    /// Integer formal charge, e.g. from SMILES bracket atoms like `[NH4+]`.
+    pub formal_charge: i8,
+    /// The number of hydrogens bonded to this atom that aren't present as explicit atoms,
+    /// if known. E.g. as inferred when parsing SMILES.
+    pub implicit_h: Option<u8>,
+    /// Mass number, if specified. E.g. 13 for carbon-13.
+    pub isotope: Option<u16>,
     pub alt_conformation_id: Option<String>,
 }
 
@@ -1212,6 +1239,12 @@ impl MoleculePeptide {
             .collect();
 
         let mut res_gen: Vec<_> = self.residues.iter().map(|a| a.to_generic()).collect();
+        // Generic residues don't include these.
+        let seq_nums: HashMap<_, _> = self
+            .residues
+            .iter()
+            .map(|r| (r.serial_number, (r.seq_num, r.ins_code)))
+            .collect();
         let mut chains_gen: Vec<_> = self.chains.iter().map(|a| a.to_generic()).collect();
 
         println!("Populating Hydrogens and dihedral angles...");
@@ -1226,9 +1259,16 @@ impl MoleculePeptide {
 
         let bonds_gen = create_bonds(&atoms_gen);
 
-        let (atoms, bonds, residues, chains) =
+        let (atoms, bonds, mut residues, chains) =
             init_bonds_chains_res(&atoms_gen, &bonds_gen, &res_gen, &chains_gen, &dihedrals)?;
 
+        for res in &mut residues {
+            if let Some(&(seq_num, ins_code)) = seq_nums.get(&res.serial_number) {
+                res.seq_num = seq_num;
+                res.ins_code = ins_code;
+            }
+        }
+
         self.common.atoms = atoms;
         self.common.bonds = bonds;
         self.residues = residues;
@@ -1272,7 +1312,7 @@ pub fn build_adjacency_list(bonds: &Vec<Bond>, atoms_len: usize) -> Vec<Vec<usiz
 }
 
 /// A helper, shared between mmCIF parsing, and H regenerating from changed pH.
-fn init_bonds_chains_res(
+pub(crate) fn init_bonds_chains_res(
     atoms_: &[AtomGeneric],
     bonds_: &[BondGeneric],
     residues_: &[ResidueGeneric],
@@ -1289,7 +1329,8 @@ fn init_bonds_chains_res(
     let mut residues = Vec::with_capacity(residues_.len());
 
     let len_matches = residues_.len() == dihedrals.len();
-    if !len_matches {
+    // Dihedrals aren't available from all sources; e.g. topology files.
+    if !len_matches && !dihedrals.is_empty() {
         eprintln!(
             "Error: Diehedral, residue len mismatch. Dihedrals: {}, residues: {}",
             dihedrals.len(),
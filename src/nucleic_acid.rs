@@ -290,6 +290,8 @@ fn build_single_strand(
         };
         let mut res = Residue {
             serial_number: res_i as u32 + 1,
+            seq_num: res_i as i32 + 1,
+            ins_code: None,
             res_type: ResidueType::Other(format!("Nucleotide: {nt}")),
             atom_sns: Vec::new(),
             atoms: Vec::new(),
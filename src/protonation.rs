@@ -0,0 +1,625 @@
+//! Ligand protonation states and tautomers. Acidic and basic groups are found with SMARTS, and
+//! assigned typical pKa values; populations at a pH follow the Henderson–Hasselbalch equation,
+//! treating sites as independent. Tautomers are enumerated by shifting hydrogens between
+//! heteroatoms (N, O, S), along alternating single and double bonds.
+//!
+//! Molecules with explicit hydrogens get hydrogen atoms added and removed, with positions
+//! estimated from the local geometry; for others, we update implicit hydrogen counts.
+
+use std::collections::{HashSet, VecDeque};
+
+use bio_files::BondType;
+use lin_alg::f64::Vec3;
+use na_seq::Element::{self, Hydrogen, Nitrogen, Oxygen, Sulfur};
+
+use crate::{
+    State,
+    mol_characterization::{Descriptors, PerceivedMol},
+    mol_lig::MoleculeSmall,
+    molecule::{Atom, Bond, MoleculeCommon},
+    selfies::smiles_to_selfies,
+    smarts::parse_smarts,
+};
+
+/// Enumerate protomers over at most this many sites; others take their majority state.
+const MAX_SITES: usize = 10;
+/// Protomers less populated than this at the target pH aren't listed.
+const MIN_POPULATION: f32 = 0.01;
+const MAX_TAUTOMERS: usize = 20;
+/// The number of double bonds a hydrogen shift may pass along. 3 allows, for example,
+/// 2-hydroxypyridine to 2-pyridone from either Kekulé form.
+const MAX_SHIFT_DOUBLE_BONDS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SiteKind {
+    /// Loses a proton, becoming negative.
+    Acid,
+    /// Gains a proton, becoming positive.
+    Base,
+}
+
+struct IonRule {
+    name: &'static str,
+    /// The first pattern atom is the one protonated, or deprotonated.
+    smarts: &'static str,
+    pka: f32,
+    kind: SiteKind,
+}
+
+const fn rule(name: &'static str, smarts: &'static str, pka: f32, kind: SiteKind) -> IonRule {
+    IonRule {
+        name,
+        smarts,
+        pka,
+        kind,
+    }
+}
+
+/// Typical pKa values of common groups. Where more than one rule matches an atom, the first
+/// applies.
+const ION_RULES: [IonRule; 18] = [
+    rule("Sulfonic acid", "[OX2H1]S(=O)=O", -1., SiteKind::Acid),
+    rule("Phosphate", "[OX2H1]P=O", 2., SiteKind::Acid),
+    rule("Carboxylic acid", "[OX2H1][CX3]=O", 4.2, SiteKind::Acid),
+    rule("Tetrazole", "[nH]1nnnc1", 4.9, SiteKind::Acid),
+    rule("Tetrazole", "[nH]1nncn1", 4.9, SiteKind::Acid),
+    rule(
+        "Acyl sulfonamide",
+        "[NX3H1](C=O)S(=O)=O",
+        4.5,
+        SiteKind::Acid,
+    ),
+    rule("Thiophenol", "[SX2H1]c", 6.5, SiteKind::Acid),
+    rule("Imide", "[NX3H1](C=O)C=O", 9.6, SiteKind::Acid),
+    rule("Phenol", "[OX2H1]c", 10., SiteKind::Acid),
+    rule("Sulfonamide", "[NX3;H1,H2]S(=O)=O", 10., SiteKind::Acid),
+    rule("Thiol", "[SX2H1][CX4]", 10.5, SiteKind::Acid),
+    rule(
+        "Guanidine",
+        "[NX2;!$(N[a]);!$(NC=O)]=C([NX3])[NX3]",
+        13.,
+        SiteKind::Base,
+    ),
+    rule(
+        "Amidine",
+        "[NX2;!$(N[a])]=[CX3]([#6])[NX3;!$(NC=[O,S])]",
+        12.,
+        SiteKind::Base,
+    ),
+    rule(
+        "Primary amine",
+        "[NX3H2;!$(N[a]);!$(N[#6,#7,#15,#16]=[#6,#7,#8,#16]);!$(N[#7,#8])][CX4]",
+        10.6,
+        SiteKind::Base,
+    ),
+    rule(
+        "Secondary amine",
+        "[NX3H1;!$(N[a]);!$(N[#6,#7,#15,#16]=[#6,#7,#8,#16]);!$(N[#7,#8])]([CX4])[CX4]",
+        10.5,
+        SiteKind::Base,
+    ),
+    rule(
+        "Tertiary amine",
+        "[NX3H0;!$(N[a]);!$(N[#6,#7,#15,#16]=[#6,#7,#8,#16]);!$(N[#7,#8])]([CX4])([CX4])[CX4]",
+        9.8,
+        SiteKind::Base,
+    ),
+    rule("Imidazole", "[nX2]1c[nH]cc1", 7., SiteKind::Base),
+    rule("Pyridine", "[nX2;r6]", 5.2, SiteKind::Base),
+];
+
+/// An acidic or basic group.
+#[derive(Clone, Debug)]
+pub struct IonSite {
+    /// Index into the neutral molecule's atoms.
+    pub atom: usize,
+    pub name: &'static str,
+    pub pka: f32,
+    pub kind: SiteKind,
+}
+
+impl IonSite {
+    /// The fraction of this site in its ionized form.
+    pub fn ionized_frac(&self, ph: f32) -> f32 {
+        let exp = match self.kind {
+            SiteKind::Acid => self.pka - ph,
+            SiteKind::Base => ph - self.pka,
+        };
+        1. / (1. + 10_f32.powf(exp))
+    }
+}
+
+/// A protonation state, or tautomer of a molecule.
+#[derive(Clone, Debug)]
+pub struct Species {
+    pub mol: MoleculeCommon,
+    pub smiles: String,
+    pub net_charge: i32,
+    /// For protomers, the fraction present at the target pH.
+    pub population: Option<f32>,
+}
+
+impl Species {
+    fn new(mol: MoleculeCommon, population: Option<f32>) -> Self {
+        Self {
+            smiles: mol.to_smiles(),
+            net_charge: mol.atoms.iter().map(|a| a.formal_charge as i32).sum(),
+            mol,
+            population,
+        }
+    }
+}
+
+/// Protonation states and tautomers of a ligand, for display.
+#[derive(Clone, Debug)]
+pub struct ProtonationStates {
+    pub lig_i: usize,
+    pub ph: f32,
+    pub sites: Vec<IonSite>,
+    /// Most populated first.
+    pub protomers: Vec<Species>,
+    pub tautomers: Vec<Species>,
+}
+
+impl ProtonationStates {
+    pub fn new(mol: &MoleculeCommon, lig_i: usize, ph: f32) -> Self {
+        let (sites, protomers) = protomers(mol, ph);
+        Self {
+            lig_i,
+            ph,
+            sites,
+            protomers,
+            tautomers: tautomers(mol),
+        }
+    }
+}
+
+fn is_hetero(el: Element) -> bool {
+    matches!(el, Nitrogen | Oxygen | Sulfur)
+}
+
+/// Explicit and implicit hydrogens on an atom. Requires an up-to-date adjacency list.
+fn h_count(mol: &MoleculeCommon, i: usize) -> u8 {
+    let explicit = mol.adjacency_list[i]
+        .iter()
+        .filter(|&&j| mol.atoms[j].element == Hydrogen)
+        .count() as u8;
+    explicit + mol.atoms[i].implicit_h.unwrap_or(0)
+}
+
+fn bond_between(mol: &MoleculeCommon, a: usize, b: usize) -> Option<usize> {
+    mol.bonds
+        .iter()
+        .position(|b_| (b_.atom_0 == a && b_.atom_1 == b) || (b_.atom_0 == b && b_.atom_1 == a))
+}
+
+/// A position for a new hydrogen on an atom: Opposite its neighbors, or for atoms with a single
+/// neighbor, at a tetrahedral angle to it, and anti to the neighbor's other neighbors.
+fn h_posit(posits: &[Vec3], adj: &[Vec<usize>], el: Element, i: usize) -> Vec3 {
+    let center = posits[i];
+    let nbrs: Vec<(usize, Vec3)> = adj[i]
+        .iter()
+        .map(|&j| (j, posits[j] - center))
+        .filter(|(_, v)| v.magnitude() > 1e-6)
+        .map(|(j, v)| (j, v.to_normalized()))
+        .collect();
+
+    let any_perp = |u: Vec3| {
+        let axis = if u.x.abs() < 0.9 {
+            Vec3::new(1., 0., 0.)
+        } else {
+            Vec3::new(0., 1., 0.)
+        };
+        u.cross(axis)
+    };
+
+    let dir = match nbrs.as_slice() {
+        [] => Vec3::new(1., 0., 0.),
+        [(j, u)] => {
+            let perp = adj[*j]
+                .iter()
+                .find(|&&k| k != i)
+                .map(|&k| posits[k] - posits[*j])
+                .map(|r| r - *u * r.dot(*u))
+                .filter(|p| p.magnitude() > 1e-3)
+                .unwrap_or_else(|| any_perp(*u))
+                .to_normalized();
+            // cos and sin of 109.5°.
+            *u * -0.334 - perp * 0.943
+        }
+        [(_, u0), (_, u1), ..] => {
+            let sum = nbrs.iter().fold(Vec3::new_zero(), |acc, (_, u)| acc + *u);
+            if sum.magnitude() > 1e-3 {
+                (sum * -1.).to_normalized()
+            } else {
+                u0.cross(*u1).to_normalized()
+            }
+        }
+    };
+
+    let bond_len = match el {
+        Nitrogen => 1.01,
+        Oxygen => 0.96,
+        Sulfur => 1.34,
+        _ => 1.09,
+    };
+
+    center + dir * bond_len
+}
+
+fn add_h_atom(mol: &mut MoleculeCommon, i: usize) {
+    let el = mol.atoms[i].element;
+    let posits_orig: Vec<_> = mol.atoms.iter().map(|a| a.posit).collect();
+    let posit = h_posit(&posits_orig, &mol.adjacency_list, el, i);
+    let posit_current = h_posit(&mol.atom_posits, &mol.adjacency_list, el, i);
+
+    let sn = mol.atoms.iter().map(|a| a.serial_number).max().unwrap_or(0) + 1;
+    let i_h = mol.atoms.len();
+
+    mol.atoms.push(Atom {
+        serial_number: sn,
+        posit,
+        element: Hydrogen,
+        implicit_h: Some(0),
+        ..Default::default()
+    });
+    mol.atom_posits.push(posit_current);
+    mol.bonds.push(Bond {
+        bond_type: BondType::Single,
+        atom_0_sn: mol.atoms[i].serial_number,
+        atom_1_sn: sn,
+        atom_0: i,
+        atom_1: i_h,
+        is_backbone: false,
+    });
+
+    mol.adjacency_list[i].push(i_h);
+    mol.adjacency_list.push(vec![i]);
+}
+
+/// Add a hydrogen to each atom in `add`, and remove one from each in `remove`. Doesn't change
+/// formal charges. Removing hydrogen atoms shifts the indices of atoms after them.
+fn move_protons(mol: &mut MoleculeCommon, add: &[usize], remove: &[usize]) {
+    mol.build_adjacency_list();
+    let explicit = mol.atoms.iter().any(|a| a.element == Hydrogen);
+
+    let mut h_to_remove = Vec::new();
+    for &i in remove {
+        let h = mol.adjacency_list[i]
+            .iter()
+            .copied()
+            .find(|&j| mol.atoms[j].element == Hydrogen && !h_to_remove.contains(&j));
+
+        match h {
+            Some(j) => h_to_remove.push(j),
+            None => {
+                let atom = &mut mol.atoms[i];
+                atom.implicit_h = Some(atom.implicit_h.unwrap_or(0).saturating_sub(1));
+            }
+        }
+    }
+
+    for &i in add {
+        if explicit {
+            add_h_atom(mol, i);
+        } else {
+            let atom = &mut mol.atoms[i];
+            atom.implicit_h = Some(atom.implicit_h.unwrap_or(0) + 1);
+        }
+    }
+
+    h_to_remove.sort_unstable_by(|a, b| b.cmp(a));
+    for j in h_to_remove {
+        mol.remove_atom(j);
+    }
+}
+
+/// A copy of the molecule, with implicit hydrogen counts inferred from standard valences if
+/// there's no hydrogen information.
+fn with_h_counts(mol: &MoleculeCommon) -> MoleculeCommon {
+    let mut result = mol.clone();
+    result.build_adjacency_list();
+
+    let h_known = result
+        .atoms
+        .iter()
+        .any(|a| a.element == Hydrogen || a.implicit_h.is_some());
+
+    if !h_known {
+        let implicit_h = PerceivedMol::new(&result).implicit_h;
+        for (atom, h) in result.atoms.iter_mut().zip(implicit_h) {
+            atom.implicit_h = Some(h);
+        }
+    }
+
+    result
+}
+
+/// Neutralize charged acids and bases, e.g. carboxylates and ammonium ions. Leaves charges that
+/// are balanced by a neighbor (e.g. nitro groups), and quaternary ammonium ions.
+pub fn neutralized(mol: &MoleculeCommon) -> MoleculeCommon {
+    let mut result = with_h_counts(mol);
+
+    let charged_nbr = |i: usize, sign: i8| {
+        result.adjacency_list[i]
+            .iter()
+            .any(|&j| result.atoms[j].formal_charge.signum() == sign)
+    };
+
+    let mut add = Vec::new();
+    let mut remove = Vec::new();
+    for (i, atom) in result.atoms.iter().enumerate() {
+        if atom.formal_charge == -1 && is_hetero(atom.element) && !charged_nbr(i, 1) {
+            add.push(i);
+        } else if atom.formal_charge == 1
+            && atom.element == Nitrogen
+            && h_count(&result, i) > 0
+            && !charged_nbr(i, -1)
+        {
+            remove.push(i);
+        }
+    }
+
+    for &i in add.iter().chain(&remove) {
+        result.atoms[i].formal_charge = 0;
+    }
+    move_protons(&mut result, &add, &remove);
+
+    result
+}
+
+/// Find acidic and basic groups in a neutral molecule.
+pub fn ionizable_sites(mol: &MoleculeCommon) -> Vec<IonSite> {
+    let mut result: Vec<IonSite> = Vec::new();
+
+    for rule in &ION_RULES {
+        let Ok(pattern) = parse_smarts(rule.smarts) else {
+            eprintln!("Error parsing the SMARTS for {}", rule.name);
+            continue;
+        };
+
+        for m in mol.substruct_matches(&pattern) {
+            if result.iter().any(|s| s.atom == m[0]) {
+                continue;
+            }
+            result.push(IonSite {
+                atom: m[0],
+                name: rule.name,
+                pka: rule.pka,
+                kind: rule.kind,
+            });
+        }
+    }
+
+    result
+}
+
+/// Ionize sites of a neutral molecule.
+fn ionize(neutral: &MoleculeCommon, sites: &[&IonSite]) -> MoleculeCommon {
+    let mut result = neutral.clone();
+    let mut add = Vec::new();
+    let mut remove = Vec::new();
+
+    for site in sites {
+        match site.kind {
+            SiteKind::Acid => {
+                result.atoms[site.atom].formal_charge -= 1;
+                remove.push(site.atom);
+            }
+            SiteKind::Base => {
+                result.atoms[site.atom].formal_charge += 1;
+                add.push(site.atom);
+            }
+        }
+    }
+
+    move_protons(&mut result, &add, &remove);
+    result
+}
+
+/// Enumerate protonation states at a pH, most populated first. The first is always the dominant
+/// one, even if below the population cutoff. Also returns the ionizable sites.
+pub fn protomers(mol: &MoleculeCommon, ph: f32) -> (Vec<IonSite>, Vec<Species>) {
+    let neutral = neutralized(mol);
+    let sites = ionizable_sites(&neutral);
+
+    let (enumerated, fixed) = sites.split_at(sites.len().min(MAX_SITES));
+    let fixed_ionized: Vec<&IonSite> = fixed.iter().filter(|s| s.ionized_frac(ph) > 0.5).collect();
+
+    let dominant_mask = enumerated
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.ionized_frac(ph) > 0.5)
+        .fold(0, |mask, (k, _)| mask | (1 << k));
+
+    let mut result = Vec::new();
+    for mask in 0..1_usize << enumerated.len() {
+        let mut population = 1.;
+        let mut ionized = fixed_ionized.clone();
+
+        for (k, site) in enumerated.iter().enumerate() {
+            let frac = site.ionized_frac(ph);
+            if mask & (1 << k) != 0 {
+                population *= frac;
+                ionized.push(site);
+            } else {
+                population *= 1. - frac;
+            }
+        }
+
+        if population < MIN_POPULATION && mask != dominant_mask {
+            continue;
+        }
+        result.push(Species::new(ionize(&neutral, &ionized), Some(population)));
+    }
+
+    result.sort_by(|a, b| b.population.unwrap().total_cmp(&a.population.unwrap()));
+    (sites, result)
+}
+
+/// Paths from a hydrogen donor along alternating single and double bonds, ending at a
+/// heteroatom able to accept it.
+fn shift_paths(mol: &MoleculeCommon, path: &mut Vec<usize>, result: &mut Vec<Vec<usize>>) {
+    let last = *path.last().unwrap();
+    let bond_type = |a, b| bond_between(mol, a, b).map(|i| mol.bonds[i].bond_type);
+
+    for &y in &mol.adjacency_list[last] {
+        if path.contains(&y)
+            || mol.atoms[y].element == Hydrogen
+            || bond_type(last, y) != Some(BondType::Single)
+        {
+            continue;
+        }
+
+        for &z in &mol.adjacency_list[y] {
+            if path.contains(&z) || bond_type(y, z) != Some(BondType::Double) {
+                continue;
+            }
+
+            path.push(y);
+            path.push(z);
+
+            if is_hetero(mol.atoms[z].element) && mol.atoms[z].formal_charge == 0 {
+                result.push(path.clone());
+            }
+            if path.len() / 2 < MAX_SHIFT_DOUBLE_BONDS {
+                shift_paths(mol, path, result);
+            }
+
+            path.pop();
+            path.pop();
+        }
+    }
+}
+
+/// Molecules one hydrogen shift away from this one. Expects a Kekulé form.
+fn proton_shifts(mol: &MoleculeCommon) -> Vec<MoleculeCommon> {
+    let mut paths = Vec::new();
+    for (i, atom) in mol.atoms.iter().enumerate() {
+        if is_hetero(atom.element) && atom.formal_charge == 0 && h_count(mol, i) > 0 {
+            shift_paths(mol, &mut vec![i], &mut paths);
+        }
+    }
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let mut result = mol.clone();
+            for (k, pair) in path.windows(2).enumerate() {
+                if let Some(b) = bond_between(&result, pair[0], pair[1]) {
+                    result.bonds[b].bond_type = if k % 2 == 0 {
+                        BondType::Double
+                    } else {
+                        BondType::Single
+                    };
+                }
+            }
+            move_protons(&mut result, &[*path.last().unwrap()], &[path[0]]);
+            result
+        })
+        .collect()
+}
+
+/// Enumerate heteroatom tautomers, including the molecule as given, first.
+pub fn tautomers(mol: &MoleculeCommon) -> Vec<Species> {
+    let mut start = with_h_counts(mol);
+    let _ = start.kekulize();
+
+    let first = Species::new(start.clone(), None);
+    let mut seen = HashSet::from([first.smiles.clone()]);
+    let mut result = vec![first];
+    let mut queue = VecDeque::from([start]);
+
+    while let Some(mol) = queue.pop_front() {
+        for shifted in proton_shifts(&mol) {
+            if result.len() >= MAX_TAUTOMERS {
+                return result;
+            }
+
+            let species = Species::new(shifted, None);
+            if seen.insert(species.smiles.clone()) {
+                queue.push_back(species.mol.clone());
+                result.push(species);
+            }
+        }
+    }
+
+    result
+}
+
+impl MoleculeSmall {
+    /// Replace atoms and bonds with those of another protonation state, or tautomer of this
+    /// molecule. Clears force field types and partial charges, so they're re-assigned.
+    pub fn set_species(&mut self, species: &MoleculeCommon) {
+        self.common.atoms = species.atoms.clone();
+        self.common.bonds = species.bonds.clone();
+        self.common.atom_posits = species.atom_posits.clone();
+        self.common.build_adjacency_list();
+
+        for atom in &mut self.common.atoms {
+            atom.force_field_type = None;
+            atom.partial_charge = None;
+        }
+        self.ff_params_loaded = false;
+        self.frcmod_loaded = false;
+
+        let smiles = self.common.to_smiles();
+        self.selfies = smiles_to_selfies(&smiles).ok();
+        self.smiles = Some(smiles);
+        self.descriptors = Some(Descriptors::new(&self.common));
+    }
+
+    /// Set to the dominant protonation state at a pH, if different from the current one.
+    /// Returns `true` if changed.
+    pub fn protonate(&mut self, ph: f32) -> bool {
+        let (_, protomers) = protomers(&self.common, ph);
+
+        match protomers.into_iter().next() {
+            Some(dominant) if dominant.smiles != self.common.to_smiles() => {
+                self.set_species(&dominant.mol);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl State {
+    /// Set a ligand to a given protonation state or tautomer, and update its force field
+    /// parameters.
+    pub fn set_lig_species(&mut self, lig_i: usize, species: &MoleculeCommon) {
+        if let Some(lig) = self.ligands.get_mut(lig_i) {
+            lig.set_species(species);
+            self.update_lig_ff(lig_i);
+        }
+    }
+
+    /// Set each open ligand to its dominant protonation state at the configured pH. Returns the
+    /// number of ligands changed.
+    pub fn protonate_ligands(&mut self) -> usize {
+        let mut changed = 0;
+
+        for i in 0..self.ligands.len() {
+            if self.ligands[i].protonate(self.to_save.ph) {
+                self.update_lig_ff(i);
+                changed += 1;
+            }
+        }
+
+        changed
+    }
+
+    /// Re-assign force field types and partial charges after changing a ligand's hydrogens.
+    fn update_lig_ff(&mut self, lig_i: usize) {
+        let lig = &mut self.ligands[lig_i];
+
+        // Parameters specific to the previous species no longer apply.
+        let ident = lig.common.ident.clone();
+        self.mol_specific_params
+            .retain(|k, _| !k.eq_ignore_ascii_case(&ident));
+
+        if let Some(p) = &self.ff_param_set.small_mol {
+            lig.update_ff_related(&mut self.mol_specific_params, p);
+        }
+    }
+}
@@ -0,0 +1,517 @@
+//! Ring perception, aromaticity, and kekulization for small molecules.
+//!
+//! Rings are the smallest set of smallest rings (SSSR): A minimum cycle basis, found from Horton's
+//! candidate cycles. Aromaticity uses Hückel's 4n + 2 rule on individual rings, and on fused ring
+//! systems.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, ErrorKind},
+};
+
+use bio_files::BondType;
+use na_seq::Element;
+
+use crate::{
+    molecule::{Atom, Bond, MoleculeCommon},
+    smiles::bond_order,
+};
+
+impl MoleculeCommon {
+    /// Find the smallest set of smallest rings. Each ring is a list of atom indices, in order
+    /// around the ring.
+    pub fn find_rings(&self) -> Vec<Vec<usize>> {
+        find_sssr(&self.adjacency_list)
+    }
+
+    /// Replace aromatic bonds with alternating single and double bonds.
+    pub fn kekulize(&mut self) -> io::Result<()> {
+        kekulize(&self.atoms, &mut self.bonds, &self.adjacency_list)
+    }
+
+    /// Set bonds in aromatic rings to aromatic, using Hückel's rule. Existing aromatic bonds are
+    /// kekulized first, so they're re-evaluated. Returns the number of aromatic rings.
+    ///
+    /// Leaves the molecule unchanged if existing aromatic bonds can't be kekulized.
+    pub fn perceive_aromaticity(&mut self) -> io::Result<usize> {
+        self.kekulize()?;
+
+        let rings = self.find_rings();
+        let aromatic = aromatic_rings(self, &rings);
+
+        let bond_i: HashMap<(usize, usize), usize> = self
+            .bonds
+            .iter()
+            .enumerate()
+            .map(|(i, b)| ((b.atom_0.min(b.atom_1), b.atom_0.max(b.atom_1)), i))
+            .collect();
+
+        for (ring, _) in rings.iter().zip(&aromatic).filter(|(_, a)| **a) {
+            for (j, &a) in ring.iter().enumerate() {
+                let b = ring[(j + 1) % ring.len()];
+                if let Some(&i) = bond_i.get(&(a.min(b), a.max(b))) {
+                    self.bonds[i].bond_type = BondType::Aromatic;
+                }
+            }
+        }
+
+        Ok(aromatic.iter().filter(|a| **a).count())
+    }
+}
+
+/// Find the smallest set of smallest rings, given an adjacency list. The number of rings is
+/// the cyclomatic number: bonds - atoms + connected components.
+pub fn find_sssr(adj: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let n = adj.len();
+
+    let mut edge_i = HashMap::new();
+    for (a, nbrs) in adj.iter().enumerate() {
+        for &b in nbrs {
+            if a < b {
+                let i = edge_i.len();
+                edge_i.entry((a, b)).or_insert(i);
+            }
+        }
+    }
+
+    let mut num_components = 0;
+    let mut seen = vec![false; n];
+    for root in 0..n {
+        if seen[root] {
+            continue;
+        }
+        num_components += 1;
+        seen[root] = true;
+        let mut stack = vec![root];
+        while let Some(a) = stack.pop() {
+            for &b in &adj[a] {
+                if !seen[b] {
+                    seen[b] = true;
+                    stack.push(b);
+                }
+            }
+        }
+    }
+
+    let num_rings = (edge_i.len() + num_components).saturating_sub(n);
+    if num_rings == 0 {
+        return Vec::new();
+    }
+
+    // Only atoms in rings can contribute; prune chains, so candidates stay few.
+    let mut degree: Vec<usize> = adj.iter().map(|nbrs| nbrs.len()).collect();
+    let mut in_core = vec![true; n];
+    let mut prune: Vec<usize> = (0..n).filter(|&i| degree[i] <= 1).collect();
+    while let Some(a) = prune.pop() {
+        if !in_core[a] {
+            continue;
+        }
+        in_core[a] = false;
+        for &b in &adj[a] {
+            if in_core[b] {
+                degree[b] -= 1;
+                if degree[b] == 1 {
+                    prune.push(b);
+                }
+            }
+        }
+    }
+
+    // Horton's candidates: for each root and edge (x, y), the shortest paths root -> x and
+    // root -> y, joined by the edge, if these only meet at the root.
+    let words = edge_i.len().div_ceil(64);
+    let mut candidates: Vec<(Vec<usize>, Vec<u64>)> = Vec::new();
+    let mut candidate_set = HashSet::new();
+
+    for root in (0..n).filter(|&i| in_core[i]) {
+        let mut pred = vec![usize::MAX; n];
+        let mut dist = vec![usize::MAX; n];
+        dist[root] = 0;
+        let mut queue = VecDeque::from([root]);
+        while let Some(a) = queue.pop_front() {
+            let mut nbrs: Vec<_> = adj[a].iter().copied().filter(|&b| in_core[b]).collect();
+            nbrs.sort_unstable();
+            for b in nbrs {
+                if dist[b] == usize::MAX {
+                    dist[b] = dist[a] + 1;
+                    pred[b] = a;
+                    queue.push_back(b);
+                }
+            }
+        }
+
+        let path = |mut a: usize| {
+            let mut result = vec![a];
+            while a != root {
+                a = pred[a];
+                result.push(a);
+            }
+            result
+        };
+
+        for &(x, y) in edge_i.keys() {
+            if !in_core[x] || !in_core[y] || dist[x] == usize::MAX || dist[y] == usize::MAX {
+                continue;
+            }
+            if pred[x] == y || pred[y] == x {
+                continue;
+            }
+
+            let p_x = path(x);
+            let p_y = path(y);
+            let on_x: HashSet<_> = p_x.iter().copied().collect();
+            if p_y[..p_y.len() - 1].iter().any(|a| on_x.contains(a)) {
+                continue;
+            }
+
+            // x -> root -> y, closed by the edge (y, x).
+            let mut ring: Vec<usize> = p_x;
+            ring.extend(p_y.iter().rev().skip(1));
+            if ring.len() < 3 {
+                continue;
+            }
+
+            let mut bits = vec![0u64; words];
+            for (j, &a) in ring.iter().enumerate() {
+                let b = ring[(j + 1) % ring.len()];
+                let i = edge_i[&(a.min(b), a.max(b))];
+                bits[i / 64] |= 1 << (i % 64);
+            }
+
+            if candidate_set.insert(bits.clone()) {
+                candidates.push((ring, bits));
+            }
+        }
+    }
+
+    candidates.sort_by(|a, b| a.0.len().cmp(&b.0.len()).then_with(|| a.1.cmp(&b.1)));
+
+    // Keep the shortest cycles that are linearly independent, over GF(2).
+    let mut basis: Vec<(usize, Vec<u64>)> = Vec::new();
+    let mut result = Vec::new();
+
+    for (ring, mut bits) in candidates {
+        for (pivot, row) in &basis {
+            if bits[pivot / 64] & (1 << (pivot % 64)) != 0 {
+                for (w, r) in bits.iter_mut().zip(row) {
+                    *w ^= r;
+                }
+            }
+        }
+
+        let Some(pivot) = (0..words * 64).find(|&i| bits[i / 64] & (1 << (i % 64)) != 0) else {
+            continue;
+        };
+
+        // Keep the basis reduced, so each pivot appears in only its own row.
+        for (_, row) in &mut basis {
+            if row[pivot / 64] & (1 << (pivot % 64)) != 0 {
+                for (w, b) in row.iter_mut().zip(&bits) {
+                    *w ^= b;
+                }
+            }
+        }
+        basis.push((pivot, bits));
+        result.push(ring);
+
+        if result.len() == num_rings {
+            break;
+        }
+    }
+
+    result
+}
+
+/// Group ring indices into fused systems: rings that share a bond.
+fn fused_systems(rings: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let shares_bond = |a: &[usize], b: &[usize]| a.iter().filter(|i| b.contains(i)).count() >= 2;
+
+    let mut system = vec![usize::MAX; rings.len()];
+    let mut result = Vec::new();
+    for start in 0..rings.len() {
+        if system[start] != usize::MAX {
+            continue;
+        }
+        let id = result.len();
+        system[start] = id;
+        let mut members = vec![start];
+        let mut stack = vec![start];
+        while let Some(r) = stack.pop() {
+            for other in 0..rings.len() {
+                if system[other] == usize::MAX && shares_bond(&rings[r], &rings[other]) {
+                    system[other] = id;
+                    members.push(other);
+                    stack.push(other);
+                }
+            }
+        }
+        members.sort_unstable();
+        result.push(members);
+    }
+
+    result
+}
+
+/// The π electrons an atom contributes to a ring or ring system, given Kekulé bonds. `None` if it
+/// can't be part of an aromatic system, e.g. sp3 carbon.
+fn pi_electrons(mol: &MoleculeCommon, atom: usize, ring_atoms: &HashSet<usize>) -> Option<u8> {
+    let a = &mol.atoms[atom];
+
+    let mut endo_double = false;
+    let mut exo_double = None;
+    let mut degree = a.implicit_h.unwrap_or(0) as usize;
+
+    for bond in &mol.bonds {
+        let other = if bond.atom_0 == atom {
+            bond.atom_1
+        } else if bond.atom_1 == atom {
+            bond.atom_0
+        } else {
+            continue;
+        };
+        degree += 1;
+
+        match bond.bond_type {
+            BondType::Triple => return None,
+            // Bonds we couldn't kekulize.
+            BondType::Double | BondType::Aromatic if ring_atoms.contains(&other) => {
+                endo_double = true
+            }
+            BondType::Double => exo_double = Some(mol.atoms[other].element),
+            _ => (),
+        }
+    }
+
+    if endo_double {
+        return Some(1);
+    }
+
+    // An exocyclic double bond to an electronegative atom, e.g. the carbonyl of pyridone,
+    // leaves an empty p orbital.
+    if let Some(el) = exo_double {
+        return match el {
+            Element::Oxygen | Element::Nitrogen | Element::Sulfur => Some(0),
+            _ => None,
+        };
+    }
+
+    // Lone pair donors, e.g. pyrrole N, furan O, thiophene S, and carbanions; and empty p
+    // orbitals, e.g. carbocations and trivalent boron.
+    match (a.element.to_letter().as_str(), a.formal_charge) {
+        ("N" | "P" | "As", 0) if degree <= 3 => Some(2),
+        ("O" | "S" | "Se" | "Te", 0) if degree <= 2 => Some(2),
+        ("N" | "P", -1) if degree <= 2 => Some(2),
+        ("O" | "S", 1) if degree <= 3 => Some(2),
+        ("C", -1) if degree <= 3 => Some(2),
+        ("C", 1) if degree <= 3 => Some(0),
+        ("B", 0) if degree <= 3 => Some(0),
+        _ => None,
+    }
+}
+
+fn is_huckel(mol: &MoleculeCommon, atoms: &HashSet<usize>) -> bool {
+    let mut total = 0;
+    for &a in atoms {
+        match pi_electrons(mol, a, atoms) {
+            Some(e) => total += e as usize,
+            None => return false,
+        }
+    }
+    total % 4 == 2
+}
+
+/// Which rings are aromatic. We evaluate each ring, then pairs of fused rings, then entire fused
+/// systems; e.g. azulene is only aromatic as a whole.
+fn aromatic_rings(mol: &MoleculeCommon, rings: &[Vec<usize>]) -> Vec<bool> {
+    let sets: Vec<HashSet<usize>> = rings.iter().map(|r| r.iter().copied().collect()).collect();
+    let mut result: Vec<bool> = sets.iter().map(|s| is_huckel(mol, s)).collect();
+
+    for system in fused_systems(rings) {
+        for (k, &i) in system.iter().enumerate() {
+            for &j in &system[k + 1..] {
+                if (result[i] && result[j]) || sets[i].intersection(&sets[j]).count() < 2 {
+                    continue;
+                }
+                let union = sets[i].union(&sets[j]).copied().collect();
+                if is_huckel(mol, &union) {
+                    result[i] = true;
+                    result[j] = true;
+                }
+            }
+        }
+
+        if system.len() > 2 && system.iter().any(|&i| !result[i]) {
+            let union = system
+                .iter()
+                .flat_map(|&i| sets[i].iter().copied())
+                .collect();
+            if is_huckel(mol, &union) {
+                for &i in &system {
+                    result[i] = true;
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// The valence an atom has in an aromatic system, from its isoelectronic main group element.
+/// E.g. N+ behaves as C, and O+ as N.
+fn aromatic_valence(el: Element, charge: i8) -> Option<u8> {
+    let group: i8 = match el.to_letter().as_str() {
+        "B" => 13,
+        "C" => 14,
+        "N" | "P" | "As" => 15,
+        "O" | "S" | "Se" | "Te" => 16,
+        _ => return None,
+    };
+
+    match group - charge {
+        13 => Some(3),
+        14 => Some(4),
+        15 => Some(3),
+        16 => Some(2),
+        _ => None,
+    }
+}
+
+/// Replace aromatic bonds with alternating single and double bonds. Each aromatic atom with a free
+/// valence receives exactly one double bond. If hydrogen counts aren't known, heteroatoms may
+/// receive one or none; e.g. N in pyridine vs pyrrole.
+///
+/// Bonds are left unchanged on error.
+pub fn kekulize(atoms: &[Atom], bonds: &mut [Bond], adj: &[Vec<usize>]) -> io::Result<()> {
+    let n = atoms.len();
+
+    let mut used = vec![0; n];
+    let mut aromatic = vec![false; n];
+    for bond in bonds.iter() {
+        for i in [bond.atom_0, bond.atom_1] {
+            used[i] += bond_order(bond.bond_type);
+            if bond.bond_type == BondType::Aromatic {
+                aromatic[i] = true;
+            }
+        }
+    }
+    if !aromatic.contains(&true) {
+        return Ok(());
+    }
+
+    let h_known = atoms
+        .iter()
+        .any(|a| a.element == Element::Hydrogen || a.implicit_h.is_some());
+
+    // Atoms which must, or may receive a double bond.
+    let mut required = vec![false; n];
+    let mut optional = vec![false; n];
+    for (i, atom) in atoms.iter().enumerate() {
+        if !aromatic[i] {
+            continue;
+        }
+        let Some(valence) = aromatic_valence(atom.element, atom.formal_charge) else {
+            continue;
+        };
+        let used = used[i] + atom.implicit_h.unwrap_or(0);
+
+        if h_known || atom.element == Element::Carbon {
+            required[i] = valence == used + 1 || (!h_known && valence > used);
+        } else {
+            optional[i] = valence > used;
+        }
+    }
+
+    let bond_i: HashMap<(usize, usize), usize> = bonds
+        .iter()
+        .enumerate()
+        .map(|(i, b)| ((b.atom_0.min(b.atom_1), b.atom_0.max(b.atom_1)), i))
+        .collect();
+    let aromatic_bond = |a: usize, b: usize| {
+        bond_i
+            .get(&(a.min(b), a.max(b)))
+            .is_some_and(|&i| bonds[i].bond_type == BondType::Aromatic)
+    };
+
+    let candidates: Vec<Vec<usize>> = (0..n)
+        .map(|i| {
+            if !required[i] && !optional[i] {
+                return Vec::new();
+            }
+            adj[i]
+                .iter()
+                .copied()
+                .filter(|&j| (required[j] || optional[j]) && aromatic_bond(i, j))
+                .collect()
+        })
+        .collect();
+
+    let mut matched = vec![None; n];
+    if !match_doubles(&candidates, &required, &mut matched) {
+        let i = (0..n)
+            .find(|&i| required[i] && matched[i].is_none())
+            .unwrap_or_default();
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Unable to kekulize the aromatic system containing atom {}",
+                i + 1
+            ),
+        ));
+    }
+
+    for bond in bonds.iter_mut() {
+        if bond.bond_type == BondType::Aromatic {
+            bond.bond_type = if matched[bond.atom_0] == Some(bond.atom_1) {
+                BondType::Double
+            } else {
+                BondType::Single
+            };
+        }
+    }
+
+    Ok(())
+}
+
+/// Match each required atom with a neighbor for a double bond, by backtracking. We extend from the
+/// most constrained atom at each step, which resolves most fused systems without backtracking.
+fn match_doubles(
+    candidates: &[Vec<usize>],
+    required: &[bool],
+    matched: &mut [Option<usize>],
+) -> bool {
+    let mut best: Option<(usize, usize)> = None;
+    for i in 0..matched.len() {
+        if !required[i] || matched[i].is_some() {
+            continue;
+        }
+        let options = candidates[i]
+            .iter()
+            .filter(|&&j| matched[j].is_none())
+            .count();
+        if best.is_none_or(|(_, n)| options < n) {
+            best = Some((i, options));
+        }
+    }
+
+    let Some((i, options)) = best else {
+        return true;
+    };
+    if options == 0 {
+        return false;
+    }
+
+    for &j in &candidates[i] {
+        if matched[j].is_some() {
+            continue;
+        }
+        matched[i] = Some(j);
+        matched[j] = Some(i);
+        if match_doubles(candidates, required, matched) {
+            return true;
+        }
+        matched[i] = None;
+        matched[j] = None;
+    }
+
+    false
+}
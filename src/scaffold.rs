@@ -0,0 +1,309 @@
+//! Bemis–Murcko scaffolds, and grouping molecules into series: By shared scaffold, shared generic
+//! framework, or by fingerprint similarity using Butina clustering. For structure-activity
+//! review.
+
+use std::collections::HashMap;
+
+use bio_files::BondType;
+use na_seq::Element::Carbon;
+use rayon::prelude::*;
+
+use crate::{
+    State,
+    fingerprint::{FpKind, HitSource},
+    mol_characterization::PerceivedMol,
+    mol_lig::MoleculeSmall,
+    molecule::{Atom, Bond, MoleculeCommon},
+    smiles::bond_order,
+};
+
+/// Molecules with at least this Morgan fingerprint Tanimoto similarity to a cluster's centroid
+/// join the cluster.
+const BUTINA_SIM_THRESH: f32 = 0.6;
+
+/// Shown in place of a scaffold, for molecules without rings.
+const ACYCLIC_LABEL: &str = "Acyclic";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ClusterBy {
+    #[default]
+    Scaffold,
+    /// The scaffold, with all atoms carbon and all bonds single.
+    Framework,
+    Butina,
+}
+
+impl ClusterBy {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Scaffold => "Scaffold",
+            Self::Framework => "Framework",
+            Self::Butina => "Butina",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ClusterSource {
+    #[default]
+    Ligands,
+    Library,
+}
+
+#[derive(Clone, Debug)]
+pub struct Cluster {
+    /// The scaffold or framework SMILES, or for Butina clusters, the centroid's identifier.
+    pub label: String,
+    pub members: Vec<HitSource>,
+}
+
+/// Clustering results, for display.
+#[derive(Clone, Debug)]
+pub struct Clustering {
+    pub source: ClusterSource,
+    pub by: ClusterBy,
+    /// Largest first.
+    pub clusters: Vec<Cluster>,
+}
+
+impl MoleculeCommon {
+    /// The Bemis–Murcko scaffold: Ring systems, and the linkers between them, with side chains
+    /// removed. Exocyclic double bonded atoms (e.g. carbonyl oxygens) on the scaffold are kept.
+    /// Returns `None` for molecules without rings.
+    pub fn murcko_scaffold(&self) -> Option<MoleculeCommon> {
+        let p = PerceivedMol::new(self);
+        if p.rings.is_empty() {
+            return None;
+        }
+
+        let heavy = p.heavy();
+        let mut keep = vec![false; p.atoms.len()];
+        for &i in &heavy {
+            keep[i] = true;
+        }
+
+        // Repeatedly strip terminal, non-ring atoms.
+        loop {
+            let terminal: Vec<usize> = heavy
+                .iter()
+                .copied()
+                .filter(|&i| {
+                    keep[i]
+                        && !p.atoms[i].in_ring
+                        && p.atoms[i].nbrs.iter().filter(|(j, _)| keep[*j]).count() <= 1
+                })
+                .collect();
+
+            if terminal.is_empty() {
+                break;
+            }
+            for i in terminal {
+                keep[i] = false;
+            }
+        }
+
+        let exocyclic: Vec<usize> = heavy
+            .iter()
+            .copied()
+            .filter(|&i| {
+                !keep[i]
+                    && matches!(p.atoms[i].nbrs.as_slice(), [(j, BondType::Double)] if keep[*j])
+            })
+            .collect();
+        for i in exocyclic {
+            keep[i] = true;
+        }
+
+        // Removed neighbors are replaced with hydrogens.
+        let h_counts: Vec<u8> = (0..p.atoms.len())
+            .map(|i| {
+                let removed: u8 = p.atoms[i]
+                    .nbrs
+                    .iter()
+                    .filter(|(j, _)| !keep[*j])
+                    .map(|(_, bt)| bond_order(*bt))
+                    .sum();
+                p.atoms[i].h + removed
+            })
+            .collect();
+
+        Some(subgraph(&p, &keep, |i, atom| {
+            atom.element = p.atoms[i].element;
+            atom.formal_charge = p.atoms[i].charge;
+            atom.implicit_h = Some(h_counts[i]);
+        }))
+    }
+
+    /// The generic (graph) framework: The Murcko scaffold, with all atoms carbon, all bonds
+    /// single, and no charges. Returns `None` for molecules without rings.
+    pub fn generic_framework(&self) -> Option<MoleculeCommon> {
+        let mut result = self.murcko_scaffold()?;
+
+        for atom in &mut result.atoms {
+            atom.element = Carbon;
+            atom.formal_charge = 0;
+            // Infer hydrogens from standard valences.
+            atom.implicit_h = None;
+        }
+        for bond in &mut result.bonds {
+            bond.bond_type = BondType::Single;
+        }
+
+        Some(result)
+    }
+}
+
+/// Build a molecule from kept atoms, with bonds between them. Positions are left at the origin,
+/// so no stereochemistry is inferred when writing SMILES.
+fn subgraph(
+    p: &PerceivedMol,
+    keep: &[bool],
+    set_atom: impl Fn(usize, &mut Atom),
+) -> MoleculeCommon {
+    let mut index_map = HashMap::new();
+    let mut atoms = Vec::new();
+
+    for i in (0..p.atoms.len()).filter(|&i| keep[i]) {
+        index_map.insert(i, atoms.len());
+
+        let mut atom = Atom {
+            serial_number: atoms.len() as u32 + 1,
+            ..Default::default()
+        };
+        set_atom(i, &mut atom);
+        atoms.push(atom);
+    }
+
+    let bonds = p
+        .mol
+        .bonds
+        .iter()
+        .filter_map(|b| {
+            let (atom_0, atom_1) = (*index_map.get(&b.atom_0)?, *index_map.get(&b.atom_1)?);
+            Some(Bond {
+                bond_type: b.bond_type,
+                atom_0_sn: atom_0 as u32 + 1,
+                atom_1_sn: atom_1 as u32 + 1,
+                atom_0,
+                atom_1,
+                is_backbone: false,
+            })
+        })
+        .collect();
+
+    MoleculeCommon::new(p.mol.ident.clone(), atoms, bonds, HashMap::new(), None)
+}
+
+/// The grouping key of a molecule, for scaffold and framework clustering.
+fn scaffold_key(mol: &MoleculeSmall, by: ClusterBy) -> String {
+    let scaffold = match by {
+        ClusterBy::Framework => mol.common.generic_framework(),
+        _ => mol.common.murcko_scaffold(),
+    };
+
+    match scaffold {
+        Some(s) => s.to_smiles(),
+        None => ACYCLIC_LABEL.to_owned(),
+    }
+}
+
+/// Butina (Taylor–Butina) clustering: In order of decreasing neighbor count (molecules above the
+/// similarity threshold), take each unassigned molecule as a centroid, and cluster it with its
+/// unassigned neighbors. Returns (centroid, members) for each cluster; indices are into `mols`.
+fn butina(mols: &[&MoleculeSmall]) -> Vec<(usize, Vec<usize>)> {
+    let fps: Vec<_> = mols
+        .par_iter()
+        .map(|m| m.common.fingerprint(FpKind::Morgan))
+        .collect();
+
+    let neighbors: Vec<Vec<usize>> = (0..fps.len())
+        .into_par_iter()
+        .map(|i| {
+            (0..fps.len())
+                .filter(|&j| j != i && fps[i].tanimoto(&fps[j]) >= BUTINA_SIM_THRESH)
+                .collect()
+        })
+        .collect();
+
+    // Stable, so ties go to the earlier molecule.
+    let mut order: Vec<usize> = (0..mols.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(neighbors[i].len()));
+
+    let mut assigned = vec![false; mols.len()];
+    let mut result = Vec::new();
+
+    for i in order {
+        if assigned[i] {
+            continue;
+        }
+        assigned[i] = true;
+
+        let mut members = vec![i];
+        for &j in &neighbors[i] {
+            if !assigned[j] {
+                assigned[j] = true;
+                members.push(j);
+            }
+        }
+        result.push((i, members));
+    }
+
+    result
+}
+
+impl State {
+    /// Group open ligands, or molecules in the loaded library, into series.
+    pub fn cluster_mols(&self, source: ClusterSource, by: ClusterBy) -> Clustering {
+        let (mols, sources): (Vec<&MoleculeSmall>, Vec<HitSource>) = match source {
+            ClusterSource::Ligands => self
+                .ligands
+                .iter()
+                .enumerate()
+                .map(|(i, m)| (m, HitSource::Ligand(i)))
+                .unzip(),
+            ClusterSource::Library => match &self.library {
+                Some(lib) => lib
+                    .filtered
+                    .iter()
+                    .map(|&i| (&lib.mols[i], HitSource::Library(i)))
+                    .unzip(),
+                None => Default::default(),
+            },
+        };
+
+        let mut clusters: Vec<Cluster> = match by {
+            ClusterBy::Scaffold | ClusterBy::Framework => {
+                let keys: Vec<String> = mols.par_iter().map(|m| scaffold_key(m, by)).collect();
+
+                let mut groups: Vec<Cluster> = Vec::new();
+                let mut group_of_key = HashMap::new();
+                for (key, source) in keys.into_iter().zip(sources) {
+                    let i = *group_of_key.entry(key.clone()).or_insert_with(|| {
+                        groups.push(Cluster {
+                            label: key,
+                            members: Vec::new(),
+                        });
+                        groups.len() - 1
+                    });
+                    groups[i].members.push(source);
+                }
+                groups
+            }
+            ClusterBy::Butina => butina(&mols)
+                .into_iter()
+                .map(|(centroid, members)| Cluster {
+                    label: mols[centroid].common.ident.clone(),
+                    members: members.into_iter().map(|i| sources[i]).collect(),
+                })
+                .collect(),
+        };
+
+        clusters.sort_by_key(|c| std::cmp::Reverse(c.members.len()));
+
+        Clustering {
+            source,
+            by,
+            clusters,
+        }
+    }
+}
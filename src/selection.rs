@@ -769,6 +769,34 @@ pub(crate) fn handle_selection_attempt(
         Selection::AtomLipid((mol_i, _)) | Selection::BondLipid((mol_i, _)) => {
             state.volatile.active_mol = Some((MolType::Lipid, mol_i));
         }
+        Selection::None => {
+            // Clicking away from atoms may select a docking site; the nearest to the camera.
+            if let Some(mol) = &state.peptide
+                && state.ui.mol_view != MoleculeView::Surface
+            {
+                let ray_dir = diff.to_normalized();
+
+                let site = mol
+                    .docking_sites
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, site)| {
+                        let (dist_to_ray, t) =
+                            ray_metrics(selected_ray.0, ray_dir, site.site.site_center.into());
+                        (dist_to_ray < site.site.site_radius as f32).then_some((i, t))
+                    })
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(i, _)| i);
+
+                if site.is_some() {
+                    state.ui.docking_site_sel = if site == state.ui.docking_site_sel {
+                        None
+                    } else {
+                        site
+                    };
+                }
+            }
+        }
         _ => (),
     }
 
@@ -0,0 +1,842 @@
+//! Convert between molecules and SELFIES (Self-Referencing Embedded Strings) text. Any sequence
+//! of SELFIES symbols decodes to a valid molecule, which makes them suitable for generative models.
+//! See [the SELFIES paper](https://arxiv.org/abs/1905.13741).
+//!
+//! We go through the SMILES layer: SMILES is parsed, kekulized, and encoded symbol by symbol.
+//! Decoding derives a molecule from the symbols under SELFIES' bonding capacity rules, then writes
+//! it as SMILES.
+
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+};
+
+use bio_files::BondType;
+use na_seq::Element;
+
+use crate::{
+    molecule::{Atom, MoleculeCommon},
+    rings::kekulize,
+    smiles::{
+        BondDir, SmilesChirality, SmilesGraph, bond_order, default_implicit_h, default_valences,
+        parse_bracket_atom, parse_smiles, ring_digit,
+    },
+};
+
+/// Symbols used to encode branch lengths and ring offsets, as base-16 digits.
+const INDEX_ALPHABET: [&str; 16] = [
+    "[C]",
+    "[Ring1]",
+    "[Ring2]",
+    "[Branch1]",
+    "[=Branch1]",
+    "[#Branch1]",
+    "[Branch2]",
+    "[=Branch2]",
+    "[#Branch2]",
+    "[O]",
+    "[N]",
+    "[=N]",
+    "[=C]",
+    "[#C]",
+    "[S]",
+    "[P]",
+];
+
+impl MoleculeCommon {
+    /// Create SELFIES text for this molecule, from its canonical SMILES. Aromatic systems are
+    /// written in a Kekulé form.
+    pub fn to_selfies(&self) -> io::Result<String> {
+        smiles_to_selfies(&self.to_smiles())
+    }
+
+    /// Create a molecule from SELFIES text. Atoms are placed at the origin.
+    pub fn from_selfies(data: &str) -> io::Result<Self> {
+        Self::from_smiles(&selfies_to_smiles(data)?)
+    }
+}
+
+fn selfies_err(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("Invalid SELFIES: {msg}"))
+}
+
+/// The maximum number of bonds an atom may form, per the SELFIES default constraints. Elements and
+/// charges not listed are unconstrained.
+fn bonding_capacity(el: Element, charge: i8) -> u8 {
+    match (el.to_letter().as_str(), charge) {
+        ("H" | "F" | "Cl" | "Br" | "I", 0) => 1,
+        ("B", 0) => 3,
+        ("B", 1) => 2,
+        ("B", -1) => 4,
+        ("O", 0) => 2,
+        ("O", 1) => 3,
+        ("O", -1) => 1,
+        ("N", 0) => 3,
+        ("N", 1) => 4,
+        ("N", -1) => 2,
+        ("C", 0) => 4,
+        ("C", 1) => 5,
+        ("C", -1) => 3,
+        ("P", 0) => 5,
+        ("P", 1) => 6,
+        ("P", -1) => 4,
+        ("S", 0) => 6,
+        ("S", 1) => 7,
+        ("S", -1) => 5,
+        _ => 8,
+    }
+}
+
+/// The contents of a bracket atom, e.g. "13C@@H1+1". SELFIES always writes hydrogen counts and
+/// charge magnitudes; SMILES omits them when 1.
+fn bracket_spec(atom: &Atom, chirality: Option<&str>, h_count: u8, selfies_style: bool) -> String {
+    let mut result = String::new();
+    if let Some(iso) = atom.isotope {
+        result += &iso.to_string();
+    }
+    result += &atom.element.to_letter();
+    if let Some(c) = chirality {
+        result += c;
+    }
+    match h_count {
+        0 => (),
+        1 if !selfies_style => result.push('H'),
+        h => result += &format!("H{h}"),
+    }
+    match atom.formal_charge {
+        0 => (),
+        1 if !selfies_style => result.push('+'),
+        -1 if !selfies_style => result.push('-'),
+        c => result += &format!("{c:+}"),
+    }
+
+    result
+}
+
+fn chirality_text(chirality: &SmilesChirality, invert: bool) -> String {
+    match (chirality, invert) {
+        (SmilesChirality::Anticlockwise, false) | (SmilesChirality::Clockwise, true) => {
+            "@".to_owned()
+        }
+        (SmilesChirality::Anticlockwise, true) | (SmilesChirality::Clockwise, false) => {
+            "@@".to_owned()
+        }
+        (SmilesChirality::Other(class), _) => format!("@{class}"),
+    }
+}
+
+/// Whether `order` is an odd permutation of `reference`. `None` if they don't contain the
+/// same neighbors.
+fn odd_permutation(reference: &[Option<usize>], order: &[Option<usize>]) -> Option<bool> {
+    if reference.len() != order.len() {
+        return None;
+    }
+    let perm: Vec<usize> = order
+        .iter()
+        .map(|n| reference.iter().position(|r| r == n))
+        .collect::<Option<_>>()?;
+
+    let mut inversions = 0;
+    for i in 0..perm.len() {
+        for j in i + 1..perm.len() {
+            if perm[i] > perm[j] {
+                inversions += 1;
+            }
+        }
+    }
+    Some(inversions % 2 == 1)
+}
+
+/// Symbols encoding `q` in base 16, using the index alphabet.
+fn index_symbols(q: usize) -> io::Result<Vec<&'static str>> {
+    let n = match q {
+        0..16 => 1,
+        16..256 => 2,
+        256..4_096 => 3,
+        _ => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Branch or ring too long to encode as SELFIES",
+            ));
+        }
+    };
+
+    Ok((0..n)
+        .rev()
+        .map(|i| INDEX_ALPHABET[(q >> (4 * i)) & 0xf])
+        .collect())
+}
+
+/// Encode SMILES text as SELFIES. Stereo markers are preserved, except for bond directions
+/// on ring bonds.
+pub fn smiles_to_selfies(smiles: &str) -> io::Result<String> {
+    let mut graph = parse_smiles(smiles)?;
+    kekulize(&graph.atoms, &mut graph.bonds, &graph.adjacency_list)?;
+
+    Encoder::new(&graph)?.write()
+}
+
+/// Encoder state: A depth-first traversal of the molecule, in the order we write symbols.
+struct Encoder<'a> {
+    graph: &'a SmilesGraph,
+    bonds: HashMap<(usize, usize), BondType>,
+    /// Keyed by (from atom, to atom), as written.
+    bond_dirs: HashMap<(usize, usize), BondDir>,
+    h_count: Vec<u8>,
+    /// Atoms which must be written with explicit hydrogen counts.
+    bracket: Vec<bool>,
+    /// Derivation order; the index an atom has once decoded.
+    pos: Vec<usize>,
+    parent: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    /// Ring bonds closed at each atom, to atoms derived earlier. These are written as ring
+    /// symbols directly after the atom.
+    closings: Vec<Vec<usize>>,
+    /// Ring bonds opened at each atom, to atoms derived later.
+    openings: Vec<Vec<usize>>,
+}
+
+impl<'a> Encoder<'a> {
+    fn new(graph: &'a SmilesGraph) -> io::Result<Self> {
+        let n = graph.atoms.len();
+        let adj = &graph.adjacency_list;
+
+        let bonds: HashMap<(usize, usize), BondType> = graph
+            .bonds
+            .iter()
+            .map(|b| {
+                (
+                    (b.atom_0.min(b.atom_1), b.atom_0.max(b.atom_1)),
+                    b.bond_type,
+                )
+            })
+            .collect();
+        let bond_dirs = graph
+            .bond_dirs
+            .iter()
+            .map(|&(from, to, dir)| ((from, to), dir))
+            .collect();
+
+        let mut h_count = Vec::with_capacity(n);
+        let mut bracket = Vec::with_capacity(n);
+        for (i, atom) in graph.atoms.iter().enumerate() {
+            let h = atom.implicit_h.unwrap_or(0);
+            let sum: u8 = adj[i]
+                .iter()
+                .map(|&j| bond_order(bonds[&(i.min(j), i.max(j))]))
+                .sum();
+
+            let organic = !default_valences(atom.element).is_empty();
+            let plain = organic
+                && atom.formal_charge == 0
+                && atom.isotope.is_none()
+                && graph.chirality[i].is_none()
+                && h == default_implicit_h(atom.element, sum, false);
+
+            let used = sum + if plain { 0 } else { h };
+            let cap = bonding_capacity(atom.element, atom.formal_charge);
+            if used > cap {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Unable to encode atom {} ({}) as SELFIES: Its {used} bonds exceed \
+                        its bonding capacity of {cap}",
+                        i + 1,
+                        atom.element.to_letter()
+                    ),
+                ));
+            }
+
+            h_count.push(h);
+            bracket.push(!plain);
+        }
+
+        // Traverse in atom order, as written; this keeps the SMILES branch structure where possible.
+        let mut sorted_adj = adj.clone();
+        for nbrs in &mut sorted_adj {
+            nbrs.sort_unstable();
+        }
+
+        let mut pos = vec![usize::MAX; n];
+        let mut num_visited = 0;
+        let mut parent = vec![None; n];
+        let mut children = vec![Vec::new(); n];
+
+        for root in 0..n {
+            if pos[root] != usize::MAX {
+                continue;
+            }
+            pos[root] = num_visited;
+            num_visited += 1;
+
+            let mut stack = vec![(root, 0)];
+            while let Some((x, k)) = stack.last_mut() {
+                let x = *x;
+                let Some(&y) = sorted_adj[x].get(*k) else {
+                    stack.pop();
+                    continue;
+                };
+                *k += 1;
+
+                if pos[y] == usize::MAX {
+                    pos[y] = num_visited;
+                    num_visited += 1;
+                    parent[y] = Some(x);
+                    children[x].push(y);
+                    stack.push((y, 0));
+                }
+            }
+        }
+
+        let mut closings = vec![Vec::new(); n];
+        let mut openings = vec![Vec::new(); n];
+
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by_key(|&i| pos[i]);
+
+        for &x in &order {
+            let mut earlier: Vec<usize> = adj[x]
+                .iter()
+                .copied()
+                .filter(|&y| pos[y] < pos[x] && parent[x] != Some(y))
+                .collect();
+            earlier.sort_by_key(|&y| pos[y]);
+
+            for &y in &earlier {
+                openings[y].push(x);
+            }
+            closings[x] = earlier;
+        }
+
+        Ok(Self {
+            graph,
+            bonds,
+            bond_dirs,
+            h_count,
+            bracket,
+            pos,
+            parent,
+            children,
+            closings,
+            openings,
+        })
+    }
+
+    fn bond_type(&self, a: usize, b: usize) -> BondType {
+        self.bonds[&(a.min(b), a.max(b))]
+    }
+
+    /// Neighbors in the order they appear in SMILES decoded from our output. Chirality is
+    /// relative to this.
+    fn decoded_nbr_order(&self, x: usize) -> Vec<Option<usize>> {
+        let mut result = Vec::new();
+        if let Some(p) = self.parent[x] {
+            result.push(Some(p));
+        }
+        if self.bracket[x] && self.h_count[x] > 0 {
+            result.push(None);
+        }
+        result.extend(self.closings[x].iter().map(|&y| Some(y)));
+        result.extend(self.openings[x].iter().map(|&y| Some(y)));
+        result.extend(self.children[x].iter().map(|&y| Some(y)));
+
+        result
+    }
+
+    fn atom_symbol(&self, x: usize) -> String {
+        let atom = &self.graph.atoms[x];
+
+        let mut bond = "";
+        if let Some(p) = self.parent[x] {
+            bond = match self.bond_type(p, x) {
+                BondType::Double => "=",
+                BondType::Triple => "#",
+                _ => match (self.bond_dirs.get(&(p, x)), self.bond_dirs.get(&(x, p))) {
+                    (Some(BondDir::Up), _) | (None, Some(BondDir::Down)) => "/",
+                    (Some(BondDir::Down), _) | (None, Some(BondDir::Up)) => "\\",
+                    (None, None) => "",
+                },
+            };
+        }
+
+        if !self.bracket[x] {
+            return format!("[{bond}{}]", atom.element.to_letter());
+        }
+
+        let chirality = self.graph.chirality[x].as_ref().map(|c| {
+            let invert = odd_permutation(&self.graph.neighbor_order[x], &self.decoded_nbr_order(x));
+            chirality_text(c, invert.unwrap_or_default())
+        });
+
+        format!(
+            "[{bond}{}]",
+            bracket_spec(atom, chirality.as_deref(), self.h_count[x], true)
+        )
+    }
+
+    /// Write the symbols of the subtree rooted at `x`.
+    fn write_subtree(&self, x: usize, symbols: &mut Vec<String>) -> io::Result<()> {
+        symbols.push(self.atom_symbol(x));
+
+        for &y in &self.closings[x] {
+            let prefix = match self.bond_type(x, y) {
+                BondType::Double => "=",
+                BondType::Triple => "#",
+                _ => "",
+            };
+            // The number of atoms derived between the two.
+            let q = self.pos[x] - self.pos[y] - 1;
+            let index = index_symbols(q)?;
+            symbols.push(format!("[{prefix}Ring{}]", index.len()));
+            symbols.extend(index.into_iter().map(str::to_owned));
+        }
+
+        let children = &self.children[x];
+        for (i, &child) in children.iter().enumerate() {
+            let mut branch = Vec::new();
+            self.write_subtree(child, &mut branch)?;
+
+            if i + 1 < children.len() {
+                let prefix = match self.bond_type(x, child) {
+                    BondType::Double => "=",
+                    BondType::Triple => "#",
+                    _ => "",
+                };
+                let index = index_symbols(branch.len() - 1)?;
+                symbols.push(format!("[{prefix}Branch{}]", index.len()));
+                symbols.extend(index.into_iter().map(str::to_owned));
+            }
+            symbols.extend(branch);
+        }
+
+        Ok(())
+    }
+
+    fn write(&self) -> io::Result<String> {
+        let mut fragments = Vec::new();
+        for root in (0..self.graph.atoms.len()).filter(|&i| self.parent[i].is_none()) {
+            let mut symbols = Vec::new();
+            self.write_subtree(root, &mut symbols)?;
+            fragments.push(symbols.concat());
+        }
+
+        Ok(fragments.join("."))
+    }
+}
+
+/// A SELFIES symbol, as interpreted during derivation.
+enum Symbol {
+    Atom {
+        bond_order: u8,
+        dir: Option<BondDir>,
+        atom: Atom,
+        chirality: Option<SmilesChirality>,
+        /// Hydrogen count, if specified, e.g. "[CH1]". Otherwise it follows SMILES valence rules.
+        h_count: Option<u8>,
+    },
+    /// Opens a branch of the given bond order. The following `num_index` symbols encode its
+    /// length.
+    Branch {
+        bond_order: u8,
+        num_index: usize,
+    },
+    /// Closes a ring with an atom derived earlier. The following `num_index` symbols encode
+    /// its offset.
+    Ring {
+        bond_order: u8,
+        num_index: usize,
+    },
+    Nop,
+}
+
+fn parse_symbol(symbol: &str) -> io::Result<Symbol> {
+    let inner = &symbol[1..symbol.len() - 1];
+    if inner == "nop" {
+        return Ok(Symbol::Nop);
+    }
+
+    // Bond symbols may precede branch and ring keywords; for rings, these include stereo
+    // markers, which we don't use.
+    for (keyword, is_ring) in [("Branch", false), ("Ring", true)] {
+        let Some((prefix, n)) = inner.split_once(keyword) else {
+            continue;
+        };
+        let num_index = match n {
+            "1" => 1,
+            "2" => 2,
+            "3" => 3,
+            _ => return Err(selfies_err(&format!("unknown symbol {symbol}"))),
+        };
+        let bond_order = if prefix.contains('=') {
+            2
+        } else if prefix.contains('#') {
+            3
+        } else {
+            1
+        };
+
+        let valid_prefix = if is_ring {
+            prefix.len() <= 2 && prefix.chars().all(|c| "-=#/\\".contains(c))
+        } else {
+            ["", "=", "#"].contains(&prefix)
+        };
+        if !valid_prefix {
+            return Err(selfies_err(&format!("unknown symbol {symbol}")));
+        }
+
+        return Ok(if is_ring {
+            Symbol::Ring {
+                bond_order,
+                num_index,
+            }
+        } else {
+            Symbol::Branch {
+                bond_order,
+                num_index,
+            }
+        });
+    }
+
+    let (bond_order, dir, spec) = match inner.chars().next() {
+        Some('=') => (2, None, &inner[1..]),
+        Some('#') => (3, None, &inner[1..]),
+        Some('/') => (1, Some(BondDir::Up), &inner[1..]),
+        Some('\\') => (1, Some(BondDir::Down), &inner[1..]),
+        _ => (1, None, inner),
+    };
+
+    let parsed = parse_bracket_atom(spec.as_bytes(), 0)
+        .map_err(|_| selfies_err(&format!("unknown symbol {symbol}")))?;
+
+    let plain = spec == parsed.element.to_letter() && !default_valences(parsed.element).is_empty();
+
+    Ok(Symbol::Atom {
+        bond_order,
+        dir,
+        atom: Atom {
+            element: parsed.element,
+            isotope: parsed.isotope,
+            formal_charge: parsed.charge,
+            ..Default::default()
+        },
+        chirality: parsed.chirality,
+        h_count: (!plain).then_some(parsed.h_count),
+    })
+}
+
+fn tokenize(data: &str) -> io::Result<Vec<&str>> {
+    let mut result = Vec::new();
+
+    let mut i = 0;
+    while i < data.len() {
+        match data.as_bytes()[i] {
+            b'.' => {
+                result.push(".");
+                i += 1;
+            }
+            b'[' => {
+                let Some(len) = data[i..].find(']') else {
+                    return Err(selfies_err("unclosed symbol"));
+                };
+                result.push(&data[i..=i + len]);
+                i += len + 1;
+            }
+            _ => {
+                return Err(selfies_err(&format!(
+                    "unexpected character at position {}",
+                    i + 1
+                )));
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Decode SELFIES text to SMILES. Symbols that would exceed an atom's bonding capacity are
+/// demoted or ignored, per the SELFIES derivation rules; this never produces an invalid molecule.
+pub fn selfies_to_smiles(selfies: &str) -> io::Result<String> {
+    let symbols = tokenize(selfies.trim())?;
+    if symbols.is_empty() {
+        return Err(selfies_err("no symbols"));
+    }
+
+    let mut decoder = Decoder::default();
+    for fragment in symbols.split(|s| *s == ".") {
+        let symbols: Vec<Symbol> = fragment
+            .iter()
+            .map(|s| parse_symbol(s))
+            .collect::<io::Result<_>>()?;
+        let index: Vec<usize> = fragment
+            .iter()
+            .map(|s| INDEX_ALPHABET.iter().position(|a| a == s).unwrap_or(0))
+            .collect();
+
+        let mut i = 0;
+        decoder.derive(&symbols, &index, &mut i, symbols.len(), None);
+    }
+    decoder.form_rings();
+
+    Ok(decoder.write())
+}
+
+#[derive(Default)]
+struct Decoder {
+    atoms: Vec<Atom>,
+    chirality: Vec<Option<SmilesChirality>>,
+    h_count: Vec<Option<u8>>,
+    capacity: Vec<u8>,
+    /// Bond orders used, per atom.
+    used: Vec<u8>,
+    /// (parent, bond order, direction)
+    parent: Vec<Option<(usize, u8, Option<BondDir>)>>,
+    children: Vec<Vec<usize>>,
+    /// Ring bonds requested by ring symbols: (earlier atom, later atom, bond order).
+    ring_requests: Vec<(usize, usize, u8)>,
+    /// (earlier atom, later atom, bond order)
+    ring_bonds: Vec<(usize, usize, u8)>,
+}
+
+impl Decoder {
+    /// Derive atoms from `symbols[*i..end]`. `prev` is the atom we're bonding to, and its remaining
+    /// bonding capacity. Derivation stops when that reaches 0.
+    fn derive(
+        &mut self,
+        symbols: &[Symbol],
+        index: &[usize],
+        i: &mut usize,
+        end: usize,
+        mut prev: Option<(usize, u8)>,
+    ) {
+        // Index symbols are base-16 digits; there may be fewer than requested at the end.
+        let read_index = |i: &mut usize, n: usize| {
+            let mut q = 0;
+            for _ in 0..n {
+                if *i >= end {
+                    break;
+                }
+                q = q * 16 + index[*i];
+                *i += 1;
+            }
+            q
+        };
+
+        while *i < end && prev.is_none_or(|(_, state)| state > 0) {
+            let symbol = &symbols[*i];
+            *i += 1;
+
+            match symbol {
+                Symbol::Nop => (),
+                Symbol::Atom {
+                    bond_order,
+                    dir,
+                    atom,
+                    chirality,
+                    h_count,
+                } => {
+                    let cap = bonding_capacity(atom.element, atom.formal_charge)
+                        .saturating_sub(h_count.unwrap_or(0));
+                    let idx = self.atoms.len();
+
+                    self.atoms.push(atom.clone());
+                    self.chirality.push(chirality.clone());
+                    self.h_count.push(*h_count);
+                    self.capacity.push(cap);
+                    self.children.push(Vec::new());
+
+                    let order = match prev {
+                        Some((_, state)) => (*bond_order).min(state).min(cap),
+                        None => 0,
+                    };
+
+                    match prev {
+                        Some((p, _)) if order > 0 => {
+                            let dir = if order == 1 { *dir } else { None };
+                            self.parent.push(Some((p, order, dir)));
+                            self.children[p].push(idx);
+                            self.used[p] += order;
+                            self.used.push(order);
+                        }
+                        // The first atom of a fragment; or one with no capacity.
+                        _ => {
+                            self.parent.push(None);
+                            self.used.push(0);
+                        }
+                    }
+
+                    prev = Some((idx, cap - order));
+                }
+                Symbol::Branch {
+                    bond_order,
+                    num_index,
+                } => {
+                    // Branches are ignored unless capacity remains to continue the main chain.
+                    let Some((p, state)) = prev.filter(|(_, state)| *state > 1) else {
+                        continue;
+                    };
+                    let q = read_index(i, *num_index);
+                    let branch_state = (state - 1).min(*bond_order);
+                    let branch_end = (*i + q + 1).min(end);
+
+                    self.derive(symbols, index, i, branch_end, Some((p, branch_state)));
+                    *i = branch_end;
+
+                    prev = Some((p, state - branch_state));
+                }
+                Symbol::Ring {
+                    bond_order,
+                    num_index,
+                } => {
+                    let Some((p, state)) = prev else {
+                        continue;
+                    };
+                    let q = read_index(i, *num_index);
+                    let order = (*bond_order).min(state);
+
+                    self.ring_requests.push((p.saturating_sub(q + 1), p, order));
+                    prev = Some((p, state - order));
+                }
+            }
+        }
+    }
+
+    /// Create ring bonds once all atoms are derived, subject to both atoms' capacities. A ring
+    /// bond between already-bonded atoms increases that bond's order.
+    fn form_rings(&mut self) {
+        for (a, b, order) in std::mem::take(&mut self.ring_requests) {
+            if a == b {
+                continue;
+            }
+            let free = |i: usize| self.capacity[i].saturating_sub(self.used[i]);
+            let order = order.min(free(a)).min(free(b));
+            if order == 0 {
+                continue;
+            }
+
+            let existing = if let Some((p, o, _)) = &mut self.parent[b]
+                && *p == a
+            {
+                Some(o)
+            } else if let Some((p, o, _)) = &mut self.parent[a]
+                && *p == b
+            {
+                Some(o)
+            } else {
+                self.ring_bonds
+                    .iter_mut()
+                    .find(|(x, y, _)| (*x, *y) == (a, b) || (*x, *y) == (b, a))
+                    .map(|(_, _, o)| o)
+            };
+
+            let added = match existing {
+                Some(o) => {
+                    let new = (*o + order).min(3);
+                    let added = new - *o;
+                    *o = new;
+                    added
+                }
+                None => {
+                    self.ring_bonds.push((a, b, order));
+                    order
+                }
+            };
+            self.used[a] += added;
+            self.used[b] += added;
+        }
+    }
+
+    fn bond_symbol(order: u8, dir: Option<BondDir>) -> &'static str {
+        match (order, dir) {
+            (2, _) => "=",
+            (3, _) => "#",
+            (_, Some(BondDir::Up)) => "/",
+            (_, Some(BondDir::Down)) => "\\",
+            _ => "",
+        }
+    }
+
+    fn write_atom(
+        &self,
+        x: usize,
+        result: &mut String,
+        digits: &mut HashMap<usize, usize>,
+        digits_used: &mut Vec<bool>,
+        written: &mut [bool],
+    ) {
+        if let Some((_, order, dir)) = self.parent[x] {
+            *result += Self::bond_symbol(order, dir);
+        }
+
+        let atom = &self.atoms[x];
+        match self.h_count[x] {
+            None => *result += &atom.element.to_letter(),
+            Some(h) => {
+                let chirality = self.chirality[x].as_ref().map(|c| chirality_text(c, false));
+                *result += &format!("[{}]", bracket_spec(atom, chirality.as_deref(), h, false));
+            }
+        }
+        written[x] = true;
+
+        // Ring bonds to atoms already written close here; others open.
+        let mut closed = Vec::new();
+        for (bond_i, &(a, b, _)) in self.ring_bonds.iter().enumerate() {
+            if (a == x && written[b]) || (b == x && written[a]) {
+                let d = digits[&bond_i];
+                *result += &ring_digit(d);
+                closed.push(d);
+            }
+        }
+        for (bond_i, &(a, b, order)) in self.ring_bonds.iter().enumerate() {
+            if (a == x && !written[b]) || (b == x && !written[a]) {
+                let d = match digits_used.iter().position(|u| !u) {
+                    Some(d) => d,
+                    None => {
+                        digits_used.push(false);
+                        digits_used.len() - 1
+                    }
+                };
+                digits_used[d] = true;
+                digits.insert(bond_i, d);
+                *result += Self::bond_symbol(order, None);
+                *result += &ring_digit(d);
+            }
+        }
+        for d in closed {
+            digits_used[d] = false;
+        }
+
+        let children = &self.children[x];
+        for (i, &child) in children.iter().enumerate() {
+            let branch = i + 1 < children.len();
+            if branch {
+                result.push('(');
+            }
+            self.write_atom(child, result, digits, digits_used, written);
+            if branch {
+                result.push(')');
+            }
+        }
+    }
+
+    fn write(&self) -> String {
+        let mut fragments = Vec::new();
+
+        let mut digits = HashMap::new();
+        let mut digits_used = Vec::new();
+        let mut written = vec![false; self.atoms.len()];
+
+        for root in (0..self.atoms.len()).filter(|&i| self.parent[i].is_none()) {
+            let mut fragment = String::new();
+            self.write_atom(
+                root,
+                &mut fragment,
+                &mut digits,
+                &mut digits_used,
+                &mut written,
+            );
+            fragments.push(fragment);
+        }
+
+        fragments.join(".")
+    }
+}
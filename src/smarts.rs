@@ -0,0 +1,801 @@
+//! SMARTS substructure queries: Parsing, and matching against small molecules. Used to select
+//! atoms (e.g. "which of these ligands contain a sulfonamide"), and to filter libraries.
+//!
+//! Hydrogens are matched through counts (e.g. `[NH2]`), not as atoms; explicit hydrogens in the
+//! molecule are folded into their heavy atom, as with implicit ones. Chirality in a pattern is
+//! accepted, but not enforced.
+
+use std::{
+    collections::HashSet,
+    io::{self, ErrorKind},
+};
+
+use bio_files::BondType;
+use na_seq::Element;
+
+use crate::{
+    Selection, State,
+    mol_characterization::PerceivedMol,
+    molecule::{MolType, MoleculeCommon},
+};
+
+/// Stop searching after this many unique matches, e.g. for patterns like `*`.
+const MAX_MATCHES: usize = 1_000;
+
+/// Element symbols by atomic number, for `#n` primitives.
+const ELEMENT_SYMBOLS: [&str; 54] = [
+    "H", "He", "Li", "Be", "B", "C", "N", "O", "F", "Ne", "Na", "Mg", "Al", "Si", "P", "S", "Cl",
+    "Ar", "K", "Ca", "Sc", "Ti", "V", "Cr", "Mn", "Fe", "Co", "Ni", "Cu", "Zn", "Ga", "Ge", "As",
+    "Se", "Br", "Kr", "Rb", "Sr", "Y", "Zr", "Nb", "Mo", "Tc", "Ru", "Rh", "Pd", "Ag", "Cd", "In",
+    "Sn", "Sb", "Te", "I", "Xe",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum AtomPrim {
+    Any,
+    /// `a`, or `A`.
+    Aromatic(bool),
+    /// `C`, `c`, or `#6`. Aromaticity is `None` for atomic numbers.
+    Element(Element, Option<bool>),
+    /// Heavy atom neighbors.
+    Degree(u8),
+    TotalH(u8),
+    /// Neighbors, including hydrogens.
+    Connectivity(u8),
+    Valence(u8),
+    /// The number of SSSR rings the atom is in. `None` means any.
+    RingCount(Option<u8>),
+    /// The size of the smallest SSSR ring the atom is in. `None` means any.
+    RingSize(Option<u8>),
+    RingConnectivity(u8),
+    Charge(i8),
+    Isotope(u16),
+    /// Parsed, but not enforced.
+    Chiral,
+    /// `$(...)`: The atom is the first atom of a match of this pattern.
+    Recursive(Box<SmartsPattern>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum AtomExpr {
+    Prim(AtomPrim),
+    Not(Box<AtomExpr>),
+    And(Vec<AtomExpr>),
+    Or(Vec<AtomExpr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BondPrim {
+    Single,
+    Double,
+    Triple,
+    Aromatic,
+    Any,
+    Ring,
+    /// No bond symbol: Single or aromatic.
+    Implicit,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum BondExpr {
+    Prim(BondPrim),
+    Not(Box<BondExpr>),
+    And(Vec<BondExpr>),
+    Or(Vec<BondExpr>),
+}
+
+/// A parsed SMARTS query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmartsPattern {
+    atoms: Vec<AtomExpr>,
+    /// Pattern atom indices, and the bond query between them.
+    bonds: Vec<(usize, usize, BondExpr)>,
+}
+
+fn syntax_err(pos: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid SMARTS at position {}: {msg}", pos + 1),
+    )
+}
+
+/// Parse SMARTS text. Supports atom primitives `* a A #n D H X v R r x + - @` and isotopes,
+/// element symbols, recursive SMARTS, logical operators `! & , ;`, bond primitives
+/// `- = # : ~ @ / \`, branches, ring closures, and disconnected components.
+pub fn parse_smarts(text: &str) -> io::Result<SmartsPattern> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Empty SMARTS string",
+        ));
+    }
+
+    let mut parser = Parser {
+        s: text.as_bytes(),
+        pos: 0,
+    };
+    let result = parser.pattern()?;
+
+    if parser.pos < parser.s.len() {
+        return Err(syntax_err(parser.pos, "unexpected character"));
+    }
+    Ok(result)
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.s.get(self.pos + offset).copied()
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.s[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    fn small_number(&mut self) -> io::Result<Option<u8>> {
+        let start = self.pos;
+        match self.number() {
+            Some(n) => Ok(Some(
+                u8::try_from(n).map_err(|_| syntax_err(start, "number out of range"))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// A full pattern, ending at the end of input, or an unmatched `)`.
+    fn pattern(&mut self) -> io::Result<SmartsPattern> {
+        let mut result = SmartsPattern {
+            atoms: Vec::new(),
+            bonds: Vec::new(),
+        };
+
+        let mut prev: Option<usize> = None;
+        let mut pending: Option<BondExpr> = None;
+        let mut branches: Vec<usize> = Vec::new();
+        let mut rings: Vec<(u32, usize, Option<BondExpr>)> = Vec::new();
+
+        while let Some(c) = self.peek() {
+            match c {
+                b'(' => {
+                    let Some(p) = prev else {
+                        return Err(syntax_err(self.pos, "branch without a preceding atom"));
+                    };
+                    if pending.is_some() {
+                        return Err(syntax_err(self.pos, "bond symbol before a branch"));
+                    }
+                    branches.push(p);
+                    self.pos += 1;
+                }
+                b')' => {
+                    // The end of a recursive pattern.
+                    let Some(p) = branches.pop() else {
+                        break;
+                    };
+                    if pending.is_some() {
+                        return Err(syntax_err(self.pos, "bond without a following atom"));
+                    }
+                    prev = Some(p);
+                    self.pos += 1;
+                }
+                b'.' => {
+                    if pending.is_some() {
+                        return Err(syntax_err(self.pos, "bond without a following atom"));
+                    }
+                    prev = None;
+                    self.pos += 1;
+                }
+                b'0'..=b'9' | b'%' => {
+                    let start = self.pos;
+                    let Some(atom) = prev else {
+                        return Err(syntax_err(start, "ring bond without a preceding atom"));
+                    };
+                    let num = if c == b'%' {
+                        self.pos += 1;
+                        let n0 = self.pos;
+                        if !(self.peek().is_some_and(|c| c.is_ascii_digit())
+                            && self.peek_at(1).is_some_and(|c| c.is_ascii_digit()))
+                        {
+                            return Err(syntax_err(start, "'%' must be followed by two digits"));
+                        }
+                        self.pos += 2;
+                        std::str::from_utf8(&self.s[n0..self.pos])
+                            .unwrap()
+                            .parse()
+                            .unwrap()
+                    } else {
+                        self.pos += 1;
+                        (c - b'0') as u32
+                    };
+
+                    let bond = pending.take();
+                    match rings.iter().position(|(n, _, _)| *n == num) {
+                        Some(i) => {
+                            let (_, other, bond_open) = rings.remove(i);
+                            if other == atom {
+                                return Err(syntax_err(start, "ring bond to the same atom"));
+                            }
+                            let bond = bond
+                                .or(bond_open)
+                                .unwrap_or(BondExpr::Prim(BondPrim::Implicit));
+                            result.bonds.push((other, atom, bond));
+                        }
+                        None => rings.push((num, atom, bond)),
+                    }
+                }
+                b'-' | b'=' | b'#' | b':' | b'~' | b'@' | b'!' | b'/' | b'\\' => {
+                    if prev.is_none() {
+                        return Err(syntax_err(self.pos, "bond without a preceding atom"));
+                    }
+                    if pending.is_some() {
+                        return Err(syntax_err(self.pos, "consecutive bond expressions"));
+                    }
+                    pending = Some(self.bond_low_and()?);
+                }
+                _ => {
+                    let start = self.pos;
+                    let atom = self.atom()?;
+                    let i = result.atoms.len();
+                    result.atoms.push(atom);
+
+                    if let Some(p) = prev {
+                        let bond = pending.take().unwrap_or(BondExpr::Prim(BondPrim::Implicit));
+                        result.bonds.push((p, i, bond));
+                    } else if pending.is_some() {
+                        return Err(syntax_err(start, "bond without a preceding atom"));
+                    }
+                    prev = Some(i);
+                }
+            }
+        }
+
+        if pending.is_some() {
+            return Err(syntax_err(self.pos, "bond without a following atom"));
+        }
+        if !branches.is_empty() {
+            return Err(syntax_err(self.pos, "unclosed branch"));
+        }
+        if !rings.is_empty() {
+            return Err(syntax_err(self.pos, "unclosed ring bond"));
+        }
+        if result.atoms.is_empty() {
+            return Err(syntax_err(self.pos, "pattern without atoms"));
+        }
+
+        Ok(result)
+    }
+
+    /// An unbracketed atom: The organic subset, aromatic or not, or `*`, `a` or `A`.
+    fn atom(&mut self) -> io::Result<AtomExpr> {
+        let start = self.pos;
+        let c = self.peek().unwrap();
+
+        let element = |sym: &str, aromatic: bool| -> io::Result<AtomExpr> {
+            Ok(AtomExpr::Prim(AtomPrim::Element(
+                Element::from_letter(sym)?,
+                Some(aromatic),
+            )))
+        };
+
+        let (result, len) = match c {
+            b'[' => {
+                self.pos += 1;
+                let result = self.atom_low_and()?;
+                if self.peek() != Some(b']') {
+                    return Err(syntax_err(self.pos, "expected ']'"));
+                }
+                self.pos += 1;
+                return Ok(result);
+            }
+            b'*' => (AtomExpr::Prim(AtomPrim::Any), 1),
+            b'a' => (AtomExpr::Prim(AtomPrim::Aromatic(true)), 1),
+            b'A' => (AtomExpr::Prim(AtomPrim::Aromatic(false)), 1),
+            b'C' if self.peek_at(1) == Some(b'l') => (element("Cl", false)?, 2),
+            b'B' if self.peek_at(1) == Some(b'r') => (element("Br", false)?, 2),
+            b'B' | b'C' | b'N' | b'O' | b'P' | b'S' | b'F' | b'I' => {
+                (element(&(c as char).to_string(), false)?, 1)
+            }
+            b'b' | b'c' | b'n' | b'o' | b'p' | b's' => (
+                element(&(c.to_ascii_uppercase() as char).to_string(), true)?,
+                1,
+            ),
+            _ => {
+                return Err(syntax_err(
+                    start,
+                    &format!("unexpected character '{}'", c as char),
+                ));
+            }
+        };
+
+        self.pos += len;
+        Ok(result)
+    }
+
+    // Atom expressions, by increasing operator precedence: `;`, `,`, `&` or implicit, `!`.
+
+    fn atom_low_and(&mut self) -> io::Result<AtomExpr> {
+        let mut terms = vec![self.atom_or()?];
+        while self.peek() == Some(b';') {
+            self.pos += 1;
+            terms.push(self.atom_or()?);
+        }
+        Ok(collapse(terms, AtomExpr::And))
+    }
+
+    fn atom_or(&mut self) -> io::Result<AtomExpr> {
+        let mut terms = vec![self.atom_high_and()?];
+        while self.peek() == Some(b',') {
+            self.pos += 1;
+            terms.push(self.atom_high_and()?);
+        }
+        Ok(collapse(terms, AtomExpr::Or))
+    }
+
+    fn atom_high_and(&mut self) -> io::Result<AtomExpr> {
+        let mut terms = vec![self.atom_not()?];
+        loop {
+            match self.peek() {
+                Some(b'&') => self.pos += 1,
+                Some(b']' | b';' | b',') | None => break,
+                _ => (),
+            }
+            terms.push(self.atom_not()?);
+        }
+        Ok(collapse(terms, AtomExpr::And))
+    }
+
+    fn atom_not(&mut self) -> io::Result<AtomExpr> {
+        if self.peek() == Some(b'!') {
+            self.pos += 1;
+            return Ok(AtomExpr::Not(Box::new(self.atom_not()?)));
+        }
+        Ok(AtomExpr::Prim(self.atom_prim()?))
+    }
+
+    fn atom_prim(&mut self) -> io::Result<AtomPrim> {
+        let start = self.pos;
+        let Some(c) = self.peek() else {
+            return Err(syntax_err(start, "unclosed bracket atom"));
+        };
+        let next = self.peek_at(1);
+        // Bracket hydrogen, e.g. `[H]`, or `[2H+]`, vs. a hydrogen count.
+        let before = &self.s[..start];
+        let first = before
+            .iter()
+            .rposition(|&b| b == b'[')
+            .is_some_and(|open| before[open + 1..].iter().all(u8::is_ascii_digit));
+
+        // Two-letter element symbols take precedence over single letter primitives, e.g. "Cl".
+        if c.is_ascii_uppercase()
+            && let Some(n) = next
+            && n.is_ascii_lowercase()
+        {
+            let sym = format!("{}{}", c as char, n as char);
+            if ELEMENT_SYMBOLS.contains(&sym.as_str())
+                && let Ok(el) = Element::from_letter(&sym)
+            {
+                self.pos += 2;
+                return Ok(AtomPrim::Element(el, Some(false)));
+            }
+        }
+
+        self.pos += 1;
+        let result = match c {
+            b'*' => AtomPrim::Any,
+            b'a' => AtomPrim::Aromatic(true),
+            b'A' => AtomPrim::Aromatic(false),
+            b'H' if first && matches!(self.peek(), Some(b']' | b'+' | b'-')) => {
+                AtomPrim::Element(Element::Hydrogen, None)
+            }
+            b'D' => AtomPrim::Degree(self.small_number()?.unwrap_or(1)),
+            b'H' => AtomPrim::TotalH(self.small_number()?.unwrap_or(1)),
+            b'X' => AtomPrim::Connectivity(self.small_number()?.unwrap_or(1)),
+            b'v' => AtomPrim::Valence(self.small_number()?.unwrap_or(1)),
+            b'x' => AtomPrim::RingConnectivity(self.small_number()?.unwrap_or(1)),
+            b'R' => AtomPrim::RingCount(self.small_number()?),
+            b'r' => AtomPrim::RingSize(self.small_number()?),
+            b'#' => {
+                let n = self
+                    .number()
+                    .ok_or_else(|| syntax_err(self.pos, "'#' without an atomic number"))?;
+                let sym = ELEMENT_SYMBOLS
+                    .get((n as usize).wrapping_sub(1))
+                    .ok_or_else(|| syntax_err(start, "unsupported atomic number"))?;
+                AtomPrim::Element(Element::from_letter(sym)?, None)
+            }
+            b'+' | b'-' => {
+                let sign = if c == b'+' { 1 } else { -1 };
+                let mut magnitude = 1;
+                if let Some(n) = self.small_number()? {
+                    magnitude = n as i8;
+                } else {
+                    while self.peek() == Some(c) {
+                        magnitude += 1;
+                        self.pos += 1;
+                    }
+                }
+                AtomPrim::Charge(sign * magnitude)
+            }
+            b'@' => {
+                while self.peek() == Some(b'@') {
+                    self.pos += 1;
+                }
+                AtomPrim::Chiral
+            }
+            b'$' => {
+                if self.peek() != Some(b'(') {
+                    return Err(syntax_err(start, "expected '(' after '$'"));
+                }
+                self.pos += 1;
+                let pattern = self.pattern()?;
+                if self.peek() != Some(b')') {
+                    return Err(syntax_err(self.pos, "unclosed recursive SMARTS"));
+                }
+                self.pos += 1;
+                AtomPrim::Recursive(Box::new(pattern))
+            }
+            b'0'..=b'9' => {
+                self.pos -= 1;
+                let n = self.number().unwrap();
+                AtomPrim::Isotope(
+                    u16::try_from(n).map_err(|_| syntax_err(start, "isotope out of range"))?,
+                )
+            }
+            b'b' | b'c' | b'n' | b'o' | b'p' | b's' => {
+                // Aromatic selenium.
+                let sym = if c == b's' && self.peek() == Some(b'e') {
+                    self.pos += 1;
+                    "Se".to_owned()
+                } else {
+                    (c.to_ascii_uppercase() as char).to_string()
+                };
+                AtomPrim::Element(Element::from_letter(&sym)?, Some(true))
+            }
+            c if c.is_ascii_uppercase() => {
+                AtomPrim::Element(Element::from_letter(&(c as char).to_string())?, Some(false))
+            }
+            _ => {
+                return Err(syntax_err(
+                    start,
+                    &format!("unexpected character '{}'", c as char),
+                ));
+            }
+        };
+
+        Ok(result)
+    }
+
+    // Bond expressions, with the same precedence as atom expressions.
+
+    fn bond_low_and(&mut self) -> io::Result<BondExpr> {
+        let mut terms = vec![self.bond_or()?];
+        while self.peek() == Some(b';') {
+            self.pos += 1;
+            terms.push(self.bond_or()?);
+        }
+        Ok(collapse(terms, BondExpr::And))
+    }
+
+    fn bond_or(&mut self) -> io::Result<BondExpr> {
+        let mut terms = vec![self.bond_high_and()?];
+        while self.peek() == Some(b',') {
+            self.pos += 1;
+            terms.push(self.bond_high_and()?);
+        }
+        Ok(collapse(terms, BondExpr::Or))
+    }
+
+    fn bond_high_and(&mut self) -> io::Result<BondExpr> {
+        let mut terms = vec![self.bond_not()?];
+        loop {
+            match self.peek() {
+                Some(b'&') => self.pos += 1,
+                Some(b'-' | b'=' | b'#' | b':' | b'~' | b'@' | b'!' | b'/' | b'\\') => (),
+                _ => break,
+            }
+            terms.push(self.bond_not()?);
+        }
+        Ok(collapse(terms, BondExpr::And))
+    }
+
+    fn bond_not(&mut self) -> io::Result<BondExpr> {
+        let start = self.pos;
+        let Some(c) = self.peek() else {
+            return Err(syntax_err(start, "bond without a following atom"));
+        };
+        self.pos += 1;
+
+        let prim = match c {
+            b'!' => return Ok(BondExpr::Not(Box::new(self.bond_not()?))),
+            b'-' | b'/' | b'\\' => BondPrim::Single,
+            b'=' => BondPrim::Double,
+            b'#' => BondPrim::Triple,
+            b':' => BondPrim::Aromatic,
+            b'~' => BondPrim::Any,
+            b'@' => BondPrim::Ring,
+            _ => return Err(syntax_err(start, "expected a bond symbol")),
+        };
+        Ok(BondExpr::Prim(prim))
+    }
+}
+
+/// Avoid wrapping single terms in logical operators.
+fn collapse<T>(mut terms: Vec<T>, op: fn(Vec<T>) -> T) -> T {
+    if terms.len() == 1 {
+        terms.pop().unwrap()
+    } else {
+        op(terms)
+    }
+}
+
+/// Ring information and bonds of the molecule being searched, derived once per search.
+struct Target {
+    p: PerceivedMol,
+    /// The number of SSSR rings each atom is in.
+    ring_count: Vec<u8>,
+    /// The size of the smallest SSSR ring each atom is in; 0 if none.
+    smallest_ring: Vec<u8>,
+    /// The number of ring bonds to each atom.
+    ring_connectivity: Vec<u8>,
+    heavy: Vec<usize>,
+}
+
+impl Target {
+    fn new(mol: &MoleculeCommon) -> Self {
+        let p = PerceivedMol::new(mol);
+        let n = p.atoms.len();
+
+        let mut ring_count = vec![0; n];
+        let mut smallest_ring = vec![0; n];
+        for ring in &p.rings {
+            for &i in ring {
+                ring_count[i] += 1;
+                if smallest_ring[i] == 0 || (ring.len() as u8) < smallest_ring[i] {
+                    smallest_ring[i] = ring.len() as u8;
+                }
+            }
+        }
+
+        let ring_connectivity = (0..n)
+            .map(|i| {
+                p.atoms[i]
+                    .nbrs
+                    .iter()
+                    .filter(|(j, _)| p.is_ring_bond(i, *j))
+                    .count() as u8
+            })
+            .collect();
+
+        let heavy = p.heavy();
+
+        Self {
+            p,
+            ring_count,
+            smallest_ring,
+            ring_connectivity,
+            heavy,
+        }
+    }
+
+    fn bond(&self, a: usize, b: usize) -> Option<BondType> {
+        self.p.atoms[a]
+            .nbrs
+            .iter()
+            .find(|(j, _)| *j == b)
+            .map(|(_, bt)| *bt)
+    }
+
+    fn atom_matches(&self, expr: &AtomExpr, i: usize) -> bool {
+        match expr {
+            AtomExpr::Prim(prim) => self.prim_matches(prim, i),
+            AtomExpr::Not(e) => !self.atom_matches(e, i),
+            AtomExpr::And(terms) => terms.iter().all(|e| self.atom_matches(e, i)),
+            AtomExpr::Or(terms) => terms.iter().any(|e| self.atom_matches(e, i)),
+        }
+    }
+
+    fn prim_matches(&self, prim: &AtomPrim, i: usize) -> bool {
+        let a = &self.p.atoms[i];
+
+        match prim {
+            AtomPrim::Any | AtomPrim::Chiral => true,
+            AtomPrim::Aromatic(ar) => a.aromatic == *ar,
+            AtomPrim::Element(el, ar) => a.element == *el && ar.is_none_or(|ar| a.aromatic == ar),
+            AtomPrim::Degree(n) => a.nbrs.len() == *n as usize,
+            AtomPrim::TotalH(n) => a.h == *n,
+            AtomPrim::Connectivity(n) => a.nbrs.len() + a.h as usize == *n as usize,
+            AtomPrim::Valence(n) => a.valence == *n,
+            AtomPrim::RingCount(n) => match n {
+                Some(n) => self.ring_count[i] == *n,
+                None => self.ring_count[i] > 0,
+            },
+            AtomPrim::RingSize(n) => match n {
+                Some(n) => self.smallest_ring[i] == *n,
+                None => self.smallest_ring[i] > 0,
+            },
+            AtomPrim::RingConnectivity(n) => self.ring_connectivity[i] == *n,
+            AtomPrim::Charge(c) => a.charge == *c,
+            AtomPrim::Isotope(n) => self.p.mol.atoms[i].isotope == Some(*n),
+            AtomPrim::Recursive(pattern) => !self.find(pattern, Some(i), 1).is_empty(),
+        }
+    }
+
+    fn bond_matches(&self, expr: &BondExpr, a: usize, b: usize, bt: BondType) -> bool {
+        match expr {
+            BondExpr::Prim(prim) => match prim {
+                BondPrim::Single => bt == BondType::Single,
+                BondPrim::Double => bt == BondType::Double,
+                BondPrim::Triple => bt == BondType::Triple,
+                BondPrim::Aromatic => bt == BondType::Aromatic,
+                BondPrim::Any => true,
+                BondPrim::Ring => self.p.is_ring_bond(a, b),
+                BondPrim::Implicit => matches!(bt, BondType::Single | BondType::Aromatic),
+            },
+            BondExpr::Not(e) => !self.bond_matches(e, a, b, bt),
+            BondExpr::And(terms) => terms.iter().all(|e| self.bond_matches(e, a, b, bt)),
+            BondExpr::Or(terms) => terms.iter().any(|e| self.bond_matches(e, a, b, bt)),
+        }
+    }
+
+    /// Find unique matches (by atom set) of a pattern, up to `max`. Each match lists target atom
+    /// indices, in pattern atom order. `first` optionally fixes the first pattern atom.
+    fn find(&self, pattern: &SmartsPattern, first: Option<usize>, max: usize) -> Vec<Vec<usize>> {
+        let mut search = Search {
+            target: self,
+            pattern,
+            mapping: Vec::with_capacity(pattern.atoms.len()),
+            used: vec![false; self.p.atoms.len()],
+            seen: HashSet::new(),
+            result: Vec::new(),
+            first,
+            max,
+        };
+        search.extend();
+        search.result
+    }
+}
+
+/// Backtracking subgraph isomorphism. Pattern atoms are mapped in order; after the first atom of
+/// each component, each is bonded to an earlier one, so candidates are its neighbors.
+struct Search<'a> {
+    target: &'a Target,
+    pattern: &'a SmartsPattern,
+    mapping: Vec<usize>,
+    used: Vec<bool>,
+    seen: HashSet<Vec<usize>>,
+    result: Vec<Vec<usize>>,
+    first: Option<usize>,
+    max: usize,
+}
+
+impl Search<'_> {
+    fn extend(&mut self) {
+        if self.result.len() >= self.max {
+            return;
+        }
+
+        let pattern = self.pattern;
+        let k = self.mapping.len();
+        if k == pattern.atoms.len() {
+            let mut key = self.mapping.clone();
+            key.sort_unstable();
+            if self.seen.insert(key) {
+                self.result.push(self.mapping.clone());
+            }
+            return;
+        }
+
+        // Bonds to previously-mapped pattern atoms.
+        let prior: Vec<(usize, &BondExpr)> = pattern
+            .bonds
+            .iter()
+            .filter_map(|(a, b, e)| match (*a, *b) {
+                (a, b) if a == k && b < k => Some((b, e)),
+                (a, b) if b == k && a < k => Some((a, e)),
+                _ => None,
+            })
+            .collect();
+
+        let candidates: Vec<usize> = match (k, self.first, prior.first()) {
+            (0, Some(i), _) => vec![i],
+            (_, _, Some((m, _))) => self.target.p.atoms[self.mapping[*m]]
+                .nbrs
+                .iter()
+                .map(|(j, _)| *j)
+                .collect(),
+            _ => self.target.heavy.clone(),
+        };
+
+        for cand in candidates {
+            if self.used[cand] || !self.target.atom_matches(&pattern.atoms[k], cand) {
+                continue;
+            }
+
+            let bonds_match = prior.iter().all(|(m, expr)| {
+                let other = self.mapping[*m];
+                self.target
+                    .bond(cand, other)
+                    .is_some_and(|bt| self.target.bond_matches(expr, cand, other, bt))
+            });
+            if !bonds_match {
+                continue;
+            }
+
+            self.mapping.push(cand);
+            self.used[cand] = true;
+            self.extend();
+            self.used[cand] = false;
+            self.mapping.pop();
+        }
+    }
+}
+
+impl MoleculeCommon {
+    /// Find unique matches of a SMARTS pattern. Each match is a set of atom indices, in the order
+    /// of the pattern's atoms.
+    pub fn substruct_matches(&self, pattern: &SmartsPattern) -> Vec<Vec<usize>> {
+        Target::new(self).find(pattern, None, MAX_MATCHES)
+    }
+
+    pub fn has_substruct(&self, pattern: &SmartsPattern) -> bool {
+        !Target::new(self).find(pattern, None, 1).is_empty()
+    }
+}
+
+impl State {
+    /// Select atoms matching a SMARTS pattern in the active ligand. If no ligand is active, use
+    /// the first ligand containing a match. Returns a status message.
+    pub fn select_smarts(&mut self, text: &str) -> io::Result<String> {
+        let pattern = parse_smarts(text)?;
+
+        let lig_indices: Vec<usize> = match self.volatile.active_mol {
+            Some((MolType::Ligand, i)) if i < self.ligands.len() => vec![i],
+            _ => (0..self.ligands.len()).collect(),
+        };
+        if lig_indices.is_empty() {
+            return Err(io::Error::new(ErrorKind::NotFound, "No ligands are open"));
+        }
+
+        for lig_i in lig_indices {
+            let mol = &self.ligands[lig_i].common;
+            let matches = mol.substruct_matches(&pattern);
+            if matches.is_empty() {
+                continue;
+            }
+
+            let mut atoms: Vec<usize> = matches.iter().flatten().copied().collect();
+            atoms.sort_unstable();
+            atoms.dedup();
+
+            let msg = format!(
+                "{} matches of {text} in {}; {} atoms selected",
+                matches.len(),
+                mol.ident,
+                atoms.len()
+            );
+            self.ui.selection = Selection::AtomsLig((lig_i, atoms));
+
+            return Ok(msg);
+        }
+
+        Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("No matches for {text}"),
+        ))
+    }
+}
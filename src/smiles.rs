@@ -2,7 +2,10 @@
 
 //! Convert between molecules and SMILES text
 
-use std::{collections::HashMap, io};
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+};
 
 use bio_files::BondType;
 use lin_alg::f64::Vec3;
@@ -11,330 +14,1419 @@ use na_seq::Element;
 use crate::molecule::{Atom, Bond, MoleculeCommon};
 
 impl MoleculeCommon {
+    /// Create canonical SMILES text for this molecule: Identical molecules produce identical
+    /// text, regardless of atom order. Hydrogens bonded to heavy atoms are folded into hydrogen
+    /// counts. Aromaticity is perceived with Hückel's rule, so Kekulé and aromatic forms of a
+    /// molecule produce the same text.
+    ///
+    /// Tetrahedral centers and double bond geometry are inferred from atom positions, if these
+    /// aren't degenerate. (e.g. they are for molecules created from SMILES)
     pub fn to_smiles(&self) -> String {
-        return String::new(); // todo temp
+        let mut mol = self.clone();
+        mol.build_adjacency_list();
 
-        if self.atoms.is_empty() {
-            return String::new();
+        // If we can't kekulize, use aromaticity from bond types as-is.
+        if mol.perceive_aromaticity().is_ok() {
+            SmilesWriter::new(&mol).write()
+        } else {
+            SmilesWriter::new(self).write()
         }
+    }
 
-        // Minimal DFS linearization with explicit bond symbols for non-single.
-        let mut out = String::new();
-        let mut visited = vec![false; self.atoms.len()];
-        let mut stack: Vec<(usize, Option<usize>)> = Vec::new();
+    /// Create a molecule from SMILES text, using the OpenSMILES grammar. Atoms are placed at the
+    /// origin; use `embed::mol_from_smiles` for 3D coordinates. Formal charges and implicit
+    /// hydrogen counts are stored on the atoms.
+    ///
+    /// Stereo markers are parsed and validated, but not stored on the molecule; use
+    /// `parse_smiles` to access them.
+    pub fn from_smiles(data: &str) -> io::Result<Self> {
+        Ok(parse_smiles(data)?.into_molecule(data))
+    }
+}
 
-        // Start each disconnected component.
-        let mut first_component = true;
-        for start in 0..self.atoms.len() {
-            if visited[start] {
-                continue;
-            }
-            if !first_component {
-                out.push('.');
-            }
-            first_component = false;
+/// A stereo marker on a SMILES bracket atom.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SmilesChirality {
+    /// `@`, or `@TH1`. Looking from the first neighbor, the remaining ones are arranged
+    /// anticlockwise, in the order written.
+    Anticlockwise,
+    /// `@@`, or `@TH2`.
+    Clockwise,
+    /// Allene, square planar, trigonal bipyramidal and octahedral classes. E.g. "AL1", "SP2", "TB12".
+    Other(String),
+}
 
-            stack.push((start, None));
-            while let Some((u, parent)) = stack.pop() {
-                if visited[u] {
-                    continue;
-                }
-                visited[u] = true;
-
-                if let Some(p) = parent {
-                    // Print bond symbol if non-single.
-                    if let Some(b) = self.find_bond(p, u) {
-                        match b.bond_type {
-                            BondType::Single => {} // implicit
-                            BondType::Double => out.push('='),
-                            BondType::Triple => out.push('#'),
-                            _ => {} // keep minimal
-                        }
-                    }
-                }
+/// Directional single bonds, used to specify double bond geometry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BondDir {
+    /// `/`
+    Up,
+    /// `\`
+    Down,
+}
 
-                out.push_str(&self.atoms[u].element.to_letter());
+/// The result of parsing SMILES text. This includes stereo information that `MoleculeCommon`
+/// doesn't store.
+#[derive(Debug, Default)]
+pub struct SmilesGraph {
+    pub atoms: Vec<Atom>,
+    pub bonds: Vec<Bond>,
+    pub adjacency_list: Vec<Vec<usize>>,
+    /// Per atom: Neighbor indices in the order they're written, which chirality markers refer to.
+    /// Ring bonds are placed at the position of their ring bond digit. `None` is a bracket atom's
+    /// hydrogen count, which immediately follows the preceding atom.
+    pub neighbor_order: Vec<Vec<Option<usize>>>,
+    /// Per atom.
+    pub chirality: Vec<Option<SmilesChirality>>,
+    /// (from atom, to atom, direction), in the order written.
+    pub bond_dirs: Vec<(usize, usize, BondDir)>,
+    /// Text following the SMILES string, separated by whitespace; conventionally a name.
+    pub name: Option<String>,
+}
 
-                // Push children; simple heuristic: neighbors except parent.
-                // We add parentheses for branch degree > 1.
-                let nbrs: Vec<usize> = self.adjacency_list[u]
-                    .iter()
-                    .copied()
-                    .filter(|&v| !visited[v])
-                    .collect();
+impl SmilesGraph {
+    /// Create a molecule with atoms at the origin. `text` is its identifier, if it isn't named.
+    pub fn into_molecule(self, text: &str) -> MoleculeCommon {
+        let atom_posits = vec![Vec3::new_zero(); self.atoms.len()];
 
-                if !nbrs.is_empty() {
-                    // First neighbor continues main chain; others as branches.
-                    let mut it = nbrs.into_iter();
-                    if let Some(first) = it.next() {
-                        stack.push((first, Some(u)));
-                    }
-                    for v in it {
-                        out.push('(');
-                        // Print bond symbol for branched bond if needed.
-                        if let Some(b) = self.find_bond(u, v) {
-                            match b.bond_type {
-                                BondType::Single => {}
-                                BondType::Double => out.push('='),
-                                BondType::Triple => out.push('#'),
-                                _ => {}
-                            }
-                        }
-                        out.push_str(&self.atoms[v].element.to_letter());
-                        // Continue along one neighbor if it exists; otherwise close.
-                        // For minimal output, just close immediately; deeper branches
-                        // will be reached when that neighbor is popped from stack.
-                        out.push(')');
-                        stack.push((v, Some(u)));
-                    }
-                }
-            }
+        MoleculeCommon {
+            ident: self.name.unwrap_or_else(|| text.trim().to_owned()),
+            atoms: self.atoms,
+            bonds: self.bonds,
+            adjacency_list: self.adjacency_list,
+            atom_posits,
+            ..Default::default()
         }
+    }
+}
+
+/// A bond symbol as written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BondSymbol {
+    Single,
+    Double,
+    Triple,
+    Aromatic,
+    Up,
+    Down,
+}
 
-        out
+impl BondSymbol {
+    fn from_byte(c: u8) -> Option<Self> {
+        Some(match c {
+            b'-' => Self::Single,
+            b'=' => Self::Double,
+            b'#' => Self::Triple,
+            b':' => Self::Aromatic,
+            b'/' => Self::Up,
+            b'\\' => Self::Down,
+            _ => return None,
+        })
     }
 
-    pub fn from_smiles(data: &str) -> io::Result<Self> {
-        let mut atoms: Vec<Atom> = Vec::new();
-        let mut bonds: Vec<Bond> = Vec::new();
-        let mut adjacency_list: Vec<Vec<usize>> = Vec::new();
-        let mut atom_posits: Vec<Vec3> = Vec::new();
-
-        let mut current: Option<usize> = None;
-        let mut last_bond: Option<BondType> = None;
-        let mut branch_stack: Vec<usize> = Vec::new();
-        let mut ring_map: HashMap<u8, (usize, BondType)> = HashMap::new();
-
-        let mut chars = data.chars().peekable();
-        let mut next_serial: u32 = 1;
-
-        while let Some(ch) = chars.peek().copied() {
-            match ch {
-                // Bonds
-                '-' => {
-                    last_bond = Some(BondType::Single);
-                    chars.next();
+    fn bond_type(self) -> BondType {
+        match self {
+            Self::Double => BondType::Double,
+            Self::Triple => BondType::Triple,
+            Self::Aromatic => BondType::Aromatic,
+            _ => BondType::Single,
+        }
+    }
+
+    fn dir(self) -> Option<BondDir> {
+        match self {
+            Self::Up => Some(BondDir::Up),
+            Self::Down => Some(BondDir::Down),
+            _ => None,
+        }
+    }
+}
+
+/// A ring bond digit awaiting its partner.
+struct RingOpen {
+    atom: usize,
+    bond: Option<BondSymbol>,
+    /// Index into the atom's `neighbor_order`.
+    slot: usize,
+}
+
+pub struct ParsedAtom {
+    pub element: Element,
+    pub aromatic: bool,
+    pub bracket: bool,
+    pub isotope: Option<u16>,
+    pub charge: i8,
+    /// Hydrogen count, for bracket atoms.
+    pub h_count: u8,
+    pub chirality: Option<SmilesChirality>,
+}
+
+fn syntax_err(pos: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid SMILES at position {}: {msg}", pos + 1),
+    )
+}
+
+/// Parse SMILES text, per the OpenSMILES specification. Supports bracket atoms (isotopes,
+/// chirality, hydrogen counts, charges and atom classes), `%nn` ring bonds, branches,
+/// disconnected components, and all bond symbols except quadruple bonds. Wildcard atoms (`*`)
+/// aren't supported.
+///
+/// Implicit bonds between aromatic atoms are aromatic if in a ring, and single otherwise.
+pub fn parse_smiles(data: &str) -> io::Result<SmilesGraph> {
+    let data = data.trim();
+    let (smiles, name) = match data.split_once(char::is_whitespace) {
+        Some((s, n)) => (s, Some(n.trim().to_owned())),
+        None => (data, None),
+    };
+
+    if smiles.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Empty SMILES string",
+        ));
+    }
+
+    let s = smiles.as_bytes();
+    let mut result = SmilesGraph {
+        name,
+        ..Default::default()
+    };
+
+    let mut aromatic = Vec::new();
+    let mut bracket = Vec::new();
+    // Bonds created implicitly between two aromatic atoms; these are only aromatic if in a ring.
+    let mut implicit_aromatic = Vec::new();
+
+    let mut prev: Option<usize> = None;
+    let mut pending: Option<BondSymbol> = None;
+    let mut branches: Vec<usize> = Vec::new();
+    let mut rings: HashMap<u32, RingOpen> = HashMap::new();
+
+    let mut i = 0;
+    while i < s.len() {
+        let c = s[i];
+
+        if let Some(sym) = BondSymbol::from_byte(c) {
+            if prev.is_none() {
+                return Err(syntax_err(i, "bond without a preceding atom"));
+            }
+            if pending.is_some() {
+                return Err(syntax_err(i, "consecutive bond symbols"));
+            }
+            pending = Some(sym);
+            i += 1;
+            continue;
+        }
+
+        match c {
+            b'(' => {
+                let Some(p) = prev else {
+                    return Err(syntax_err(i, "branch without a preceding atom"));
+                };
+                if pending.is_some() {
+                    return Err(syntax_err(i, "bond symbol before a branch"));
                 }
-                '=' => {
-                    last_bond = Some(BondType::Double);
-                    chars.next();
+                branches.push(p);
+                i += 1;
+            }
+            b')' => {
+                let Some(p) = branches.pop() else {
+                    return Err(syntax_err(i, "unmatched ')'"));
+                };
+                if pending.is_some() {
+                    return Err(syntax_err(i, "bond without a following atom"));
                 }
-                '#' => {
-                    last_bond = Some(BondType::Triple);
-                    chars.next();
+                prev = Some(p);
+                i += 1;
+            }
+            b'.' => {
+                if pending.is_some() {
+                    return Err(syntax_err(i, "bond without a following atom"));
                 }
-
-                // Branching
-                '(' => {
-                    if let Some(ci) = current {
-                        branch_stack.push(ci);
+                prev = None;
+                i += 1;
+            }
+            b'$' => {
+                return Err(syntax_err(i, "quadruple bonds are not supported"));
+            }
+            b'0'..=b'9' | b'%' => {
+                let (num, len) = if c == b'%' {
+                    match (s.get(i + 1), s.get(i + 2)) {
+                        (Some(d0), Some(d1)) if d0.is_ascii_digit() && d1.is_ascii_digit() => {
+                            ((d0 - b'0') as u32 * 10 + (d1 - b'0') as u32, 3)
+                        }
+                        _ => return Err(syntax_err(i, "'%' must be followed by two digits")),
                     }
-                    chars.next();
-                }
-                ')' => {
-                    current = branch_stack.pop();
-                    chars.next();
-                }
+                } else {
+                    ((c - b'0') as u32, 1)
+                };
 
-                // Disconnected components
-                '.' => {
-                    current = None;
-                    last_bond = None;
-                    chars.next();
-                }
+                let Some(atom) = prev else {
+                    return Err(syntax_err(i, "ring bond without a preceding atom"));
+                };
+                let sym = pending.take();
 
-                // Ring closures 0-9
-                '0'..='9' => {
-                    let d = ch as u8 - b'0';
-                    let cur_idx = match current {
-                        Some(i) => i,
-                        None => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::InvalidData,
-                                "ring digit without current atom",
-                            ));
+                match rings.remove(&num) {
+                    Some(open) => {
+                        if open.atom == atom {
+                            return Err(syntax_err(i, "ring bond from an atom to itself"));
                         }
-                    };
-                    let bt = last_bond.unwrap_or(BondType::Single);
-                    if let Some((other, bt_saved)) = ring_map.remove(&d) {
-                        add_bond(
-                            cur_idx,
-                            other,
-                            bt_saved,
-                            &mut bonds,
-                            &mut adjacency_list,
-                            &atoms,
+                        if let (Some(a), Some(b)) = (open.bond, sym)
+                            && a.bond_type() != b.bond_type()
+                        {
+                            return Err(syntax_err(i, "conflicting ring bond symbols"));
+                        }
+
+                        // The bond symbol may be written at either end.
+                        let (sym, from, to) = match (sym, open.bond) {
+                            (Some(b), _) => (Some(b), atom, open.atom),
+                            (None, b) => (b, open.atom, atom),
+                        };
+
+                        let bond_type = match sym {
+                            Some(b) => b.bond_type(),
+                            None if aromatic[from] && aromatic[to] => {
+                                implicit_aromatic.push(result.bonds.len());
+                                BondType::Aromatic
+                            }
+                            None => BondType::Single,
+                        };
+
+                        add_bond(&mut result, from, to, bond_type, i)?;
+                        if let Some(dir) = sym.and_then(BondSymbol::dir) {
+                            result.bond_dirs.push((from, to, dir));
+                        }
+
+                        result.neighbor_order[open.atom][open.slot] = Some(atom);
+                        result.neighbor_order[atom].push(Some(open.atom));
+                    }
+                    None => {
+                        // A placeholder, filled when the ring closes.
+                        let slot = result.neighbor_order[atom].len();
+                        result.neighbor_order[atom].push(None);
+                        rings.insert(
+                            num,
+                            RingOpen {
+                                atom,
+                                bond: sym,
+                                slot,
+                            },
                         );
-                    } else {
-                        ring_map.insert(d, (cur_idx, bt));
                     }
-                    last_bond = None;
-                    chars.next();
                 }
 
-                // Atoms (organic subset, including two-letter halogens)
-                _ => {
-                    if let Some((sym, aromatic)) = parse_atom_token(&mut chars)? {
-                        let element = Element::from_letter(sym)?;
-                        let idx = atoms.len();
-                        atoms.push(Atom {
-                            serial_number: next_serial,
-                            posit: Vec3::new(0.0, 0.0, 0.0),
-                            element,
-                            type_in_res: None,
-                            type_in_res_general: None,
-                            force_field_type: None,
-                            role: None,
-                            residue: None,
-                            chain: None,
-                            hetero: false,
-                            occupancy: None,
-                            partial_charge: None,
-                            alt_conformation_id: None,
-                        });
-                        adjacency_list.push(Vec::new());
-                        atom_posits.push(Vec3::new(0.0, 0.0, 0.0));
-
-                        if let Some(prev) = current {
-                            let bt = last_bond.take().unwrap_or_else(|| {
-                                // minimal behavior: treat implicit/aromatic bonds as single
-                                BondType::Single
-                            });
-                            add_bond(prev, idx, bt, &mut bonds, &mut adjacency_list, &atoms);
+                i += len;
+            }
+            _ => {
+                let (parsed, len) = parse_atom(&s[i..], i)?;
+                let idx = result.atoms.len();
+
+                result.atoms.push(Atom {
+                    serial_number: idx as u32 + 1,
+                    element: parsed.element,
+                    formal_charge: parsed.charge,
+                    isotope: parsed.isotope,
+                    implicit_h: parsed.bracket.then_some(parsed.h_count),
+                    ..Default::default()
+                });
+                result.adjacency_list.push(Vec::new());
+                result.neighbor_order.push(Vec::new());
+                result.chirality.push(parsed.chirality);
+                aromatic.push(parsed.aromatic);
+                bracket.push(parsed.bracket);
+
+                if let Some(p) = prev {
+                    let bond_type = match pending {
+                        Some(b) => b.bond_type(),
+                        None if aromatic[p] && parsed.aromatic => {
+                            implicit_aromatic.push(result.bonds.len());
+                            BondType::Aromatic
                         }
+                        None => BondType::Single,
+                    };
 
-                        current = Some(idx);
-                        next_serial += 1;
-                    } else {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "unable to parse atom token",
-                        ));
+                    add_bond(&mut result, p, idx, bond_type, i)?;
+                    if let Some(dir) = pending.and_then(BondSymbol::dir) {
+                        result.bond_dirs.push((p, idx, dir));
                     }
+
+                    result.neighbor_order[p].push(Some(idx));
+                    result.neighbor_order[idx].push(Some(p));
+                }
+
+                if parsed.h_count > 0 {
+                    result.neighbor_order[idx].push(None);
                 }
+
+                pending = None;
+                prev = Some(idx);
+                i += len;
             }
         }
+    }
 
-        if !ring_map.is_empty() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "unclosed ring index",
-            ));
-        }
+    if pending.is_some() {
+        return Err(syntax_err(s.len() - 1, "bond without a following atom"));
+    }
+    if !branches.is_empty() {
+        return Err(syntax_err(s.len() - 1, "unclosed branch"));
+    }
+    if let Some(num) = rings.keys().min() {
+        return Err(syntax_err(
+            s.len() - 1,
+            &format!("unclosed ring bond {num}"),
+        ));
+    }
 
-        Ok(MoleculeCommon {
-            ident: data.trim().to_string(),
-            atoms,
-            bonds,
-            adjacency_list,
-            atom_posits,
-            metadata: HashMap::new(),
-            visible: true,
-            path: None,
-            selected_for_md: false,
-            entity_i_range: None,
-        })
+    for bond_i in implicit_aromatic {
+        let bond = &result.bonds[bond_i];
+        if !in_ring(&result.adjacency_list, bond.atom_0, bond.atom_1) {
+            result.bonds[bond_i].bond_type = BondType::Single;
+        }
     }
 
-    fn find_bond(&self, a: usize, b: usize) -> Option<&Bond> {
-        let (lo, hi) = if a < b { (a, b) } else { (b, a) };
-        self.bonds
-            .iter()
-            .find(|bb| (bb.atom_0 == lo && bb.atom_1 == hi) || (bb.atom_0 == hi && bb.atom_1 == lo))
+    for i in 0..result.atoms.len() {
+        if !bracket[i] {
+            let h = implicit_h_count(&result, i, aromatic[i]);
+            result.atoms[i].implicit_h = Some(h);
+        }
     }
+
+    Ok(result)
 }
 
 fn add_bond(
+    graph: &mut SmilesGraph,
     a: usize,
     b: usize,
     bond_type: BondType,
-    bonds: &mut Vec<Bond>,
-    adj: &mut Vec<Vec<usize>>,
-    atoms: &Vec<Atom>,
-) {
+    pos: usize,
+) -> io::Result<()> {
+    if graph.adjacency_list[a].contains(&b) {
+        return Err(syntax_err(pos, "duplicate bond between two atoms"));
+    }
+
     let (lo, hi) = if a < b { (a, b) } else { (b, a) };
-    bonds.push(Bond {
+    graph.bonds.push(Bond {
         bond_type,
-        atom_0_sn: atoms[lo].serial_number,
-        atom_1_sn: atoms[hi].serial_number,
+        atom_0_sn: graph.atoms[lo].serial_number,
+        atom_1_sn: graph.atoms[hi].serial_number,
         atom_0: lo,
         atom_1: hi,
         is_backbone: false,
     });
-    adj[a].push(b);
-    adj[b].push(a);
+    graph.adjacency_list[a].push(b);
+    graph.adjacency_list[b].push(a);
+
+    Ok(())
+}
+
+/// Is the bond between atoms `a` and `b` part of a ring? I.e., are they still connected
+/// without it?
+fn in_ring(adj: &[Vec<usize>], a: usize, b: usize) -> bool {
+    let mut visited = vec![false; adj.len()];
+    let mut stack = vec![a];
+    visited[a] = true;
+
+    while let Some(u) = stack.pop() {
+        for &v in &adj[u] {
+            if u == a && v == b {
+                continue;
+            }
+            if v == b {
+                return true;
+            }
+            if !visited[v] {
+                visited[v] = true;
+                stack.push(v);
+            }
+        }
+    }
+    false
+}
+
+/// Standard valences of the organic subset, in ascending order.
+pub fn default_valences(el: Element) -> &'static [u8] {
+    match el.to_letter().as_str() {
+        "B" => &[3],
+        "C" => &[4],
+        "N" => &[3, 5],
+        "O" => &[2],
+        "P" => &[3, 5],
+        "S" => &[2, 4, 6],
+        "F" | "Cl" | "Br" | "I" => &[1],
+        _ => &[],
+    }
+}
+
+/// Standard valences, adjusted for formal charge. Charged N, O, and halogens gain or lose a bond
+/// per charge, e.g. ammonium and alkoxides; B and C lose one either way, e.g. carbanions.
+fn charged_valences(el: Element, charge: i8) -> Vec<u8> {
+    let charge = charge as i16;
+    let sym = el.to_letter();
+
+    default_valences(el)
+        .iter()
+        .filter_map(|&v| {
+            let v = v as i16;
+            let adjusted = match sym.as_str() {
+                "C" => v - charge.abs(),
+                "B" => v - charge,
+                _ => v + charge,
+            };
+            u8::try_from(adjusted).ok()
+        })
+        .collect()
+}
+
+pub fn bond_order(bond_type: BondType) -> u8 {
+    match bond_type {
+        BondType::Double => 2,
+        BondType::Triple => 3,
+        _ => 1,
+    }
+}
+
+/// The implicit hydrogen count of an organic subset (non-bracket) atom: The lowest standard
+/// valence that accommodates its bonds, less its bond order sum. Aromatic atoms contribute one
+/// additional bond order to the sum, and aromatic bonds count as 1.
+pub fn default_implicit_h(el: Element, bond_order_sum: u8, aromatic: bool) -> u8 {
+    let sum = bond_order_sum + aromatic as u8;
+
+    match default_valences(el).iter().find(|&&v| v >= sum) {
+        Some(v) => v - sum,
+        None => 0,
+    }
+}
+
+fn implicit_h_count(graph: &SmilesGraph, atom: usize, aromatic: bool) -> u8 {
+    let sum = graph
+        .bonds
+        .iter()
+        .filter(|b| b.atom_0 == atom || b.atom_1 == atom)
+        .map(|b| bond_order(b.bond_type))
+        .sum();
+
+    default_implicit_h(graph.atoms[atom].element, sum, aromatic)
 }
 
-fn parse_atom_token(
-    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
-) -> io::Result<Option<(&'static str, bool)>> {
-    // Organic subset + halogens; minimal bracket handling (rejects bracketed forms)
-    let ch = match chars.peek().copied() {
-        Some(c) => c,
-        None => return Ok(None),
+fn parse_number(s: &[u8], j: &mut usize) -> Option<u32> {
+    let start = *j;
+    while *j < s.len() && s[*j].is_ascii_digit() {
+        *j += 1;
+    }
+    if *j == start {
+        return None;
+    }
+    std::str::from_utf8(&s[start..*j]).ok()?.parse().ok()
+}
+
+/// Parse an atom at the start of `s`, returning it and the number of bytes consumed. `pos`
+/// is used for error messages.
+fn parse_atom(s: &[u8], pos: usize) -> io::Result<(ParsedAtom, usize)> {
+    let organic = |sym: &str, aromatic: bool, len: usize| -> io::Result<(ParsedAtom, usize)> {
+        Ok((
+            ParsedAtom {
+                element: Element::from_letter(sym)?,
+                aromatic,
+                bracket: false,
+                isotope: None,
+                charge: 0,
+                h_count: 0,
+                chirality: None,
+            },
+            len,
+        ))
     };
 
-    // Two-letter halogens need special-casing
-    if ch == 'C' {
-        // Could be "Cl"
-        let mut tmp = chars.clone();
-        tmp.next();
-        if let Some('l') = tmp.peek().copied() {
-            // Cl
-            chars.next(); // 'C'
-            chars.next(); // 'l'
-            return Ok(Some(("Cl", false)));
+    match s[0] {
+        b'C' if s.get(1) == Some(&b'l') => organic("Cl", false, 2),
+        b'B' if s.get(1) == Some(&b'r') => organic("Br", false, 2),
+        b'B' | b'C' | b'N' | b'O' | b'P' | b'S' | b'F' | b'I' => {
+            organic(&(s[0] as char).to_string(), false, 1)
+        }
+        b'b' | b'c' | b'n' | b'o' | b'p' | b's' => {
+            organic(&(s[0].to_ascii_uppercase() as char).to_string(), true, 1)
+        }
+        b'*' => Err(syntax_err(pos, "wildcard atoms are not supported")),
+        b'[' => {
+            let Some(end) = s.iter().position(|&c| c == b']') else {
+                return Err(syntax_err(pos, "unclosed bracket atom"));
+            };
+            let atom = parse_bracket_atom(&s[1..end], pos + 1)?;
+            Ok((atom, end + 1))
+        }
+        c => Err(syntax_err(
+            pos,
+            &format!("unexpected character '{}'", c as char),
+        )),
+    }
+}
+
+/// Parse the contents of a bracket atom, e.g. "13CH3+" or "C@@H".
+pub fn parse_bracket_atom(s: &[u8], pos: usize) -> io::Result<ParsedAtom> {
+    let mut j = 0;
+
+    let isotope = match parse_number(s, &mut j) {
+        Some(n) => Some(u16::try_from(n).map_err(|_| syntax_err(pos, "isotope out of range"))?),
+        None => None,
+    };
+
+    // Element symbol.
+    let (element, aromatic) = match s.get(j) {
+        None => return Err(syntax_err(pos + j, "bracket atom without an element")),
+        Some(b'*') => return Err(syntax_err(pos + j, "wildcard atoms are not supported")),
+        Some(c) if c.is_ascii_lowercase() => {
+            let two = s.get(j..j + 2).unwrap_or_default();
+            let sym = if [b"se", b"as", b"te"].iter().any(|a| a.as_slice() == two) {
+                j += 2;
+                format!("{}{}", two[0].to_ascii_uppercase() as char, two[1] as char)
+            } else if b"bcnops".contains(c) {
+                j += 1;
+                (c.to_ascii_uppercase() as char).to_string()
+            } else {
+                return Err(syntax_err(pos + j, "invalid aromatic element"));
+            };
+            (Element::from_letter(&sym)?, true)
+        }
+        Some(c) if c.is_ascii_uppercase() => {
+            // Use the two-letter symbol if one exists, e.g. "Cl", "Se", "Na".
+            let two = match s.get(j + 1) {
+                Some(c1) if c1.is_ascii_lowercase() => {
+                    Element::from_letter(&format!("{}{}", *c as char, *c1 as char)).ok()
+                }
+                _ => None,
+            };
+            match two {
+                Some(el) => {
+                    j += 2;
+                    (el, false)
+                }
+                None => {
+                    j += 1;
+                    (Element::from_letter(&(*c as char).to_string())?, false)
+                }
+            }
+        }
+        Some(c) => {
+            return Err(syntax_err(
+                pos + j,
+                &format!("unexpected character '{}' in bracket atom", *c as char),
+            ));
+        }
+    };
+
+    // Chirality.
+    let mut chirality = None;
+    if s.get(j) == Some(&b'@') {
+        j += 1;
+        if s.get(j) == Some(&b'@') {
+            j += 1;
+            chirality = Some(SmilesChirality::Clockwise);
+        } else if let Some(class) = s.get(j..j + 2).filter(|c| {
+            [b"TH", b"AL", b"SP", b"TB", b"OH"]
+                .iter()
+                .any(|a| a.as_slice() == *c)
+        }) {
+            let class = std::str::from_utf8(class).unwrap_or_default().to_owned();
+            j += 2;
+            let Some(n) = parse_number(s, &mut j) else {
+                return Err(syntax_err(pos + j, "chirality class without a number"));
+            };
+
+            chirality = Some(match (class.as_str(), n) {
+                ("TH", 1) => SmilesChirality::Anticlockwise,
+                ("TH", 2) => SmilesChirality::Clockwise,
+                ("TH" | "AL", 3..) | ("SP", 4..) | ("TB", 21..) | ("OH", 31..) | (_, 0) => {
+                    return Err(syntax_err(pos + j, "chirality class number out of range"));
+                }
+                _ => SmilesChirality::Other(format!("{class}{n}")),
+            });
+        } else {
+            chirality = Some(SmilesChirality::Anticlockwise);
+        }
+    }
+
+    // Hydrogen count.
+    let mut h_count = 0;
+    if s.get(j) == Some(&b'H') {
+        j += 1;
+        h_count = match parse_number(s, &mut j) {
+            Some(n) => {
+                u8::try_from(n).map_err(|_| syntax_err(pos, "hydrogen count out of range"))?
+            }
+            None => 1,
+        };
+    }
+
+    // Charge. E.g. "+", "-2", and the deprecated "++".
+    let mut charge: i32 = 0;
+    if let Some(&sign) = s.get(j).filter(|c| **c == b'+' || **c == b'-') {
+        let sign_val = if sign == b'+' { 1 } else { -1 };
+        j += 1;
+
+        let magnitude = match parse_number(s, &mut j) {
+            Some(n) => n as i32,
+            None => {
+                let mut n = 1;
+                while s.get(j) == Some(&sign) {
+                    n += 1;
+                    j += 1;
+                }
+                n
+            }
+        };
+        if magnitude > 15 {
+            return Err(syntax_err(pos + j, "charge out of range"));
+        }
+        charge = sign_val * magnitude;
+    }
+
+    // Atom class. We don't use this.
+    if s.get(j) == Some(&b':') {
+        j += 1;
+        if parse_number(s, &mut j).is_none() {
+            return Err(syntax_err(pos + j, "atom class without a number"));
+        }
+    }
+
+    if j != s.len() {
+        return Err(syntax_err(pos + j, "unexpected characters in bracket atom"));
+    }
+
+    Ok(ParsedAtom {
+        element,
+        aromatic,
+        bracket: true,
+        isotope,
+        charge: charge as i8,
+        h_count,
+        chirality,
+    })
+}
+
+// Writing
+
+/// Elements which may be written as aromatic (lowercase).
+const AROMATIC_ELEMENTS: [&str; 8] = ["B", "C", "N", "O", "P", "S", "Se", "As"];
+
+/// For ranking; distinguishes bond types.
+fn bond_code(bond_type: BondType) -> u8 {
+    match bond_type {
+        BondType::Aromatic => 4,
+        bt => bond_order(bt),
+    }
+}
+
+/// Replace each key with its index among the sorted, unique keys.
+fn dense_ranks<T: Ord>(keys: &[T]) -> Vec<usize> {
+    let mut sorted: Vec<&T> = keys.iter().collect();
+    sorted.sort();
+    sorted.dedup();
+
+    keys.iter()
+        .map(|k| sorted.binary_search(&k).unwrap_or_default())
+        .collect()
+}
+
+fn num_classes(ranks: &[usize]) -> usize {
+    ranks.iter().max().map_or(0, |r| r + 1)
+}
+
+/// Split atom classes by their neighbors' classes, until this no longer separates any.
+fn refine_ranks(ranks: &mut Vec<usize>, nbrs: &[Vec<(usize, usize)>], bonds: &[Bond]) {
+    let mut num = num_classes(ranks);
+
+    loop {
+        let keys: Vec<_> = (0..ranks.len())
+            .map(|i| {
+                let mut n: Vec<_> = nbrs[i]
+                    .iter()
+                    .map(|&(j, bi)| (ranks[j], bond_code(bonds[bi].bond_type)))
+                    .collect();
+                n.sort_unstable();
+                (ranks[i], n)
+            })
+            .collect();
+
+        *ranks = dense_ranks(&keys);
+
+        let num_new = num_classes(ranks);
+        if num_new == num {
+            break;
+        }
+        num = num_new;
+    }
+}
+
+/// Unit vector from `center` to `p`, or `None` if they're too close.
+fn unit_dir(center: Vec3, p: Vec3) -> Option<Vec3> {
+    let d = p - center;
+    if d.magnitude() < 1e-3 {
+        return None;
+    }
+    Some(d.to_normalized())
+}
+
+enum WriteStep {
+    Atom(usize),
+    BranchOpen,
+    BranchClose,
+}
+
+/// Writes canonical SMILES. Indices are into `atoms`: the atoms written, i.e. excluding hydrogens
+/// folded into their neighbors' counts.
+struct SmilesWriter<'a> {
+    mol: &'a MoleculeCommon,
+    atoms: Vec<usize>,
+    posits: Vec<Vec3>,
+    /// (neighbor, bond index), sorted by neighbor rank.
+    nbrs: Vec<Vec<(usize, usize)>>,
+    /// Positions of hydrogens folded into this atom. Used for stereo.
+    h_posits: Vec<Vec<Vec3>>,
+    h_count: Vec<u8>,
+    /// Written in lowercase.
+    aromatic: Vec<bool>,
+    /// Ranks prior to breaking ties; atoms in the same class are (likely) symmetric.
+    sym_classes: Vec<usize>,
+    /// Canonical ranks; unique per atom.
+    ranks: Vec<usize>,
+    // Traversal results
+    roots: Vec<usize>,
+    order: Vec<usize>,
+    parent: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    /// (Opening atom, closing atom)
+    ring_bonds: Vec<(usize, usize)>,
+    /// Markers on tree bonds, keyed by (parent, child).
+    bond_dirs: HashMap<(usize, usize), BondDir>,
+}
+
+impl<'a> SmilesWriter<'a> {
+    fn new(mol: &'a MoleculeCommon) -> Self {
+        let n = mol.atoms.len();
+        let posit = |i: usize| {
+            if mol.atom_posits.len() == n {
+                mol.atom_posits[i]
+            } else {
+                mol.atoms[i].posit
+            }
+        };
+
+        let mut all_nbrs = vec![Vec::new(); n];
+        for (bi, b) in mol.bonds.iter().enumerate() {
+            if b.atom_0 < n && b.atom_1 < n && b.atom_0 != b.atom_1 {
+                all_nbrs[b.atom_0].push((b.atom_1, bi));
+                all_nbrs[b.atom_1].push((b.atom_0, bi));
+            }
+        }
+
+        let is_h = |i: usize| mol.atoms[i].element == Element::Hydrogen;
+        let folded: Vec<_> = (0..n)
+            .map(|i| {
+                let a = &mol.atoms[i];
+                is_h(i)
+                    && a.isotope.is_none()
+                    && a.formal_charge == 0
+                    && all_nbrs[i].len() == 1
+                    && !is_h(all_nbrs[i][0].0)
+            })
+            .collect();
+
+        let atoms: Vec<_> = (0..n).filter(|&i| !folded[i]).collect();
+        let mut local = vec![0; n];
+        for (l, &i) in atoms.iter().enumerate() {
+            local[i] = l;
+        }
+
+        let mut nbrs = Vec::with_capacity(atoms.len());
+        let mut h_posits = Vec::with_capacity(atoms.len());
+        for &i in &atoms {
+            nbrs.push(
+                all_nbrs[i]
+                    .iter()
+                    .filter(|(j, _)| !folded[*j])
+                    .map(|&(j, bi)| (local[j], bi))
+                    .collect::<Vec<_>>(),
+            );
+            h_posits.push(
+                all_nbrs[i]
+                    .iter()
+                    .filter(|(j, _)| folded[*j])
+                    .map(|&(j, _)| posit(j))
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        let aromatic: Vec<_> = atoms
+            .iter()
+            .zip(&nbrs)
+            .map(|(&i, n)| {
+                AROMATIC_ELEMENTS.contains(&mol.atoms[i].element.to_letter().as_str())
+                    && n.iter()
+                        .any(|&(_, bi)| mol.bonds[bi].bond_type == BondType::Aromatic)
+            })
+            .collect();
+
+        let h_info = mol
+            .atoms
+            .iter()
+            .any(|a| a.element == Element::Hydrogen || a.implicit_h.is_some());
+
+        let mut result = Self {
+            mol,
+            posits: atoms.iter().map(|&i| posit(i)).collect(),
+            atoms,
+            nbrs,
+            h_posits,
+            h_count: Vec::new(),
+            aromatic,
+            sym_classes: Vec::new(),
+            ranks: Vec::new(),
+            roots: Vec::new(),
+            order: Vec::new(),
+            parent: Vec::new(),
+            children: Vec::new(),
+            ring_bonds: Vec::new(),
+            bond_dirs: HashMap::new(),
+        };
+
+        // If the molecule has no hydrogen information, or only some of it (e.g. polar hydrogens
+        // only), assume standard valences, so we don't write radicals.
+        let explicit_h: Vec<u8> = (0..result.atoms.len())
+            .map(|l| result.h_posits[l].len() as u8 + result.atom(l).implicit_h.unwrap_or(0))
+            .collect();
+        let h_known = h_info
+            && explicit_h
+                .iter()
+                .enumerate()
+                .all(|(l, &h)| result.valence_satisfied(l, h));
+
+        result.h_count = if h_known {
+            explicit_h
+        } else {
+            (0..result.atoms.len())
+                .map(|l| result.implied_h(l))
+                .collect()
+        };
+
+        result.rank();
+        result.traverse();
+        result.assign_bond_dirs();
+
+        result
+    }
+
+    fn atom(&self, l: usize) -> &Atom {
+        &self.mol.atoms[self.atoms[l]]
+    }
+
+    fn bond(&self, a: usize, b: usize) -> Option<&Bond> {
+        self.nbrs[a]
+            .iter()
+            .find(|(j, _)| *j == b)
+            .map(|&(_, bi)| &self.mol.bonds[bi])
+    }
+
+    fn bond_order_sum(&self, l: usize) -> u8 {
+        self.nbrs[l]
+            .iter()
+            .map(|&(_, bi)| bond_order(self.mol.bonds[bi].bond_type))
+            .sum()
+    }
+
+    /// The hydrogen count implied by writing this atom without brackets.
+    fn default_h(&self, l: usize) -> u8 {
+        default_implicit_h(
+            self.atom(l).element,
+            self.bond_order_sum(l),
+            self.aromatic[l],
+        )
+    }
+
+    /// The hydrogen count from standard valences, accounting for formal charge.
+    fn implied_h(&self, l: usize) -> u8 {
+        let atom = self.atom(l);
+        let sum = self.bond_order_sum(l) + self.aromatic[l] as u8;
+
+        match charged_valences(atom.element, atom.formal_charge)
+            .into_iter()
+            .find(|&v| v >= sum)
+        {
+            Some(v) => v - sum,
+            None => 0,
+        }
+    }
+
+    /// Whether `h` hydrogens fill at least the atom's lowest standard valence. Aromatic bonds
+    /// count as 1, plus 1 for the atom, so this is lenient for e.g. pyrrole N.
+    fn valence_satisfied(&self, l: usize, h: u8) -> bool {
+        let atom = self.atom(l);
+        let sum = self.bond_order_sum(l) + self.aromatic[l] as u8 + h;
+
+        match charged_valences(atom.element, atom.formal_charge).first() {
+            Some(&v) => sum >= v,
+            None => true,
+        }
+    }
+
+    /// Assign canonical ranks: Classify atoms by invariant properties, refine these by
+    /// neighbors, then break ties among symmetric atoms one at a time.
+    fn rank(&mut self) {
+        let invariants: Vec<_> = (0..self.atoms.len())
+            .map(|l| {
+                let a = self.atom(l);
+                (
+                    a.element.to_letter(),
+                    self.nbrs[l].len(),
+                    self.h_count[l],
+                    a.formal_charge,
+                    a.isotope,
+                    self.aromatic[l],
+                )
+            })
+            .collect();
+
+        let mut ranks = dense_ranks(&invariants);
+        refine_ranks(&mut ranks, &self.nbrs, &self.mol.bonds);
+        self.sym_classes = ranks.clone();
+
+        loop {
+            let mut counts = vec![0; ranks.len()];
+            for &r in &ranks {
+                counts[r] += 1;
+            }
+            let Some(tied) = counts.iter().position(|&c| c > 1) else {
+                break;
+            };
+            let i = ranks.iter().position(|&r| r == tied).unwrap_or_default();
+
+            let keys: Vec<_> = ranks
+                .iter()
+                .enumerate()
+                .map(|(j, &r)| (r, j != i))
+                .collect();
+            ranks = dense_ranks(&keys);
+            refine_ranks(&mut ranks, &self.nbrs, &self.mol.bonds);
+        }
+
+        for n in &mut self.nbrs {
+            n.sort_by_key(|&(j, _)| ranks[j]);
+        }
+        self.ranks = ranks;
+    }
+
+    /// Depth-first traversal, starting each component at its lowest-ranked atom, and visiting
+    /// neighbors in rank order. Non-tree bonds become ring bonds.
+    fn traverse(&mut self) {
+        let m = self.atoms.len();
+        self.order = vec![usize::MAX; m];
+        self.parent = vec![None; m];
+        self.children = vec![Vec::new(); m];
+
+        let mut by_rank: Vec<_> = (0..m).collect();
+        by_rank.sort_by_key(|&l| self.ranks[l]);
+
+        let mut count = 0;
+        for root in by_rank {
+            if self.order[root] != usize::MAX {
+                continue;
+            }
+            self.roots.push(root);
+            self.order[root] = count;
+            count += 1;
+
+            let mut stack = vec![(root, 0)];
+            while let Some(&(u, k)) = stack.last() {
+                if k >= self.nbrs[u].len() {
+                    stack.pop();
+                    continue;
+                }
+                if let Some(top) = stack.last_mut() {
+                    top.1 += 1;
+                }
+
+                let v = self.nbrs[u][k].0;
+                if self.parent[u] == Some(v) {
+                    continue;
+                }
+
+                if self.order[v] == usize::MAX {
+                    self.order[v] = count;
+                    count += 1;
+                    self.parent[v] = Some(u);
+                    self.children[u].push(v);
+                    stack.push((v, 0));
+                } else if self.order[v] < self.order[u] {
+                    self.ring_bonds.push((v, u));
+                }
+            }
+        }
+    }
+
+    /// Substituents of a double bond's atom `a` (other end `b`) we can mark with a directional
+    /// bond, or `None` if this end doesn't make the double bond stereogenic.
+    fn stereo_end(&self, a: usize, b: usize) -> Option<Vec<usize>> {
+        let subs: Vec<_> = self.nbrs[a]
+            .iter()
+            .filter(|(j, _)| *j != b)
+            .map(|&(j, bi)| (j, self.mol.bonds[bi].bond_type))
+            .collect();
+
+        if subs.is_empty() || subs.len() + self.h_count[a] as usize > 2 {
+            return None;
+        }
+        if subs.iter().any(|(_, bt)| *bt != BondType::Single) {
+            return None;
+        }
+        if subs.len() == 2 && self.sym_classes[subs[0].0] == self.sym_classes[subs[1].0] {
+            return None;
+        }
+
+        // Only tree bonds; not ring bonds.
+        let mut result: Vec<_> = subs
+            .into_iter()
+            .map(|(j, _)| j)
+            .filter(|&j| self.parent[j] == Some(a) || self.parent[a] == Some(j))
+            .collect();
+        result.sort_by_key(|&j| self.order[j]);
+
+        (!result.is_empty()).then_some(result)
+    }
+
+    /// The tree bond key (parent, child) between two atoms.
+    fn tree_key(&self, a: usize, b: usize) -> (usize, usize) {
+        if self.parent[b] == Some(a) {
+            (a, b)
         } else {
-            chars.next();
-            return Ok(Some(("C", false)));
+            (b, a)
         }
     }
-    if ch == 'B' {
-        let mut tmp = chars.clone();
-        tmp.next();
-        if let Some('r') = tmp.peek().copied() {
-            chars.next(); // 'B'
-            chars.next(); // 'r'
-            return Ok(Some(("Br", false)));
+
+    /// Is substituent `x` on the "up" side of double bond atom `a`, per its marker?
+    fn marked_side(&self, x: usize, a: usize) -> Option<bool> {
+        let key = self.tree_key(a, x);
+        let dir = self.bond_dirs.get(&key)?;
+        // "a/x" places x up from a; "x/a" places x down from a.
+        Some((*dir == BondDir::Up) == (key.0 == a))
+    }
+
+    fn mark_side(&mut self, x: usize, a: usize, up: bool) {
+        let key = self.tree_key(a, x);
+        let dir = if up == (key.0 == a) {
+            BondDir::Up
         } else {
-            chars.next();
-            return Ok(Some(("B", false)));
+            BondDir::Down
+        };
+        self.bond_dirs.insert(key, dir);
+    }
+
+    /// Are `x` (bonded to `a`) and `y` (bonded to `b`) on the same side of the `a`=`b` double
+    /// bond? `None` if geometry doesn't make this clear.
+    fn is_cis(&self, x: usize, a: usize, b: usize, y: usize) -> Option<bool> {
+        let axis = unit_dir(self.posits[a], self.posits[b])?;
+
+        let perp = |v: Vec3| v - axis * v.dot(axis);
+        let vx = perp(self.posits[x] - self.posits[a]);
+        let vy = perp(self.posits[y] - self.posits[b]);
+
+        if vx.magnitude() < 1e-3 || vy.magnitude() < 1e-3 {
+            return None;
+        }
+        let cos = vx.to_normalized().dot(vy.to_normalized());
+        if cos.abs() < 0.2 {
+            return None;
+        }
+
+        Some(cos > 0.)
+    }
+
+    /// Mark directional bonds around stereogenic double bonds that aren't in rings.
+    fn assign_bond_dirs(&mut self) {
+        let adj: Vec<Vec<usize>> = self
+            .nbrs
+            .iter()
+            .map(|n| n.iter().map(|(j, _)| *j).collect())
+            .collect();
+
+        let mut doubles = Vec::new();
+        for a in 0..self.atoms.len() {
+            for &(b, bi) in &self.nbrs[a] {
+                if self.order[a] < self.order[b] && self.mol.bonds[bi].bond_type == BondType::Double
+                {
+                    doubles.push((a, b));
+                }
+            }
+        }
+        doubles.sort_by_key(|&(a, _)| self.order[a]);
+
+        for (a, b) in doubles {
+            if in_ring(&adj, a, b) {
+                continue;
+            }
+            let (Some(xs), Some(ys)) = (self.stereo_end(a, b), self.stereo_end(b, a)) else {
+                continue;
+            };
+
+            // Prefer substituents already marked, e.g. from a conjugated double bond.
+            let x = *xs
+                .iter()
+                .find(|&&x| self.marked_side(x, a).is_some())
+                .unwrap_or(&xs[0]);
+            let y = *ys
+                .iter()
+                .find(|&&y| self.marked_side(y, b).is_some())
+                .unwrap_or(&ys[0]);
+
+            let Some(cis) = self.is_cis(x, a, b, y) else {
+                continue;
+            };
+
+            match (self.marked_side(x, a), self.marked_side(y, b)) {
+                (Some(_), Some(_)) => (), // Either consistent, or we can't express it.
+                (Some(up_x), None) => self.mark_side(y, b, up_x == cis),
+                (None, Some(up_y)) => self.mark_side(x, a, up_y == cis),
+                (None, None) => {
+                    self.mark_side(x, a, true);
+                    self.mark_side(y, b, cis);
+                }
+            }
         }
     }
 
-    // Single-letter elements / aromatic lower-case subset
-    match ch {
-        'c' => {
-            chars.next();
-            Ok(Some(("C", true)))
+    /// The tetrahedral chirality marker for an atom, given its neighbors in the order written.
+    /// `None` in `nbr_order` is a hydrogen in the atom's count.
+    fn chirality(&self, l: usize, nbr_order: &[Option<usize>]) -> Option<&'static str> {
+        let h = self.h_count[l] as usize;
+        if h > 1 || self.nbrs[l].len() + h != 4 {
+            return None;
         }
-        'n' => {
-            chars.next();
-            Ok(Some(("N", true)))
+        if self.nbrs[l]
+            .iter()
+            .any(|&(_, bi)| self.mol.bonds[bi].bond_type != BondType::Single)
+        {
+            return None;
         }
-        'o' => {
-            chars.next();
-            Ok(Some(("O", true)))
+
+        let mut classes: Vec<_> = self.nbrs[l]
+            .iter()
+            .map(|(j, _)| self.sym_classes[*j])
+            .collect();
+        classes.sort_unstable();
+        classes.dedup();
+        if classes.len() != self.nbrs[l].len() {
+            return None;
         }
-        'p' => {
-            chars.next();
-            Ok(Some(("P", true)))
+
+        let center = self.posits[l];
+        let heavy_dirs: Option<Vec<_>> = self.nbrs[l]
+            .iter()
+            .map(|(j, _)| unit_dir(center, self.posits[*j]))
+            .collect();
+        let heavy_dirs = heavy_dirs?;
+
+        // A hydrogen without coordinates is opposite the heavy atoms.
+        let h_dir = match self.h_posits[l].first() {
+            Some(p) => unit_dir(center, *p),
+            None => {
+                let sum = heavy_dirs.iter().fold(Vec3::new_zero(), |acc, d| acc + *d);
+                (sum.magnitude() > 0.1).then(|| (sum * -1.).to_normalized())
+            }
+        };
+
+        let dirs: Option<Vec<Vec3>> = nbr_order
+            .iter()
+            .map(|n| match n {
+                Some(j) => unit_dir(center, self.posits[*j]),
+                None => h_dir,
+            })
+            .collect();
+        let d = dirs?;
+        if d.len() != 4 {
+            return None;
         }
-        's' => {
-            chars.next();
-            Ok(Some(("S", true)))
+
+        let vol = (d[1] - d[0]).dot((d[2] - d[0]).cross(d[3] - d[0]));
+        if vol.abs() < 0.1 {
+            return None;
         }
-        'C' | 'N' | 'O' | 'P' | 'S' | 'F' | 'I' => {
-            chars.next();
-            Ok(Some((&*ch.to_string().leak(), false)))
+
+        // Negative: Viewed from the first, the rest are anticlockwise.
+        Some(if vol < 0. { "@" } else { "@@" })
+    }
+
+    fn bond_symbol(&self, a: usize, b: usize) -> &'static str {
+        if let Some(dir) = self.bond_dirs.get(&(a, b)) {
+            return match dir {
+                BondDir::Up => "/",
+                BondDir::Down => "\\",
+            };
         }
-        '[' => {
-            // Minimal parser: reject bracketed atoms to keep scope small for now.
-            Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "bracketed atoms not supported in minimal parser",
-            ))
+
+        let both_aromatic = self.aromatic[a] && self.aromatic[b];
+        match self.bond(a, b).map(|b| b.bond_type) {
+            Some(BondType::Double) => "=",
+            Some(BondType::Triple) => "#",
+            Some(BondType::Aromatic) if !both_aromatic => ":",
+            Some(BondType::Aromatic) => "",
+            _ if both_aromatic => "-",
+            _ => "",
         }
-        _ => Ok(None),
+    }
+
+    fn atom_token(&self, l: usize, chirality: Option<&str>) -> String {
+        let atom = self.atom(l);
+        let mut sym = atom.element.to_letter();
+        if self.aromatic[l] {
+            sym = sym.to_lowercase();
+        }
+
+        let h = self.h_count[l];
+        let organic = !default_valences(atom.element).is_empty();
+        if organic
+            && atom.formal_charge == 0
+            && atom.isotope.is_none()
+            && chirality.is_none()
+            && h == self.default_h(l)
+        {
+            return sym;
+        }
+
+        let mut result = String::from("[");
+        if let Some(iso) = atom.isotope {
+            result += &iso.to_string();
+        }
+        result += &sym;
+        if let Some(c) = chirality {
+            result += c;
+        }
+        match h {
+            0 => (),
+            1 => result.push('H'),
+            _ => result += &format!("H{h}"),
+        }
+        match atom.formal_charge {
+            0 => (),
+            1 => result.push('+'),
+            -1 => result.push('-'),
+            c if c > 0 => result += &format!("+{c}"),
+            c => result += &c.to_string(),
+        }
+        result.push(']');
+
+        result
+    }
+
+    fn write(&self) -> String {
+        let mut result = String::new();
+
+        // Ring bond digits currently open, keyed by (opening atom, closing atom).
+        let mut digits: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut digits_used: Vec<bool> = Vec::new();
+
+        for (i, &root) in self.roots.iter().enumerate() {
+            if i > 0 {
+                result.push('.');
+            }
+
+            let mut stack = vec![WriteStep::Atom(root)];
+            while let Some(step) = stack.pop() {
+                let u = match step {
+                    WriteStep::BranchOpen => {
+                        result.push('(');
+                        continue;
+                    }
+                    WriteStep::BranchClose => {
+                        result.push(')');
+                        continue;
+                    }
+                    WriteStep::Atom(u) => u,
+                };
+
+                if let Some(p) = self.parent[u] {
+                    result += self.bond_symbol(p, u);
+                }
+
+                let mut closings: Vec<_> = self
+                    .ring_bonds
+                    .iter()
+                    .filter(|(_, c)| *c == u)
+                    .map(|(o, _)| *o)
+                    .collect();
+                closings.sort_by_key(|&o| self.order[o]);
+
+                let mut openings: Vec<_> = self
+                    .ring_bonds
+                    .iter()
+                    .filter(|(o, _)| *o == u)
+                    .map(|(_, c)| *c)
+                    .collect();
+                openings.sort_by_key(|&c| self.order[c]);
+
+                // Neighbors in the order written; this defines chirality.
+                let mut nbr_order = Vec::new();
+                if let Some(p) = self.parent[u] {
+                    nbr_order.push(Some(p));
+                }
+                if self.h_count[u] == 1 {
+                    nbr_order.push(None);
+                }
+                nbr_order.extend(closings.iter().map(|&o| Some(o)));
+                nbr_order.extend(openings.iter().map(|&c| Some(c)));
+                nbr_order.extend(self.children[u].iter().map(|&c| Some(c)));
+
+                result += &self.atom_token(u, self.chirality(u, &nbr_order));
+
+                let mut freed = Vec::new();
+                for &o in &closings {
+                    if let Some(d) = digits.remove(&(o, u)) {
+                        result += &ring_digit(d);
+                        freed.push(d);
+                    }
+                }
+                for &c in &openings {
+                    let d = match digits_used.iter().position(|used| !used) {
+                        Some(d) => d,
+                        None => {
+                            digits_used.push(false);
+                            digits_used.len() - 1
+                        }
+                    };
+                    digits_used[d] = true;
+                    digits.insert((u, c), d);
+
+                    result += self.bond_symbol(u, c);
+                    result += &ring_digit(d);
+                }
+                // Free these after assigning new ones, to avoid e.g. "C11".
+                for d in freed {
+                    digits_used[d] = false;
+                }
+
+                // The last child continues the chain; the others are branches.
+                if let Some((&last, rest)) = self.children[u].split_last() {
+                    stack.push(WriteStep::Atom(last));
+                    for &c in rest.iter().rev() {
+                        stack.push(WriteStep::BranchClose);
+                        stack.push(WriteStep::Atom(c));
+                        stack.push(WriteStep::BranchOpen);
+                    }
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// Ring bond digits start at 1.
+pub fn ring_digit(i: usize) -> String {
+    let d = i + 1;
+    if d < 10 {
+        d.to_string()
+    } else {
+        format!("%{d}")
     }
 }
@@ -0,0 +1,760 @@
+//! Stereochemistry: CIP (Cahn–Ingold–Prelog) R/S labels of tetrahedral centers, and E/Z labels of
+//! double bonds, perceived from 3D coordinates. Also MDL parity values and wedge bonds for SDF
+//! files, and enumeration of all stereoisomers of a molecule.
+//!
+//! CIP priorities use the hierarchical digraph with duplicate atoms for ring closures and
+//! multiple bonds (the Kekulé form for aromatic rings), comparing sphere by sphere with rule 1a
+//! (atomic number), then rule 2 (mass). Centers whose substituents differ only in their own
+//! stereochemistry (rules 3–5) aren't treated as stereogenic.
+
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    io::{self, ErrorKind},
+};
+
+use bio_files::BondType;
+use graphics::{EngineUpdates, Scene};
+use lin_alg::f64::Vec3;
+use na_seq::Element::{Carbon, Hydrogen, Nitrogen, Phosphorus};
+use rayon::prelude::*;
+
+use crate::{
+    State,
+    embed::{StereoConstraints, add_hydrogens, embed_3d, minimize_gaff2},
+    file_io::topology::atomic_number,
+    mol_characterization::PerceivedMol,
+    mol_lig::MoleculeSmall,
+    molecule::{MoleculeCommon, MoleculeGeneric},
+    smiles::bond_order,
+};
+
+/// Limits the depth of CIP digraph exploration.
+const MAX_CIP_SPHERES: usize = 16;
+/// Limits digraph size for polycyclic molecules.
+const MAX_CIP_NODES: usize = 20_000;
+/// For normalized neighbor directions. Below this, we consider the center flat, e.g. from a 2D
+/// structure, and don't assign a label.
+const MIN_CHIRAL_VOL: f64 = 0.1;
+/// |cos| of the substituent dihedral angle across a double bond, below which we don't assign
+/// E or Z.
+const MIN_DOUBLE_BOND_COS: f64 = 0.2;
+/// Double bonds in rings up to this size can only be cis.
+const MAX_RIGID_RING: usize = 7;
+/// Enumerate isomers over at most this many stereo centers and bonds; 2^n isomers.
+const MAX_STEREO_ELEMENTS: usize = 6;
+/// How far we raise or lower atoms at the wide end of wedge bonds, in flat SDF structures. Å.
+const WEDGE_Z: f64 = 0.8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CipLabel {
+    R,
+    S,
+    E,
+    Z,
+}
+
+impl CipLabel {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::R => "R",
+            Self::S => "S",
+            Self::E => "E",
+            Self::Z => "Z",
+        }
+    }
+}
+
+/// A tetrahedral stereocenter.
+#[derive(Clone, Debug)]
+pub struct StereoCenter {
+    pub atom: usize,
+    /// Substituent atoms, by decreasing CIP priority. `None` is an implicit hydrogen.
+    pub ranked: [Option<usize>; 4],
+    /// `None` if the geometry is flat, e.g. from a 2D structure.
+    pub label: Option<CipLabel>,
+}
+
+/// A stereogenic double bond.
+#[derive(Clone, Debug)]
+pub struct StereoBond {
+    pub bond: usize,
+    pub atom_0: usize,
+    pub atom_1: usize,
+    /// The highest priority substituent on each end. `None` is an implicit hydrogen.
+    pub sub_0: Option<usize>,
+    pub sub_1: Option<usize>,
+    pub label: Option<CipLabel>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Stereo {
+    pub centers: Vec<StereoCenter>,
+    pub bonds: Vec<StereoBond>,
+}
+
+impl Stereo {
+    /// Per atom: R or S for centers, and E or Z on the first atom of double bonds.
+    pub fn atom_labels(&self, num_atoms: usize) -> Vec<Option<CipLabel>> {
+        let mut result = vec![None; num_atoms];
+        for center in &self.centers {
+            result[center.atom] = center.label;
+        }
+        for bond in &self.bonds {
+            if result[bond.atom_0].is_none() {
+                result[bond.atom_0] = bond.label;
+            }
+        }
+        result
+    }
+
+    /// A summary, e.g. "R, S, E". Unassigned elements are shown as "?".
+    pub fn summary(&self) -> String {
+        self.centers
+            .iter()
+            .map(|c| c.label)
+            .chain(self.bonds.iter().map(|b| b.label))
+            .map(|l| l.map(|l| l.to_str()).unwrap_or("?"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// A node of the hierarchical digraph, rooted at a stereocenter.
+struct CipNode {
+    /// `None` for hydrogens folded into counts.
+    atom: Option<usize>,
+    /// (Atomic number, mass × 1000).
+    key: (u32, u32),
+    /// Index of the parent node; `None` for the root.
+    parent: Option<usize>,
+    /// Duplicate atoms and hydrogens have no children.
+    terminal: bool,
+}
+
+/// Connectivity for CIP ranking, from the Kekulé form. Hydrogens are folded into counts.
+struct CipGraph {
+    key: Vec<(u32, u32)>,
+    /// Heavy atom neighbors, and bond orders.
+    nbrs: Vec<Vec<(usize, u8)>>,
+    h: Vec<u8>,
+}
+
+const KEY_H: (u32, u32) = (1, 1_008);
+
+impl CipGraph {
+    fn new(mol: &MoleculeCommon) -> Self {
+        let p = PerceivedMol::new(mol);
+
+        let mut kekule = mol.clone();
+        kekule.build_adjacency_list();
+        let _ = kekule.kekulize();
+
+        let key = mol
+            .atoms
+            .iter()
+            .map(|a| {
+                let mass = match a.isotope {
+                    Some(m) => m as u32 * 1_000,
+                    None => (a.element.atomic_weight() as f64 * 1_000.) as u32,
+                };
+                (atomic_number(a.element) as u32, mass)
+            })
+            .collect();
+
+        let mut nbrs = vec![Vec::new(); mol.atoms.len()];
+        for bond in &kekule.bonds {
+            let (a, b) = (bond.atom_0, bond.atom_1);
+            if mol.atoms[a].element == Hydrogen || mol.atoms[b].element == Hydrogen {
+                continue;
+            }
+            let order = bond_order(bond.bond_type);
+            nbrs[a].push((b, order));
+            nbrs[b].push((a, order));
+        }
+
+        Self {
+            key,
+            nbrs,
+            h: p.atoms.iter().map(|a| a.h).collect(),
+        }
+    }
+
+    /// Add a node's children to the arena. Returns their indices, highest key first.
+    fn expand(&self, arena: &mut Vec<CipNode>, i: usize) -> Vec<usize> {
+        let Some(atom) = arena[i].atom else {
+            return Vec::new();
+        };
+        if arena[i].terminal {
+            return Vec::new();
+        }
+
+        let parent_atom = arena[i].parent.and_then(|p| arena[p].atom);
+        let mut ancestors = Vec::new();
+        let mut node = arena[i].parent;
+        while let Some(n) = node {
+            if let Some(a) = arena[n].atom {
+                ancestors.push(a);
+            }
+            node = arena[n].parent;
+        }
+
+        let mut children = Vec::new();
+        let mut push =
+            |arena: &mut Vec<CipNode>, atom: Option<usize>, key: (u32, u32), terminal| {
+                arena.push(CipNode {
+                    atom,
+                    key,
+                    parent: Some(i),
+                    terminal,
+                });
+                children.push(arena.len() - 1);
+            };
+
+        for &(nbr, order) in &self.nbrs[atom] {
+            let key = self.key[nbr];
+            // Multiple bonds add duplicates of the atom at each end.
+            let num_dups = order.saturating_sub(1);
+
+            if Some(nbr) == parent_atom {
+                for _ in 0..num_dups {
+                    push(arena, Some(nbr), key, true);
+                }
+                continue;
+            }
+
+            // Ring closures end in a duplicate.
+            let ring_closure = ancestors.contains(&nbr);
+            push(arena, Some(nbr), key, ring_closure);
+            for _ in 0..num_dups {
+                push(arena, Some(nbr), key, true);
+            }
+        }
+
+        for _ in 0..self.h[atom] {
+            push(arena, None, KEY_H, true);
+        }
+
+        children.sort_by(|a, b| arena[*b].key.cmp(&arena[*a].key));
+        children
+    }
+
+    /// Keys of a branch from `center`, by sphere. Each node's children are padded, so nodes
+    /// line up when comparing spheres.
+    fn branch(&self, center: usize, sub: Option<usize>) -> Vec<Vec<(u32, u32)>> {
+        let mut arena = vec![CipNode {
+            atom: Some(center),
+            key: self.key[center],
+            parent: None,
+            terminal: false,
+        }];
+
+        let root_key = match sub {
+            Some(s) => self.key[s],
+            None => KEY_H,
+        };
+        arena.push(CipNode {
+            atom: sub,
+            key: root_key,
+            parent: Some(0),
+            terminal: sub.is_none(),
+        });
+
+        let mut result = vec![vec![root_key]];
+        let mut frontier = vec![1];
+
+        for _ in 0..MAX_CIP_SPHERES {
+            let mut sphere = Vec::new();
+            let mut next = Vec::new();
+
+            for &node in &frontier {
+                let children = self.expand(&mut arena, node);
+                sphere.extend(children.iter().map(|&c| arena[c].key));
+                // Up to 6 neighbors, e.g. hypervalent sulfur, less the parent.
+                sphere.extend((children.len()..5).map(|_| (0, 0)));
+                next.extend(children);
+            }
+
+            if next.is_empty() {
+                break;
+            }
+            result.push(sphere);
+            frontier = next;
+
+            if arena.len() > MAX_CIP_NODES {
+                break;
+            }
+        }
+
+        result
+    }
+}
+
+/// Compare branches by rule 1a (atomic number) over all spheres, then rule 2 (mass).
+fn compare_branches(a: &[Vec<(u32, u32)>], b: &[Vec<(u32, u32)>]) -> Ordering {
+    let compare_by = |field: fn(&(u32, u32)) -> u32| {
+        for k in 0..a.len().max(b.len()) {
+            let sphere_a = a.get(k).map(|s| s.as_slice()).unwrap_or_default();
+            let sphere_b = b.get(k).map(|s| s.as_slice()).unwrap_or_default();
+
+            for j in 0..sphere_a.len().max(sphere_b.len()) {
+                let key_a = sphere_a.get(j).map(field).unwrap_or(0);
+                let key_b = sphere_b.get(j).map(field).unwrap_or(0);
+                if key_a != key_b {
+                    return key_a.cmp(&key_b);
+                }
+            }
+        }
+        Ordering::Equal
+    };
+
+    compare_by(|k| k.0).then_with(|| compare_by(|k| k.1))
+}
+
+/// Rank substituents of an atom by decreasing priority. Returns `None` if any two tie.
+fn rank(graph: &CipGraph, center: usize, subs: &[Option<usize>]) -> Option<Vec<Option<usize>>> {
+    let mut branches: Vec<_> = subs.iter().map(|&s| (s, graph.branch(center, s))).collect();
+
+    branches.sort_by(|a, b| compare_branches(&b.1, &a.1));
+
+    if branches
+        .windows(2)
+        .any(|w| compare_branches(&w[0].1, &w[1].1) == Ordering::Equal)
+    {
+        return None;
+    }
+
+    Some(branches.into_iter().map(|(s, _)| s).collect())
+}
+
+/// The signed volume of 4 substituents, from normalized directions. `None` is an implicit
+/// hydrogen, opposite the others. Viewed with the last substituent away from the viewer, the
+/// first three run anticlockwise if this is positive.
+fn signed_volume(mol: &MoleculeCommon, center: usize, subs: &[Option<usize>; 4]) -> f64 {
+    let ctr = mol.atom_posits[center];
+    let dir = |s: Option<usize>| {
+        s.map(|j| mol.atom_posits[j] - ctr)
+            .filter(|v| v.magnitude() > 1e-6)
+            .map(|v| v.to_normalized())
+    };
+
+    let known: Vec<Vec3> = subs.iter().filter_map(|&s| dir(s)).collect();
+    let opposite = known.iter().fold(Vec3::new_zero(), |acc, d| acc + *d) * -1.;
+    let implicit = if opposite.magnitude() > 0.1 {
+        opposite.to_normalized()
+    } else {
+        Vec3::new_zero()
+    };
+
+    let d: Vec<Vec3> = subs.iter().map(|&s| dir(s).unwrap_or(implicit)).collect();
+
+    (d[0] - d[3]).dot((d[1] - d[3]).cross(d[2] - d[3]))
+}
+
+/// Neighbors of an atom, and an implicit hydrogen per implicit hydrogen count.
+fn substituents(mol: &MoleculeCommon, graph: &CipGraph, i: usize) -> Vec<Option<usize>> {
+    let mut result: Vec<_> = mol.adjacency_list[i].iter().map(|&j| Some(j)).collect();
+
+    let explicit_h = mol.adjacency_list[i]
+        .iter()
+        .filter(|&&j| mol.atoms[j].element == Hydrogen)
+        .count() as u8;
+    for _ in 0..graph.h[i].saturating_sub(explicit_h) {
+        result.push(None);
+    }
+
+    result
+}
+
+impl MoleculeCommon {
+    /// Find stereocenters and stereogenic double bonds, and label them from current atom
+    /// positions.
+    pub fn perceive_stereo(&self) -> Stereo {
+        let mut mol = self.clone();
+        mol.build_adjacency_list();
+
+        let graph = CipGraph::new(&mol);
+        let mut result = Stereo::default();
+
+        for (i, atom) in mol.atoms.iter().enumerate() {
+            let candidate = match atom.element {
+                Carbon => atom.formal_charge == 0,
+                // Protonated amines invert by exchanging protons.
+                Nitrogen => atom.formal_charge == 1 && graph.h[i] == 0,
+                Phosphorus => true,
+                _ => false,
+            };
+            if !candidate {
+                continue;
+            }
+
+            let subs = substituents(&mol, &graph, i);
+            let all_single =
+                atom.element == Phosphorus || graph.nbrs[i].iter().all(|(_, order)| *order == 1);
+            if subs.len() != 4 || !all_single {
+                continue;
+            }
+
+            let Some(ranked) = rank(&graph, i, &subs) else {
+                continue;
+            };
+            let ranked: [Option<usize>; 4] = ranked.try_into().unwrap();
+
+            let vol = signed_volume(&mol, i, &ranked);
+            let label = if vol.abs() < MIN_CHIRAL_VOL {
+                None
+            } else if vol > 0. {
+                Some(CipLabel::S)
+            } else {
+                Some(CipLabel::R)
+            };
+
+            result.centers.push(StereoCenter {
+                atom: i,
+                ranked,
+                label,
+            });
+        }
+
+        let rings = mol.find_rings();
+
+        for (i_bond, bond) in mol.bonds.iter().enumerate() {
+            let (a, b) = (bond.atom_0, bond.atom_1);
+            let order = graph.nbrs[a].iter().find(|(n, _)| *n == b).map(|(_, o)| *o);
+
+            if order != Some(2)
+                || bond.bond_type == BondType::Aromatic
+                || ![a, b]
+                    .iter()
+                    .all(|&k| matches!(mol.atoms[k].element, Carbon | Nitrogen))
+            {
+                continue;
+            }
+
+            let in_rigid_ring = rings
+                .iter()
+                .any(|r| r.len() <= MAX_RIGID_RING && r.contains(&a) && r.contains(&b));
+            if in_rigid_ring {
+                continue;
+            }
+
+            // The top substituent on each end, other than the other end.
+            let top = |end: usize, other: usize| {
+                let subs: Vec<_> = substituents(&mol, &graph, end)
+                    .into_iter()
+                    .filter(|&s| s != Some(other))
+                    .collect();
+                match subs.len() {
+                    1 => Some(subs[0]),
+                    2 => rank(&graph, end, &subs).map(|r| r[0]),
+                    _ => None,
+                }
+            };
+
+            let (Some(sub_0), Some(sub_1)) = (top(a, b), top(b, a)) else {
+                continue;
+            };
+
+            let label = match (sub_0, sub_1) {
+                (Some(x), Some(y)) => {
+                    let axis = (mol.atom_posits[b] - mol.atom_posits[a]).to_normalized();
+                    let perp = |v: Vec3| v - axis * v.dot(axis);
+                    let vx = perp(mol.atom_posits[x] - mol.atom_posits[a]);
+                    let vy = perp(mol.atom_posits[y] - mol.atom_posits[b]);
+
+                    if vx.magnitude() < 1e-6 || vy.magnitude() < 1e-6 {
+                        None
+                    } else {
+                        let cos = vx.to_normalized().dot(vy.to_normalized());
+                        if cos.abs() < MIN_DOUBLE_BOND_COS {
+                            None
+                        } else if cos > 0. {
+                            Some(CipLabel::Z)
+                        } else {
+                            Some(CipLabel::E)
+                        }
+                    }
+                }
+                _ => None,
+            };
+
+            result.bonds.push(StereoBond {
+                bond: i_bond,
+                atom_0: a,
+                atom_1: b,
+                sub_0,
+                sub_1,
+                label,
+            });
+        }
+
+        result
+    }
+
+    /// The MDL parity of an atom from its geometry: Number neighbors by atom index, with
+    /// hydrogens last. Viewed with the last away from the viewer, 1 if the others run clockwise,
+    /// and 2 if anticlockwise. 0 if not a tetrahedral center, or flat. Requires an up-to-date
+    /// adjacency list.
+    fn parity(&self, i: usize) -> u8 {
+        let mut nbrs = self.adjacency_list[i].clone();
+        nbrs.sort_by_key(|&j| (self.atoms[j].element == Hydrogen, j));
+
+        let subs: [Option<usize>; 4] = match nbrs.as_slice() {
+            [a, b, c] => [Some(*a), Some(*b), Some(*c), None],
+            [a, b, c, d] => [Some(*a), Some(*b), Some(*c), Some(*d)],
+            _ => return 0,
+        };
+
+        let vol = signed_volume(self, i, &subs);
+        if vol.abs() < MIN_CHIRAL_VOL {
+            0
+        } else if vol > 0. {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// MDL parity values for each atom, for writing SDF files. Non-zero for labeled
+    /// stereocenters only.
+    pub fn sdf_parities(&self) -> Vec<u8> {
+        let mut mol = self.clone();
+        mol.build_adjacency_list();
+
+        let mut result = vec![0; self.atoms.len()];
+        for center in mol.perceive_stereo().centers {
+            if center.label.is_some() {
+                result[center.atom] = mol.parity(center.atom);
+            }
+        }
+        result
+    }
+
+    /// For flat (2D) structures, move atoms out of plane to match SDF wedge bonds and parities,
+    /// so stereo can be perceived from coordinates. Structures with 3D coordinates already
+    /// define their stereo, and are left unchanged.
+    pub fn apply_sdf_stereo(&mut self, stereo: &SdfStereo) {
+        let flat = self.atoms.iter().all(|a| a.posit.z.abs() < 0.01);
+        if !flat || self.atoms.is_empty() {
+            return;
+        }
+        self.build_adjacency_list();
+
+        let lift = |mol: &mut Self, i: usize, z: f64| {
+            mol.atoms[i].posit.z = z;
+            mol.atom_posits[i].z = z;
+        };
+
+        let mut wedged = vec![false; self.atoms.len()];
+        for &(from, to, up) in &stereo.wedges {
+            if from >= self.atoms.len() || to >= self.atoms.len() {
+                continue;
+            }
+            lift(self, to, if up { WEDGE_Z } else { -WEDGE_Z });
+            wedged[from] = true;
+        }
+
+        for (i, &parity) in stereo.parities.iter().enumerate() {
+            if i >= self.atoms.len() || wedged[i] || !matches!(parity, 1 | 2) {
+                continue;
+            }
+
+            let Some(&j) = self.adjacency_list[i]
+                .iter()
+                .min_by_key(|&&j| (self.atoms[j].element == Hydrogen, j))
+            else {
+                continue;
+            };
+
+            lift(self, j, WEDGE_Z);
+            if self.parity(i) != parity {
+                lift(self, j, -WEDGE_Z);
+            }
+        }
+    }
+}
+
+/// Stereo fields of a V2000 SDF record, which `bio_files` doesn't retain.
+#[derive(Clone, Debug, Default)]
+pub struct SdfStereo {
+    /// Per atom; 1 or 2 for stereocenters.
+    pub parities: Vec<u8>,
+    /// (Narrow end atom, wide end atom, wedge up) for wedge and hash bonds.
+    pub wedges: Vec<(usize, usize, bool)>,
+}
+
+impl SdfStereo {
+    /// Parse from the text of the first record of an SDF file.
+    pub fn from_text(text: &str) -> Self {
+        let mut result = Self::default();
+
+        let lines: Vec<_> = text.lines().collect();
+        let Some(counts) = lines.get(3) else {
+            return result;
+        };
+        if !counts.contains("V2000") {
+            return result;
+        }
+
+        let field = |line: &str, range: std::ops::Range<usize>| -> Option<usize> {
+            line.get(range)?.trim().parse().ok()
+        };
+        let (Some(num_atoms), Some(num_bonds)) = (field(counts, 0..3), field(counts, 3..6)) else {
+            return result;
+        };
+
+        for line in lines.iter().skip(4).take(num_atoms) {
+            result
+                .parities
+                .push(field(line, 39..42).unwrap_or_default() as u8);
+        }
+
+        for line in lines.iter().skip(4 + num_atoms).take(num_bonds) {
+            let (Some(a), Some(b)) = (field(line, 0..3), field(line, 3..6)) else {
+                continue;
+            };
+            if a == 0 || b == 0 {
+                continue;
+            }
+            match field(line, 9..12) {
+                Some(1) => result.wedges.push((a - 1, b - 1, true)),
+                Some(6) => result.wedges.push((a - 1, b - 1, false)),
+                _ => (),
+            }
+        }
+
+        result
+    }
+}
+
+/// Generate all stereoisomers of a molecule, with hydrogens and 3D coordinates. Centers and
+/// double bonds past the first few keep whatever the embedding gives them. Duplicates, e.g.
+/// meso forms, are removed.
+pub fn stereoisomers(mol: &MoleculeCommon) -> Vec<MoleculeCommon> {
+    let mut base = mol.clone();
+    base.build_adjacency_list();
+
+    // Infer hydrogens for molecules without hydrogen information, e.g. from PDB files.
+    if !base
+        .atoms
+        .iter()
+        .any(|a| a.element == Hydrogen || a.implicit_h.is_some())
+    {
+        let implicit_h = PerceivedMol::new(&base).implicit_h;
+        for (atom, h) in base.atoms.iter_mut().zip(implicit_h) {
+            atom.implicit_h = Some(h);
+        }
+    }
+    add_hydrogens(&mut base);
+
+    let stereo = base.perceive_stereo();
+
+    let centers: Vec<_> = stereo
+        .centers
+        .iter()
+        .filter_map(|center| {
+            let [a, b, c, d] = center.ranked;
+            Some([d?, a?, b?, c?])
+        })
+        .collect();
+    let bonds: Vec<_> = stereo
+        .bonds
+        .iter()
+        .filter_map(|b| Some((b.sub_0?, b.atom_0, b.atom_1, b.sub_1?)))
+        .collect();
+
+    let num_centers = centers.len().min(MAX_STEREO_ELEMENTS);
+    let num_bonds = bonds.len().min(MAX_STEREO_ELEMENTS - num_centers);
+    let num_elements = num_centers + num_bonds;
+    if num_elements == 0 {
+        return Vec::new();
+    }
+
+    let isomers: Vec<_> = (0..1_usize << num_elements)
+        .into_par_iter()
+        .filter_map(|mask| {
+            let mut constraints = StereoConstraints::default();
+
+            // Viewed from the lowest priority substituent, the others run anticlockwise for R.
+            for (k, ids) in centers.iter().take(num_centers).enumerate() {
+                constraints.chiral.push((*ids, mask & (1 << k) != 0));
+            }
+            for (k, &(x, a, b, y)) in bonds.iter().take(num_bonds).enumerate() {
+                let cis = mask & (1 << (num_centers + k)) != 0;
+                constraints.double_bonds.push((x, a, b, y, cis));
+            }
+
+            let mut isomer = base.clone();
+            embed_3d(&mut isomer, &constraints, 0).ok()?;
+            Some(isomer)
+        })
+        .collect();
+
+    let mut seen = HashSet::new();
+    isomers
+        .into_iter()
+        .filter(|m| seen.insert(m.to_smiles()))
+        .collect()
+}
+
+impl State {
+    /// Add all stereoisomers of a ligand to the scene, relaxed with GAFF2. Returns a status
+    /// message.
+    pub fn add_stereoisomers(
+        &mut self,
+        lig_i: usize,
+        scene: &mut Scene,
+        engine_updates: &mut EngineUpdates,
+    ) -> io::Result<String> {
+        let Some(lig) = self.ligands.get(lig_i) else {
+            return Err(io::Error::new(ErrorKind::NotFound, "No ligand"));
+        };
+        let ident = lig.common.ident.clone();
+
+        let isomers = stereoisomers(&lig.common);
+        if isomers.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{ident} has no stereocenters or stereogenic double bonds"),
+            ));
+        }
+
+        let num = isomers.len();
+        let mut num_unrelaxed = 0;
+
+        for isomer in isomers {
+            let labels = isomer.perceive_stereo().summary();
+            let mut mol = MoleculeSmall::new(
+                format!("{ident} ({labels})"),
+                isomer.atoms,
+                isomer.bonds,
+                HashMap::new(),
+                None,
+            );
+
+            if minimize_gaff2(
+                &mut mol,
+                &self.dev,
+                &self.ff_param_set,
+                &mut self.mol_specific_params,
+            )
+            .is_err()
+            {
+                num_unrelaxed += 1;
+            }
+
+            self.load_mol_to_state(
+                MoleculeGeneric::Ligand(mol),
+                Some(scene),
+                engine_updates,
+                None,
+            );
+        }
+
+        let mut msg = format!("Added {num} stereoisomers of {ident}");
+        if num_unrelaxed > 0 {
+            msg += &format!(". Unable to relax {num_unrelaxed} with GAFF2");
+        }
+        Ok(msg)
+    }
+}
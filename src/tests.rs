@@ -1,4 +1,1598 @@
 use super::*;
+use bio_files::{BondType, md_params::ForceFieldParams};
+use graphics::{EngineUpdates, Mesh, Vertex};
+use lin_alg::f64::{Quaternion, Vec3};
+use na_seq::Element;
+
+use crate::{
+    align::find_mcs,
+    docking::{
+        DockingSite,
+        external::{load_sdf_poses, poses_from_pdbqt},
+        find_sites::find_docking_sites,
+        score::{DockType, ScoreGrid, ScoreReceptor, dock_types},
+        screen::{ScreenJob, ScreenPaths, failure_record, load_progress, progress_header},
+        search::{Nonbonded, Receptor, search_poses},
+    },
+    embed::mol_from_smiles,
+    file_io::{
+        amber::{Prmtop, find_coord_file, load_inpcrd},
+        gromacs::{GmxTop, Gro},
+        mesh_export::ExportMesh,
+        pdb::Pdb,
+        session::{SESSION_MAGIC, SESSION_VERSION, Session},
+        topology::{SystemTopology, TopAngle, TopAtom, TopBond, TopDihedral, TopLj, TopResidue},
+        trajectory::{TrajFormat, TrajFrame, read_traj, write_traj},
+    },
+    fingerprint::FpKind,
+    mol_characterization::{Descriptors, PerceivedMol},
+    mol_library::{MolLibrary, sdf_record},
+    mol_lig::MoleculeSmall,
+    molecule::{Atom, Bond, MoleculeCommon, MoleculePeptide, init_bonds_chains_res},
+    protonation::{protomers, tautomers},
+    selfies::{selfies_to_smiles, smiles_to_selfies},
+    smarts::parse_smarts,
+    smiles::{SmilesChirality, parse_smiles},
+    stereo::{CipLabel, SdfStereo, stereoisomers},
+};
 
 #[test]
 fn test_basic_forces() {}
+
+/// Drug-like molecules, covering fused aromatics, heteroaromatics, charges, hypervalent sulfur,
+/// and tetrahedral and double bond stereo.
+const SELFIES_CORPUS: [&str; 18] = [
+    "CC(=O)Oc1ccccc1C(=O)O",
+    "Cn1cnc2c1c(=O)n(C)c(=O)n2C",
+    "CC(C)Cc1ccc(cc1)[C@@H](C)C(=O)O",
+    "CC(=O)Nc1ccc(O)cc1",
+    "CN1CCC[C@H]1c1cccnc1",
+    "CN(C)C(=N)N=C(N)N",
+    "CN1C(=O)CN=C(c2ccccc2)c2cc(Cl)ccc12",
+    "CC[C@H](C)[C@H](NC(=O)[C@@H](N)Cc1c[nH]c2ccccc12)C(=O)O",
+    "Cc1ccc(cc1)[N+](=O)[O-]",
+    "C/C=C/C(=O)O",
+    "OC[C@H]1O[C@@H](O)[C@H](O)[C@@H](O)[C@@H]1O",
+    "c1ccc2cc3ccccc3cc2c1",
+    "NS(=O)(=O)c1ccc(cc1)C(=O)O",
+    "CC(C)(C)NCC(O)c1ccc(O)c(CO)c1",
+    "[NH3+][C@@H](Cc1ccccc1)C(=O)[O-]",
+    "C1CC2(CC1)OCCO2",
+    "OC(=O)COCCN1CCN(CC1)C(c1ccccc1)c1ccc(Cl)cc1",
+    "Fc1ccc(cc1)-c1ccc2[nH]ccc2c1",
+];
+
+/// Element symbols, hydrogen count, net charge, and number of bonds.
+fn composition(smiles: &str) -> (Vec<String>, u32, i32, usize) {
+    let mol = MoleculeCommon::from_smiles(smiles).unwrap();
+
+    let mut elements: Vec<String> = mol.atoms.iter().map(|a| a.element.to_letter()).collect();
+    elements.sort();
+    let h = mol
+        .atoms
+        .iter()
+        .map(|a| a.implicit_h.unwrap_or(0) as u32)
+        .sum();
+    let charge = mol.atoms.iter().map(|a| a.formal_charge as i32).sum();
+
+    (elements, h, charge, mol.bonds.len())
+}
+
+#[test]
+fn test_smiles_parse() {
+    // Bracket atoms: Isotopes, hydrogen counts, charges, and atom classes.
+    let mol = MoleculeCommon::from_smiles("[13CH3:1]C(=O)[O-].[Na+]").unwrap();
+    assert_eq!(mol.atoms.len(), 5);
+    assert_eq!(mol.bonds.len(), 3);
+    let props: Vec<_> = mol
+        .atoms
+        .iter()
+        .map(|a| (a.isotope, a.implicit_h, a.formal_charge))
+        .collect();
+    assert_eq!(
+        props,
+        [
+            (Some(13), Some(3), 0),
+            (None, Some(0), 0),
+            (None, Some(0), 0),
+            (None, Some(0), -1),
+            (None, Some(0), 1),
+        ]
+    );
+    assert_eq!(mol.atoms[4].element, Element::from_letter("Na").unwrap());
+
+    // Multi-digit charges, and the deprecated repeated sign form.
+    for (smiles, charge) in [("[Fe+2]", 2), ("[Fe++]", 2), ("[O-2]", -2), ("[O--]", -2)] {
+        let mol = MoleculeCommon::from_smiles(smiles).unwrap();
+        assert_eq!(mol.atoms[0].formal_charge, charge, "{smiles}");
+    }
+
+    // Organic subset atoms get hydrogens to their lowest standard valence; charged N needs brackets.
+    let mol = MoleculeCommon::from_smiles("C[N+](C)(C)C").unwrap();
+    assert_eq!(mol.atoms[1].formal_charge, 1);
+    assert_eq!(mol.atoms[1].implicit_h, Some(0));
+    assert!(mol.atoms.iter().step_by(2).all(|a| a.implicit_h == Some(3)));
+
+    // `%nn` ring bonds are the same as single digits, and may be reused after closing.
+    let ring = |smiles: &str| MoleculeCommon::from_smiles(smiles).unwrap().to_smiles();
+    assert_eq!(ring("C%12CCCCC%12"), ring("C1CCCCC1"));
+    assert_eq!(ring("C%10CC%10C%10CC%10"), ring("C1CC1C1CC1"));
+    let mol = MoleculeCommon::from_smiles("C%99CC%99").unwrap();
+    assert_eq!((mol.atoms.len(), mol.bonds.len()), (3, 3));
+
+    // Bond symbols may be written at either end of a ring bond.
+    let mol = MoleculeCommon::from_smiles("C=1CCCCC1").unwrap();
+    let mol_b = MoleculeCommon::from_smiles("C1CCCCC=1").unwrap();
+    for m in [&mol, &mol_b] {
+        let doubles = m.bonds.iter().filter(|b| b.bond_type == BondType::Double);
+        assert_eq!(doubles.count(), 1);
+    }
+
+    // Chirality refers to neighbors in written order, including the bracket hydrogen.
+    let graph = parse_smiles("N[C@@H](C)C(=O)O alanine").unwrap();
+    assert_eq!(graph.chirality[1], Some(SmilesChirality::Clockwise));
+    assert_eq!(graph.neighbor_order[1], [Some(0), None, Some(2), Some(3)]);
+    assert_eq!(graph.name.as_deref(), Some("alanine"));
+
+    for invalid in [
+        "C1CC", "C(C", "C)C", "[C", "C%1C", "C==C", "[C@TH3H]", "C1C1",
+    ] {
+        assert!(MoleculeCommon::from_smiles(invalid).is_err(), "{invalid}");
+    }
+}
+
+/// The atoms of `mol` at `order`, in that order, with bonds between them.
+fn reordered(mol: &MoleculeCommon, order: &[usize]) -> MoleculeCommon {
+    let mut new_i = vec![None; mol.atoms.len()];
+    for (i, &orig) in order.iter().enumerate() {
+        new_i[orig] = Some(i);
+    }
+
+    let atoms: Vec<_> = order
+        .iter()
+        .enumerate()
+        .map(|(i, &orig)| Atom {
+            serial_number: i as u32 + 1,
+            ..mol.atoms[orig].clone()
+        })
+        .collect();
+
+    let bonds = mol
+        .bonds
+        .iter()
+        .filter_map(|b| {
+            let (a0, a1) = (new_i[b.atom_0]?, new_i[b.atom_1]?);
+            Some(Bond {
+                atom_0: a0,
+                atom_1: a1,
+                atom_0_sn: a0 as u32 + 1,
+                atom_1_sn: a1 as u32 + 1,
+                ..b.clone()
+            })
+        })
+        .collect();
+
+    let mut result = MoleculeCommon {
+        atoms,
+        bonds,
+        atom_posits: order.iter().map(|&i| mol.atom_posits[i]).collect(),
+        ..mol.clone()
+    };
+    result.build_adjacency_list();
+    result
+}
+
+#[test]
+fn test_smiles_canonical() {
+    // Atom order doesn't affect the output, including stereo from 3D positions.
+    for smiles in SELFIES_CORPUS {
+        let mol = mol_from_smiles(smiles).unwrap();
+        let expected = mol.to_smiles();
+        let n = mol.atoms.len();
+
+        let reversed: Vec<_> = (0..n).rev().collect();
+        let rotated: Vec<_> = (0..n).map(|i| (i + 7) % n).collect();
+        let interleaved: Vec<_> = (0..n).step_by(2).chain((1..n).step_by(2)).collect();
+
+        for order in [reversed, rotated, interleaved] {
+            assert_eq!(reordered(&mol, &order).to_smiles(), expected, "{smiles}");
+        }
+    }
+
+    // With only polar hydrogens, e.g. from some PDB files, we don't write radicals.
+    let smiles = "CC(=O)NCCO";
+    let mol = mol_from_smiles(smiles).unwrap();
+    let polar_h: Vec<_> = (0..mol.atoms.len())
+        .filter(|&i| {
+            mol.atoms[i].element != Element::Hydrogen
+                || mol.adjacency_list[i]
+                    .iter()
+                    .all(|&j| mol.atoms[j].element != Element::Carbon)
+        })
+        .collect();
+    let polar_only = reordered(&mol, &polar_h);
+    assert!(polar_only.atoms.len() < mol.atoms.len());
+    assert!(
+        polar_only
+            .atoms
+            .iter()
+            .any(|a| a.element == Element::Hydrogen)
+    );
+    assert_eq!(polar_only.to_smiles(), mol.to_smiles());
+
+    // Without hydrogen information, charged atoms get hydrogens for their charged valence.
+    let smiles = "[NH3+]CC(=O)[O-]";
+    let mut heavy = MoleculeCommon::from_smiles(smiles).unwrap();
+    for atom in &mut heavy.atoms {
+        atom.implicit_h = None;
+    }
+    assert_eq!(
+        heavy.to_smiles(),
+        MoleculeCommon::from_smiles(smiles).unwrap().to_smiles()
+    );
+}
+
+#[test]
+fn test_selfies_round_trip() {
+    for smiles in SELFIES_CORPUS {
+        let selfies = smiles_to_selfies(smiles).unwrap();
+        let decoded = selfies_to_smiles(&selfies).unwrap();
+
+        assert_eq!(
+            composition(smiles),
+            composition(&decoded),
+            "{smiles} -> {selfies} -> {decoded}"
+        );
+
+        // Decoded SMILES are in Kekulé form; aromaticity is perceived when writing.
+        assert_eq!(
+            MoleculeCommon::from_smiles(smiles).unwrap().to_smiles(),
+            MoleculeCommon::from_smiles(&decoded).unwrap().to_smiles(),
+            "{smiles} -> {selfies} -> {decoded}"
+        );
+    }
+}
+
+#[test]
+fn test_selfies_known() {
+    let cases = [
+        ("c1ccccc1", "[C][=C][C][=C][C][=C][Ring1][=Branch1]"),
+        (
+            "N[C@@H](C)C(=O)O",
+            "[N][C@@H1][Branch1][C][C][C][=Branch1][C][=O][O]",
+        ),
+        ("C/C=C/C", "[C][/C][=C][/C]"),
+    ];
+
+    for (smiles, selfies) in cases {
+        assert_eq!(smiles_to_selfies(smiles).unwrap(), selfies);
+    }
+
+    assert_eq!(
+        selfies_to_smiles("[C][=C][C][=C][C][=C][Ring1][=Branch1]").unwrap(),
+        "C1=CC=CC=C1"
+    );
+    assert_eq!(
+        selfies_to_smiles("[N][C@@H1][Branch1][C][C][C][=Branch1][C][=O][O]").unwrap(),
+        "N[C@@H](C)C(=O)O"
+    );
+    assert_eq!(selfies_to_smiles("[C][/C][=C][/C]").unwrap(), "C/C=C/C");
+}
+
+#[test]
+fn test_selfies_robust() {
+    // Symbols exceeding bonding capacity are demoted or ignored, rather than producing an
+    // invalid molecule.
+    assert_eq!(selfies_to_smiles("[C][O][C][F][C]").unwrap(), "COCF");
+    assert_eq!(selfies_to_smiles("[O][=C][=O][C]").unwrap(), "O=C=O");
+    assert_eq!(selfies_to_smiles("[F][=C][=C][#C]").unwrap(), "FC=C=C");
+
+    assert!(selfies_to_smiles("[C]x[C]").is_err());
+    assert!(selfies_to_smiles("[C][Xq]").is_err());
+}
+
+#[test]
+fn test_rings_aromaticity() {
+    // (SMILES, number of rings, number of aromatic rings)
+    let cases = [
+        ("C1=CC=CC=C1", 1, 1),
+        ("c1ccncc1", 1, 1),
+        ("c1ccoc1", 1, 1),
+        ("c1ccc2ccccc2c1", 2, 2),
+        ("c1ccc2[nH]ccc2c1", 2, 2),
+        ("Cn1cnc2c1c(=O)n(C)c(=O)n2C", 2, 2),
+        // Azulene is only aromatic as a whole.
+        ("c1ccc2cccc2cc1", 2, 2),
+        ("C1=CCC=C1", 1, 0),
+        ("O=C1C=CC(=O)C=C1", 1, 0),
+        ("C1CCC2(CC1)CCCC2", 2, 0),
+        // Cubane
+        ("C12C3C4C1C5C2C3C45", 5, 0),
+    ];
+
+    for (smiles, num_rings, num_aromatic) in cases {
+        let mut mol = MoleculeCommon::from_smiles(smiles).unwrap();
+        assert_eq!(mol.find_rings().len(), num_rings, "{smiles}");
+        assert_eq!(
+            mol.perceive_aromaticity().unwrap(),
+            num_aromatic,
+            "{smiles}"
+        );
+    }
+
+    // Kekulé and aromatic forms write the same SMILES.
+    assert_eq!(
+        MoleculeCommon::from_smiles("C1=CC=C2C=CC=CC2=C1")
+            .unwrap()
+            .to_smiles(),
+        MoleculeCommon::from_smiles("c1ccc2ccccc2c1")
+            .unwrap()
+            .to_smiles()
+    );
+}
+
+#[test]
+fn test_descriptors() {
+    let aspirin = Descriptors::new(&MoleculeCommon::from_smiles("CC(=O)Oc1ccccc1C(=O)O").unwrap());
+
+    assert_eq!(aspirin.formula, "C9H8O4");
+    assert!((aspirin.mol_weight - 180.16).abs() < 0.01);
+    assert!((aspirin.exact_mass - 180.0423).abs() < 0.001);
+    assert!((aspirin.log_p - 1.3101).abs() < 0.001);
+    assert!((aspirin.tpsa - 63.6).abs() < 0.01);
+    assert_eq!(aspirin.h_donors, 1);
+    assert_eq!(aspirin.h_acceptors, 4);
+    assert_eq!(aspirin.rotatable_bonds, 3);
+    assert_eq!(aspirin.heavy_atoms, 13);
+    assert!((aspirin.fraction_sp3 - 1. / 9.).abs() < 1e-6);
+    assert!(aspirin.lipinski() && aspirin.veber() && !aspirin.lead_like());
+
+    let caffeine =
+        Descriptors::new(&MoleculeCommon::from_smiles("Cn1cnc2c1c(=O)n(C)c(=O)n2C").unwrap());
+
+    assert_eq!(caffeine.formula, "C8H10N4O2");
+    assert!((caffeine.log_p - -1.0293).abs() < 0.001);
+    assert!((caffeine.tpsa - 61.82).abs() < 0.01);
+    assert_eq!(caffeine.h_donors, 0);
+    assert_eq!(caffeine.rotatable_bonds, 0);
+
+    let glycine = Descriptors::new(&MoleculeCommon::from_smiles("[NH3+]CC(=O)[O-]").unwrap());
+    assert_eq!(glycine.formula, "C2H5NO2");
+    assert_eq!(glycine.formal_charge, 0);
+}
+
+#[test]
+fn test_fingerprints() {
+    let fp = |smiles: &str, kind| {
+        MoleculeCommon::from_smiles(smiles)
+            .unwrap()
+            .fingerprint(kind)
+    };
+
+    for kind in [FpKind::Morgan, FpKind::Maccs] {
+        let aspirin = fp("CC(=O)Oc1ccccc1C(=O)O", kind);
+        let salicylic = fp("OC(=O)c1ccccc1O", kind);
+        let caffeine = fp("Cn1cnc2c1c(=O)n(C)c(=O)n2C", kind);
+
+        assert_eq!(aspirin.tanimoto(&aspirin), 1.);
+        assert!(aspirin.tanimoto(&salicylic) > aspirin.tanimoto(&caffeine));
+        assert_eq!(aspirin.tanimoto(&salicylic), salicylic.tanimoto(&aspirin));
+
+        // Kekulé and aromatic forms are the same molecule.
+        assert_eq!(fp("C1=CC=C2C=CC=CC2=C1", kind), fp("c1ccc2ccccc2c1", kind));
+    }
+}
+
+#[test]
+fn test_smarts() {
+    let num_matches = |smiles: &str, smarts: &str| {
+        let pattern = parse_smarts(smarts).unwrap();
+        MoleculeCommon::from_smiles(smiles)
+            .unwrap()
+            .substruct_matches(&pattern)
+            .len()
+    };
+
+    let aspirin = "CC(=O)Oc1ccccc1C(=O)O";
+    // (SMILES, SMARTS, number of unique matches)
+    let cases = [
+        (aspirin, "*", 13),
+        (aspirin, "a", 6),
+        (aspirin, "c1ccccc1", 1),
+        (aspirin, "C(=O)[OH]", 1),
+        (aspirin, "[CX3](=O)[OX2H0]", 1),
+        (aspirin, "[!#6]", 4),
+        (aspirin, "[C;$(C(=O)O)]", 2),
+        (aspirin, "C=C", 0),
+        ("c1ccccc1", "c:c", 6),
+        ("c1ccccc1", "[cv4H1]", 6),
+        ("NS(=O)(=O)c1ccc(cc1)C(=O)O", "S(=O)(=O)[NH2]", 1),
+        ("Cn1cnc2c1c(=O)n(C)c(=O)n2C", "[#7]", 4),
+        ("Fc1ccc(cc1)-c1ccc2[nH]ccc2c1", "[r5]", 5),
+        ("Fc1ccc(cc1)-c1ccc2[nH]ccc2c1", "[nH]", 1),
+        ("Fc1ccc(cc1)-c1ccc2[nH]ccc2c1", "c!@c", 1),
+        ("c1ccc2ccccc2c1", "[R2]", 2),
+        ("CCC1CCCCC1", "[CH3]!@C", 1),
+        ("CCC1CCCCC1", "[CH2;R]@[CH2;R]", 4),
+        ("[NH3+][C@@H](Cc1ccccc1)C(=O)[O-]", "[NH3+].[O-]", 1),
+        ("CCO.CN", "[OH,NH2]", 2),
+    ];
+
+    for (smiles, smarts, expected) in cases {
+        assert_eq!(num_matches(smiles, smarts), expected, "{smiles} {smarts}");
+    }
+
+    for invalid in ["", "C(", "C1CC", "[C", "C=", "C)", "[C;]"] {
+        assert!(parse_smarts(invalid).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn test_mcs_align() {
+    let perceived = |smiles: &str| PerceivedMol::new(&MoleculeCommon::from_smiles(smiles).unwrap());
+
+    let aspirin = perceived("CC(=O)Oc1ccccc1C(=O)O");
+    let salicylic = perceived("OC(=O)c1ccccc1O");
+    let caffeine = perceived("Cn1cnc2c1c(=O)n(C)c(=O)n2C");
+
+    // Salicylic acid is a substructure of aspirin.
+    let mcs = find_mcs(&salicylic, &aspirin);
+    assert_eq!(mcs.len(), 10);
+    for (i, j) in mcs {
+        assert_eq!(salicylic.atoms[i].element, aspirin.atoms[j].element);
+    }
+    assert_eq!(find_mcs(&aspirin, &salicylic).len(), 10);
+    assert!(find_mcs(&aspirin, &caffeine).len() < 10);
+
+    // A rotated, and translated copy superposes back onto the original.
+    let mut reference = MoleculeSmall {
+        common: MoleculeCommon::from_smiles("CC(=O)Nc1ccc(O)cc1").unwrap(),
+        ..Default::default()
+    };
+    reference.common.atom_posits = (0..reference.common.atoms.len())
+        .map(|i| {
+            let i = i as f64;
+            Vec3::new(i.sin() * 3., (i * 0.7).cos() * 2., i * 0.4)
+        })
+        .collect();
+
+    let mut mobile = reference.clone();
+    let rot = Quaternion::new(0.3, 0.5, -0.2, 0.7).to_normalized();
+    mobile.common.rotate(rot, None);
+    mobile.common.move_to(Vec3::new(5., -2., 10.));
+
+    let alignment = mobile.align_to(&reference).unwrap();
+    assert_eq!(alignment.atom_map.len(), 11);
+    assert!(alignment.rmsd < 1e-6);
+    for (a, b) in mobile
+        .common
+        .atom_posits
+        .iter()
+        .zip(&reference.common.atom_posits)
+    {
+        assert!((*a - *b).magnitude() < 1e-6);
+    }
+}
+
+#[test]
+fn test_scaffolds() {
+    let smiles = |s: &str| MoleculeCommon::from_smiles(s).unwrap().to_smiles();
+    let scaffold = |s: &str| {
+        MoleculeCommon::from_smiles(s)
+            .unwrap()
+            .murcko_scaffold()
+            .map(|m| m.to_smiles())
+    };
+    let framework = |s: &str| {
+        MoleculeCommon::from_smiles(s)
+            .unwrap()
+            .generic_framework()
+            .map(|m| m.to_smiles())
+    };
+
+    let benzene = Some(smiles("c1ccccc1"));
+    assert_eq!(scaffold("CC(=O)Oc1ccccc1C(=O)O"), benzene);
+    assert_eq!(scaffold("CC(=O)Nc1ccc(O)cc1"), benzene);
+    assert_eq!(scaffold("CC(=O)c1ccccc1"), benzene);
+    assert_eq!(scaffold("CCCCCC"), None);
+
+    // Exocyclic double bonds on rings are kept; ring NH hydrogens are preserved.
+    assert_eq!(scaffold("O=C1CCCCC1"), Some(smiles("O=C1CCCCC1")));
+    assert_eq!(
+        scaffold("Cc1c[nH]c2ccccc12"),
+        Some(smiles("c1c[nH]c2ccccc12"))
+    );
+    // Linkers between rings are kept.
+    assert_eq!(
+        scaffold("CN1C(=O)CN=C(c2ccccc2)c2cc(Cl)ccc12"),
+        Some(smiles("O=C1CN=C(c2ccccc2)c2ccccc2N1"))
+    );
+    assert_eq!(
+        scaffold("OC(=O)COCCN1CCN(CC1)C(c1ccccc1)c1ccc(Cl)cc1"),
+        Some(smiles("C1CN(CCN1)C(c1ccccc1)c1ccccc1"))
+    );
+
+    let cyclohexane = Some(smiles("C1CCCCC1"));
+    assert_eq!(framework("c1ccncc1"), cyclohexane);
+    assert_eq!(framework("O=C1CCCCC1"), Some(smiles("CC1CCCCC1")));
+}
+
+#[test]
+fn test_protonation() {
+    let smiles = |s: &str| MoleculeCommon::from_smiles(s).unwrap().to_smiles();
+    let dominant = |s: &str, ph: f32| {
+        let (_, species) = protomers(&MoleculeCommon::from_smiles(s).unwrap(), ph);
+        species[0].smiles.clone()
+    };
+
+    assert_eq!(dominant("CC(=O)O", 7.4), smiles("CC(=O)[O-]"));
+    assert_eq!(dominant("CC(=O)O", 2.), smiles("CC(=O)O"));
+    assert_eq!(dominant("CC(=O)[O-]", 2.), smiles("CC(=O)O"));
+    assert_eq!(dominant("CN", 7.4), smiles("C[NH3+]"));
+    assert_eq!(dominant("NCC(=O)O", 7.4), smiles("[NH3+]CC(=O)[O-]"));
+    assert_eq!(dominant("Oc1ccccc1", 7.4), smiles("Oc1ccccc1"));
+    assert_eq!(dominant("Oc1ccccc1", 12.), smiles("[O-]c1ccccc1"));
+    assert_eq!(dominant("c1ccncc1", 3.), smiles("c1cc[nH+]cc1"));
+    assert_eq!(dominant("c1ccncc1", 7.4), smiles("c1ccncc1"));
+
+    let (sites, species) = protomers(&MoleculeCommon::from_smiles("NCC(=O)O").unwrap(), 7.4);
+    assert_eq!(sites.len(), 2);
+    assert_eq!(species[0].net_charge, 0);
+
+    assert_eq!(
+        tautomers(&MoleculeCommon::from_smiles("Cc1cnc[nH]1").unwrap()).len(),
+        2
+    );
+
+    // 2-hydroxypyridine, and 2-pyridone.
+    let pyridone = tautomers(&MoleculeCommon::from_smiles("Oc1ccccn1").unwrap())
+        .iter()
+        .any(|t| {
+            t.mol.bonds.iter().any(|b| {
+                b.bond_type == BondType::Double
+                    && [b.atom_0, b.atom_1]
+                        .iter()
+                        .any(|&i| t.mol.atoms[i].element == Element::Oxygen)
+            })
+        });
+    assert!(pyridone);
+}
+
+#[test]
+fn test_embed() {
+    for smiles in SELFIES_CORPUS {
+        let mol = mol_from_smiles(smiles).unwrap();
+        let posits = &mol.atom_posits;
+        assert!(
+            posits
+                .iter()
+                .all(|p| p.x.is_finite() && p.y.is_finite() && p.z.is_finite())
+        );
+
+        // Bond lengths: About 1.1 Å to hydrogen, and 1.2 to 1.9 Å between heavy atoms.
+        for b in &mol.bonds {
+            let len = (posits[b.atom_0] - posits[b.atom_1]).magnitude();
+            let has_h = [b.atom_0, b.atom_1]
+                .iter()
+                .any(|&i| mol.atoms[i].element == Element::Hydrogen);
+            let range = if has_h { 0.9..1.2 } else { 1.15..1.9 };
+            assert!(range.contains(&len), "{smiles}: bond length {len}");
+        }
+
+        // No clashes between atoms that aren't bonded.
+        for i in 0..posits.len() {
+            for j in i + 1..posits.len() {
+                if !mol.adjacency_list[i].contains(&j) {
+                    let dist = (posits[i] - posits[j]).magnitude();
+                    assert!(dist > 1.4, "{smiles}: atoms {i} and {j} are {dist} Å apart");
+                }
+            }
+        }
+    }
+
+    // Aromatic rings are planar.
+    let benzene = mol_from_smiles("c1ccccc1").unwrap();
+    let p = &benzene.atom_posits;
+    for i in 0..6 {
+        let [a, b, c, d] = [0, 1, 2, 3].map(|k| p[(i + k) % 6]);
+        let vol = (b - a).dot((c - a).cross(d - a));
+        assert!(vol.abs() < 0.1, "Benzene volume: {vol}");
+    }
+
+    // Enantiomers are embedded with the specified configuration.
+    let center_label = |s: &str| mol_from_smiles(s).unwrap().perceive_stereo().centers[0].label;
+    assert_eq!(center_label("C[C@H](N)C(=O)O"), Some(CipLabel::S));
+    assert_eq!(center_label("C[C@@H](N)C(=O)O"), Some(CipLabel::R));
+    assert_eq!(center_label("N[C@@H](C)C(=O)O"), Some(CipLabel::S));
+}
+
+#[test]
+fn test_stereo() {
+    // L-alanine.
+    let ala = mol_from_smiles("C[C@H](N)C(=O)O").unwrap();
+    let stereo = ala.perceive_stereo();
+    assert_eq!(stereo.centers.len(), 1);
+    assert_eq!(stereo.centers[0].label, Some(CipLabel::S));
+
+    let bond_label = |s: &str| mol_from_smiles(s).unwrap().perceive_stereo().bonds[0].label;
+    assert_eq!(bond_label("C/C=C/C"), Some(CipLabel::E));
+    assert_eq!(bond_label("C/C=C\\C"), Some(CipLabel::Z));
+
+    // Parities survive flattening to 2D, as when reading an SDF file without 3D coordinates.
+    let parities = ala.sdf_parities();
+    let mut flat = ala.clone();
+    for (atom, posit) in flat.atoms.iter_mut().zip(flat.atom_posits.iter_mut()) {
+        atom.posit.z = 0.;
+        posit.z = 0.;
+    }
+    flat.apply_sdf_stereo(&SdfStereo {
+        parities,
+        wedges: Vec::new(),
+    });
+    assert_eq!(flat.perceive_stereo().centers[0].label, Some(CipLabel::S));
+
+    let isomers = stereoisomers(&MoleculeCommon::from_smiles("CC(N)C(=O)O").unwrap());
+    assert_eq!(isomers.len(), 2);
+    let mut labels: Vec<_> = isomers
+        .iter()
+        .map(|m| m.perceive_stereo().centers[0].label)
+        .collect();
+    labels.sort_by_key(|l| l.map(|l| l.to_str()));
+    assert_eq!(labels, vec![Some(CipLabel::R), Some(CipLabel::S)]);
+}
+
+/// A closed shell of carbon atoms, with an empty cavity inside.
+fn cavity_shell(radius: f64) -> Vec<Atom> {
+    let n = 400;
+    (0..n)
+        .map(|i| {
+            let y = 1. - 2. * (i as f64 + 0.5) / n as f64;
+            let r = (1. - y * y).sqrt();
+            let theta = i as f64 * std::f64::consts::PI * (3. - 5_f64.sqrt());
+
+            Atom {
+                serial_number: i + 1,
+                posit: Vec3::new(r * theta.cos(), y, r * theta.sin()) * radius,
+                element: Element::Carbon,
+                residue: Some(0),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_docking_sites() {
+    let atoms = cavity_shell(8.);
+
+    let mol = MoleculePeptide {
+        common: MoleculeCommon::new(
+            "shell".to_owned(),
+            atoms,
+            Vec::new(),
+            Default::default(),
+            None,
+        ),
+        ..Default::default()
+    };
+
+    let sites = find_docking_sites(&mol);
+    assert_eq!(sites.len(), 1);
+
+    let site = &sites[0];
+    assert!(site.site.site_center.magnitude() < 1.);
+    assert!(site.site.site_radius > 4. && site.site.site_radius < 8.);
+    assert!(site.volume > 300.);
+    assert_eq!(site.residues, vec![0]);
+    assert!(site.score > 0. && site.score <= 1.);
+}
+
+#[test]
+fn test_docking_poses() {
+    let shell = cavity_shell(8.);
+    let rec_posits: Vec<_> = shell.iter().map(|a| a.posit).collect();
+    let rec_params: Vec<_> = shell
+        .iter()
+        .map(|a| Nonbonded::from_element(a.element))
+        .collect();
+
+    let lig = mol_from_smiles("CCCCO").unwrap();
+    let lig_params: Vec<_> = lig
+        .atoms
+        .iter()
+        .map(|a| Nonbonded::from_element(a.element))
+        .collect();
+
+    let site = DockingSite {
+        site_radius: 4.,
+        ..Default::default()
+    };
+    let receptor = Receptor::new(&rec_posits, &rec_params, &site, 4.);
+
+    let poses = search_poses(&lig, &lig_params, &receptor, &site);
+    assert!(!poses.is_empty());
+    assert!(
+        poses
+            .windows(2)
+            .all(|w| w[0].potential_energy <= w[1].potential_energy)
+    );
+
+    let best = &poses[0];
+    assert!(best.potential_energy < 0.);
+    assert_eq!(best.rmsd_to_best, 0.);
+    assert_eq!(best.lig_atom_posits.len(), lig.atoms.len());
+
+    let n = best.lig_atom_posits.len() as f64;
+    let centroid = best
+        .lig_atom_posits
+        .iter()
+        .fold(Vec3::new_zero(), |a, b| a + *b)
+        / n;
+    assert!(centroid.magnitude() < 5.);
+}
+
+#[test]
+fn test_vina_score() {
+    let ethanol = mol_from_smiles("CCO").unwrap();
+    assert_eq!(
+        dock_types(&ethanol)[..3],
+        [
+            Some(DockType::CHydrophobic),
+            Some(DockType::CPolar),
+            Some(DockType::ODonorAcceptor)
+        ]
+    );
+
+    let shell = MoleculeCommon::new(
+        "shell".to_owned(),
+        cavity_shell(8.),
+        Vec::new(),
+        Default::default(),
+        None,
+    );
+    let site = DockingSite {
+        site_radius: 3.,
+        ..Default::default()
+    };
+    let atoms: Vec<_> = (0..shell.atoms.len()).collect();
+    let receptor = ScoreReceptor::new(&shell, &atoms, &site);
+
+    let mut lig = mol_from_smiles("CCCCO").unwrap();
+    let ctr = lig.centroid();
+    for p in &mut lig.atom_posits {
+        *p -= ctr;
+    }
+    let lig_types = dock_types(&lig);
+
+    // Centered in the cavity, the ligand has favorable contacts, and no clashes.
+    let centered = receptor.score(&lig.atom_posits, &lig_types, 2);
+    assert!(centered.score < 0.);
+    assert_eq!(centered.terms.repulsion, 0.);
+    assert!((centered.terms.sum() / 1.117 - centered.score).abs() < 0.01);
+
+    // The grid agrees with direct evaluation.
+    let grid = ScoreGrid::new(
+        &receptor,
+        &site,
+        &lig_types.iter().flatten().copied().collect::<Vec<_>>(),
+    );
+    let from_grid = grid.score(&lig.atom_posits, &lig_types, 2);
+    assert!((from_grid - centered.score).abs() < 0.05 * centered.score.abs() + 0.01);
+
+    // Pushed into the wall, it clashes.
+    let shift = shell.atom_posits[0] - lig.atom_posits[0];
+    let clashing: Vec<_> = lig.atom_posits.iter().map(|p| *p + shift).collect();
+    let clash = receptor.score(&clashing, &lig_types, 2);
+    assert!(clash.terms.repulsion > 0.);
+    assert!(clash.score > centered.score);
+}
+
+#[test]
+fn test_library_smiles_sdf() {
+    let dir = std::env::temp_dir();
+    let path_smi = dir.join("molchanica_test_library.smi");
+    std::fs::write(
+        &path_smi,
+        "# Test library\nCCO ethanol\n\nc1ccccc1O phenol\nC1CC\n[NH3+]CC(=O)[O-] glycine\n",
+    )
+    .unwrap();
+
+    // The unclosed ring fails to parse, and is skipped.
+    let lib = MolLibrary::load(&path_smi).unwrap();
+    assert_eq!(lib.mols.len(), 3);
+    assert_eq!(lib.num_failed, 1);
+    assert_eq!(lib.mols[1].common.ident, "phenol");
+    assert_eq!(lib.mols[1].common.metadata["SMILES"], "c1ccccc1O");
+
+    // Tags survive a round trip through SDF, e.g. as used by screening progress files.
+    let mut mol = lib.mols[0].clone();
+    mol.common
+        .metadata
+        .insert("SCREEN_SCORE".to_owned(), "-5.250".to_owned());
+
+    // Charges and isotopes are written as property lines.
+    let mut charged = lib.mols[2].clone();
+    charged.common.atoms[1].isotope = Some(13);
+    let charges: Vec<_> = charged
+        .common
+        .atoms
+        .iter()
+        .map(|a| a.formal_charge)
+        .collect();
+    assert_eq!(charges.iter().filter(|&&q| q != 0).count(), 2);
+
+    let mut text = String::new();
+    sdf_record(&mol, &mut text).unwrap();
+    sdf_record(&lib.mols[1], &mut text).unwrap();
+    sdf_record(&charged, &mut text).unwrap();
+    assert!(text.contains("M  CHG  2"));
+    assert!(text.contains("M  ISO  1   2  13"));
+
+    let path_sdf = dir.join("molchanica_test_library.sdf");
+    std::fs::write(&path_sdf, text).unwrap();
+
+    let lib_sdf = MolLibrary::load(&path_sdf).unwrap();
+    assert_eq!(lib_sdf.mols.len(), 3);
+    assert_eq!(lib_sdf.mols[0].common.ident, "ethanol");
+    assert_eq!(lib_sdf.mols[0].common.metadata["SCREEN_SCORE"], "-5.250");
+    assert_eq!(
+        lib_sdf.mols[1].common.atoms.len(),
+        mol_from_smiles("c1ccccc1O").unwrap().atoms.len()
+    );
+
+    let atoms_back = &lib_sdf.mols[2].common.atoms;
+    assert_eq!(
+        atoms_back
+            .iter()
+            .map(|a| a.formal_charge)
+            .collect::<Vec<_>>(),
+        charges
+    );
+    assert_eq!(atoms_back[1].isotope, Some(13));
+    assert_eq!(atoms_back[0].isotope, None);
+
+    let _ = std::fs::remove_file(path_smi);
+    let _ = std::fs::remove_file(path_sdf);
+}
+
+#[test]
+fn test_screen_resume() {
+    let shell = cavity_shell(8.);
+    let pep_params: Vec<_> = shell
+        .iter()
+        .map(|a| Nonbonded::from_element(a.element))
+        .collect();
+    let pep = MoleculeCommon::new(
+        "shell".to_owned(),
+        shell,
+        Vec::new(),
+        Default::default(),
+        None,
+    );
+    let site = DockingSite {
+        site_radius: 2.,
+        ..Default::default()
+    };
+    let ph = 7.4;
+
+    let mut lib = MolLibrary {
+        path: std::env::temp_dir().join("molchanica_test_screen.sdf"),
+        mols: ["CCO", "CCN", "CCC"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, smiles)| {
+                let mut common = MoleculeCommon::from_smiles(smiles).unwrap();
+                common.ident = format!("mol_{i}");
+                MoleculeSmall {
+                    common,
+                    ..Default::default()
+                }
+            })
+            .collect(),
+        ..Default::default()
+    };
+    lib.apply_filter();
+
+    let tagged = |i: usize, score: &str| {
+        let mut mol = lib.mols[i].clone();
+        let meta = &mut mol.common.metadata;
+        meta.insert("SCREEN_LIB_INDEX".to_owned(), i.to_string());
+        meta.insert("SCREEN_SCORE".to_owned(), score.to_owned());
+        meta.insert("SCREEN_ENERGY".to_owned(), "-10.000".to_owned());
+        mol
+    };
+
+    // One docked molecule, one failure, and a record cut off by an interrupted write.
+    let header = progress_header(&lib, &pep, &site, ph);
+    let mut text = header.clone();
+    sdf_record(&tagged(0, "-4.500"), &mut text).unwrap();
+    text += &failure_record("mol_1", 1, "Unable to assign force field parameters");
+
+    let mut partial = String::new();
+    sdf_record(&tagged(2, "-6.000"), &mut partial).unwrap();
+    text += &partial[..partial.len() - "$$$$\n".len()];
+
+    let paths = ScreenPaths::new(&lib.path);
+    std::fs::write(&paths.progress, text).unwrap();
+
+    let progress = load_progress(&paths.progress, &header);
+    assert_eq!(progress.docked.len(), 1);
+    assert_eq!(progress.docked[0].0.lib_i, 0);
+    assert_eq!(progress.docked[0].0.score, -4.5);
+    assert_eq!(progress.failed.len(), 1);
+    assert_eq!(progress.failed[0].0, 1);
+
+    // Progress from a screen with different settings, filter, or library contents doesn't apply.
+    let mut other_headers = vec![progress_header(&lib, &pep, &site, 5.)];
+    lib.filter.name = "mol_0".to_owned();
+    other_headers.push(progress_header(&lib, &pep, &site, ph));
+    lib.filter.name.clear();
+    lib.mols.swap(0, 1);
+    other_headers.push(progress_header(&lib, &pep, &site, ph));
+    lib.mols.swap(0, 1);
+    assert_eq!(progress_header(&lib, &pep, &site, ph), header);
+
+    for other_header in other_headers {
+        let other = load_progress(&paths.progress, &other_header);
+        assert!(other.docked.is_empty() && other.failed.is_empty());
+    }
+
+    // Resuming skips the docked and failed molecules. The interrupted one isn't in the filter,
+    // so nothing is left to dock.
+    lib.filtered = vec![0, 1];
+    let gaff2 = ForceFieldParams::default();
+    let mut job = ScreenJob::start(&lib, &pep, pep_params, &site, &gaff2, ph).unwrap();
+    assert_eq!((job.total, job.done, job.failed), (2, 2, 1));
+    assert_eq!(job.best.as_ref().unwrap().lib_i, 0);
+
+    let num_ranked = loop {
+        if let Some(result) = job.poll() {
+            break result.unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+    assert_eq!(num_ranked, 1);
+    assert!(!paths.progress.exists());
+
+    let csv = std::fs::read_to_string(&paths.csv).unwrap();
+    assert_eq!(csv.lines().count(), 2);
+    assert!(csv.lines().nth(1).unwrap().starts_with("1,mol_0,-4.50,"));
+    assert_eq!(MolLibrary::load(&paths.sdf).unwrap().mols.len(), 1);
+
+    let _ = std::fs::remove_file(&paths.sdf);
+    let _ = std::fs::remove_file(&paths.csv);
+}
+
+#[test]
+fn test_docking_results_import() {
+    // Vina output: One MODEL per pose, with its affinity, and RMSD bounds from the top pose.
+    let mut pdbqt = String::new();
+    for (model, (affinity, rmsd_ub, shift)) in [(-7.5, 0., 0.), (-6.2, 1.8, 1.5)].iter().enumerate()
+    {
+        pdbqt.push_str(&format!("MODEL {}\n", model + 1));
+        pdbqt.push_str(&format!(
+            "REMARK VINA RESULT:    {affinity:.1}      0.000      {rmsd_ub:.3}\n"
+        ));
+        pdbqt.push_str("REMARK INTER + INTRA:         -9.100\n");
+        pdbqt.push_str("REMARK  2 active torsions:\n");
+        pdbqt.push_str("ROOT\n");
+        for (i, x) in [0., 1.5, 3.].iter().enumerate() {
+            pdbqt.push_str(&format!(
+                "ATOM  {:>5} {:<4} UNL     1    {:>8.3}{:>8.3}{:>8.3}  0.00  0.00    +0.000 C\n",
+                i + 1,
+                "C",
+                x + shift,
+                -2.,
+                10.
+            ));
+        }
+        pdbqt.push_str("ENDROOT\nTORSDOF 2\nENDMDL\n");
+    }
+
+    let poses = poses_from_pdbqt(&pdbqt).unwrap();
+    assert_eq!(poses.len(), 2);
+    assert_eq!(poses[0].score, Some(-7.5));
+    assert_eq!(poses[1].score, Some(-6.2));
+    assert!(poses[1].props.contains(&("RMSD u.b.".to_owned(), 1.8)));
+    assert!(poses[1].props.contains(&("INTER + INTRA".to_owned(), -9.1)));
+    assert_eq!(poses[1].posits.len(), 3);
+    assert!((poses[1].posits[1] - Vec3::new(3., -2., 10.)).magnitude() < 1e-6);
+
+    // Gnina output: Records of the same molecule, with score tags.
+    let mut mol = MoleculeSmall {
+        common: MoleculeCommon::from_smiles("CCO").unwrap(),
+        ..Default::default()
+    };
+    mol.common.ident = "ethanol".to_owned();
+
+    let mut sdf = String::new();
+    for (affinity, cnn) in [("-4.10", "0.92"), ("-3.80", "0.55")] {
+        let meta = &mut mol.common.metadata;
+        meta.insert("minimizedAffinity".to_owned(), affinity.to_owned());
+        meta.insert("CNNscore".to_owned(), cnn.to_owned());
+        sdf_record(&mol, &mut sdf).unwrap();
+
+        // Records are written from atom positions.
+        for atom in &mut mol.common.atoms {
+            atom.posit += Vec3::new(2., 0., 0.);
+        }
+    }
+
+    let lig = load_sdf_poses(&sdf).unwrap().unwrap();
+    assert_eq!(lig.ext_poses.len(), 2);
+    assert_eq!(lig.ext_poses[1].score, Some(-3.8));
+    assert_eq!(lig.ext_poses[1].props, vec![("CNNscore".to_owned(), 0.55)]);
+    assert!(
+        (lig.ext_poses[1].posits[0] - lig.ext_poses[0].posits[0] - Vec3::new(2., 0., 0.))
+            .magnitude()
+            < 1e-3
+    );
+
+    // Without score tags, multi-record files are libraries.
+    mol.common.metadata.clear();
+    let mut library = String::new();
+    sdf_record(&mol, &mut library).unwrap();
+    sdf_record(&mol, &mut library).unwrap();
+    assert!(load_sdf_poses(&library).unwrap().is_none());
+}
+
+fn session_cam() -> CamSnapshot {
+    CamSnapshot {
+        position: lin_alg::f32::Vec3::new(0., 0., -30.),
+        orientation: lin_alg::f32::Quaternion::new_identity(),
+        far: 1_000.,
+        name: "Session".to_owned(),
+    }
+}
+
+#[test]
+fn test_session_bytes() {
+    let mut state = State::default();
+    state.ligands.push(MoleculeSmall {
+        common: MoleculeCommon::from_smiles("[NH3+]CC(=O)[O-]").unwrap(),
+        ..Default::default()
+    });
+
+    let session = Session::from_state(&state, session_cam(), false).unwrap();
+    let bytes = session.to_bytes().unwrap();
+    assert_eq!(&bytes[..4], SESSION_MAGIC);
+    assert_eq!(bytes[4..6], SESSION_VERSION.to_le_bytes());
+
+    let loaded = Session::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded.ligands.len(), 1);
+    assert_eq!(
+        loaded.ligands[0].mol.atom_posits,
+        state.ligands[0].common.atom_posits
+    );
+
+    // Formal charges are kept.
+    let charges: Vec<_> = loaded.ligands[0]
+        .mol
+        .atoms
+        .iter()
+        .map(|a| a.formal_charge)
+        .collect();
+    let expected: Vec<_> = state.ligands[0]
+        .common
+        .atoms
+        .iter()
+        .map(|a| a.formal_charge)
+        .collect();
+    assert_eq!(charges, expected);
+    assert!(charges.contains(&1) && charges.contains(&-1));
+
+    // Files from newer versions are rejected.
+    let mut newer = bytes;
+    newer[4..6].copy_from_slice(&(SESSION_VERSION + 1).to_le_bytes());
+    assert!(Session::from_bytes(&newer).is_err());
+}
+
+#[test]
+fn test_session_round_trip() {
+    // Opening a session saves prefs; keep them out of the working directory.
+    let prefs_dir = std::env::temp_dir().join("molchanica_test_session");
+    std::fs::create_dir_all(&prefs_dir).unwrap();
+
+    let text = "\
+ATOM      1  N   ALA A  10       0.000   0.000   0.000  1.00 11.50           N\n\
+ATOM      2  CA  ALA A  10       1.458   0.000   0.000  1.00  0.00           C\n\
+ATOM      3  N   GLY A  10A      3.300   1.600   0.000  1.00  0.00           N\n\
+ATOM      4  CA  GLY A  10A      3.900   2.900   0.000  1.00  0.00           C\n\
+END\n";
+
+    let mut state = State::default();
+    state.volatile.prefs_dir = prefs_dir.clone();
+    state.peptide = Some(peptide_from_pdb(&Pdb::new(text).unwrap()));
+
+    // A ligand moved from its initial position.
+    let mut lig = MoleculeSmall {
+        common: MoleculeCommon::from_smiles("[NH3+]CC(=O)[O-]").unwrap(),
+        smiles: Some("[NH3+]CC(=O)[O-]".to_owned()),
+        ..Default::default()
+    };
+    for p in &mut lig.common.atom_posits {
+        *p += Vec3::new(5., -2., 1.);
+    }
+    state.ligands.push(lig);
+
+    state.ui.selection = Selection::AtomLig((0, 2));
+    state.ui.mol_view = MoleculeView::SpaceFill;
+    state.ui.visibility.hide_water = true;
+    state.to_save.ph = 6.5;
+    state.to_save.md_config.temp_target = 280.;
+
+    let path = prefs_dir.join("test.mcs");
+    let session = Session::from_state(&state, session_cam(), false).unwrap();
+    std::fs::write(&path, session.to_bytes().unwrap()).unwrap();
+
+    let mut loaded = State::default();
+    loaded.volatile.prefs_dir = prefs_dir;
+    loaded
+        .open_session(&path, None, &mut EngineUpdates::default())
+        .unwrap();
+
+    let (pep, pep_loaded) = (
+        state.peptide.as_ref().unwrap(),
+        loaded.peptide.as_ref().unwrap(),
+    );
+    assert_eq!(pep_loaded.common.atom_posits, pep.common.atom_posits);
+    assert_eq!(pep_loaded.chains.len(), pep.chains.len());
+    let res_labels: Vec<_> = pep_loaded.residues.iter().map(|r| r.seq_label()).collect();
+    assert_eq!(res_labels, ["10", "10A"]);
+
+    assert_eq!(loaded.ligands.len(), 1);
+    let (lig, lig_loaded) = (&state.ligands[0], &loaded.ligands[0]);
+    assert_eq!(lig_loaded.common.atom_posits, lig.common.atom_posits);
+    assert_eq!(lig_loaded.smiles, lig.smiles);
+    assert_eq!(lig_loaded.common.bonds.len(), lig.common.bonds.len());
+    for (a, b) in lig_loaded.common.atoms.iter().zip(&lig.common.atoms) {
+        assert_eq!(a.element, b.element);
+        assert_eq!(a.formal_charge, b.formal_charge);
+    }
+
+    assert_eq!(loaded.ui.selection, Selection::AtomLig((0, 2)));
+    assert_eq!(loaded.ui.mol_view, MoleculeView::SpaceFill);
+    assert_eq!(loaded.ui.visibility, state.ui.visibility);
+    assert_eq!(loaded.to_save.ph, 6.5);
+    assert_eq!(loaded.to_save.md_config.temp_target, 280.);
+}
+
+#[test]
+fn test_pdb_residues() {
+    // A leading negative residue number, and an insertion code.
+    let text = "\
+ATOM      1  N   GLY A  -1       0.000   0.000   0.000  1.00  0.00           N\n\
+ATOM      2  CA  GLY A  -1       1.450   0.000   0.000  1.00  0.00           C\n\
+ATOM      3  CA  ALA A  52       4.500   0.000   0.000  1.00  0.00           C\n\
+ATOM      4  CA  ALA A  52A      8.000   0.000   0.000  1.00  0.00           C\n\
+ATOM      5  CA  GLY A  53      11.500   0.000   0.000  1.00  0.00           C\n\
+END\n";
+
+    let pdb = Pdb::new(text).unwrap();
+    assert!(!pdb.is_ligand_only());
+    assert_eq!(pdb.residues.len(), 4);
+    assert_eq!(pdb.chains[0].residue_sns, vec![1, 2, 3, 4]);
+
+    let seq: Vec<_> = pdb
+        .residues
+        .iter()
+        .map(|r| pdb.res_seq[&r.serial_number])
+        .collect();
+    assert_eq!(
+        seq,
+        vec![(-1, None), (52, None), (52, Some('A')), (53, None)]
+    );
+    assert_eq!(pdb.residues[2].atom_sns, vec![4]);
+
+    // Ligands exported by docking programs often use ATOM records.
+    let text = "\
+ATOM      1  C1  LIG A   1       0.000   0.000   0.000  1.00  0.00           C\n\
+ATOM      2  C2  LIG A   1       1.500   0.000   0.000  1.00  0.00           C\n\
+ATOM      3  O1  LIG A   1       2.200   1.200   0.000  1.00  0.00           O\n\
+END\n";
+
+    assert!(Pdb::new(text).unwrap().is_ligand_only());
+}
+
+/// Build a peptide directly from parsed PDB data, without adding hydrogens.
+fn peptide_from_pdb(pdb: &Pdb) -> MoleculePeptide {
+    let (atoms, bonds, mut residues, chains) =
+        init_bonds_chains_res(&pdb.atoms, &pdb.bonds, &pdb.residues, &pdb.chains, &[]).unwrap();
+    pdb.apply_res_props(&mut residues);
+
+    let mut mol = MoleculePeptide::new(
+        pdb.ident.clone(),
+        atoms,
+        bonds,
+        chains,
+        residues,
+        pdb.metadata.clone(),
+        None,
+    );
+    pdb.apply_atom_props(&mut mol.common.atoms);
+    mol
+}
+
+#[test]
+fn test_pdb_round_trip() {
+    // Chain A has an insertion code, and a ligand listed after chain B.
+    let text = "\
+HEADER    TEST                                    01-JAN-00   1ABC\n\
+ATOM      1  N   ALA A  10       0.000   0.000   0.000  1.00 11.50           N\n\
+ATOM      2  CA  ALA A  10       1.458   0.000   0.000  1.00  0.00           C\n\
+ATOM      3  C   ALA A  10       2.009   1.420   0.000  1.00  0.00           C\n\
+ATOM      4  N   ALA A  10A      3.300   1.600   0.000  1.00  0.00           N\n\
+ATOM      5  CA  ALA A  10A      3.900   2.900   0.000  1.00  0.00           C\n\
+ATOM      6  N   GLY B  -3      10.000   0.000   0.000  1.00  0.00           N\n\
+ATOM      7  CA  GLY B  -3      11.450   0.000   0.000  1.00  0.00           C\n\
+HETATM    8  C1  LIG A 201      20.000   0.000   0.000  1.00  0.00           C\n\
+HETATM    9  O1  LIG A 201      21.200   0.500   0.000  1.00  0.00           O\n\
+CONECT    8    9\n\
+CONECT    9    8\n\
+END\n";
+
+    let pdb = Pdb::new(text).unwrap();
+    let mut mol = peptide_from_pdb(&pdb);
+
+    let written = mol.to_pdb();
+    assert_eq!(written.lines().filter(|l| l.starts_with("TER")).count(), 2);
+
+    // TER records take serial numbers, so atoms after them are renumbered.
+    let serials: Vec<_> = written
+        .lines()
+        .filter(|l| ["ATOM", "HETATM", "TER"].iter().any(|r| l.starts_with(r)))
+        .map(|l| l[6..11].trim().parse::<u32>().unwrap())
+        .collect();
+    assert_eq!(serials, (1..=11).collect::<Vec<_>>());
+    assert!(written.contains("CONECT   10   11\n"));
+
+    let reread = Pdb::new(&written).unwrap();
+    assert_eq!(reread.ident, "1ABC");
+    assert_eq!(
+        reread.seq_res,
+        vec![
+            ("A".to_owned(), vec!["ALA".to_owned(), "ALA".to_owned()]),
+            ("B".to_owned(), vec!["GLY".to_owned()]),
+        ]
+    );
+    assert_eq!(reread.atoms.len(), pdb.atoms.len());
+    for (a, (b, sn)) in reread
+        .atoms
+        .iter()
+        .zip(pdb.atoms.iter().zip([1, 2, 3, 4, 5, 7, 8, 10, 11]))
+    {
+        assert_eq!(a.serial_number, sn);
+        assert_eq!(a.element, b.element);
+        assert_eq!(a.hetero, b.hetero);
+        assert!((a.posit - b.posit).magnitude() < 1e-3);
+    }
+    assert_eq!(reread.b_factors[&1], 11.5);
+    assert_eq!(reread.bonds.len(), 1);
+
+    let chains: Vec<_> = reread.chains.iter().map(|c| c.id.as_str()).collect();
+    assert_eq!(chains, vec!["A", "B"]);
+
+    let seq: Vec<_> = reread
+        .residues
+        .iter()
+        .map(|r| reread.res_seq[&r.serial_number])
+        .collect();
+    assert_eq!(
+        seq,
+        vec![(10, None), (10, Some('A')), (-3, None), (201, None)]
+    );
+
+    // Label sequence IDs number each chain's polymer residues from 1; insertion codes are
+    // written separately.
+    let cif = mol.to_mmcif();
+    let atom_fields = |sn: &str| -> Vec<String> {
+        let line = cif
+            .lines()
+            .find(|l| l.split_whitespace().nth(1) == Some(sn))
+            .unwrap();
+        line.split_whitespace().map(|f| f.to_owned()).collect()
+    };
+    assert_eq!(atom_fields("4")[8..10], ["2", "A"]);
+    assert_eq!(atom_fields("6")[8..10], ["1", "?"]);
+    assert_eq!(atom_fields("8")[8], ".");
+
+    // Chain IDs longer than one character are truncated, and large sequence numbers wrap.
+    mol.chains[1].id = "BC".to_owned();
+    mol.residues[3].seq_num = 12_345;
+
+    let reread = Pdb::new(&mol.to_pdb()).unwrap();
+    assert_eq!(reread.chains[1].id, "B");
+    assert_eq!(reread.res_seq[&4], (2_345, None));
+}
+
+#[test]
+fn test_prmtop_round_trip() {
+    let mut top = SystemTopology::new("Test system");
+
+    // A ligand with an improper whose hub is atom 0, and a 4-point OPC water.
+    let atoms = [
+        ("C1", "c", Some(Element::Carbon), 0.5, 12.01, 0),
+        ("O1", "o", Some(Element::Oxygen), -0.5, 16., 0),
+        ("N1", "n", Some(Element::Nitrogen), -0.3, 14.01, 0),
+        ("C2", "c3", Some(Element::Carbon), 0.1, 12.01, 0),
+        ("H1", "hc", Some(Element::Hydrogen), 0.2, 1.008, 0),
+        ("O", "OW", Some(Element::Oxygen), 0., 16., 1),
+        ("H1", "HW", Some(Element::Hydrogen), 0.6791, 1.008, 1),
+        ("H2", "HW", Some(Element::Hydrogen), 0.6791, 1.008, 1),
+        ("EPW", "EP", None, -1.3582, 0., 1),
+    ];
+    for (i, (name, ff_type, element, charge, mass, residue)) in atoms.into_iter().enumerate() {
+        top.atoms.push(TopAtom {
+            name: name.to_owned(),
+            ff_type: ff_type.to_owned(),
+            element,
+            charge,
+            mass,
+            residue,
+            posit: Vec3::new(i as f64 * 1.1, (i as f64).sin(), -2.5),
+        });
+    }
+
+    for (name, first_atom) in [("LIG", 0), ("WAT", 5)] {
+        top.residues.push(TopResidue {
+            name: name.to_owned(),
+            serial_number: top.residues.len() as u32 + 1,
+            first_atom,
+        });
+    }
+
+    for atoms in [(0, 1), (0, 2), (0, 3), (3, 4), (5, 6), (5, 7), (6, 7)] {
+        top.bonds.push(TopBond {
+            atoms,
+            k_b: 300.,
+            r_0: 1.4,
+        });
+    }
+    for atoms in [(1, 0, 2), (1, 0, 3), (2, 0, 3), (0, 3, 4)] {
+        top.angles.push(TopAngle {
+            atoms,
+            k: 60.,
+            theta_0: 2.,
+        });
+    }
+    for (atoms, improper) in [
+        ((1, 0, 3, 4), false),
+        ((2, 0, 3, 4), false),
+        ((1, 2, 0, 3), true),
+    ] {
+        top.dihedrals.push(TopDihedral {
+            atoms,
+            k: 1.1,
+            periodicity: 2,
+            phase: std::f32::consts::PI,
+            improper,
+        });
+    }
+
+    for ff_type in ["c", "o", "n", "c3", "hc", "OW", "HW", "EP"] {
+        top.lj.push((
+            ff_type.to_owned(),
+            TopLj {
+                sigma: 3.,
+                eps: 0.1,
+            },
+        ));
+    }
+    top.box_size = Some(Vec3::new(30., 31., 32.));
+
+    let path = std::env::temp_dir().join("molchanica_test.prmtop");
+    top.save_amber(&path).unwrap();
+
+    let prmtop = Prmtop::load(&path).unwrap();
+    let coords = std::fs::read_to_string(find_coord_file(&path).unwrap()).unwrap();
+    let posits = load_inpcrd(&coords, top.atoms.len()).unwrap();
+    let loaded = SystemTopology::from_prmtop(&prmtop, &posits).unwrap();
+
+    assert_eq!(loaded.atoms.len(), top.atoms.len());
+    for (a, b) in loaded.atoms.iter().zip(&top.atoms) {
+        assert_eq!(a.name, b.name);
+        assert_eq!(a.ff_type, b.ff_type);
+        assert_eq!(a.element, b.element);
+        assert_eq!(a.residue, b.residue);
+        assert!((a.charge - b.charge).abs() < 1e-5);
+        assert!((a.mass - b.mass).abs() < 1e-5);
+        assert!((a.posit - b.posit).magnitude() < 1e-6);
+    }
+    assert_eq!(loaded.bonds.len(), top.bonds.len());
+    assert_eq!(loaded.residues[1].first_atom, 5);
+    assert!((loaded.box_size.unwrap() - Vec3::new(30., 31., 32.)).magnitude() < 1e-6);
+
+    // Negative indices in the third and fourth position flag excluded 1-4 pairs, and impropers.
+    // Neither can be atom 0, as its negative is also 0.
+    let mut dihedrals = prmtop.ints("DIHEDRALS_INC_HYDROGEN").unwrap();
+    dihedrals.extend(prmtop.ints("DIHEDRALS_WITHOUT_HYDROGEN").unwrap());
+    assert_eq!(dihedrals.len(), 15);
+    for d in dihedrals.chunks_exact(5) {
+        assert!(d[2] != 0 && d[3] != 0);
+        if d[3] < 0 {
+            assert!(d[2] < 0);
+        }
+    }
+    assert_eq!(dihedrals.chunks_exact(5).filter(|d| d[3] < 0).count(), 1);
+
+    // The water's virtual site is excluded from its H atoms, though not bonded to them.
+    let num_excluded = prmtop.ints("NUMBER_EXCLUDED_ATOMS").unwrap();
+    let excluded = prmtop.ints("EXCLUDED_ATOMS_LIST").unwrap();
+    let start: i64 = num_excluded[..6].iter().sum();
+    let (start, end) = (start as usize, (start + num_excluded[6]) as usize);
+    assert_eq!(excluded[start..end], [8, 9]);
+}
+
+#[test]
+fn test_gromacs_missing_includes() {
+    let dir = std::env::temp_dir().join("molchanica_test_gmx");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("system.top");
+
+    // Water and ion molecule types come from force field includes which aren't present.
+    let top_text = "; Solvated ions\n\
+        #include \"notinstalled.ff/forcefield.itp\"\n\
+        #include \"notinstalled.ff/tip3p.itp\"\n\n\
+        [ system ]\nWater and ions\n\n\
+        [ molecules ]\n; name  count\nSOL  2\nNA  1\nCL  1\n";
+    std::fs::write(&path, top_text).unwrap();
+
+    let gro_atoms = [
+        (1, "SOL", "OW"),
+        (1, "SOL", "HW1"),
+        (1, "SOL", "HW2"),
+        (2, "SOL", "OW"),
+        (2, "SOL", "HW1"),
+        (2, "SOL", "HW2"),
+        (3, "NA", "NA"),
+        (4, "CL", "CL"),
+    ];
+    let mut gro_text = format!("Water and ions\n{}\n", gro_atoms.len());
+    for (i, (res_sn, res_name, name)) in gro_atoms.iter().enumerate() {
+        let x = i as f64 * 0.3;
+        gro_text.push_str(&format!(
+            "{res_sn:>5}{res_name:<5}{name:>5}{:>5}{x:>8.3}{:>8.3}{:>8.3}\n",
+            i + 1,
+            0.5,
+            1.
+        ));
+    }
+    gro_text.push_str("   3.00000   3.00000   3.00000\n");
+
+    let top = GmxTop::load(&path).unwrap();
+    let gro = Gro::new(&gro_text).unwrap();
+    let system = SystemTopology::from_gromacs(&gro, &top).unwrap();
+
+    assert_eq!(system.atoms.len(), gro_atoms.len());
+    assert_eq!(system.residues.len(), 4);
+    assert_eq!(system.residues[1].first_atom, 3);
+
+    let elements: Vec<_> = system.atoms.iter().map(|a| a.element).collect();
+    let (o, h) = (Some(Element::Oxygen), Some(Element::Hydrogen));
+    assert_eq!(elements[..6], [o, h, h, o, h, h]);
+    assert_eq!(elements[6], Element::from_letter("Na").ok());
+    assert_eq!(elements[7], Some(Element::Chlorine));
+    assert_eq!((system.atoms[6].charge, system.atoms[7].charge), (1., -1.));
+
+    // O-H bonds of each water.
+    let mut bonds: Vec<_> = system.bonds.iter().map(|b| b.atoms).collect();
+    bonds.sort();
+    assert_eq!(bonds, [(0, 1), (0, 2), (3, 4), (3, 5)]);
+    assert!((system.atoms[3].posit.x - 9.).abs() < 1e-6);
+
+    // Other molecule types are still required.
+    std::fs::write(&path, top_text.replace("CL  1", "CL  1\nLIG  1")).unwrap();
+    let top = GmxTop::load(&path).unwrap();
+    assert!(SystemTopology::from_gromacs(&gro, &top).is_err());
+}
+
+#[test]
+fn test_traj_round_trip() {
+    // Water-like groups of close atoms exercise XTC's run compression; the outlier, its large
+    // integer sizes.
+    let frames: Vec<_> = (0..3)
+        .map(|f| {
+            let mut posits: Vec<_> = (0..30)
+                .map(|i| {
+                    let t = (i + f) as f64;
+                    Vec3::new(
+                        10. + (i / 3) as f64 * 3.1 + (i % 3) as f64 * 0.96,
+                        5. + (t * 0.7).sin(),
+                        -3. + (t * 1.3).cos() * 2.,
+                    )
+                })
+                .collect();
+            posits.push(Vec3::new(151.2, -80.4, 33.3));
+
+            TrajFrame {
+                step: f * 500,
+                time: f as f64,
+                posits,
+                box_size: Some(Vec3::new(40., 41.5, 43.)),
+            }
+        })
+        .collect();
+
+    // DCD stores f32 Å; XTC, integers in units of 0.001 nm.
+    for (format, tol) in [(TrajFormat::Dcd, 1e-4), (TrajFormat::Xtc, 6e-3)] {
+        let data = write_traj(format, &frames, &[]);
+        let loaded = read_traj(format, &data).unwrap();
+
+        assert_eq!(loaded.len(), frames.len());
+        for (a, b) in loaded.iter().zip(&frames) {
+            assert_eq!(a.step, b.step);
+            assert!((a.time - b.time).abs() < 1e-4);
+            assert!((a.box_size.unwrap() - b.box_size.unwrap()).magnitude() < 1e-3);
+
+            assert_eq!(a.posits.len(), b.posits.len());
+            for (pa, pb) in a.posits.iter().zip(&b.posits) {
+                for (va, vb) in [(pa.x, pb.x), (pa.y, pb.y), (pa.z, pb.z)] {
+                    assert!((va - vb).abs() < tol, "{format:?}: {pa:?} vs {pb:?}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_mesh_export() {
+    use lin_alg::f32::Vec3 as Vec3F32;
+
+    // A tetrahedron, with one colored vertex.
+    let mut vertices: Vec<_> = [[0., 0., 0.], [1., 0., 0.], [0., 1., 0.], [0., 0., 1.]]
+        .into_iter()
+        .map(|p| Vertex::new(p, Vec3F32::new(p[0], p[1], p[2])))
+        .collect();
+    vertices[1].color = Some((255, 0, 0, 128));
+    let mesh = Mesh {
+        vertices,
+        indices: vec![0, 2, 1, 0, 1, 3, 0, 3, 2, 1, 2, 3],
+        material: 0,
+    };
+
+    let export = ExportMesh::new(&mesh, [0.5, 0.5, 0.5, 1.], None, 10.);
+
+    // PLY: 4 vertices of 6 floats and 4 color bytes; 4 faces of a count byte and 3 indices.
+    let ply = export.to_ply();
+    let header_end = ply.windows(11).position(|w| w == b"end_header\n").unwrap() + 11;
+    let header = std::str::from_utf8(&ply[..header_end]).unwrap();
+    assert!(header.starts_with("ply\nformat binary_little_endian 1.0\n"));
+    assert!(header.contains("element vertex 4\n") && header.contains("element face 4\n"));
+    assert_eq!(ply.len() - header_end, 4 * (6 * 4 + 4) + 4 * (1 + 3 * 4));
+
+    // Vertices are in order of first use by a triangle; the third is the colored one, scaled.
+    let vert = &ply[header_end + 2 * 28..header_end + 3 * 28];
+    assert_eq!(f32::from_le_bytes(vert[..4].try_into().unwrap()), 10.);
+    assert_eq!(vert[24..], [255, 0, 0, 128]);
+
+    // GLB: A 12-byte header, then JSON and binary chunks, each 4-byte aligned.
+    let glb = export.to_glb("Surface");
+    let u32_at = |i: usize| u32::from_le_bytes(glb[i..i + 4].try_into().unwrap()) as usize;
+    assert_eq!(&glb[..4], b"glTF");
+    assert_eq!(u32_at(4), 2);
+    assert_eq!(u32_at(8), glb.len());
+
+    let json_len = u32_at(12);
+    assert_eq!(&glb[16..20], b"JSON");
+    assert_eq!(json_len % 4, 0);
+    let json = std::str::from_utf8(&glb[20..20 + json_len]).unwrap();
+
+    let bin_start = 20 + json_len;
+    let bin_len = u32_at(bin_start);
+    assert_eq!(&glb[bin_start + 4..bin_start + 8], b"BIN\0");
+    assert_eq!(bin_start + 8 + bin_len, glb.len());
+
+    // Positions, normals, RGBA colors, and indices.
+    let buf_len = 4 * 12 + 4 * 12 + 4 * 16 + 4 * 12;
+    assert!(json.contains(&format!(r#""buffers":[{{"byteLength":{buf_len}}}]"#)));
+    assert!(json.contains(r#""alphaMode":"BLEND""#));
+    assert_eq!(bin_len, buf_len);
+
+    // STL: An 80-byte header, the count, and 50 bytes per triangle.
+    assert_eq!(export.to_stl().len(), 84 + 4 * 50);
+
+    // Triangles touching an excluded vertex are dropped.
+    let keep = [true, true, true, false];
+    let export = ExportMesh::new(&mesh, [0.5, 0.5, 0.5, 1.], Some(&keep), 1.);
+    assert_eq!(export.to_stl().len(), 84 + 50);
+}
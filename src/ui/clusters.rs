@@ -0,0 +1,212 @@
+//! A panel listing ligand or library series, grouped by scaffold or fingerprint similarity.
+
+use egui::{Align, Color32, Layout, Popup, PopupAnchor, Pos2, RectAlign, RichText, ScrollArea, Ui};
+use graphics::{EngineUpdates, EntityUpdate, Scene};
+
+use crate::{
+    State,
+    fingerprint::HitSource,
+    label,
+    scaffold::{ClusterBy, ClusterSource},
+    ui::{COL_SPACING, COLOR_ACTION, COLOR_ACTIVE_RADIO, COLOR_INACTIVE, ROW_SPACING},
+};
+
+/// Member names displayed per cluster, before summarizing the rest.
+const MAX_MEMBERS_DISP: usize = 20;
+
+pub(in crate::ui) fn clusters(
+    state: &mut State,
+    scene: &mut Scene,
+    ui: &mut Ui,
+    redraw_lig: &mut bool,
+    engine_updates: &mut EngineUpdates,
+) {
+    let Some(clustering) = &state.ui.popup.clusters else {
+        return;
+    };
+
+    let popup_id = ui.make_persistent_id("clusters_popup");
+
+    let mut source = clustering.source;
+    let mut by = clustering.by;
+    // Cluster index, and visibility.
+    let mut set_visible = None;
+    let mut open = None;
+    let mut close = false;
+
+    Popup::new(
+        popup_id,
+        ui.ctx().clone(),
+        PopupAnchor::Position(Pos2::new(60., 60.)),
+        ui.layer_id(),
+    )
+    .align(RectAlign::BOTTOM_START)
+    .open(true)
+    .gap(4.0)
+    .show(|ui| {
+        ui.horizontal(|ui| {
+            ui.heading(RichText::new("Clusters").color(Color32::WHITE));
+
+            ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                if ui
+                    .button(RichText::new("Close").color(Color32::LIGHT_RED))
+                    .clicked()
+                {
+                    close = true;
+                }
+            });
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Molecules:");
+            let mut sources = vec![(ClusterSource::Ligands, "Open ligands")];
+            if state.library.is_some() {
+                sources.push((ClusterSource::Library, "Library"));
+            }
+            for (s, text) in sources {
+                let color = if s == source {
+                    COLOR_ACTIVE_RADIO
+                } else {
+                    COLOR_INACTIVE
+                };
+                if ui.button(RichText::new(text).color(color)).clicked() {
+                    source = s;
+                }
+            }
+
+            ui.add_space(COL_SPACING);
+            ui.label("Group by:");
+            for (b, help) in [
+                (
+                    ClusterBy::Scaffold,
+                    "Identical Bemis–Murcko scaffolds: ring systems, and the \
+                    linkers between them.",
+                ),
+                (
+                    ClusterBy::Framework,
+                    "Identical generic frameworks: scaffolds, with all atoms \
+                    treated as carbon, and all bonds as single.",
+                ),
+                (
+                    ClusterBy::Butina,
+                    "Butina clustering of Morgan fingerprints, by Tanimoto \
+                    similarity to each cluster's centroid.",
+                ),
+            ] {
+                let color = if b == by {
+                    COLOR_ACTIVE_RADIO
+                } else {
+                    COLOR_INACTIVE
+                };
+                if ui
+                    .button(RichText::new(b.to_str()).color(color))
+                    .on_hover_text(help)
+                    .clicked()
+                {
+                    by = b;
+                }
+            }
+        });
+
+        let num_mols: usize = clustering.clusters.iter().map(|c| c.members.len()).sum();
+        ui.label(format!(
+            "{} clusters of {num_mols} molecules",
+            clustering.clusters.len()
+        ));
+
+        ui.add_space(ROW_SPACING);
+
+        ScrollArea::vertical().max_height(600.0).show(ui, |ui| {
+            for (i, cluster) in clustering.clusters.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    label!(ui, format!("{}", cluster.members.len()), Color32::GOLD);
+                    label!(ui, &cluster.label, Color32::WHITE);
+
+                    match clustering.source {
+                        ClusterSource::Ligands => {
+                            if ui
+                                .button(RichText::new("Show").color(COLOR_ACTION))
+                                .on_hover_text("Show all ligands in this cluster.")
+                                .clicked()
+                            {
+                                set_visible = Some((i, true));
+                            }
+                            if ui
+                                .button(RichText::new("Hide").color(COLOR_ACTION))
+                                .on_hover_text("Hide all ligands in this cluster.")
+                                .clicked()
+                            {
+                                set_visible = Some((i, false));
+                            }
+                        }
+                        ClusterSource::Library => {
+                            if ui
+                                .button(RichText::new("Open").color(COLOR_ACTION))
+                                .on_hover_text("Add all molecules in this cluster to the scene.")
+                                .clicked()
+                            {
+                                open = Some(i);
+                            }
+                        }
+                    }
+                });
+
+                ui.horizontal_wrapped(|ui| {
+                    for member in cluster.members.iter().take(MAX_MEMBERS_DISP) {
+                        let ident = match member {
+                            HitSource::Ligand(j) => state.ligands.get(*j).map(|m| &m.common.ident),
+                            HitSource::Library(j) => state
+                                .library
+                                .as_ref()
+                                .and_then(|l| l.mols.get(*j))
+                                .map(|m| &m.common.ident),
+                        };
+                        if let Some(ident) = ident {
+                            label!(ui, ident, Color32::GRAY);
+                        }
+                    }
+                    if cluster.members.len() > MAX_MEMBERS_DISP {
+                        label!(
+                            ui,
+                            format!("and {} more", cluster.members.len() - MAX_MEMBERS_DISP),
+                            Color32::GRAY
+                        );
+                    }
+                });
+
+                ui.separator();
+            }
+        });
+    });
+
+    if source != clustering.source || by != clustering.by {
+        state.ui.popup.clusters = Some(state.cluster_mols(source, by));
+        return;
+    }
+
+    if let Some((i, visible)) = set_visible {
+        for member in &clustering.clusters[i].members {
+            if let HitSource::Ligand(j) = member
+                && let Some(lig) = state.ligands.get_mut(*j)
+            {
+                lig.common.visible = visible;
+            }
+        }
+
+        *redraw_lig = true;
+        engine_updates.entities = EntityUpdate::All;
+    }
+
+    if let Some(i) = open {
+        let members = clustering.clusters[i].members.clone();
+        for member in members {
+            if let HitSource::Library(j) = member {
+                state.promote_library_mol(j, scene, engine_updates);
+            }
+        }
+    }
+
+    if close {
+        state.ui.popup.clusters = None;
+    }
+}
@@ -0,0 +1,303 @@
+//! A panel for browsing molecules loaded from multi-record files.
+
+use egui::{
+    Align, Color32, ComboBox, Key, Layout, Popup, PopupAnchor, Pos2, RectAlign, RichText,
+    ScrollArea, TextEdit, Ui,
+};
+use graphics::{EngineUpdates, Scene};
+
+use crate::{
+    State, label,
+    mol_library::{LIB_PAGE_SIZE, LibProperty},
+    smarts::parse_smarts,
+    ui::{COL_SPACING, COLOR_ACTION, COLOR_HIGHLIGHT, ROW_SPACING},
+};
+
+pub(in crate::ui) fn library(
+    state: &mut State,
+    scene: &mut Scene,
+    ui: &mut Ui,
+    engine_updates: &mut EngineUpdates,
+) {
+    let Some(lib) = &mut state.library else {
+        state.ui.popup.library = false;
+        return;
+    };
+
+    let popup_id = ui.make_persistent_id("library_popup");
+
+    let mut promote = None;
+    let mut export = false;
+    let mut export_csv = false;
+    let mut close = false;
+    let mut unload = false;
+
+    Popup::new(
+        popup_id,
+        ui.ctx().clone(),
+        PopupAnchor::Position(Pos2::new(60., 60.)),
+        ui.layer_id(),
+    )
+    .align(RectAlign::BOTTOM_START)
+    .open(true)
+    .gap(4.0)
+    .show(|ui| {
+        ui.horizontal(|ui| {
+            let filename = lib
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            ui.heading(RichText::new(format!("Library: {filename}")).color(Color32::WHITE));
+
+            ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                if ui
+                    .button(RichText::new("Close").color(Color32::LIGHT_RED))
+                    .clicked()
+                {
+                    close = true;
+                }
+                if ui
+                    .button(RichText::new("Unload").color(Color32::LIGHT_RED))
+                    .on_hover_text("Remove this library from memory.")
+                    .clicked()
+                {
+                    unload = true;
+                }
+            });
+        });
+
+        label!(
+            ui,
+            format!(
+                "{} molecules. {} pass the filter.",
+                lib.mols.len(),
+                lib.filtered.len()
+            ),
+            Color32::GRAY
+        );
+        ui.add_space(ROW_SPACING);
+
+        // Filters
+        let mut filter_changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Name:");
+            if ui
+                .add_sized(
+                    [100., Ui::available_height(ui)],
+                    TextEdit::singleline(&mut lib.filter.name),
+                )
+                .changed()
+            {
+                filter_changed = true;
+            }
+            ui.add_space(COL_SPACING);
+
+            ui.label("Property:");
+            let selected = match &lib.filter.property {
+                Some(p) => p.to_str(),
+                None => "None".to_owned(),
+            };
+
+            ComboBox::from_id_salt(1200)
+                .width(100.)
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    let mut opts = vec![
+                        None,
+                        Some(LibProperty::NumAtoms),
+                        Some(LibProperty::NumHeavyAtoms),
+                        Some(LibProperty::MolWeight),
+                    ];
+                    opts.extend(lib.tags().into_iter().map(|t| Some(LibProperty::Tag(t))));
+
+                    for opt in opts {
+                        let text = match &opt {
+                            Some(p) => p.to_str(),
+                            None => "None".to_owned(),
+                        };
+                        if ui
+                            .selectable_value(&mut lib.filter.property, opt, text)
+                            .changed()
+                        {
+                            filter_changed = true;
+                        }
+                    }
+                });
+
+            ui.label("Min:");
+            if ui
+                .add_sized(
+                    [40., Ui::available_height(ui)],
+                    TextEdit::singleline(&mut state.ui.lib_min_input),
+                )
+                .changed()
+            {
+                lib.filter.min = state.ui.lib_min_input.trim().parse().ok();
+                filter_changed = true;
+            }
+
+            ui.label("Max:");
+            if ui
+                .add_sized(
+                    [40., Ui::available_height(ui)],
+                    TextEdit::singleline(&mut state.ui.lib_max_input),
+                )
+                .changed()
+            {
+                lib.filter.max = state.ui.lib_max_input.trim().parse().ok();
+                filter_changed = true;
+            }
+
+            ui.add_space(COL_SPACING);
+            ui.label("SMARTS:");
+            let edit_resp = ui
+                .add_sized(
+                    [120., Ui::available_height(ui)],
+                    TextEdit::singleline(&mut state.ui.lib_smarts_input),
+                )
+                .on_hover_text(
+                    "Only show molecules containing this substructure, e.g. c1ccccc1, or \
+                    S(=O)(=O)N. Press Enter to apply.",
+                );
+            let enter_pressed = edit_resp.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+
+            if ui
+                .button(RichText::new("Apply").color(COLOR_ACTION))
+                .clicked()
+                || enter_pressed
+            {
+                let input = state.ui.lib_smarts_input.trim();
+
+                if input.is_empty() {
+                    lib.filter.smarts = None;
+                    state.ui.lib_smarts_err = None;
+                    filter_changed = true;
+                } else {
+                    // Keep filtering by the previous pattern if this one is invalid.
+                    match parse_smarts(input) {
+                        Ok(pattern) => {
+                            lib.filter.smarts = Some(pattern);
+                            state.ui.lib_smarts_err = None;
+                            filter_changed = true;
+                        }
+                        Err(e) => state.ui.lib_smarts_err = Some(e.to_string()),
+                    }
+                }
+            }
+
+            if let Some(e) = &state.ui.lib_smarts_err {
+                ui.label(RichText::new("Invalid SMARTS").color(Color32::LIGHT_RED))
+                    .on_hover_text(e);
+            }
+        });
+
+        if filter_changed {
+            lib.apply_filter();
+        }
+
+        ui.add_space(ROW_SPACING);
+
+        // Paging, and export
+        ui.horizontal(|ui| {
+            let num_pages = lib.num_pages();
+
+            if ui.button("◀").clicked() && lib.page > 0 {
+                lib.page -= 1;
+            }
+            ui.label(format!("Page {} of {}", lib.page + 1, num_pages.max(1)));
+            if ui.button("▶").clicked() && lib.page + 1 < num_pages {
+                lib.page += 1;
+            }
+
+            ui.add_space(COL_SPACING);
+
+            if ui
+                .button(RichText::new("Export filtered").color(COLOR_HIGHLIGHT))
+                .on_hover_text(
+                    "Save the molecules that pass the filter to a multi-record SDF file.",
+                )
+                .clicked()
+            {
+                export = true;
+            }
+
+            if ui
+                .button(RichText::new("Export CSV").color(COLOR_HIGHLIGHT))
+                .on_hover_text(
+                    "Save descriptors (MW, logP, TPSA etc), and drug-likeness verdicts of the \
+                    molecules that pass the filter to a CSV file.",
+                )
+                .clicked()
+            {
+                export_csv = true;
+            }
+        });
+
+        ui.add_space(ROW_SPACING);
+
+        ScrollArea::vertical().max_height(600.0).show(ui, |ui| {
+            for (j, &i) in lib.page_indices().iter().enumerate() {
+                let mol = &lib.mols[i];
+
+                ui.horizontal(|ui| {
+                    label!(
+                        ui,
+                        format!("{}", lib.page * LIB_PAGE_SIZE + j + 1),
+                        Color32::GRAY
+                    );
+                    label!(ui, &mol.common.ident, Color32::WHITE);
+
+                    let weight = LibProperty::MolWeight.value(mol).unwrap_or_default();
+                    label!(
+                        ui,
+                        format!("Atoms: {}  MW: {weight:.1}", mol.common.atoms.len()),
+                        Color32::GRAY
+                    );
+
+                    if ui
+                        .button(RichText::new("Add").color(COLOR_ACTION))
+                        .on_hover_text("Add this molecule to the scene.")
+                        .clicked()
+                    {
+                        promote = Some(i);
+                    }
+                });
+            }
+        });
+    });
+
+    if let Some(i) = promote {
+        state.promote_library_mol(i, scene, engine_updates);
+    }
+
+    if export {
+        state
+            .volatile
+            .dialogs
+            .library_save
+            .config_mut()
+            .default_file_name = "library_filtered.sdf".to_owned();
+        state.volatile.dialogs.library_save.save_file();
+    }
+
+    if export_csv {
+        state
+            .volatile
+            .dialogs
+            .library_csv_save
+            .config_mut()
+            .default_file_name = "library_descriptors.csv".to_owned();
+        state.volatile.dialogs.library_csv_save.save_file();
+    }
+
+    if close {
+        state.ui.popup.library = false;
+    }
+
+    if unload {
+        state.library = None;
+        state.ui.popup.library = false;
+    }
+}
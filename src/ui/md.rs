@@ -9,6 +9,7 @@ use lin_alg::f64::Vec3;
 use crate::{
     State,
     drawing::EntityClass,
+    file_io::trajectory::TrajAtomSet,
     label,
     md::{launch_md, post_run_cleanup},
     ui::{
@@ -320,6 +321,43 @@ pub fn md_setup(
             ui.add_space(COL_SPACING / 2.);
             ui.label(format!("Runtime: {:.1} ps", state.volatile.md_runtime));
 
+            ui.add_space(COL_SPACING / 2.);
+            let snaps_present = state.mol_dynamics.as_ref().is_some_and(|md| !md.snapshots.is_empty());
+            if snaps_present && !state.volatile.md_local.running {
+                num_field(&mut state.ui.md.traj_stride, "Stride:", 22, ui);
+
+                let help_text = "Atoms to include when saving the trajectory";
+                ComboBox::from_id_salt(6)
+                    .width(80.)
+                    .selected_text(state.ui.md.traj_atom_set.to_str())
+                    .show_ui(ui, |ui| {
+                        for v in [TrajAtomSet::All, TrajAtomSet::Solute, TrajAtomSet::Peptide, TrajAtomSet::Ligands] {
+                            ui.selectable_value(&mut state.ui.md.traj_atom_set, v, v.to_str());
+                        }
+                    })
+                    .response
+                    .on_hover_text(help_text);
+
+                if ui
+                    .button(RichText::new("Save traj").color(COLOR_ACTION))
+                    .on_hover_text("Save MD snapshots as a trajectory: DCD, XTC, or multi-model PDB or mmCIF")
+                    .clicked()
+                {
+                    let dialog = &mut state.volatile.dialogs.traj_save;
+                    dialog.config_mut().default_file_name = "trajectory.dcd".to_string();
+                    dialog.save_file();
+                }
+            }
+
+            if ui
+                .button(RichText::new("Load traj").color(COLOR_ACTION))
+                .on_hover_text("Load a trajectory for playback. Its atoms must match the molecules selected for MD, \
+                followed by water, as saved with the \"All\" atom set.")
+                .clicked()
+            {
+                state.volatile.dialogs.traj_load.pick_file();
+            }
+
             if let Some(md) = &state.mol_dynamics {
                 if state.ui.current_snapshot < md.snapshots.len() {
                     energy_disp(&md.snapshots[state.ui.current_snapshot], ui);
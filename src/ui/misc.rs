@@ -89,7 +89,7 @@ pub fn dynamics_player(
                 ));
                 ui.label(format!(
                     "{:.2} ps",
-                    state.ui.current_snapshot as f32 * state.to_save.md_dt
+                    md.snapshots[state.ui.current_snapshot.min(md.snapshots.len() - 1)].time
                 ));
             }
 
@@ -29,8 +29,11 @@ use crate::{
     molecule::MolGenericRef,
     render::set_flashlight,
     sa_surface,
+    smiles::parse_smiles,
     ui::{
         cam::{cam_controls, cam_snapshots},
+        clusters::clusters,
+        library::library,
         misc::section_box,
         mol_data::{display_mol_data_peptide, metadata_disp},
         mol_type_tools::mol_type_toolbars,
@@ -51,6 +54,8 @@ use crate::{
 };
 
 pub mod cam;
+mod clusters;
+mod library;
 mod md;
 pub mod misc;
 mod mol_data;
@@ -283,6 +288,10 @@ fn draw_cli(
             state.ui.cmd_line_output =
                 match cli::handle_cmd(state, scene, engine_updates, redraw_pep, reset_cam) {
                     Ok(out) => {
+                        // E.g. from `select smarts`.
+                        if matches!(state.ui.selection, Selection::AtomsLig(_)) {
+                            *redraw_lig = true;
+                        }
                         state.ui.cmd_line_out_is_err = false;
                         out
                     }
@@ -618,9 +627,21 @@ fn selection_section(
                             }
                         }
                     }
+
+                    // Set ligands to their dominant protonation state. Redrawing the peptide
+                    // redraws ligands as well.
+                    if state.protonate_ligands() > 0 {
+                        *redraw = true;
+                    }
                 }
             }
 
+            ui.checkbox(&mut state.ui.protonate_on_open, "Protonate on open")
+                .on_hover_text(
+                    "Set ligands opened without force field parameters to their dominant \
+                    protonation state at this pH. This changes their hydrogens and charges.",
+                );
+
             if state.ui.show_near_sel_only || state.ui.show_near_lig_only {
                 ui.label("Dist:");
                 let dist_prev = state.ui.nearby_dist_thresh;
@@ -868,7 +889,7 @@ fn residue_selector(state: &mut State, scene: &mut Scene, ui: &mut Ui, redraw: &
                         }
                         if ui
                             .button(
-                                RichText::new(format!("{} {name}", res.serial_number))
+                                RichText::new(format!("{} {name}", res.seq_label()))
                                     .size(10.)
                                     .color(color),
                             )
@@ -963,6 +984,30 @@ pub fn ui_handler(state: &mut State, ctx: &Context, scene: &mut Scene) -> Engine
             recent_files(state, scene, ui, &mut engine_updates);
         }
 
+        if state.ui.popup.library {
+            library(state, scene, ui, &mut engine_updates);
+        }
+
+        if state.ui.popup.similar.is_some() {
+            mol_data::similar_mols(state, scene, ui, &mut engine_updates);
+        }
+
+        if state.ui.popup.clusters.is_some() {
+            clusters(state, scene, ui, &mut redraw_lig, &mut engine_updates);
+        }
+
+        if state.ui.popup.protonation.is_some() {
+            mol_data::protonation_states(state, ui, &mut redraw_lig);
+        }
+
+        if state.ui.popup.docking_poses {
+            mol_type_tools::docking_poses(state, scene, ui, &mut engine_updates);
+        }
+
+        if state.ui.popup.pose_comparison.is_some() {
+            mol_type_tools::pose_comparison(state, scene, ui, &mut engine_updates);
+        }
+
         if state.ui.popup.rama_plot {
             if let Some(mol) = &state.peptide {
                 plot_rama(&mol.residues, &mol.common.ident, ui, &mut state.ui.popup.rama_plot);
@@ -1140,7 +1185,7 @@ pub fn ui_handler(state: &mut State, ctx: &Context, scene: &mut Scene) -> Engine
                 COLOR_INACTIVE
             };
 
-            let query_help = "Download and view a molecule from RCSB PDB, PubChem, DrugBank, or Amber Geostd";
+            let query_help = "Download and view a molecule from RCSB PDB, PubChem, DrugBank, or Amber Geostd, or create one from SMILES";
             ui.label(RichText::new("Query DBs:").color(color_open_tools))
                 .on_hover_text(query_help);
 
@@ -1253,6 +1298,26 @@ pub fn ui_handler(state: &mut State, ctx: &Context, scene: &mut Scene) -> Engine
                 }
             }
 
+            if !state.ui.db_input.trim().is_empty()
+                && parse_smiles(&state.ui.db_input).is_ok()
+                && ui
+                    .button("Load SMILES")
+                    .on_hover_text(
+                        "Create a ligand from this SMILES text, with hydrogens and 3D coordinates.",
+                    )
+                    .clicked()
+            {
+                let text = state.ui.db_input.trim().to_owned();
+                match state.open_smiles(&text, scene, &mut engine_updates) {
+                    Ok(()) => {
+                        state.ui.db_input = String::new();
+                        redraw_lig = true;
+                        reset_cam = true;
+                    }
+                    Err(e) => handle_err(&mut state.ui, format!("Error loading SMILES: {e}")),
+                }
+            }
+
             if state.peptide.is_none() && state.active_mol().is_none() {
                 ui.add_space(COL_SPACING / 2.);
                 if ui
@@ -1350,6 +1415,27 @@ pub fn ui_handler(state: &mut State, ctx: &Context, scene: &mut Scene) -> Engine
             }
         }
 
+        if state.ui.ui_vis.selfies {
+            if let Some(mol) = &state.active_mol() &&
+                let MolGenericRef::Ligand(m) = mol {
+                if let Some(selfies) = &m.selfies {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label("SELFIES: ");
+                        ui.label(RichText::new(selfies).color(Color32::LIGHT_GRAY));
+                    });
+                }
+            }
+        }
+
+        if state.ui.ui_vis.descriptors {
+            if let Some(mol) = &state.active_mol() &&
+                let MolGenericRef::Ligand(m) = mol {
+                if let Some(d) = &m.descriptors {
+                    mol_data::descriptors_disp(d, ui);
+                }
+            }
+        }
+
         draw_cli(
             state,
             scene,
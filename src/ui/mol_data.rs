@@ -3,7 +3,9 @@
 use bio_apis::{drugbank, lmsd, pdbe, pubchem, rcsb};
 use bio_files::{ResidueType, md_params::ForceFieldParams};
 use dynamics::params::FfParamSet;
-use egui::{Align, Color32, Layout, Popup, PopupAnchor, Pos2, RectAlign, RichText, ScrollArea, Ui};
+use egui::{
+    Align, Color32, Layout, Popup, PopupAnchor, Pos2, RectAlign, RichText, ScrollArea, TextEdit, Ui,
+};
 use graphics::{ControlScheme, EngineUpdates, EntityUpdate, Scene};
 use lin_alg::f64::Vec3;
 
@@ -12,8 +14,10 @@ use crate::{
     cam_misc::move_mol_to_cam,
     download_mols, drawing,
     drawing::{CHARGE_MAP_MAX, CHARGE_MAP_MIN, COLOR_AA_NON_RESIDUE_EGUI},
+    fingerprint::{FpKind, HitSource, SimilaritySearch},
     label,
     lipid::MoleculeLipid,
+    mol_characterization::Descriptors,
     mol_lig::MoleculeSmall,
     mol_manip::{ManipMode, set_manip},
     molecule::{
@@ -21,6 +25,7 @@ use crate::{
         aa_color,
     },
     nucleic_acid::MoleculeNucleicAcid,
+    protonation::{ProtonationStates, SiteKind, Species},
     ui::{
         COL_SPACING, COLOR_ACTION, COLOR_ACTIVE, COLOR_ACTIVE_RADIO, COLOR_HIGHLIGHT,
         COLOR_INACTIVE, ROW_SPACING, cam::move_cam_to_active_mol, mol_descrip,
@@ -112,6 +117,10 @@ fn disp_atom_data(
         // todo: In some cases, this is getting rendered over the initial text? EGUI error?
         label!(ui, format!("{plus}q: {q:.2}"), color);
     }
+
+    if let Some(b) = &atom.b_factor {
+        label!(ui, format!("B: {b:.1}"), Color32::LIGHT_GRAY);
+    }
 }
 
 // todo: This would ideally be a method on FfParamSet, but that lib doesn't have access to our MolType enum.
@@ -774,41 +783,57 @@ pub(in crate::ui) fn display_mol_data(
                 if ui
                     .button(RichText::new("Similar mols").color(COLOR_HIGHLIGHT))
                     .on_hover_text(
-                        "Using PubChem, find, download, and open similar molecules to this one.",
+                        "Find molecules similar to this one among open ligands, and the loaded \
+                        library, using fingerprint Tanimoto similarity.",
                     )
                     .clicked()
                 {
-                    let mol = &state.ligands[active_mol_i];
-
-                    // todo: This needs to be in its own thread; long-running blockign call.
-
-                    // todo: Support more than CID. Requires a mode to rcsb api.
-                    for ident in &mol.idents {
-                        if let MolIdent::PubChem(cid) = ident {
-                            // todo: This doesn't show because it doesn't get a chance to render prior to the block.
-                            handle_success(&mut state.ui, "Searching for similar molecules...".to_string());
-                            match pubchem::find_similar_mols(*cid) {
-                                Ok(cids) => {
-                                    // todo: This is temp
-                                    println!("Similar mols to {cid}: {:?}", cids);
-                                    let max_results = 20;
-                                    let cids_str = cids
-                                        .iter()
-                                        .take(max_results)
-                                        .map(|x| x.to_string())
-                                        .collect::<Vec<_>>()
-                                        .join(", ");
-
-                                    handle_success(&mut state.ui, format!("Similar PubChem CIDs: {cids_str}..."));
-                                }
+                    let kind = state.ui.popup.similar.as_ref().map(|s| s.kind).unwrap_or_default();
+                    let hits = state.find_similar(active_mol_i, kind);
+                    state.ui.popup.similar = Some(SimilaritySearch { query: active_mol_i, kind, hits });
+                }
 
-                                Err(e) => {
-                                    handle_err(&mut state.ui, "Problem finding similar molecules on PubChem".to_owned());
-                                }
-                            }
-                            break;
-                        }
+                if state.ligands.len() > 1 && ui
+                    .button(RichText::new("Align all").color(COLOR_ACTION))
+                    .on_hover_text(
+                        "Superpose all other open ligands onto this one, using their maximum \
+                        common substructure. Reports RMSD over the matched atoms.",
+                    )
+                    .clicked()
+                {
+                    match state.align_ligands_to(active_mol_i) {
+                        Ok(msg) => handle_success(&mut state.ui, msg),
+                        Err(e) => handle_err(&mut state.ui, e.to_string()),
+                    }
+                    *redraw_lig = true;
+                }
+
+                if ui
+                    .button(RichText::new("Protonation").color(COLOR_HIGHLIGHT))
+                    .on_hover_text(
+                        "Show this molecule's ionizable groups, its protonation states at the \
+                        configured pH, and its tautomers.",
+                    )
+                    .clicked()
+                {
+                    let mol = &state.ligands[active_mol_i].common;
+                    state.ui.popup.protonation =
+                        Some(ProtonationStates::new(mol, active_mol_i, state.to_save.ph));
+                }
+
+                if ui
+                    .button(RichText::new("Stereoisomers").color(COLOR_ACTION))
+                    .on_hover_text(
+                        "Add every stereoisomer of this molecule to the scene, with 3D \
+                        coordinates relaxed using GAFF2.",
+                    )
+                    .clicked()
+                {
+                    match state.add_stereoisomers(active_mol_i, scene, engine_updates) {
+                        Ok(msg) => handle_success(&mut state.ui, msg),
+                        Err(e) => handle_err(&mut state.ui, e.to_string()),
                     }
+                    *redraw_lig = true;
                 }
 
                 if ui.button("Metadata")
@@ -820,6 +845,26 @@ pub(in crate::ui) fn display_mol_data(
                         state.ui.popup.metadata = Some((MolType::Ligand, active_mol_i))
                     }
                 }
+
+                ui.add_space(COL_SPACING);
+                ui.label("SMARTS:");
+                ui.add_sized(
+                    [80., Ui::available_height(ui)],
+                    TextEdit::singleline(&mut state.ui.smarts_input),
+                );
+
+                if ui.button(RichText::new("Find").color(COLOR_ACTION))
+                    .on_hover_text("Select atoms in this molecule matching a SMARTS substructure pattern.")
+                    .clicked() {
+                    let pattern = state.ui.smarts_input.clone();
+                    match state.select_smarts(&pattern) {
+                        Ok(msg) => {
+                            handle_success(&mut state.ui, msg);
+                            *redraw_lig = true;
+                        }
+                        Err(e) => handle_err(&mut state.ui, e.to_string()),
+                    }
+                }
             }
         }
 
@@ -900,6 +945,291 @@ pub(in crate::ui) fn display_mol_data(
     });
 }
 
+/// Display physicochemical descriptors, and drug-likeness verdicts for a small molecule.
+pub(super) fn descriptors_disp(d: &Descriptors, ui: &mut Ui) {
+    let verdict = |ui: &mut Ui, name: &str, pass: bool, help: &str| {
+        let color = if pass {
+            Color32::LIGHT_GREEN
+        } else {
+            Color32::LIGHT_RED
+        };
+        label!(ui, name, color).on_hover_text(help);
+    };
+
+    ui.horizontal_wrapped(|ui| {
+        label!(ui, &d.formula, Color32::WHITE);
+        ui.add_space(COL_SPACING / 2.);
+
+        let vals = [
+            ("MW", format!("{:.2}", d.mol_weight)),
+            ("Exact", format!("{:.4}", d.exact_mass)),
+            ("logP", format!("{:.2}", d.log_p)),
+            ("TPSA", format!("{:.1}", d.tpsa)),
+            ("HBD", d.h_donors.to_string()),
+            ("HBA", d.h_acceptors.to_string()),
+            ("Rot", d.rotatable_bonds.to_string()),
+            ("Charge", d.formal_charge.to_string()),
+            ("Heavy", d.heavy_atoms.to_string()),
+            ("Fsp3", format!("{:.2}", d.fraction_sp3)),
+        ];
+        for (name, val) in vals {
+            ui.label(format!("{name}:"));
+            label!(ui, val, Color32::LIGHT_GRAY);
+        }
+
+        ui.add_space(COL_SPACING / 2.);
+
+        verdict(
+            ui,
+            &format!("Lipinski ({} viol)", d.lipinski_violations()),
+            d.lipinski(),
+            "Rule of five: MW ≤ 500, logP ≤ 5, H-bond donors ≤ 5, and H-bond acceptors ≤ 10. \
+            Passes with up to one violation.",
+        );
+        verdict(
+            ui,
+            "Veber",
+            d.veber(),
+            "≤ 10 rotatable bonds, and TPSA ≤ 140 Å²",
+        );
+        verdict(
+            ui,
+            "Lead-like",
+            d.lead_like(),
+            "250 ≤ MW ≤ 350, logP ≤ 3.5, and ≤ 7 rotatable bonds",
+        );
+    });
+}
+
+/// Ranked results of a fingerprint similarity search, which can be opened directly.
+pub(super) fn similar_mols(
+    state: &mut State,
+    scene: &mut Scene,
+    ui: &mut Ui,
+    engine_updates: &mut EngineUpdates,
+) {
+    let Some(search) = &state.ui.popup.similar else {
+        return;
+    };
+
+    let popup_id = ui.make_persistent_id("similar_mols_popup");
+
+    let mut open = None;
+    let mut kind = search.kind;
+    let mut close = false;
+
+    Popup::new(
+        popup_id,
+        ui.ctx().clone(),
+        PopupAnchor::Position(Pos2::new(60., 60.)),
+        ui.layer_id(),
+    )
+    .align(RectAlign::BOTTOM_START)
+    .open(true)
+    .gap(4.0)
+    .show(|ui| {
+        ui.horizontal(|ui| {
+            let query = state
+                .ligands
+                .get(search.query)
+                .map(|m| m.common.ident.clone())
+                .unwrap_or_default();
+            ui.heading(RichText::new(format!("Similar to {query}")).color(Color32::WHITE));
+
+            ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                if ui
+                    .button(RichText::new("Close").color(Color32::LIGHT_RED))
+                    .clicked()
+                {
+                    close = true;
+                }
+            });
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Fingerprint:");
+            for k in [FpKind::Morgan, FpKind::Maccs] {
+                let color = if k == kind {
+                    COLOR_ACTIVE_RADIO
+                } else {
+                    COLOR_INACTIVE
+                };
+                if ui.button(RichText::new(k.to_str()).color(color)).clicked() {
+                    kind = k;
+                }
+            }
+        });
+
+        ui.add_space(ROW_SPACING);
+
+        if search.hits.is_empty() {
+            ui.label("No other open ligands, or library molecules to compare against.");
+        }
+
+        ScrollArea::vertical().max_height(600.0).show(ui, |ui| {
+            for hit in &search.hits {
+                ui.horizontal(|ui| {
+                    label!(ui, format!("{:.3}", hit.similarity), Color32::GOLD);
+                    label!(ui, &hit.ident, Color32::WHITE);
+
+                    let (source, help) = match hit.source {
+                        HitSource::Ligand(_) => {
+                            ("Open ligand", "Make this ligand the active molecule.")
+                        }
+                        HitSource::Library(_) => {
+                            ("Library", "Add this library molecule to the scene.")
+                        }
+                    };
+                    label!(ui, source, Color32::GRAY);
+
+                    if ui
+                        .button(RichText::new("Open").color(COLOR_ACTION))
+                        .on_hover_text(help)
+                        .clicked()
+                    {
+                        open = Some(hit.source);
+                    }
+                });
+            }
+        });
+    });
+
+    if kind != search.kind {
+        let query = search.query;
+        let hits = state.find_similar(query, kind);
+        state.ui.popup.similar = Some(SimilaritySearch { query, kind, hits });
+    }
+
+    match open {
+        Some(HitSource::Ligand(i)) => {
+            state.volatile.active_mol = Some((MolType::Ligand, i));
+            state.volatile.orbit_center = state.volatile.active_mol;
+
+            let center = match &state.peptide {
+                Some(p) => p.center,
+                None => Vec3::new_zero(),
+            };
+            move_cam_to_active_mol(state, scene, center, engine_updates);
+        }
+        Some(HitSource::Library(i)) => state.promote_library_mol(i, scene, engine_updates),
+        None => (),
+    }
+
+    if close {
+        state.ui.popup.similar = None;
+    }
+}
+
+/// A ligand's ionizable groups, protonation states, and tautomers.
+pub(super) fn protonation_states(state: &mut State, ui: &mut Ui, redraw_lig: &mut bool) {
+    let Some(states) = &state.ui.popup.protonation else {
+        return;
+    };
+
+    let popup_id = ui.make_persistent_id("protonation_popup");
+
+    let mut use_species = None;
+    let mut close = false;
+
+    let species_row = |ui: &mut Ui, species: &Species, use_species: &mut Option<MoleculeCommon>| {
+        ui.horizontal(|ui| {
+            if let Some(pop) = species.population {
+                label!(ui, format!("{:.1}%", pop * 100.), Color32::GOLD);
+            }
+            label!(ui, format!("{:+}", species.net_charge), Color32::LIGHT_BLUE);
+            label!(ui, &species.smiles, Color32::WHITE);
+
+            if ui
+                .button(RichText::new("Use").color(COLOR_ACTION))
+                .on_hover_text(
+                    "Set the ligand to this form, and re-assign its force field types, and \
+                    partial charges.",
+                )
+                .clicked()
+            {
+                *use_species = Some(species.mol.clone());
+            }
+        });
+    };
+
+    Popup::new(
+        popup_id,
+        ui.ctx().clone(),
+        PopupAnchor::Position(Pos2::new(60., 60.)),
+        ui.layer_id(),
+    )
+    .align(RectAlign::BOTTOM_START)
+    .open(true)
+    .gap(4.0)
+    .show(|ui| {
+        ui.horizontal(|ui| {
+            let ident = state
+                .ligands
+                .get(states.lig_i)
+                .map(|m| m.common.ident.clone())
+                .unwrap_or_default();
+            ui.heading(RichText::new(format!("Protonation: {ident}")).color(Color32::WHITE));
+
+            ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                if ui
+                    .button(RichText::new("Close").color(Color32::LIGHT_RED))
+                    .clicked()
+                {
+                    close = true;
+                }
+            });
+        });
+
+        ScrollArea::vertical().max_height(600.0).show(ui, |ui| {
+            if states.sites.is_empty() {
+                ui.label("No ionizable groups");
+            }
+            for site in &states.sites {
+                ui.horizontal(|ui| {
+                    let kind = match site.kind {
+                        SiteKind::Acid => "acid",
+                        SiteKind::Base => "base",
+                    };
+                    label!(ui, format!("{} ({kind})", site.name), Color32::WHITE);
+                    label!(ui, format!("pKa {:.1}", site.pka), Color32::GOLD);
+                    label!(
+                        ui,
+                        format!("{:.0}% ionized", site.ionized_frac(states.ph) * 100.),
+                        Color32::GRAY
+                    );
+                });
+            }
+
+            ui.add_space(ROW_SPACING);
+            ui.label(
+                RichText::new(format!("Protonation states at pH {:.1}", states.ph))
+                    .color(Color32::WHITE),
+            );
+            for species in &states.protomers {
+                species_row(ui, species, &mut use_species);
+            }
+
+            ui.add_space(ROW_SPACING);
+            ui.label(RichText::new("Tautomers").color(Color32::WHITE));
+            for species in &states.tautomers {
+                species_row(ui, species, &mut use_species);
+            }
+        });
+    });
+
+    if let Some(species) = use_species {
+        let lig_i = states.lig_i;
+        state.set_lig_species(lig_i, &species);
+        *redraw_lig = true;
+
+        handle_success(&mut state.ui, format!("Set the ligand to {}", species.to_smiles()));
+    }
+
+    if close {
+        state.ui.popup.protonation = None;
+    }
+}
+
 /// Display metadata stored for a given molecule.
 pub(super) fn metadata_disp(
     mol_type: MolType,
@@ -22,7 +22,7 @@ use crate::{
     mol_lig::MoleculeSmall,
     mol_manip,
     mol_manip::{ManipMode, MolManip},
-    molecule::{Bond, MolType},
+    molecule::{Bond, MolType, MoleculeCommon},
     ui::{
         COL_SPACING, COLOR_ACTION, COLOR_ACTIVE, COLOR_INACTIVE,
         cam::cam_reset_controls,
@@ -32,7 +32,7 @@ use crate::{
         mol_data::selected_data,
         view_sel_selector,
     },
-    util::handle_err,
+    util::{handle_err, handle_success},
 };
 // todo: Check DBs (with a button maybe?) to see if the molecule exists in a DB already, or if
 // todo a similar one does.
@@ -423,7 +423,8 @@ pub(in crate::ui) fn editor(
 
 fn bond_edit_tools(
     bond_sel_is: &[usize],
-    bonds: &mut [Bond],
+    mol: &mut MoleculeCommon,
+    state_ui: &mut StateUi,
     ui: &mut Ui,
     redraw: &mut bool,
     rebuild_md: &mut bool,
@@ -455,22 +456,92 @@ fn bond_edit_tools(
         }
         if ui
             .button("Ar")
-            .on_hover_text("Change this to an aromatic.")
+            .on_hover_text("Change this to an aromatic bond, along with the rest of its ring.")
             .clicked()
         {
             new_bond_type = Some(BondType::Aromatic);
         }
+
+        ui.add_space(COL_SPACING / 2.);
+
+        if ui
+            .button("Perceive Ar")
+            .on_hover_text(
+                "Find aromatic rings in the molecule using Hückel's rule, and mark their bonds \
+                aromatic. Bonds in other rings are converted to single and double bonds.",
+            )
+            .clicked()
+        {
+            mol.build_adjacency_list();
+            match mol.perceive_aromaticity() {
+                Ok(n) => handle_success(state_ui, format!("Found {n} aromatic rings")),
+                Err(e) => handle_err(state_ui, e.to_string()),
+            }
+            *redraw = true;
+            *rebuild_md = true;
+        }
+
+        if ui
+            .button("Kekulize")
+            .on_hover_text("Convert aromatic bonds to alternating single and double bonds.")
+            .clicked()
+        {
+            mol.build_adjacency_list();
+            if let Err(e) = mol.kekulize() {
+                handle_err(state_ui, e.to_string());
+            }
+            *redraw = true;
+            *rebuild_md = true;
+        }
     });
 
-    if let Some(bt) = new_bond_type {
-        for b in bond_sel_is {
-            bonds[*b].bond_type = bt;
+    let Some(bt) = new_bond_type else {
+        return;
+    };
+
+    if bt == BondType::Aromatic {
+        // Aromaticity is a property of rings; apply it to each ring containing a selected bond.
+        mol.build_adjacency_list();
+        let rings = mol.find_rings();
+        let mut ring_bonds = Vec::new();
+
+        for &b in bond_sel_is {
+            let (a0, a1) = (mol.bonds[b].atom_0, mol.bonds[b].atom_1);
+            let mut in_ring = false;
+
+            for ring in &rings {
+                let len = ring.len();
+                let contains = (0..len).any(|j| {
+                    let (x, y) = (ring[j], ring[(j + 1) % len]);
+                    (x, y) == (a0, a1) || (x, y) == (a1, a0)
+                });
+                if contains {
+                    in_ring = true;
+                    ring_bonds.extend((0..len).map(|j| (ring[j], ring[(j + 1) % len])));
+                }
+            }
+
+            if !in_ring {
+                handle_err(state_ui, "Only bonds in rings can be aromatic".to_owned());
+            }
         }
 
-        *redraw = true;
-        *rebuild_md = true;
-        *rebuild_ff_params = true;
+        for bond in &mut mol.bonds {
+            if ring_bonds.iter().any(|&(x, y)| {
+                (x, y) == (bond.atom_0, bond.atom_1) || (x, y) == (bond.atom_1, bond.atom_0)
+            }) {
+                bond.bond_type = BondType::Aromatic;
+            }
+        }
+    } else {
+        for b in bond_sel_is {
+            mol.bonds[*b].bond_type = bt;
+        }
     }
+
+    *redraw = true;
+    *rebuild_md = true;
+    *rebuild_ff_params = true;
 }
 
 fn edit_tools(
@@ -508,7 +579,8 @@ fn edit_tools(
         let mut rebuild_ff_params = false;
         bond_edit_tools(
             &selected_idxs,
-            &mut state.mol_editor.mol.common.bonds,
+            &mut state.mol_editor.mol.common,
+            &mut state.ui,
             ui,
             redraw,
             &mut rebuild_md,
@@ -1,20 +1,27 @@
 //! Optional toolbars for nucleic acids, lipids etc.
 
-use egui::{Color32, ComboBox, RichText, TextEdit, Ui};
+use egui::{
+    Align, Color32, ComboBox, Layout, Popup, PopupAnchor, Pos2, RectAlign, RichText, ScrollArea,
+    Slider, TextEdit, Ui,
+};
 use graphics::{EngineUpdates, EntityUpdate, FWD_VEC, Scene};
 use na_seq::seq_from_str;
 
 use crate::{
     State,
-    docking::dock,
-    drawing::EntityClass,
+    docking::{
+        DockingSite, dock, external::PoseComparison, find_sites::find_docking_sites, refine_md,
+        score_pose,
+    },
+    drawing::{EntityClass, draw_peptide},
     drawing_wrappers::{draw_all_lipids, draw_all_nucleic_acids},
+    label,
     lipid::{LipidShape, make_bacterial_lipids},
     molecule::MolGenericRef,
     nucleic_acid::{MoleculeNucleicAcid, NucleicAcidType, Strands},
     ui,
-    ui::{COL_SPACING, COLOR_ACTION, misc::section_box},
-    util::{clear_mol_entity_indices, handle_err},
+    ui::{COL_SPACING, COLOR_ACTION, COLOR_ACTIVE, ROW_SPACING, misc::section_box},
+    util::{clear_mol_entity_indices, handle_err, handle_success},
 };
 
 pub(in crate::ui) fn mol_type_toolbars(
@@ -34,35 +41,601 @@ pub(in crate::ui) fn mol_type_toolbars(
             aa_section(state, scene, engine_updates, ui);
         }
 
-        if let Some(mol) = &state.active_mol()
-            && state.peptide.is_some()
+        if state.peptide.is_some() {
+            docking_section(state, scene, engine_updates, ui);
+        }
+
+        if let Some(MolGenericRef::Ligand(lig)) = state.active_mol()
+            && !lig.ext_poses.is_empty()
         {
-            if let MolGenericRef::Ligand(_) = mol {
-                ui.add_space(COL_SPACING);
-                ui.label("Docking:");
+            imported_poses_section(state, scene, engine_updates, ui);
+        }
+    });
+}
+
+/// Browse the active ligand's poses imported from another docking program, e.g. Vina, and
+/// compare their interactions with the protein.
+fn imported_poses_section(
+    state: &mut State,
+    scene: &mut Scene,
+    engine_updates: &mut EngineUpdates,
+    ui: &mut Ui,
+) {
+    let lig_i = state.volatile.active_mol.unwrap().1;
+    let lig = &state.ligands[lig_i];
+
+    let num_poses = lig.ext_poses.len();
+    let pose = &lig.ext_poses[lig.ext_pose_i];
 
+    ui.add_space(COL_SPACING);
+    ui.label("Imported poses:");
+
+    let mut pose_num = lig.ext_pose_i + 1;
+    ui.spacing_mut().slider_width = 120.;
+    ui.add(Slider::new(&mut pose_num, 1..=num_poses));
+
+    let score = match pose.score {
+        Some(s) => format!("{s:.2} kcal/mol"),
+        None => "No score".to_owned(),
+    };
+    let props: Vec<_> = pose
+        .props
+        .iter()
+        .map(|(name, v)| format!("{name}: {v:.3}"))
+        .collect();
+
+    let label = ui.label(RichText::new(score).color(Color32::LIGHT_BLUE));
+    if !props.is_empty() {
+        label.on_hover_text(props.join("\n"));
+    }
+
+    if let Some(pep) = &state.peptide
+        && ui
+            .button(RichText::new("Compare").color(COLOR_ACTION))
+            .on_hover_text(
+                "Compare the interactions of each pose with the protein: Hydrogen bonds, \
+                hydrophobic contacts, clashes, and contacting residues.",
+            )
+            .clicked()
+    {
+        state.ui.popup.pose_comparison = match state.ui.popup.pose_comparison {
+            Some(_) => None,
+            None => Some(PoseComparison::new(pep, lig, lig_i)),
+        };
+    }
+
+    if pose_num != lig.ext_pose_i + 1 {
+        state.set_ext_pose(lig_i, pose_num - 1, scene, engine_updates);
+    }
+}
+
+/// Interactions of each imported pose with the protein, side by side, with the option to view each.
+pub(in crate::ui) fn pose_comparison(
+    state: &mut State,
+    scene: &mut Scene,
+    ui: &mut Ui,
+    engine_updates: &mut EngineUpdates,
+) {
+    let Some(comparison) = &state.ui.popup.pose_comparison else {
+        return;
+    };
+    let (Some(lig), Some(pep)) = (state.ligands.get(comparison.lig_i), &state.peptide) else {
+        state.ui.popup.pose_comparison = None;
+        return;
+    };
+
+    let popup_id = ui.make_persistent_id("pose_comparison_popup");
+
+    let mut view = None;
+    let mut close = false;
+
+    Popup::new(
+        popup_id,
+        ui.ctx().clone(),
+        PopupAnchor::Position(Pos2::new(60., 60.)),
+        ui.layer_id(),
+    )
+    .align(RectAlign::BOTTOM_START)
+    .open(true)
+    .gap(4.0)
+    .show(|ui| {
+        ui.horizontal(|ui| {
+            ui.heading(
+                RichText::new(format!("Imported poses: {}", lig.common.ident))
+                    .color(Color32::WHITE),
+            );
+
+            ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
                 if ui
-                    .button(RichText::new("Dock").color(Color32::GOLD))
+                    .button(RichText::new("Close").color(Color32::LIGHT_RED))
                     .clicked()
                 {
-                    // The other views make it tough to see the ligand rel the protein.
-                    // if !matches!(state.ui.mol_view, MoleculeView::SpaceFill | MoleculeView::Surface) {
-                    //     // todo: Dim peptide?
-                    //     state.ui.mol_view = MoleculeView::Surface;
-                    // }
-
-                    if let Err(e) = dock(
-                        state,
-                        state.volatile.active_mol.unwrap().1,
-                        scene,
-                        engine_updates,
-                    ) {
-                        handle_err(&mut state.ui, format!("Problem setting up docking: {e:?}"));
+                    close = true;
+                }
+            });
+        });
+        ui.add_space(ROW_SPACING);
+
+        ScrollArea::vertical().max_height(600.0).show(ui, |ui| {
+            for (i, (pose, inter)) in lig
+                .ext_poses
+                .iter()
+                .zip(&comparison.interactions)
+                .enumerate()
+            {
+                ui.horizontal(|ui| {
+                    let color = if i == lig.ext_pose_i {
+                        COLOR_ACTIVE
+                    } else {
+                        Color32::WHITE
+                    };
+
+                    label!(ui, format!("#{}", i + 1), color);
+                    if let Some(score) = pose.score {
+                        label!(ui, format!("{score:.2}"), Color32::GOLD);
+                    }
+                    label!(
+                        ui,
+                        format!("Our score: {:.2}", inter.score.score),
+                        Color32::LIGHT_GREEN
+                    );
+                    label!(
+                        ui,
+                        format!(
+                            "H bonds: {}  Hydrophobic: {}",
+                            inter.h_bonds, inter.hydrophobic
+                        ),
+                        Color32::LIGHT_BLUE
+                    );
+
+                    let clash_color = if inter.clashes > 0 {
+                        Color32::LIGHT_RED
+                    } else {
+                        Color32::GRAY
+                    };
+                    label!(ui, format!("Clashes: {}", inter.clashes), clash_color);
+
+                    let residues: Vec<_> = inter
+                        .residues
+                        .iter()
+                        .filter_map(|&r| pep.residues.get(r))
+                        .map(|r| format!("{}{}", r.res_type, r.seq_label()))
+                        .collect();
+                    ui.label(
+                        RichText::new(format!("Residues: {}", residues.len())).color(Color32::GRAY),
+                    )
+                    .on_hover_text(residues.join(", "));
+
+                    if ui
+                        .button(RichText::new("View").color(COLOR_ACTION))
+                        .on_hover_text("Apply this pose to the ligand.")
+                        .clicked()
+                    {
+                        view = Some(i);
                     }
+                });
+            }
+        });
+        ui.add_space(ROW_SPACING);
+
+        ui.label(
+            RichText::new(
+                "The first score is the docking program's, e.g. Vina's affinity. Ours is from our \
+                Vina-like scoring function. Both are in kcal/mol; lower is better. Hover over \
+                residue counts to list them.",
+            )
+            .color(Color32::GRAY),
+        );
+    });
+
+    let lig_i = comparison.lig_i;
+    if let Some(i) = view {
+        state.set_ext_pose(lig_i, i, scene, engine_updates);
+    }
+    if close {
+        state.ui.popup.pose_comparison = None;
+    }
+}
+
+/// Pocket detection, and docking ligands.
+fn docking_section(
+    state: &mut State,
+    scene: &mut Scene,
+    engine_updates: &mut EngineUpdates,
+    ui: &mut Ui,
+) {
+    let Some(pep) = &state.peptide else {
+        return;
+    };
+
+    ui.add_space(COL_SPACING);
+    ui.label("Docking:");
+
+    let mut redraw_sites = false;
+
+    if ui
+        .button(RichText::new("Find pockets").color(COLOR_ACTION))
+        .on_hover_text(
+            "Detect binding pockets on the protein, and display them as spheres. Click a \
+            sphere, or choose a pocket here, to select it.",
+        )
+        .clicked()
+    {
+        let sites = find_docking_sites(pep);
+        let count = sites.len();
+
+        state.peptide.as_mut().unwrap().docking_sites = sites;
+        state.ui.docking_site_sel = if count > 0 { Some(0) } else { None };
+        redraw_sites = true;
+
+        handle_success(&mut state.ui, format!("Found {count} pockets"));
+    }
+
+    let pep = state.peptide.as_ref().unwrap();
+    if !pep.docking_sites.is_empty() {
+        let site_text = |i: usize| {
+            let site = &pep.docking_sites[i];
+            format!("#{}: score {:.2}, {:.0} Å³", i + 1, site.score, site.volume)
+        };
+
+        let mut sel = state.ui.docking_site_sel;
+        ComboBox::from_id_salt(103)
+            .width(160.)
+            .selected_text(sel.map(site_text).unwrap_or_default())
+            .show_ui(ui, |ui| {
+                for i in 0..pep.docking_sites.len() {
+                    ui.selectable_value(&mut sel, Some(i), site_text(i));
                 }
+            });
+
+        if sel != state.ui.docking_site_sel {
+            state.ui.docking_site_sel = sel;
+            redraw_sites = true;
+        }
+
+        if let Some(site) = sel.and_then(|i| pep.docking_sites.get(i)) {
+            let residues: Vec<_> = site
+                .residues
+                .iter()
+                .filter_map(|&i| pep.residues.get(i))
+                .map(|r| format!("{}{}", r.res_type, r.seq_label()))
+                .collect();
+
+            ui.label(format!("r: {:.1} Å", site.site.site_radius))
+                .on_hover_text(format!("Lining residues: {}", residues.join(", ")));
+        }
+
+        if ui
+            .button(RichText::new("Clear").color(Color32::LIGHT_RED))
+            .on_hover_text("Remove the detected pockets.")
+            .clicked()
+        {
+            state.peptide.as_mut().unwrap().docking_sites = Vec::new();
+            state.ui.docking_site_sel = None;
+            redraw_sites = true;
+        }
+    }
+
+    if matches!(state.active_mol(), Some(MolGenericRef::Ligand(_))) {
+        let mol_i = state.volatile.active_mol.unwrap().1;
+
+        if ui
+            .button(RichText::new("Dock").color(Color32::GOLD))
+            .on_hover_text(
+                "Dock the active ligand near its current position, and rank the resulting poses \
+                by interaction energy.",
+            )
+            .clicked()
+        {
+            // The other views make it tough to see the ligand rel the protein.
+            // if !matches!(state.ui.mol_view, MoleculeView::SpaceFill | MoleculeView::Surface) {
+            //     // todo: Dim peptide?
+            //     state.ui.mol_view = MoleculeView::Surface;
+            // }
+
+            dock_helper(state, mol_i, None, scene, engine_updates);
+        }
+
+        if ui
+            .button(RichText::new("Score").color(COLOR_ACTION))
+            .on_hover_text(
+                "Score the active ligand's current pose against the protein, using a \
+                Vina-like empirical scoring function. Lower is better.",
+            )
+            .clicked()
+        {
+            let s = score_pose(
+                state.peptide.as_ref().unwrap(),
+                &state.ligands[mol_i].common,
+            );
+            let t = &s.terms;
+
+            handle_success(
+                &mut state.ui,
+                format!(
+                    "Score: {:.2} kcal/mol. Gauss: {:.2}, {:.2}  Repulsion: {:.2}  \
+                    Hydrophobic: {:.2}  H bond: {:.2}",
+                    s.score, t.gauss_1, t.gauss_2, t.repulsion, t.hydrophobic, t.h_bond
+                ),
+            );
+        }
+
+        let site = state.peptide.as_ref().and_then(|p| {
+            state
+                .ui
+                .docking_site_sel
+                .and_then(|i| p.docking_sites.get(i))
+                .map(|s| s.site.clone())
+        });
+
+        if let Some(site) = site
+            && ui
+                .button(RichText::new("Dock here").color(Color32::GOLD))
+                .on_hover_text("Dock the active ligand in the selected pocket.")
+                .clicked()
+        {
+            let ident = state.peptide.as_ref().unwrap().common.ident.clone();
+            if let Some(data) = state.to_save.per_mol.get_mut(&ident) {
+                data.docking_site = site.clone();
             }
+
+            dock_helper(state, mol_i, Some(&site), scene, engine_updates);
+        }
+    }
+
+    if let Some(job) = &state.volatile.screening {
+        ui.add_space(COL_SPACING / 2.);
+
+        let best = match &job.best {
+            Some(b) => format!("Best so far: {} ({:.2} kcal/mol)", b.ident, b.score),
+            None => "No molecules docked yet".to_owned(),
+        };
+        ui.label(
+            RichText::new(format!("Screening: {}/{}", job.done, job.total))
+                .color(Color32::LIGHT_BLUE),
+        )
+        .on_hover_text(format!("{best}. Failed: {}", job.failed));
+
+        if ui
+            .button(RichText::new("Stop").color(Color32::LIGHT_RED))
+            .on_hover_text(
+                "Stop screening after the molecules in progress. Progress is saved; screen the \
+                library again to resume.",
+            )
+            .clicked()
+        {
+            job.cancel();
+        }
+    } else if state.library.is_some() {
+        let site = state.peptide.as_ref().and_then(|p| {
+            state
+                .ui
+                .docking_site_sel
+                .and_then(|i| p.docking_sites.get(i))
+                .map(|s| s.site.clone())
+        });
+
+        if let Some(site) = site
+            && ui
+                .button(RichText::new("Screen library").color(Color32::GOLD))
+                .on_hover_text(
+                    "Dock each molecule of the library that passes its filter in the selected \
+                    pocket, in the background. Results are ranked by score, and saved as CSV and \
+                    SDF files next to the library file.",
+                )
+                .clicked()
+            && let Err(e) = state.start_screen(&site)
+        {
+            handle_err(&mut state.ui, format!("Problem starting the screen: {e}"));
+        }
+    }
+
+    if let Some(docking) = &state.docking
+        && let Some(pose) = docking.poses.get(docking.pose_i)
+    {
+        let pose_i = docking.pose_i;
+        let num_poses = docking.poses.len();
+        let lig_i = docking.lig_i;
+
+        let mut pose_new = None;
+        let mut finish = None;
+        let mut refine = false;
+
+        ui.add_space(COL_SPACING / 2.);
+
+        if ui.button("◀").clicked() && pose_i > 0 {
+            pose_new = Some(pose_i - 1);
+        }
+
+        ui.label(
+            RichText::new(format!(
+                "Pose {}/{num_poses}: {:.1} kcal/mol",
+                pose_i + 1,
+                pose.potential_energy
+            ))
+            .color(Color32::LIGHT_BLUE),
+        )
+        .on_hover_text(format!(
+            "Lennard-Jones: {:.1} kcal/mol. Electrostatic: {:.1} kcal/mol. Score: {:.2} kcal/mol. \
+            RMSD from the top pose: {:.2} Å",
+            pose.energy_vdw, pose.energy_elec, pose.score, pose.rmsd_to_best
+        ));
+
+        if ui.button("▶").clicked() && pose_i + 1 < num_poses {
+            pose_new = Some(pose_i + 1);
+        }
+
+        if ui
+            .button(RichText::new("Poses").color(COLOR_ACTION))
+            .on_hover_text("Compare all docked poses.")
+            .clicked()
+        {
+            state.ui.popup.docking_poses = !state.ui.popup.docking_poses;
+        }
+
+        if ui
+            .button(RichText::new("Refine").color(COLOR_ACTION))
+            .on_hover_text(
+                "Refine this pose with molecular dynamics, including nearby protein atoms. \
+                View the result as MD snapshots.",
+            )
+            .clicked()
+        {
+            refine = true;
+        }
+
+        if ui
+            .button(RichText::new("Keep").color(Color32::LIGHT_GREEN))
+            .on_hover_text("Keep this pose as the ligand's position, and end docking.")
+            .clicked()
+        {
+            finish = Some(true);
+        }
+
+        if ui
+            .button(RichText::new("Discard").color(Color32::LIGHT_RED))
+            .on_hover_text("Restore the ligand's position from before docking.")
+            .clicked()
+        {
+            finish = Some(false);
         }
+
+        if let Some(i) = pose_new {
+            state.set_docking_pose(i, scene, engine_updates);
+        }
+
+        if refine && let Err(e) = refine_md(state, lig_i, scene, engine_updates) {
+            handle_err(&mut state.ui, format!("Problem refining the pose: {e:?}"));
+        }
+
+        if let Some(keep) = finish {
+            state.finish_docking(keep, scene, engine_updates);
+            state.ui.popup.docking_poses = false;
+        }
+    }
+
+    if redraw_sites {
+        draw_peptide(state, scene);
+        engine_updates.entities = EntityUpdate::All;
+    }
+}
+
+fn dock_helper(
+    state: &mut State,
+    mol_i: usize,
+    site: Option<&DockingSite>,
+    scene: &mut Scene,
+    engine_updates: &mut EngineUpdates,
+) {
+    match dock(state, mol_i, site, scene, engine_updates) {
+        Ok(count) => handle_success(
+            &mut state.ui,
+            format!("Docking complete; found {count} distinct poses"),
+        ),
+        Err(e) => handle_err(&mut state.ui, format!("Problem docking: {e:?}")),
+    }
+}
+
+/// A table comparing docked poses, with the option to view each.
+pub(in crate::ui) fn docking_poses(
+    state: &mut State,
+    scene: &mut Scene,
+    ui: &mut Ui,
+    engine_updates: &mut EngineUpdates,
+) {
+    let Some(docking) = &state.docking else {
+        state.ui.popup.docking_poses = false;
+        return;
+    };
+
+    let popup_id = ui.make_persistent_id("docking_poses_popup");
+
+    let mut view = None;
+    let mut close = false;
+
+    Popup::new(
+        popup_id,
+        ui.ctx().clone(),
+        PopupAnchor::Position(Pos2::new(60., 60.)),
+        ui.layer_id(),
+    )
+    .align(RectAlign::BOTTOM_START)
+    .open(true)
+    .gap(4.0)
+    .show(|ui| {
+        ui.horizontal(|ui| {
+            let ident = state
+                .ligands
+                .get(docking.lig_i)
+                .map(|l| l.common.ident.as_str())
+                .unwrap_or_default();
+            ui.heading(RichText::new(format!("Docked poses: {ident}")).color(Color32::WHITE));
+
+            ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                if ui
+                    .button(RichText::new("Close").color(Color32::LIGHT_RED))
+                    .clicked()
+                {
+                    close = true;
+                }
+            });
+        });
+        ui.add_space(ROW_SPACING);
+
+        ScrollArea::vertical().max_height(600.0).show(ui, |ui| {
+            for (i, pose) in docking.poses.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    let color = if i == docking.pose_i {
+                        COLOR_ACTIVE
+                    } else {
+                        Color32::WHITE
+                    };
+
+                    label!(ui, format!("#{}", i + 1), color);
+                    label!(ui, format!("{:.1}", pose.potential_energy), Color32::GOLD);
+                    label!(
+                        ui,
+                        format!("LJ: {:.1}  Elec: {:.1}", pose.energy_vdw, pose.energy_elec),
+                        Color32::LIGHT_BLUE
+                    );
+                    label!(
+                        ui,
+                        format!("Score: {:.2}", pose.score),
+                        Color32::LIGHT_GREEN
+                    );
+                    label!(
+                        ui,
+                        format!("RMSD: {:.2} Å", pose.rmsd_to_best),
+                        Color32::GRAY
+                    );
+
+                    if ui
+                        .button(RichText::new("View").color(COLOR_ACTION))
+                        .on_hover_text("Apply this pose to the ligand.")
+                        .clicked()
+                    {
+                        view = Some(i);
+                    }
+                });
+            }
+        });
+        ui.add_space(ROW_SPACING);
+
+        ui.label(
+            RichText::new(
+                "Energies are ligand-protein interaction energies, in kcal/mol. Scores are \
+                empirical estimates of binding free energy, in kcal/mol.",
+            )
+            .color(Color32::GRAY),
+        );
     });
+
+    if let Some(i) = view {
+        state.set_docking_pose(i, scene, engine_updates);
+    }
+    if close {
+        state.ui.popup.docking_poses = false;
+    }
 }
 
 /// Add and manage lipids
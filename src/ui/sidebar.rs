@@ -4,7 +4,8 @@ use graphics::{ControlScheme, EngineUpdates, EntityUpdate, Scene};
 use crate::{
     State,
     molecule::{MolType, MoleculeCommon},
-    ui::{COLOR_ACTION, COLOR_ACTIVE, COLOR_ACTIVE_RADIO, COLOR_INACTIVE},
+    scaffold::{ClusterBy, ClusterSource},
+    ui::{COLOR_ACTION, COLOR_ACTIVE, COLOR_ACTIVE_RADIO, COLOR_HIGHLIGHT, COLOR_INACTIVE},
     util::{close_mol, handle_err, orbit_center},
 };
 
@@ -311,6 +312,69 @@ pub(in crate::ui) fn sidebar(
                         handle_err(&mut state.ui, "Problem saving this file".to_owned());
                     }
                 }
+
+                if ui
+                    .button(RichText::new("Session").color(Color32::GRAY))
+                    .on_hover_text(
+                        "Save all open molecules, their positions, the view, and density maps \
+                        to a single session (.mcs) file. Open it with the Open button.",
+                    )
+                    .clicked()
+                {
+                    state.volatile.dialogs.save.config_mut().default_file_name =
+                        "session.mcs".to_owned();
+                    state.volatile.dialogs.save.save_file();
+                }
+
+                if let Some(lib) = &state.library {
+                    let color = if state.ui.popup.library {
+                        Color32::LIGHT_RED
+                    } else {
+                        COLOR_HIGHLIGHT
+                    };
+                    if ui
+                        .button(RichText::new("Library").color(color))
+                        .on_hover_text(format!(
+                            "Browse the {} molecules loaded from a multi-record file",
+                            lib.mols.len()
+                        ))
+                        .clicked()
+                    {
+                        state.ui.popup.library = !state.ui.popup.library;
+                    }
+                }
+
+                if state.ligands.len() > 1 || state.library.is_some() {
+                    let color = if state.ui.popup.clusters.is_some() {
+                        Color32::LIGHT_RED
+                    } else {
+                        COLOR_HIGHLIGHT
+                    };
+                    if ui
+                        .button(RichText::new("Clusters").color(color))
+                        .on_hover_text(
+                            "Group open ligands, or library molecules into series, by scaffold \
+                            or by fingerprint similarity.",
+                        )
+                        .clicked()
+                    {
+                        state.ui.popup.clusters = if state.ui.popup.clusters.is_some() {
+                            None
+                        } else {
+                            let source = if state.ligands.len() > 1 {
+                                ClusterSource::Ligands
+                            } else {
+                                ClusterSource::Library
+                            };
+                            Some(state.cluster_mols(source, ClusterBy::Scaffold))
+                        };
+                    }
+                }
+
+                if state.mol_dynamics.is_some() {
+                    ui.checkbox(&mut state.ui.session_include_md, "MD")
+                        .on_hover_text("Include MD snapshots in saved sessions.");
+                }
             });
 
             ui.separator();
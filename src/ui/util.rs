@@ -15,7 +15,7 @@ use crate::{
     molecule::{MolGenericRef, MolType, MoleculeGeneric},
     render::{set_flashlight, set_static_light},
     ui::{COL_SPACING, COLOR_HIGHLIGHT, ROW_SPACING, set_window_title},
-    util::{handle_err, reset_orbit_center},
+    util::{handle_err, handle_success, reset_orbit_center},
 };
 
 /// Run this each frame, after all UI elements that affect it are rendered.
@@ -31,6 +31,11 @@ pub fn update_file_dialogs(
 
     state.volatile.dialogs.load.update(ctx);
     state.volatile.dialogs.save.update(ctx);
+    state.volatile.dialogs.traj_load.update(ctx);
+    state.volatile.dialogs.traj_save.update(ctx);
+    state.volatile.dialogs.library_save.update(ctx);
+    state.volatile.dialogs.library_csv_save.update(ctx);
+    state.volatile.dialogs.mesh_save.update(ctx);
 
     if let Some(path) = &state.volatile.dialogs.load.take_picked() {
         if let Err(e) = match state.volatile.operating_mode {
@@ -63,12 +68,73 @@ pub fn update_file_dialogs(
     }
 
     if let Some(path) = &state.volatile.dialogs.save.take_picked() {
+        let is_session = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("mcs"));
+
         match state.volatile.operating_mode {
+            // Sessions include the camera, which `State::save` doesn't have access to.
+            OperatingMode::Primary if is_session => {
+                let include_md = state.ui.session_include_md;
+                if let Err(e) = state.save_session(path, scene, include_md) {
+                    handle_err(&mut state.ui, e.to_string());
+                }
+            }
             OperatingMode::Primary => state.save(path)?,
             OperatingMode::MolEditor => mol_editor::save(state, path)?,
         }
     }
 
+    if let Some(path) = &state.volatile.dialogs.traj_load.take_picked() {
+        if let Err(e) = state.open_trajectory(path, scene, engine_updates) {
+            handle_err(&mut state.ui, e.to_string());
+        }
+    }
+
+    if let Some(path) = &state.volatile.dialogs.traj_save.take_picked() {
+        let (stride, atom_set) = (state.ui.md.traj_stride, state.ui.md.traj_atom_set);
+        if let Err(e) = state.save_trajectory(path, stride, atom_set) {
+            handle_err(&mut state.ui, e.to_string());
+        }
+    }
+
+    if let Some(path) = &state.volatile.dialogs.mesh_save.take_picked() {
+        let cfg = &state.ui.mesh_export;
+        let (source, sel_only) = (cfg.source, cfg.selection_only);
+        let scale = cfg.scale_input.trim().parse().unwrap_or(1.);
+
+        if let Err(e) = state.export_mesh(path, scene, source, sel_only, scale) {
+            handle_err(&mut state.ui, e.to_string());
+        }
+    }
+
+    if let Some(path) = &state.volatile.dialogs.library_save.take_picked()
+        && let Some(lib) = &state.library
+    {
+        match lib.save_filtered_sdf(path) {
+            Ok(()) => handle_success(
+                &mut state.ui,
+                format!("Saved {} molecules to {path:?}", lib.filtered.len()),
+            ),
+            Err(e) => handle_err(&mut state.ui, e.to_string()),
+        }
+    }
+
+    if let Some(path) = &state.volatile.dialogs.library_csv_save.take_picked()
+        && let Some(lib) = &state.library
+    {
+        match lib.save_descriptors_csv(path) {
+            Ok(()) => handle_success(
+                &mut state.ui,
+                format!(
+                    "Saved descriptors for {} molecules to {path:?}",
+                    lib.filtered.len()
+                ),
+            ),
+            Err(e) => handle_err(&mut state.ui, e.to_string()),
+        }
+    }
+
     Ok(())
 }
 
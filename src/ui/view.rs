@@ -1,6 +1,6 @@
 use std::sync::atomic::Ordering;
 
-use egui::{ComboBox, RichText, Slider, Ui};
+use egui::{ComboBox, RichText, Slider, TextEdit, Ui};
 use graphics::{EngineUpdates, EntityUpdate, Scene};
 
 use crate::{
@@ -8,10 +8,11 @@ use crate::{
     drawing::{
         EntityClass, MoleculeView, draw_density_point_cloud, draw_density_surface, draw_water,
     },
+    file_io::mesh_export::MeshSource,
     drawing_wrappers::{draw_all_ligs, draw_all_lipids, draw_all_nucleic_acids},
     molecule::MolType,
     ui::{
-        COL_SPACING, DENS_ISO_MAX, DENS_ISO_MIN, misc,
+        COL_SPACING, COLOR_HIGHLIGHT, DENS_ISO_MAX, DENS_ISO_MIN, misc,
         misc::{section_box, toggle_btn, toggle_btn_inv},
     },
     util::clear_mol_entity_indices,
@@ -211,6 +212,17 @@ pub fn view_settings(
                 *redraw_lipid = true;
             }
 
+            if !state.ligands.is_empty() {
+                toggle_btn(
+                    &mut state.ui.labels_stereo,
+                    "R/S",
+                    "Show or hide CIP stereo labels on small molecules: R or S on stereocenters, \
+                    and E or Z on double bonds",
+                    ui,
+                    redraw_lig,
+                );
+            }
+
             // vis_check(&mut state.ui.visibility.dim_peptide, "Dim peptide", ui, redraw);
 
             if state.peptide.is_some() {
@@ -290,6 +302,51 @@ pub fn view_settings(
                 }
             }
         });
+
+        mesh_export(state, ui);
+    });
+}
+
+/// Export the surface, density, or cartoon meshes.
+fn mesh_export(state: &mut State, ui: &mut Ui) {
+    let available = MeshSource::available(state);
+    if available.is_empty() {
+        return;
+    }
+
+    let cfg = &mut state.ui.mesh_export;
+    if !available.contains(&cfg.source) {
+        cfg.source = available[0];
+    }
+
+    ui.horizontal(|ui| {
+        ui.label("Export mesh:");
+        ComboBox::from_id_salt(1201)
+            .width(80.)
+            .selected_text(cfg.source.to_str())
+            .show_ui(ui, |ui| {
+                for source in &available {
+                    ui.selectable_value(&mut cfg.source, *source, source.to_str());
+                }
+            });
+
+        ui.checkbox(&mut cfg.selection_only, "Sel only")
+            .on_hover_text("Only export the part of the mesh nearest the selected atoms or residue.");
+
+        ui.label("Scale:").on_hover_text(
+            "Output units per Å. For example, 0.1 for nm, or 1 for 1 mm per Å when 3D printing.",
+        );
+        ui.add_sized([30., Ui::available_height(ui)], TextEdit::singleline(&mut cfg.scale_input));
+
+        if ui
+            .button(RichText::new("Export").color(COLOR_HIGHLIGHT))
+            .on_hover_text("Save the mesh to OBJ, PLY, binary glTF, or STL, e.g. for 3D printing or rendering in other programs.")
+            .clicked()
+        {
+            let name = cfg.source.to_str().to_lowercase().replace(' ', "_");
+            state.volatile.dialogs.mesh_save.config_mut().default_file_name = format!("{name}.glb");
+            state.volatile.dialogs.mesh_save.save_file();
+        }
     });
 }
 
@@ -315,6 +372,21 @@ pub fn ui_section_vis(state: &mut State, ui: &mut Ui) {
             "Show or hide the SMILES text representation of the molecular formula",
             ui,
         );
+
+        vis_helper(
+            &mut state.ui.ui_vis.selfies,
+            "SELFIES",
+            "Show or hide the SELFIES text representation of the molecular formula. \
+            Unlike SMILES, any SELFIES string describes a valid molecule.",
+            ui,
+        );
+
+        vis_helper(
+            &mut state.ui.ui_vis.descriptors,
+            "Descriptors",
+            "Show or hide molecular descriptors, and drug-likeness rules, e.g. Lipinski's rule of five",
+            ui,
+        );
     }
 
     let tooltip = "Show or hide tools for adding lipids";
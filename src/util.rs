@@ -82,7 +82,7 @@ pub fn select_from_search(state: &mut State) {
         }
         ViewSelLevel::Residue => {
             for (i, res) in mol.residues.iter().enumerate() {
-                if query.contains(&res.serial_number.to_string()) {
+                if query.contains(&res.seq_label()) {
                     state.ui.selection = Selection::Residue(i);
                     return;
                 }
@@ -575,6 +575,7 @@ pub fn close_peptide(state: &mut State, scene: &mut Scene, engine_updates: &mut
 
     state.peptide = None;
     state.mol_dynamics = None;
+    state.docking = None;
 
     scene.entities.retain(|ent| {
         ent.class != EntityClass::Protein as u32
@@ -583,10 +584,12 @@ pub fn close_peptide(state: &mut State, scene: &mut Scene, engine_updates: &mut
             && ent.class != EntityClass::SecondaryStructure as u32
             && ent.class != EntityClass::SaSurface as u32
             && ent.class != EntityClass::SaSurfaceDots as u32
+            && ent.class != EntityClass::DockingSite as u32
     });
     clear_mol_entity_indices(state, None);
 
     state.volatile.aa_seq_text = String::new();
+    state.ui.docking_site_sel = None;
 
     if let Some(path) = path {
         for history in &mut state.to_save.open_history {
@@ -640,6 +643,8 @@ pub fn close_mol(
             let path = state.ligands[i].common.path.clone();
 
             state.ligands.remove(i);
+            // Docked poses refer to ligands by index.
+            state.docking = None;
 
             if state.ligands.is_empty() {
                 state.volatile.active_mol = None;
@@ -829,49 +834,6 @@ pub fn handle_scene_flags(
 
 /// Poll receivers for data on potentially long-running calls. E.g. HTTP.
 pub fn handle_thread_rx(state: &mut State) {
-    if let Some(rx) = &mut state.volatile.smiles_pending_data_avail {
-        match rx.try_recv() {
-            Ok((ident_type, http_result)) => {
-                let mut mol = None;
-                for mol_ in &mut state.ligands {
-                    for ident_ in &mol_.idents {
-                        if ident_ == &ident_type {
-                            mol = Some(mol_);
-                            break;
-                        }
-                    }
-                }
-
-                let Some(mol) = mol else {
-                    state.volatile.smiles_pending_data_avail = None;
-                    eprintln!("Unable to find the mol we requested smiles for: {ident_type:?}");
-                    return;
-                };
-
-                match http_result {
-                    Ok(smiles) => {
-                        println!("Loaded smiles for {ident_type:?} from Pubchem: {smiles}");
-                        mol.smiles = Some(smiles.clone());
-
-                        state
-                            .to_save
-                            .smiles_map
-                            .insert(ident_type.clone(), smiles.clone());
-                    }
-                    Err(_) => {
-                        // Note: This is currently broken.
-                        println!("Unable to find Smiles for ident {ident_type:?}, generating one.");
-                        // todo: Not saving to cache; not confident enough.
-                        mol.smiles = Some(mol.common.to_smiles());
-                    }
-                }
-                state.volatile.smiles_pending_data_avail = None;
-            }
-            // E.g. no results. COuld handle explicit errors too.
-            Err(_) => {}
-        }
-    }
-
     if state.volatile.mol_pending_data_avail.is_some()
         && let Some(mol) = &mut state.peptide
         && mol.poll_mol_pending_data(&mut state.volatile.mol_pending_data_avail)
@@ -907,6 +869,22 @@ pub fn handle_thread_rx(state: &mut State) {
             Err(_) => {}
         }
     }
+
+    if let Some(job) = &mut state.volatile.screening
+        && let Some(result) = job.poll()
+    {
+        match result {
+            Ok(count) => handle_success(
+                &mut state.ui,
+                format!(
+                    "Screening complete; ranked {count} molecules in {}",
+                    job.paths.csv.display()
+                ),
+            ),
+            Err(e) => handle_err(&mut state.ui, format!("Screening: {e}")),
+        }
+        state.volatile.screening = None;
+    }
 }
 
 pub fn make_egui_color(color: Color) -> Color32 {